@@ -1,5 +1,6 @@
 use crate::{
     error::{Error, Result},
+    raw,
     reader::BencodeReader,
     tokens::Token,
 };
@@ -66,10 +67,13 @@ impl<'de, R: BencodeReader> serde::de::Deserializer<'de> for &mut Deserializer<'
     }
 
     #[inline]
-    fn deserialize_newtype_struct<V>(self, _name: &'static str, visitor: V) -> Result<V::Value>
+    fn deserialize_newtype_struct<V>(self, name: &'static str, visitor: V) -> Result<V::Value>
     where
         V: serde::de::Visitor<'de>,
     {
+        if name == raw::TOKEN {
+            return visitor.visit_byte_buf(self.reader.read_raw()?);
+        }
         visitor.visit_newtype_struct(self)
     }
 
@@ -2,6 +2,7 @@
 
 pub mod deserializer;
 pub mod error;
+pub mod raw;
 pub mod reader;
 pub mod serializer;
 pub mod tokens;
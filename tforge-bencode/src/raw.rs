@@ -0,0 +1,131 @@
+use crate::{deserializer::from_reader, error::Result, serializer::from_writer};
+use serde::{de::Visitor, Deserialize, Deserializer, Serialize, Serializer};
+use std::io::{BufReader, Cursor};
+
+/// Name of the newtype struct the serializer and deserializer recognize as a [`RawValue`]
+pub(crate) const TOKEN: &str = "$tforge_bencode::RawValue";
+
+/// A bencoded value kept exactly as it was read.
+///
+/// Deserializing a `RawValue` captures the bytes of the value without decoding
+/// them, and serializing it writes those bytes back untouched. This is how a
+/// torrent can be edited without altering its info dictionary, whose hash
+/// identifies the torrent.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash)]
+pub struct RawValue(Vec<u8>);
+
+impl RawValue {
+    /// Wraps bytes that must already be a single valid bencoded value
+    pub fn from_bytes(bytes: Vec<u8>) -> Result<Self> {
+        let mut reader = BufReader::new(Cursor::new(&bytes));
+        let _: RawValue = from_reader(&mut reader)?;
+        Ok(RawValue(bytes))
+    }
+
+    /// Bencodes a value
+    pub fn from_value<T: Serialize>(value: &T) -> Result<Self> {
+        let mut buffer = Vec::new();
+        let mut writer = from_writer(&mut buffer);
+        value.serialize(&mut writer)?;
+        Ok(RawValue(buffer))
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.0
+    }
+
+    /// Decodes the value
+    pub fn decode<T: for<'de> Deserialize<'de>>(&self) -> Result<T> {
+        let mut reader = BufReader::new(Cursor::new(&self.0));
+        from_reader(&mut reader)
+    }
+}
+
+impl Serialize for RawValue {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_newtype_struct(TOKEN, &RawBytes(&self.0))
+    }
+}
+
+struct RawBytes<'a>(&'a [u8]);
+
+impl Serialize for RawBytes<'_> {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_bytes(self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for RawValue {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_newtype_struct(TOKEN, RawValueVisitor)
+    }
+}
+
+struct RawValueVisitor;
+
+impl<'de> Visitor<'de> for RawValueVisitor {
+    type Value = RawValue;
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(formatter, "a raw bencoded value")
+    }
+
+    fn visit_byte_buf<E>(self, v: Vec<u8>) -> std::result::Result<Self::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        Ok(RawValue(v))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Outer {
+        a: String,
+        b: RawValue,
+        c: i64,
+    }
+
+    #[test]
+    fn test_raw_value_keeps_bytes() {
+        // keys of the inner dictionary are not sorted, re-encoding it would reorder them
+        let content = b"d1:a3:foo1:bd1:zi1e1:yli2eee1:ci3ee";
+        let mut reader = BufReader::new(Cursor::new(content));
+        let outer: Outer = from_reader(&mut reader).unwrap();
+        assert_eq!(outer.b.as_bytes(), b"d1:zi1e1:yli2eee");
+        assert_eq!(outer.c, 3);
+
+        let mut buffer = Vec::new();
+        let mut writer = from_writer(&mut buffer);
+        outer.serialize(&mut writer).unwrap();
+        assert_eq!(&buffer, content);
+    }
+
+    #[test]
+    fn test_raw_value_decode() {
+        let raw = RawValue::from_bytes(b"d3:bar4:spam3:fooi42ee".to_vec()).unwrap();
+        let decoded: BTreeMap<String, RawValue> = raw.decode().unwrap();
+        assert_eq!(decoded["foo"].as_bytes(), b"i42e");
+        assert_eq!(RawValue::from_value(&decoded).unwrap(), raw);
+
+        assert!(RawValue::from_bytes(b"d3:bar".to_vec()).is_err());
+        assert!(RawValue::from_bytes(b"i1ei2e".to_vec()).is_err());
+    }
+}
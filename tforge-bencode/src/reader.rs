@@ -1,6 +1,6 @@
 use crate::{
     error::{Error, Result},
-    tokens::{Token, TOKEN_DELIM, TOKEN_END},
+    tokens::{Token, TOKEN_DELIM, TOKEN_DICT, TOKEN_END, TOKEN_INTEGER, TOKEN_LIST},
 };
 use std::io::BufRead;
 
@@ -14,6 +14,7 @@ pub trait BencodeReader {
     fn read_i64(&mut self) -> Result<i64>;
     fn read_bytes(&mut self) -> Result<Vec<u8>>;
     fn read_string(&mut self) -> Result<String>;
+    fn read_raw(&mut self) -> Result<Vec<u8>>;
     fn read(&mut self) -> Result<Vec<u8>>;
 }
 
@@ -90,6 +91,47 @@ impl<T: BufRead> BencodeReader for T {
         Ok(string)
     }
 
+    fn read_raw(&mut self) -> Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        let mut depth = 0;
+        loop {
+            match self.peek_token()? {
+                Token::Int => {
+                    self.consume_current_token()?;
+                    buf.push(TOKEN_INTEGER);
+                    buf.extend(self.read_until_end()?);
+                    buf.push(TOKEN_END);
+                }
+                Token::Bytes => {
+                    let length_buf = self.read_until_delim()?;
+                    let length = String::from_utf8(length_buf.clone())?.parse::<u64>()?;
+                    buf.extend(length_buf);
+                    buf.push(TOKEN_DELIM);
+                    buf.extend(self.read_of_size(length as usize)?);
+                }
+                Token::List => {
+                    self.consume_current_token()?;
+                    buf.push(TOKEN_LIST);
+                    depth += 1;
+                }
+                Token::Dict => {
+                    self.consume_current_token()?;
+                    buf.push(TOKEN_DICT);
+                    depth += 1;
+                }
+                Token::End if depth > 0 => {
+                    self.consume_current_token()?;
+                    buf.push(TOKEN_END);
+                    depth -= 1;
+                }
+                Token::End => return Err(Error::from_syntax("Unexpected end")),
+            }
+            if depth == 0 {
+                return Ok(buf);
+            }
+        }
+    }
+
     fn read(&mut self) -> Result<Vec<u8>> {
         let buf = self.fill_buf().map(|buf| buf.to_vec())?;
         Ok(buf)
@@ -302,5 +344,26 @@ mod tests {
             assert!(reader.read_string().is_err());
             assert_eq!(reader.fill_buf().unwrap(), b"hello world");
         }
+
+        #[test]
+        fn test_read_raw() {
+            let mut reader = Cursor::new(b"i-42ei1e");
+            assert_eq!(reader.read_raw().unwrap(), b"i-42e");
+            assert_eq!(reader.fill_buf().unwrap(), b"i1e");
+
+            let mut reader = Cursor::new(b"5:hello5:world");
+            assert_eq!(reader.read_raw().unwrap(), b"5:hello");
+            assert_eq!(reader.fill_buf().unwrap(), b"5:world");
+
+            let mut reader = Cursor::new(b"d3:bar4:spam3:fooli42eleee4:tail");
+            assert_eq!(reader.read_raw().unwrap(), b"d3:bar4:spam3:fooli42eleee");
+            assert_eq!(reader.fill_buf().unwrap(), b"4:tail");
+
+            let mut reader = Cursor::new(b"e");
+            assert!(reader.read_raw().is_err());
+
+            let mut reader = Cursor::new(b"li1e");
+            assert!(reader.read_raw().is_err());
+        }
     }
 }
@@ -1,15 +1,19 @@
 use crate::{
     error::{Error, Result},
+    raw,
     writer::BencodeWriter,
 };
 
 pub struct Serializer<'ser, W: BencodeWriter> {
     writer: &'ser mut W,
+
+    /// Set while serializing a [`RawValue`], whose bytes are written as is
+    raw: bool,
 }
 
 impl<'ser, W: BencodeWriter> Serializer<'ser, W> {
     pub fn from_writer(writer: &'ser mut W) -> Self {
-        Serializer { writer }
+        Serializer { writer, raw: false }
     }
 }
 
@@ -82,6 +86,9 @@ impl<'a, 'ser: 'a, W: BencodeWriter> serde::ser::Serializer for &'a mut Serializ
     }
 
     fn serialize_bytes(self, value: &[u8]) -> Result<()> {
+        if std::mem::take(&mut self.raw) {
+            return self.writer.write_raw(value);
+        }
         self.writer.write_bytes(value)
     }
 
@@ -104,9 +111,10 @@ impl<'a, 'ser: 'a, W: BencodeWriter> serde::ser::Serializer for &'a mut Serializ
 
     fn serialize_newtype_struct<T: ?Sized + serde::ser::Serialize>(
         self,
-        _name: &'static str,
+        name: &'static str,
         value: &T,
     ) -> Result<()> {
+        self.raw = name == raw::TOKEN;
         value.serialize(self)
     }
 
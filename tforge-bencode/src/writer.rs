@@ -10,6 +10,7 @@ pub trait BencodeWriter {
     fn write_signed_integer(&mut self, value: i64) -> Result<()>;
     fn write_unsigned_integer(&mut self, value: u64) -> Result<()>;
     fn write_bytes(&mut self, value: &[u8]) -> Result<()>;
+    fn write_raw(&mut self, value: &[u8]) -> Result<()>;
     fn write_list_start(&mut self) -> Result<()>;
     fn write_dict_start(&mut self) -> Result<()>;
     fn write_end(&mut self) -> Result<()>;
@@ -42,6 +43,11 @@ impl<T: Write> BencodeWriter for T {
         Ok(())
     }
 
+    fn write_raw(&mut self, value: &[u8]) -> Result<()> {
+        self.write_all(value)?;
+        Ok(())
+    }
+
     fn write_list_start(&mut self) -> Result<()> {
         self.write_token(Token::List)
     }
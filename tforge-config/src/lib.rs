@@ -1,12 +1,33 @@
-use anyhow::{Context, Error, Result};
+use anyhow::{bail, Context, Error, Result};
 use serde::Deserialize;
 use serde_with::{serde_as, Bytes};
+use std::{net::SocketAddr, path::PathBuf};
 
 #[serde_as]
 #[derive(Deserialize, Debug, PartialEq)]
 pub struct ClientConfig {
     #[serde_as(as = "Bytes")]
     pub peer_id: [u8; 20],
+
+    /// Announce to a tracker of every tier of a torrent instead of the first one that answers
+    #[serde(default)]
+    pub announce_to_all_tiers: bool,
+
+    /// The certificates trusted by the HTTPS trackers
+    #[serde(default)]
+    pub tls: ClientTlsConfig,
+}
+
+/// Trust for trackers whose certificate isn't signed by a public CA
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct ClientTlsConfig {
+    /// PEM file of CA certificates trusted besides the system ones, e.g. an internal CA
+    pub ca_bundle: Option<PathBuf>,
+
+    /// PEM file of the only certificates a tracker may present, compared as
+    /// they are rather than as CAs, e.g. a self-signed one
+    pub pinned_certificate: Option<PathBuf>,
 }
 
 impl TryFrom<&str> for ClientConfig {
@@ -25,17 +46,412 @@ impl TryFrom<String> for ClientConfig {
     }
 }
 
-#[derive(Deserialize, Debug, PartialEq)]
-pub struct ServerConfig {}
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct ServerConfig {
+    /// The HTTP tracker listener
+    pub http: Option<HttpConfig>,
+
+    /// The UDP tracker listeners (BEP 15)
+    pub udp: Option<UdpConfig>,
+
+    /// The listener of the Prometheus metrics and the JSON admin API
+    pub admin: Option<AdminConfig>,
+
+    /// Seconds clients are asked to wait between announces
+    pub interval: u64,
+
+    /// Seconds clients must wait at least between announces
+    pub min_interval: u64,
+
+    /// Peers returned when the client doesn't send `numwant`
+    pub default_numwant: usize,
+
+    /// Most peers returned by an announce, whatever the `numwant`
+    pub max_numwant: usize,
+
+    /// Intervals a peer may miss before it is dropped from its swarms
+    pub peer_timeout: u32,
+
+    /// Seconds between two sweeps of the timed out peers
+    pub reap_interval: u64,
+
+    /// Lengthen the interval of large swarms and of a busy tracker
+    pub adaptive_interval: Option<AdaptiveIntervalConfig>,
+
+    /// How the peers returned by an announce are picked
+    pub selection: SelectionConfig,
+
+    /// Where swarms, completed counts and registered torrents are kept
+    pub storage: StorageConfig,
+
+    /// Serve registered users and torrents only, kept in a SQLite storage
+    pub private: Option<PrivateConfig>,
+
+    /// Rate limits and filters against abusive clients
+    pub protection: ProtectionConfig,
+
+    /// Connect back to announcing peers to find those which can't be reached
+    pub nat_check: Option<NatCheckConfig>,
+
+    /// Forward the announces to upstream trackers and add their peers to the local ones
+    pub proxy: Option<ProxyConfig>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            http: Some(HttpConfig::default()),
+            udp: None,
+            admin: None,
+            interval: 1800,
+            min_interval: 900,
+            default_numwant: 50,
+            max_numwant: 200,
+            peer_timeout: 2,
+            reap_interval: 60,
+            adaptive_interval: None,
+            selection: SelectionConfig::default(),
+            storage: StorageConfig::default(),
+            private: None,
+            protection: ProtectionConfig::default(),
+            nat_check: None,
+            proxy: None,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct HttpConfig {
+    /// Address to listen on, e.g. `0.0.0.0:6969`
+    pub bind: SocketAddr,
+
+    /// Answer a `/scrape` without `info_hash` with the stats of every torrent
+    #[serde(default)]
+    pub full_scrape: bool,
+
+    /// Accept WebTorrent clients upgrading `/announce` to a WebSocket, `wss://` with `tls`
+    #[serde(default)]
+    pub websocket: bool,
+
+    /// Serves HTTPS instead of plain HTTP on `bind`
+    pub tls: Option<TlsConfig>,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        HttpConfig {
+            bind: SocketAddr::from(([0, 0, 0, 0], 6969)),
+            full_scrape: false,
+            websocket: false,
+            tls: None,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct TlsConfig {
+    /// PEM file of the certificate chain
+    pub cert: PathBuf,
+
+    /// PEM file of the private key
+    pub key: PathBuf,
+
+    /// Seconds between two checks of the certificate and key files, reloaded when they change
+    #[serde(default = "default_tls_reload_interval")]
+    pub reload_interval: u64,
+
+    /// Address of a plain HTTP listener redirecting to the HTTPS one
+    pub redirect: Option<SocketAddr>,
+}
+
+fn default_tls_reload_interval() -> u64 {
+    60
+}
+
+/// Scales `interval` and `min_interval` by how far a swarm and the load of the
+/// tracker go past their thresholds, e.g. twice as long for twice as many peers
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct AdaptiveIntervalConfig {
+    /// Peers from which a swarm gets longer intervals
+    pub swarm_size: u64,
+
+    /// Announces per second from which every swarm gets longer intervals
+    pub max_load: u64,
+
+    /// Seconds the interval never goes over, also the one peers time out from
+    pub max_interval: u64,
+}
+
+impl Default for AdaptiveIntervalConfig {
+    fn default() -> Self {
+        AdaptiveIntervalConfig {
+            swarm_size: 1000,
+            max_load: 1000,
+            max_interval: 7200,
+        }
+    }
+}
+
+/// The preferences of peer selection, which is random otherwise
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct SelectionConfig {
+    /// Give leechers the seeders before the other leechers
+    pub seeders_first: bool,
+
+    /// Give seeders other seeders, which they have nothing to exchange with
+    pub seeders_to_seeders: bool,
+
+    /// Only give peers of the address family the request came from
+    pub match_address_family: bool,
+}
+
+impl Default for SelectionConfig {
+    fn default() -> Self {
+        SelectionConfig {
+            seeders_first: true,
+            seeders_to_seeders: false,
+            match_address_family: true,
+        }
+    }
+}
+
+/// The rules of a private tracker, whose users announce to `/announce/<passkey>`
+#[derive(Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(default)]
+pub struct PrivateConfig {
+    /// Smallest uploaded/downloaded ratio users must keep to download, none by default
+    pub min_ratio: Option<f64>,
+
+    /// Bytes users may download before their ratio is enforced
+    pub ratio_grace: u64,
+
+    /// Peer id prefixes of the clients refused, like `protection.banned_clients`
+    pub banned_clients: Vec<String>,
+}
+
+/// Limits and filters applied to every request, all off by default
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct ProtectionConfig {
+    /// Announces of each IP address, IPv6 ones counted by /64
+    pub announce_rate: Option<RateLimitConfig>,
+
+    /// Scrapes of each IP address, IPv6 ones counted by /64
+    pub scrape_rate: Option<RateLimitConfig>,
+
+    /// Refuse the announces sent sooner than `min_interval` after the previous one of a peer
+    pub enforce_min_interval: bool,
+
+    /// File of the IP ranges refused, as CIDRs or in the P2P or DAT formats
+    pub blocklist: Option<PathBuf>,
+
+    /// File of the only IP ranges served, in the same formats as `blocklist`
+    pub allowlist: Option<PathBuf>,
+
+    /// File of the IP ranges whose announces may set the address of their peer
+    /// with `ip`, e.g. a LAN behind the same NAT as the tracker. The `ip` of
+    /// the announces from anywhere else is ignored.
+    pub trusted_sources: Option<PathBuf>,
+
+    /// Seconds between two checks of the lists for changes
+    pub list_reload_interval: u64,
+
+    /// Peer id prefixes of the clients refused, e.g. `-XL0012-`
+    pub banned_clients: Vec<String>,
+}
+
+impl Default for ProtectionConfig {
+    fn default() -> Self {
+        ProtectionConfig {
+            announce_rate: None,
+            scrape_rate: None,
+            enforce_min_interval: false,
+            blocklist: None,
+            allowlist: None,
+            trusted_sources: None,
+            list_reload_interval: 60,
+            banned_clients: Vec::new(),
+        }
+    }
+}
+
+/// A token bucket, refilled with `rate` requests per second up to `burst`
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct RateLimitConfig {
+    pub rate: f64,
+    pub burst: u32,
+}
+
+/// Checks of the peers with a BitTorrent handshake to the address they announce from.
+/// Peers which don't answer only get the peers which do.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct NatCheckConfig {
+    /// Seconds to connect and get the handshake back
+    pub timeout: u64,
+
+    /// Most checks running at once
+    pub concurrency: usize,
+
+    /// Seconds the result of an `ip:port` is kept before it is checked again
+    pub cache_ttl: u64,
+}
+
+impl Default for NatCheckConfig {
+    fn default() -> Self {
+        NatCheckConfig {
+            timeout: 5,
+            concurrency: 64,
+            cache_ttl: 3600,
+        }
+    }
+}
+
+/// Announces of the torrents forwarded to their upstream trackers, as one
+/// peer whatever the number of local ones. Their peers are cached and added
+/// to the local peers.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct ProxyConfig {
+    /// Tiers of upstream announce URLs of the torrents without their own
+    pub tiers: Vec<Vec<String>>,
+
+    /// Directory of .torrent files, read on start, whose tiers are used for their torrents
+    pub torrents: Option<PathBuf>,
+
+    /// Announce to a tracker of every tier instead of the first one that answers
+    pub announce_to_all_tiers: bool,
+
+    /// Port announced upstream
+    pub port: u16,
+
+    /// Seconds the upstream peers of a torrent are served before announcing
+    /// again, longer when the upstream `min interval` is
+    pub cache_ttl: u64,
+
+    /// Seconds an HTTP announce waits for the first upstream peers of its torrent
+    pub wait: u64,
+
+    /// Most upstream announces running at once
+    pub concurrency: usize,
+
+    /// Upstream announces of all torrents together
+    pub rate: RateLimitConfig,
+}
+
+impl Default for ProxyConfig {
+    fn default() -> Self {
+        ProxyConfig {
+            tiers: Vec::new(),
+            torrents: None,
+            announce_to_all_tiers: false,
+            port: 6881,
+            cache_ttl: 300,
+            wait: 5,
+            concurrency: 16,
+            rate: RateLimitConfig {
+                rate: 1.0,
+                burst: 10,
+            },
+        }
+    }
+}
+
+/// The storage backend of the tracker, chosen by `backend`
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "backend", rename_all = "lowercase")]
+pub enum StorageConfig {
+    /// Everything in memory, optionally saved to a snapshot file and restored on start
+    Memory {
+        snapshot: Option<PathBuf>,
+
+        /// Seconds between two snapshots
+        #[serde(default = "default_snapshot_interval")]
+        snapshot_interval: u64,
+    },
+
+    /// A SQLite database, keeping the stats across restarts and editable with
+    /// `tforge tracker` while the server runs
+    Sqlite { path: PathBuf },
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        StorageConfig::Memory {
+            snapshot: None,
+            snapshot_interval: default_snapshot_interval(),
+        }
+    }
+}
+
+fn default_snapshot_interval() -> u64 {
+    300
+}
 
 impl TryFrom<&str> for ServerConfig {
     type Error = Error;
 
     fn try_from(contents: &str) -> Result<Self> {
-        toml::from_str(contents).context("parsing toml")
+        let config: ServerConfig = toml::from_str(contents).context("parsing toml")?;
+        config.validate()?;
+        Ok(config)
     }
 }
 
+impl ServerConfig {
+    /// Refuses the configs which parse but can't be served
+    fn validate(&self) -> Result<()> {
+        // `tforge tracker` only adds users and torrents to a SQLite storage
+        if self.private.is_some() && matches!(self.storage, StorageConfig::Memory { .. }) {
+            bail!("a private tracker needs a sqlite storage");
+        }
+        let mut intervals = vec![
+            ("reap_interval", self.reap_interval),
+            (
+                "protection.list_reload_interval",
+                self.protection.list_reload_interval,
+            ),
+        ];
+        if let Some(tls) = self.http.as_ref().and_then(|http| http.tls.as_ref()) {
+            intervals.push(("http.tls.reload_interval", tls.reload_interval));
+        }
+        if let StorageConfig::Memory {
+            snapshot_interval, ..
+        } = self.storage
+        {
+            intervals.push(("storage.snapshot_interval", snapshot_interval));
+        }
+        // Timers can't tick every zero seconds
+        if let Some((name, _)) = intervals.iter().find(|(_, seconds)| *seconds == 0) {
+            bail!("{} must be at least a second", name);
+        }
+        Ok(())
+    }
+}
+
+impl TryFrom<String> for ServerConfig {
+    type Error = Error;
+
+    fn try_from(contents: String) -> Result<Self> {
+        ServerConfig::try_from(contents.as_str())
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct UdpConfig {
+    /// Addresses to listen on, usually an IPv4 and an IPv6 one, e.g. `0.0.0.0:6969` and `[::]:6969`
+    pub bind: Vec<SocketAddr>,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct AdminConfig {
+    /// Address to listen on, better kept private, e.g. `127.0.0.1:6970`
+    pub bind: SocketAddr,
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -50,7 +466,27 @@ mod tests {
         assert_eq!(
             result.unwrap(),
             ClientConfig {
-                peer_id: *b"1234567890-abcedfghi"
+                peer_id: *b"1234567890-abcedfghi",
+                announce_to_all_tiers: false,
+                tls: ClientTlsConfig::default(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_try_from_client_config_tls() {
+        let contents = r#"
+            peer_id = "1234567890-abcedfghi"
+
+            [tls]
+            ca_bundle = "/etc/tforge/ca.pem"
+        "#;
+        let config = ClientConfig::try_from(contents).unwrap();
+        assert_eq!(
+            config.tls,
+            ClientTlsConfig {
+                ca_bundle: Some("/etc/tforge/ca.pem".into()),
+                pinned_certificate: None,
             }
         );
     }
@@ -76,6 +512,216 @@ mod tests {
         let contents = "";
         let result = ServerConfig::try_from(contents);
         assert!(result.is_ok());
-        assert_eq!(result.unwrap(), ServerConfig {});
+        assert_eq!(result.unwrap(), ServerConfig::default());
+        assert!(ServerConfig::try_from("reap_interval = 0").is_err());
+    }
+
+    #[test]
+    fn test_try_from_server_config_http() {
+        let contents = r#"
+            interval = 600
+
+            [http]
+            bind = "127.0.0.1:8080"
+            full_scrape = true
+            websocket = true
+
+            [udp]
+            bind = ["0.0.0.0:6969", "[::]:6969"]
+
+            [admin]
+            bind = "127.0.0.1:6970"
+        "#;
+        let config = ServerConfig::try_from(contents).unwrap();
+        assert_eq!(config.interval, 600);
+        assert_eq!(config.min_interval, 900);
+        let http = config.http.unwrap();
+        assert_eq!(http.bind, "127.0.0.1:8080".parse().unwrap());
+        assert!(http.full_scrape);
+        assert!(http.websocket);
+        assert_eq!(http.tls, None);
+        assert_eq!(config.udp.unwrap().bind.len(), 2);
+        assert_eq!(
+            config.admin.unwrap().bind,
+            "127.0.0.1:6970".parse().unwrap()
+        );
+        assert_eq!(config.storage, StorageConfig::default());
+    }
+
+    #[test]
+    fn test_try_from_server_config_tls() {
+        let contents = r#"
+            [http]
+            bind = "0.0.0.0:443"
+
+            [http.tls]
+            cert = "/etc/tforge/cert.pem"
+            key = "/etc/tforge/key.pem"
+            redirect = "0.0.0.0:80"
+        "#;
+        let config = ServerConfig::try_from(contents).unwrap();
+        assert_eq!(
+            config.http.unwrap().tls,
+            Some(TlsConfig {
+                cert: "/etc/tforge/cert.pem".into(),
+                key: "/etc/tforge/key.pem".into(),
+                reload_interval: 60,
+                redirect: Some("0.0.0.0:80".parse().unwrap()),
+            })
+        );
+    }
+
+    #[test]
+    fn test_try_from_server_config_proxy() {
+        let contents = r#"
+            [proxy]
+            tiers = [["http://tracker.example.org/announce"], ["udp://tracker.example.net:6969"]]
+            torrents = "/var/lib/tforge/torrents"
+            cache_ttl = 600
+
+            [proxy.rate]
+            rate = 0.5
+            burst = 5
+        "#;
+        let config = ServerConfig::try_from(contents).unwrap();
+        assert_eq!(
+            config.proxy.unwrap(),
+            ProxyConfig {
+                tiers: vec![
+                    vec!["http://tracker.example.org/announce".to_string()],
+                    vec!["udp://tracker.example.net:6969".to_string()],
+                ],
+                torrents: Some("/var/lib/tforge/torrents".into()),
+                cache_ttl: 600,
+                rate: RateLimitConfig {
+                    rate: 0.5,
+                    burst: 5
+                },
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_try_from_server_config_storage() {
+        let contents = r#"
+            [storage]
+            backend = "memory"
+            snapshot = "/var/lib/tforge/tracker.snapshot"
+        "#;
+        let config = ServerConfig::try_from(contents).unwrap();
+        assert_eq!(
+            config.storage,
+            StorageConfig::Memory {
+                snapshot: Some("/var/lib/tforge/tracker.snapshot".into()),
+                snapshot_interval: 300,
+            }
+        );
+
+        let contents = r#"
+            [storage]
+            backend = "sqlite"
+            path = "tracker.db"
+
+            [private]
+            min_ratio = 0.5
+            banned_clients = ["-XL0012-"]
+        "#;
+        let config = ServerConfig::try_from(contents).unwrap();
+        assert_eq!(
+            config.storage,
+            StorageConfig::Sqlite {
+                path: "tracker.db".into()
+            }
+        );
+        let private = config.private.unwrap();
+        assert_eq!(private.min_ratio, Some(0.5));
+        assert_eq!(private.ratio_grace, 0);
+        assert_eq!(private.banned_clients, vec!["-XL0012-"]);
+
+        let contents = r#"
+            [storage]
+            backend = "memory"
+
+            [private]
+        "#;
+        assert!(ServerConfig::try_from(contents).is_err());
+
+        let contents = r#"
+            [storage]
+            backend = "memory"
+            snapshot_interval = 0
+        "#;
+        let err = ServerConfig::try_from(contents).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "storage.snapshot_interval must be at least a second"
+        );
+    }
+
+    #[test]
+    fn test_try_from_server_config_protection() {
+        let contents = r#"
+            [protection]
+            enforce_min_interval = true
+            blocklist = "/etc/tforge/blocklist.p2p"
+            trusted_sources = "/etc/tforge/lan.txt"
+            banned_clients = ["-XL0012-"]
+
+            [protection.announce_rate]
+            rate = 0.5
+            burst = 20
+        "#;
+        let config = ServerConfig::try_from(contents).unwrap();
+        assert_eq!(config.nat_check, None);
+        let protection = config.protection;
+        assert_eq!(
+            protection,
+            ProtectionConfig {
+                announce_rate: Some(RateLimitConfig {
+                    rate: 0.5,
+                    burst: 20
+                }),
+                scrape_rate: None,
+                enforce_min_interval: true,
+                blocklist: Some("/etc/tforge/blocklist.p2p".into()),
+                allowlist: None,
+                trusted_sources: Some("/etc/tforge/lan.txt".into()),
+                list_reload_interval: 60,
+                banned_clients: vec!["-XL0012-".to_string()],
+            }
+        );
+    }
+
+    #[test]
+    fn test_try_from_server_config_peers() {
+        let contents = r#"
+            peer_timeout = 3
+
+            [adaptive_interval]
+            swarm_size = 500
+
+            [selection]
+            match_address_family = false
+
+            [nat_check]
+            timeout = 2
+        "#;
+        let config = ServerConfig::try_from(contents).unwrap();
+        assert_eq!(config.peer_timeout, 3);
+        assert_eq!(config.reap_interval, 60);
+        let nat_check = config.nat_check.unwrap();
+        assert_eq!((nat_check.timeout, nat_check.concurrency), (2, 64));
+        let adaptive = config.adaptive_interval.unwrap();
+        assert_eq!(adaptive.swarm_size, 500);
+        assert_eq!(adaptive.max_interval, 7200);
+        assert_eq!(
+            config.selection,
+            SelectionConfig {
+                seeders_first: true,
+                seeders_to_seeders: false,
+                match_address_family: false,
+            }
+        );
     }
 }
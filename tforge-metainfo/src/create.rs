@@ -0,0 +1,343 @@
+use crate::{
+    error::{Error, Result},
+    file_tree::{FileTree, FileTreeEntry},
+    hash::Sha1Hash,
+    merkle::{FileHasher, BLOCK_SIZE},
+    piece_layers::PieceLayers,
+    text::RawString,
+    File,
+    FileAttributes,
+    FileInfo,
+    Info,
+    Version,
+};
+use sha1::{Digest, Sha1};
+use std::{
+    fs,
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+#[derive(Debug, Clone)]
+pub struct CreateOptions {
+    /// Which metadata to generate
+    pub version: Version,
+
+    /// Length of the pieces in bytes. Must be a power of two of at least 16 KiB for v2 and hybrid torrents.
+    pub piece_length: usize,
+
+    /// Name of the torrent, defaults to the name of the file or directory
+    pub name: Option<String>,
+
+    /// Marks the torrent as private (BEP 27)
+    pub private: bool,
+
+    /// Source tag, usually the name of the private tracker the torrent is made for
+    pub source: Option<String>,
+
+    /// Aligns every file of a v1 torrent to a piece boundary with padding files (BEP 47).
+    /// Hybrid torrents are always aligned.
+    pub pad_files: bool,
+
+    /// Stores the SHA-1 of every file of the v1 file list (BEP 47)
+    pub file_sha1: bool,
+}
+
+impl Default for CreateOptions {
+    fn default() -> Self {
+        CreateOptions {
+            version: Version::V1,
+            piece_length: 256 * 1024,
+            name: None,
+            private: false,
+            source: None,
+            pad_files: false,
+            file_sha1: false,
+        }
+    }
+}
+
+/// Hashes the file or directory at `path` into an info dictionary.
+/// The piece layers are returned for v2 and hybrid torrents.
+pub fn create_info(path: &Path, options: &CreateOptions) -> Result<(Info, Option<PieceLayers>)> {
+    let piece_length = options.piece_length;
+    if options.version.has_v2() && (piece_length < BLOCK_SIZE || !piece_length.is_power_of_two()) {
+        return Err(Error::from_invalid(format!(
+            "piece length {} is not a power of two of at least {}",
+            piece_length, BLOCK_SIZE
+        )));
+    }
+    if piece_length == 0 {
+        return Err(Error::from_invalid("piece length must not be zero"));
+    }
+
+    let name = match &options.name {
+        Some(name) => name.clone(),
+        None => path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .ok_or_else(|| Error::from_invalid(format!("{:?} has no file name", path)))?,
+    };
+
+    let single_file = path.is_file();
+    let files = if single_file {
+        vec![SourceFile {
+            path: vec![name.clone()],
+            full_path: path.to_path_buf(),
+            attributes: attributes(path, &fs::metadata(path)?),
+            symlink_path: None,
+        }]
+    } else {
+        let root = path.canonicalize()?;
+        let mut files = Vec::new();
+        walk(&root, path, &mut Vec::new(), &mut files)?;
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+        files
+    };
+    if files.is_empty() {
+        return Err(Error::from_invalid(format!("{:?} contains no files", path)));
+    }
+
+    let mut pieces = PieceHasher::new(piece_length);
+    let mut v1_files = Vec::new();
+    let mut file_tree = FileTree::default();
+    let mut piece_layers = PieceLayers::default();
+    let mut buffer = vec![0; piece_length.max(BLOCK_SIZE)];
+
+    let pad_files = options.version == Version::Hybrid || options.pad_files;
+
+    for (index, file) in files.iter().enumerate() {
+        let mut merkle = FileHasher::new(piece_length);
+        let mut sha1 = options.file_sha1.then(Sha1::new);
+        let mut length = 0;
+        if file.symlink_path.is_none() {
+            let mut reader = fs::File::open(&file.full_path)?;
+            loop {
+                let read = reader.read(&mut buffer)?;
+                if read == 0 {
+                    break;
+                }
+                length += read;
+                if options.version.has_v1() {
+                    pieces.update(&buffer[..read]);
+                }
+                if options.version.has_v2() {
+                    merkle.update(&buffer[..read]);
+                }
+                if let Some(sha1) = sha1.as_mut() {
+                    sha1.update(&buffer[..read]);
+                }
+            }
+        }
+        let merkle = merkle.finish();
+
+        v1_files.push(File {
+            attr: file.attributes.to_attr(),
+            length,
+            path: file.path.iter().cloned().map(RawString::from).collect(),
+            path_utf8: None,
+            sha1: sha1
+                .filter(|_| length > 0)
+                .map(|sha1| sha1.finalize().into()),
+            symlink_path: file.symlink_path.clone(),
+        });
+
+        // Padding aligns every file to a piece boundary. Hybrid torrents need it
+        // so that the v1 pieces cover exactly the same data as the v2 ones.
+        let is_last = index + 1 == files.len();
+        let remainder = length % piece_length;
+        if pad_files && !is_last && remainder != 0 {
+            let padding = piece_length - remainder;
+            pieces.pad(padding);
+            v1_files.push(File {
+                attr: FileAttributes {
+                    padding: true,
+                    ..Default::default()
+                }
+                .to_attr(),
+                length: padding,
+                path: vec![".pad".into(), padding.to_string().into()],
+                path_utf8: None,
+                sha1: None,
+                symlink_path: None,
+            });
+        }
+
+        if options.version.has_v2() {
+            if let (Some(root), false) = (merkle.pieces_root, merkle.piece_layer.is_empty()) {
+                piece_layers.0.insert(root, merkle.piece_layer);
+            }
+            file_tree.insert(
+                &file.path,
+                FileTreeEntry {
+                    attr: file.attributes.to_attr(),
+                    length,
+                    pieces_root: merkle.pieces_root,
+                    symlink_path: file.symlink_path.clone(),
+                },
+            );
+        }
+    }
+
+    let file_info = options.version.has_v1().then(|| {
+        if single_file {
+            FileInfo::SingleFile {
+                length: v1_files[0].length,
+            }
+        } else {
+            FileInfo::MultiFile { files: v1_files }
+        }
+    });
+
+    let info = Info {
+        file_tree: options.version.has_v2().then_some(file_tree),
+        file_info,
+        meta_version: options.version.has_v2().then_some(2),
+        name: name.into(),
+        name_utf8: None,
+        piece_length,
+        pieces: pieces.finish(),
+        private: options.private.then_some(true),
+        source: options.source.clone(),
+    };
+
+    Ok((info, options.version.has_v2().then_some(piece_layers)))
+}
+
+/// A file to add to the torrent
+struct SourceFile {
+    /// Path relative to the torrent root
+    path: Vec<String>,
+    full_path: PathBuf,
+    attributes: FileAttributes,
+    symlink_path: Option<Vec<String>>,
+}
+
+/// Collects the files below `dir` with their path relative to the torrent root.
+/// Symlinks pointing inside the torrent are kept as symlinks, the others are followed.
+fn walk(
+    root: &Path,
+    dir: &Path,
+    prefix: &mut Vec<String>,
+    files: &mut Vec<SourceFile>,
+) -> Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        prefix.push(entry.file_name().to_string_lossy().into_owned());
+
+        let symlink_path = match entry.file_type()?.is_symlink() {
+            true => symlink_target(root, &path),
+            false => None,
+        };
+        if let Some(symlink_path) = symlink_path {
+            files.push(SourceFile {
+                path: prefix.clone(),
+                full_path: path.clone(),
+                attributes: FileAttributes {
+                    symlink: true,
+                    hidden: is_hidden(&path),
+                    ..Default::default()
+                },
+                symlink_path: Some(symlink_path),
+            });
+        } else if path.is_dir() {
+            walk(root, &path, prefix, files)?;
+        } else if path.is_file() {
+            files.push(SourceFile {
+                path: prefix.clone(),
+                full_path: path.clone(),
+                attributes: attributes(&path, &fs::metadata(&path)?),
+                symlink_path: None,
+            });
+        }
+
+        prefix.pop();
+    }
+    Ok(())
+}
+
+/// The target of a symlink relative to the torrent root, if it points inside the torrent
+fn symlink_target(root: &Path, link: &Path) -> Option<Vec<String>> {
+    let target = link.canonicalize().ok()?;
+    let relative = target.strip_prefix(root).ok()?;
+    Some(
+        relative
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy().into_owned())
+            .collect(),
+    )
+}
+
+fn is_hidden(path: &Path) -> bool {
+    path.file_name()
+        .is_some_and(|name| name.to_string_lossy().starts_with('.'))
+}
+
+#[cfg(unix)]
+fn is_executable(metadata: &fs::Metadata) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode() & 0o111 != 0
+}
+
+#[cfg(not(unix))]
+fn is_executable(_metadata: &fs::Metadata) -> bool {
+    false
+}
+
+fn attributes(path: &Path, metadata: &fs::Metadata) -> FileAttributes {
+    FileAttributes {
+        executable: is_executable(metadata),
+        hidden: is_hidden(path),
+        ..Default::default()
+    }
+}
+
+/// SHA-1 hashes a stream of data into v1 pieces
+struct PieceHasher {
+    piece_length: usize,
+    filled: usize,
+    hasher: Sha1,
+    pieces: Vec<Sha1Hash>,
+}
+
+impl PieceHasher {
+    fn new(piece_length: usize) -> Self {
+        PieceHasher {
+            piece_length,
+            filled: 0,
+            hasher: Sha1::new(),
+            pieces: Vec::new(),
+        }
+    }
+
+    fn update(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            let take = (self.piece_length - self.filled).min(data.len());
+            self.hasher.update(&data[..take]);
+            self.filled += take;
+            data = &data[take..];
+            if self.filled == self.piece_length {
+                self.pieces.push(self.hasher.finalize_reset().into());
+                self.filled = 0;
+            }
+        }
+    }
+
+    fn pad(&mut self, length: usize) {
+        let zeros = [0; BLOCK_SIZE];
+        let mut left = length;
+        while left > 0 {
+            let take = left.min(zeros.len());
+            self.update(&zeros[..take]);
+            left -= take;
+        }
+    }
+
+    fn finish(mut self) -> Vec<Sha1Hash> {
+        if self.filled > 0 {
+            self.pieces.push(self.hasher.finalize().into());
+        }
+        self.pieces
+    }
+}
@@ -0,0 +1,375 @@
+use crate::{
+    error::{Error, Result},
+    hash::InfoHashes,
+    text::RawString,
+    web_seed::UrlList,
+    Info,
+    MetaInfo,
+};
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{BufReader, Cursor},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+use tforge_bencode::{deserializer::from_reader, raw::RawValue, serializer::from_writer};
+
+/// The keys of a torrent modelled by [`MetaInfo`], the others are kept in `extra`
+const KEYS: &[&str] = &[
+    "announce",
+    "announce-list",
+    "comment",
+    "created by",
+    "creation date",
+    "encoding",
+    "httpseeds",
+    "info",
+    "piece layers",
+    "url-list",
+];
+
+/// A torrent whose info dictionary is kept as the exact bytes it was read from.
+///
+/// Only the fields outside of the info dictionary can be changed, so writing
+/// the torrent back never changes its info-hash. The keys [`MetaInfo`] doesn't
+/// model are written back as they were read.
+pub type RawMetaInfo = MetaInfo<RawValue>;
+
+impl RawMetaInfo {
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let mut meta_info: Self = from_reader(&mut BufReader::new(Cursor::new(bytes)))?;
+        let keys: BTreeMap<String, RawValue> =
+            from_reader(&mut BufReader::new(Cursor::new(bytes)))?;
+        meta_info.extra = keys
+            .into_iter()
+            .filter(|(key, _)| !KEYS.contains(&key.as_str()))
+            .collect();
+        Ok(meta_info)
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut buffer = Vec::new();
+        let mut writer = from_writer(&mut buffer);
+        serde::Serialize::serialize(self, &mut writer)?;
+        if self.extra.is_empty() {
+            return Ok(buffer);
+        }
+
+        let mut keys: BTreeMap<String, RawValue> =
+            from_reader(&mut BufReader::new(Cursor::new(&buffer)))?;
+        keys.extend(self.extra.clone());
+        Ok(RawValue::from_value(&keys)?.into_bytes())
+    }
+
+    /// Decodes the info dictionary
+    pub fn decode_info(&self) -> Result<Info> {
+        Ok(self.info.decode()?)
+    }
+
+    /// The info-hashes, computed from the original bytes of the info dictionary
+    pub fn info_hashes(&self) -> Result<InfoHashes> {
+        let version = self.decode_info()?.version()?;
+        Ok(InfoHashes::from_info_bytes(self.info.as_bytes(), version))
+    }
+}
+
+/// Changes to apply to the fields outside of the info dictionary of a torrent
+#[derive(Debug, Default, Clone)]
+pub struct Edit {
+    /// Replaces the `announce` URL
+    pub announce: Option<String>,
+
+    /// Replaces the `announce-list` tiers, removing it when empty
+    pub announce_list: Option<Vec<Vec<String>>>,
+
+    /// Replaces the `comment`, removing it when empty
+    pub comment: Option<String>,
+
+    /// Replaces `created by`, removing it when empty
+    pub created_by: Option<String>,
+
+    /// Replaces the `creation date`
+    pub creation_date: Option<SystemTime>,
+
+    /// Replaces the BEP 19 `url-list`, removing it when empty
+    pub url_list: Option<Vec<String>>,
+
+    /// Web seeds to append to the `url-list`
+    pub add_web_seeds: Vec<String>,
+
+    /// Replaces the host of the tracker URLs matching the first host with the second one
+    pub replace_tracker_host: Option<(String, String)>,
+}
+
+impl Edit {
+    pub fn apply<I>(&self, meta_info: &mut MetaInfo<I>) {
+        if let Some(announce) = &self.announce {
+            meta_info.announce.clone_from(announce);
+        }
+        if let Some(announce_list) = &self.announce_list {
+            meta_info.announce_list = non_empty(announce_list.clone());
+        }
+        if let Some(comment) = &self.comment {
+            meta_info.comment = (!comment.is_empty()).then(|| RawString::from(comment.as_str()));
+        }
+        if let Some(created_by) = &self.created_by {
+            meta_info.created_by = (!created_by.is_empty()).then(|| created_by.clone());
+        }
+        if let Some(creation_date) = self.creation_date {
+            meta_info.creation_date = Some(creation_date);
+        }
+        if let Some(url_list) = &self.url_list {
+            meta_info.url_list = non_empty(url_list.clone()).map(UrlList::from);
+        }
+        if !self.add_web_seeds.is_empty() {
+            let mut urls: Vec<String> = meta_info
+                .web_seeds()
+                .into_iter()
+                .map(String::from)
+                .collect();
+            for url in &self.add_web_seeds {
+                if !urls.contains(url) {
+                    urls.push(url.clone());
+                }
+            }
+            meta_info.url_list = Some(UrlList::from(urls));
+        }
+        if let Some((from, to)) = &self.replace_tracker_host {
+            meta_info.replace_tracker_host(from, to);
+        }
+    }
+
+    /// Edits a .torrent file in place.
+    /// Returns whether the file changed, it is left untouched otherwise.
+    pub fn apply_to_file(&self, path: &Path) -> Result<bool> {
+        self.apply_to_file_as(path, path)
+    }
+
+    /// Edits a .torrent file, writing the result to `output`.
+    /// Returns whether the torrent changed, nothing is written otherwise.
+    pub fn apply_to_file_as(&self, path: &Path, output: &Path) -> Result<bool> {
+        let original = fs::read(path)?;
+        let mut meta_info = RawMetaInfo::from_bytes(&original)?;
+        self.apply(&mut meta_info);
+
+        let edited = meta_info.to_bytes()?;
+        if edited == original && path == output {
+            return Ok(false);
+        }
+        fs::write(output, &edited)?;
+        Ok(edited != original)
+    }
+
+    /// Edits every .torrent file of a directory and its subdirectories in place.
+    /// Returns the files that changed.
+    pub fn apply_to_dir(&self, dir: &Path) -> Result<Vec<PathBuf>> {
+        let mut torrents = Vec::new();
+        find_torrents(dir, &mut torrents)?;
+        torrents.sort();
+
+        let mut changed = Vec::new();
+        for torrent in torrents {
+            let edited = self
+                .apply_to_file(&torrent)
+                .map_err(|err| Error::from_invalid(format!("{}: {}", torrent.display(), err)))?;
+            if edited {
+                changed.push(torrent);
+            }
+        }
+        Ok(changed)
+    }
+}
+
+impl<I> MetaInfo<I> {
+    /// Replaces the host of the `announce` and `announce-list` URLs pointing at `from`.
+    /// Returns whether any URL changed.
+    pub fn replace_tracker_host(&mut self, from: &str, to: &str) -> bool {
+        let mut changed = false;
+        let urls = std::iter::once(&mut self.announce)
+            .chain(self.announce_list.iter_mut().flatten().flatten());
+        for url in urls {
+            if let Some(replaced) = replace_url_host(url, from, to) {
+                *url = replaced;
+                changed = true;
+            }
+        }
+        changed
+    }
+}
+
+/// Replaces the host of `url` with `to` if it is `from`, keeping the scheme, port and path
+pub fn replace_url_host(url: &str, from: &str, to: &str) -> Option<String> {
+    let authority_start = url.find("://")? + 3;
+    let authority_end = url[authority_start..]
+        .find(['/', '?', '#'])
+        .map_or(url.len(), |end| authority_start + end);
+    let authority = &url[authority_start..authority_end];
+    let host_start = authority.rfind('@').map_or(0, |at| at + 1);
+    let host_end = match authority[host_start..].starts_with('[') {
+        true => authority.find(']').map(|end| end + 1)?,
+        false => authority[host_start..]
+            .find(':')
+            .map_or(authority.len(), |colon| host_start + colon),
+    };
+
+    authority[host_start..host_end]
+        .eq_ignore_ascii_case(from)
+        .then(|| {
+            let host_start = authority_start + host_start;
+            let host_end = authority_start + host_end;
+            format!("{}{}{}", &url[..host_start], to, &url[host_end..])
+        })
+}
+
+fn non_empty<T>(items: Vec<T>) -> Option<Vec<T>> {
+    (!items.is_empty()).then_some(items)
+}
+
+fn find_torrents(dir: &Path, torrents: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            find_torrents(&path, torrents)?;
+        } else if path
+            .extension()
+            .is_some_and(|extension| extension == "torrent")
+        {
+            torrents.push(path);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The info dictionary has an unknown key and unsorted keys, so it would not
+    // survive being decoded into an `Info` and encoded again
+    const TORRENT: &[u8] = b"d8:announce31:http://old.example.com/announce\
+13:announce-listll31:http://old.example.com/announceel20:udp://other.org:6969ee\
+4:infod4:name4:name6:lengthi3e12:piece lengthi16e6:pieces20:aaaaaaaaaaaaaaaaaaaa\
+7:x-extra5:valueee";
+
+    /// The info dictionary of `TORRENT`, its last entry
+    fn info_bytes(torrent: &[u8]) -> &[u8] {
+        let start = torrent.windows(6).position(|w| w == b"4:info").unwrap() + 6;
+        &torrent[start..torrent.len() - 1]
+    }
+
+    #[test]
+    fn test_edit_keeps_info_bytes() {
+        let mut meta_info = RawMetaInfo::from_bytes(TORRENT).unwrap();
+        let hashes = meta_info.info_hashes().unwrap();
+        assert_eq!(hashes.v1, Some(crate::hash::sha1(info_bytes(TORRENT))));
+
+        let edit = Edit {
+            announce_list: Some(Vec::new()),
+            comment: Some("fixed comment".to_string()),
+            created_by: Some("tforge".to_string()),
+            creation_date: Some(SystemTime::UNIX_EPOCH),
+            add_web_seeds: vec!["http://seed.example.com/".to_string()],
+            replace_tracker_host: Some((
+                "old.example.com".to_string(),
+                "new.example.com".to_string(),
+            )),
+            ..Default::default()
+        };
+        edit.apply(&mut meta_info);
+
+        let bytes = meta_info.to_bytes().unwrap();
+        let edited = RawMetaInfo::from_bytes(&bytes).unwrap();
+        assert_eq!(edited.info.as_bytes(), info_bytes(TORRENT));
+        assert_eq!(edited.info_hashes().unwrap(), hashes);
+        assert_eq!(edited.announce, "http://new.example.com/announce");
+        assert_eq!(edited.announce_list, None);
+        assert_eq!(edited.display_comment().as_deref(), Some("fixed comment"));
+        assert_eq!(edited.created_by.as_deref(), Some("tforge"));
+        assert_eq!(edited.web_seeds(), vec!["http://seed.example.com/"]);
+        assert_eq!(edited.decode_info().unwrap().name, "name");
+    }
+
+    #[test]
+    fn test_edit_keeps_unknown_keys() {
+        let torrent = b"d8:announce31:http://old.example.com/announce\
+4:infod6:lengthi3e4:name4:name12:piece lengthi16e6:pieces20:aaaaaaaaaaaaaaaaaaaae\
+9:publisher6:tforge14:x-client-stated5:statei2eee";
+        let mut meta_info = RawMetaInfo::from_bytes(torrent).unwrap();
+        assert_eq!(
+            meta_info.extra.keys().collect::<Vec<_>>(),
+            vec!["publisher", "x-client-state"]
+        );
+        assert_eq!(meta_info.to_bytes().unwrap(), torrent);
+
+        let edit = Edit {
+            comment: Some("a comment".to_string()),
+            ..Default::default()
+        };
+        edit.apply(&mut meta_info);
+        assert_eq!(
+            meta_info.to_bytes().unwrap(),
+            b"d8:announce31:http://old.example.com/announce7:comment9:a comment\
+4:infod6:lengthi3e4:name4:name12:piece lengthi16e6:pieces20:aaaaaaaaaaaaaaaaaaaae\
+9:publisher6:tforge14:x-client-stated5:statei2eee"
+        );
+    }
+
+    #[test]
+    fn test_replace_url_host() {
+        let replace = |url| replace_url_host(url, "old.org", "new.net");
+        assert_eq!(
+            replace("http://old.org/announce").as_deref(),
+            Some("http://new.net/announce")
+        );
+        assert_eq!(
+            replace("udp://OLD.org:6969").as_deref(),
+            Some("udp://new.net:6969")
+        );
+        assert_eq!(
+            replace("https://user@old.org:443/a?b=c").as_deref(),
+            Some("https://user@new.net:443/a?b=c")
+        );
+        assert_eq!(replace("http://notold.org/announce"), None);
+        assert_eq!(replace("http://example.com/old.org"), None);
+        assert_eq!(replace("old.org"), None);
+        assert_eq!(
+            replace_url_host("http://[::1]:80/announce", "[::1]", "tracker").as_deref(),
+            Some("http://tracker:80/announce")
+        );
+    }
+
+    #[test]
+    fn test_apply_to_dir() {
+        let dir = std::env::temp_dir().join(format!("tforge-edit-{}", std::process::id()));
+        let sub = dir.join("sub");
+        fs::create_dir_all(&sub).unwrap();
+        fs::write(dir.join("a.torrent"), TORRENT).unwrap();
+        fs::write(sub.join("b.torrent"), TORRENT).unwrap();
+        let other = String::from_utf8(TORRENT.to_vec())
+            .unwrap()
+            .replace("old.example.com", "old.example.net");
+        fs::write(sub.join("c.torrent"), other.as_bytes()).unwrap();
+        fs::write(dir.join("notes.txt"), b"not a torrent").unwrap();
+
+        let edit = Edit {
+            replace_tracker_host: Some((
+                "old.example.com".to_string(),
+                "new.example.com".to_string(),
+            )),
+            ..Default::default()
+        };
+        let changed = edit.apply_to_dir(&dir).unwrap();
+        assert_eq!(changed, vec![dir.join("a.torrent"), sub.join("b.torrent")]);
+        assert_eq!(fs::read(sub.join("c.torrent")).unwrap(), other.as_bytes());
+
+        let edited = RawMetaInfo::from_bytes(&fs::read(dir.join("a.torrent")).unwrap()).unwrap();
+        assert_eq!(
+            edited.trackers(),
+            vec!["http://new.example.com/announce", "udp://other.org:6969"]
+        );
+        assert!(edit.apply_to_dir(&dir).unwrap().is_empty());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
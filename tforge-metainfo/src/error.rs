@@ -0,0 +1,58 @@
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug)]
+pub enum Error {
+    IO(std::io::Error),
+    Bencode(tforge_bencode::error::Error),
+    Invalid(String),
+    Magnet(String),
+    Resume(String),
+}
+
+impl Error {
+    pub fn from_io(err: std::io::Error) -> Self {
+        Error::IO(err)
+    }
+
+    pub fn from_bencode(err: tforge_bencode::error::Error) -> Self {
+        Error::Bencode(err)
+    }
+
+    pub fn from_invalid(err: impl Into<String>) -> Self {
+        Error::Invalid(err.into())
+    }
+
+    pub fn from_magnet(err: impl Into<String>) -> Self {
+        Error::Magnet(err.into())
+    }
+
+    pub fn from_resume(err: impl Into<String>) -> Self {
+        Error::Resume(err.into())
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Error::IO(err) => write!(f, "IO error: {}", err),
+            Error::Bencode(err) => write!(f, "Bencode error: {}", err),
+            Error::Invalid(err) => write!(f, "Invalid metainfo: {}", err),
+            Error::Magnet(err) => write!(f, "Invalid magnet link: {}", err),
+            Error::Resume(err) => write!(f, "Invalid resume data: {}", err),
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        Error::from_io(err)
+    }
+}
+
+impl From<tforge_bencode::error::Error> for Error {
+    fn from(err: tforge_bencode::error::Error) -> Self {
+        Error::from_bencode(err)
+    }
+}
@@ -0,0 +1,396 @@
+use crate::{
+    error::{Error, Result},
+    hash::InfoHashes,
+    resume::{ResumeState, DEFAULT_PRIORITY},
+    Info,
+};
+use serde::Deserialize;
+use serde_with::{serde_as, Bytes};
+use std::{
+    collections::BTreeMap,
+    io::{BufReader, Cursor},
+    time::{Duration, UNIX_EPOCH},
+};
+use tforge_bencode::{deserializer::from_reader, raw::RawValue};
+
+/// Keys carried over into the [`ResumeState`]
+const CARRIED: &[&str] = &[
+    "added_time",
+    "file_priority",
+    "info-hash",
+    "info-hash2",
+    "pieces",
+    "save_path",
+    "seed_mode",
+    "total_downloaded",
+    "total_uploaded",
+    "trackers",
+];
+
+/// Keys describing the file itself or duplicating the torrent, nothing is lost without them
+const IGNORED: &[&str] = &[
+    "file-format",
+    "file-version",
+    "info",
+    "libtorrent-version",
+    "name",
+];
+
+/// The part of a libtorrent `.fastresume` file tforge understands.
+/// qBittorrent writes the same format, with extra `qBt-` keys.
+#[serde_as]
+#[derive(Deserialize, Debug)]
+struct FastResume {
+    added_time: Option<i64>,
+    file_priority: Option<Vec<i64>>,
+    info: Option<RawValue>,
+
+    #[serde_as(as = "Option<Bytes>")]
+    #[serde(rename = "info-hash", default)]
+    info_hash: Option<Vec<u8>>,
+
+    #[serde_as(as = "Option<Bytes>")]
+    #[serde(rename = "info-hash2", default)]
+    info_hash2: Option<Vec<u8>>,
+
+    /// One byte per piece, whose lowest bit is set when the piece is complete
+    #[serde_as(as = "Option<Bytes>")]
+    #[serde(default)]
+    pieces: Option<Vec<u8>>,
+
+    save_path: Option<String>,
+    seed_mode: Option<i64>,
+    total_downloaded: Option<i64>,
+    total_uploaded: Option<i64>,
+    trackers: Option<Vec<Vec<String>>>,
+}
+
+/// The result of importing a `.fastresume` file
+#[derive(Debug)]
+pub struct FastResumeImport {
+    pub state: ResumeState,
+
+    /// The keys of the file carried over into the state
+    pub carried: Vec<String>,
+
+    /// What could not be carried over, and why
+    pub dropped: Vec<String>,
+}
+
+/// Converts a libtorrent or qBittorrent `.fastresume` file into a [`ResumeState`].
+///
+/// The info dictionary of the torrent is needed to know its piece count and
+/// check the info-hash. It is taken from `torrent`, the bytes of the info
+/// dictionary of the .torrent file, or failing that from the `info` key
+/// libtorrent 2 embeds in resume files. The info-hashes are those of these
+/// bytes, keys [`Info`] doesn't know of included.
+pub fn import_fastresume(bytes: &[u8], torrent: Option<&RawValue>) -> Result<FastResumeImport> {
+    let mut reader = BufReader::new(Cursor::new(bytes));
+    let keys: BTreeMap<String, RawValue> = from_reader(&mut reader)?;
+    let mut reader = BufReader::new(Cursor::new(bytes));
+    let resume: FastResume = from_reader(&mut reader)?;
+
+    let raw_info = torrent.or(resume.info.as_ref());
+    let torrent = raw_info.map(RawValue::decode::<Info>).transpose()?;
+
+    let info_hashes = match (&torrent, raw_info) {
+        (Some(info), Some(raw_info)) => {
+            let info_hashes = InfoHashes::from_info_bytes(raw_info.as_bytes(), info.version()?);
+            check_hash("info-hash", &resume.info_hash, info_hashes.v1.as_ref())?;
+            check_hash("info-hash2", &resume.info_hash2, info_hashes.v2.as_ref())?;
+            info_hashes
+        }
+        _ => InfoHashes {
+            v1: hash_field("info-hash", resume.info_hash)?,
+            v2: hash_field("info-hash2", resume.info_hash2)?,
+        },
+    };
+    if info_hashes.v1.is_none() && info_hashes.v2.is_none() {
+        return Err(Error::from_resume("no info-hash and no torrent"));
+    }
+
+    let piece_count = match (&torrent, &resume.pieces) {
+        (Some(info), _) => info.piece_count(),
+        (None, Some(pieces)) => pieces.len(),
+        (None, None) => 0,
+    };
+    let save_path = resume
+        .save_path
+        .ok_or_else(|| Error::from_resume("no save_path"))?;
+
+    let mut state = ResumeState::new(info_hashes, piece_count, save_path);
+    let mut dropped = Vec::new();
+
+    match (&resume.pieces, resume.seed_mode.unwrap_or(0) != 0) {
+        (Some(pieces), _) => {
+            if pieces.len() != piece_count {
+                return Err(Error::from_resume(format!(
+                    "pieces has {} entries, the torrent has {} pieces",
+                    pieces.len(),
+                    piece_count
+                )));
+            }
+            for (index, piece) in pieces.iter().enumerate() {
+                state.set_piece(index, piece & 1 != 0);
+            }
+        }
+        (None, true) if torrent.is_some() => {
+            (0..piece_count).for_each(|index| state.set_piece(index, true));
+        }
+        (None, true) => dropped.push(
+            "seed_mode: the torrent is needed to know its pieces, they will be checked".to_string(),
+        ),
+        (None, false) => {}
+    }
+
+    state.file_priorities = resume
+        .file_priority
+        .unwrap_or_default()
+        .into_iter()
+        .map(|priority| priority.clamp(0, 7) as u8)
+        .collect();
+    if let Some(info) = &torrent {
+        let file_count = info.files().len();
+        if state.file_priorities.len() > file_count {
+            return Err(Error::from_resume(format!(
+                "file_priority has {} entries, the torrent has {} files",
+                state.file_priorities.len(),
+                file_count
+            )));
+        }
+        state.file_priorities.resize(file_count, DEFAULT_PRIORITY);
+    }
+
+    state.trackers = resume.trackers.unwrap_or_default();
+    state.added_time = resume
+        .added_time
+        .filter(|&secs| secs > 0)
+        .map(|secs| UNIX_EPOCH + Duration::from_secs(secs as u64));
+    state.uploaded = resume.total_uploaded.unwrap_or(0).max(0) as u64;
+    state.downloaded = resume.total_downloaded.unwrap_or(0).max(0) as u64;
+
+    let mut carried = Vec::new();
+    for key in keys.keys() {
+        if CARRIED.contains(&key.as_str()) {
+            carried.push(key.clone());
+        } else if !IGNORED.contains(&key.as_str()) {
+            dropped.push(match drop_reason(key) {
+                Some(reason) => format!("{}: {}", key, reason),
+                None => key.clone(),
+            });
+        }
+    }
+
+    Ok(FastResumeImport {
+        state,
+        carried,
+        dropped,
+    })
+}
+
+/// Why a key of a resume file is not carried over, for the keys it matters most for
+fn drop_reason(key: &str) -> Option<&'static str> {
+    match key {
+        "unfinished" => Some("partially downloaded pieces will be downloaded again"),
+        "mapped_files" => Some(
+            "renamed files are not supported, they will be looked up under their original name",
+        ),
+        "piece_priority" => Some("only file priorities are kept"),
+        "peers" | "peers6" | "banned_peers" | "banned_peers6" => {
+            Some("peers are found again from the trackers")
+        }
+        "url-list" | "httpseeds" => Some("web seeds are read from the torrent"),
+        "paused" | "auto_managed" | "qBt-paused" | "qBt-queuePosition" => {
+            Some("the torrent starts in the default state")
+        }
+        "qBt-category" | "qBt-tags" => Some("categories and tags are not supported"),
+        "qBt-ratioLimit"
+        | "qBt-seedingTimeLimit"
+        | "max_uploads"
+        | "max_connections"
+        | "upload_rate_limit"
+        | "download_rate_limit" => Some("per torrent limits are not supported"),
+        _ => None,
+    }
+}
+
+fn check_hash<const N: usize>(
+    key: &str,
+    found: &Option<Vec<u8>>,
+    expected: Option<&[u8; N]>,
+) -> Result<()> {
+    match (found, expected) {
+        (Some(found), Some(expected)) if found.as_slice() != expected.as_slice() => Err(
+            Error::from_resume(format!("{} does not match the torrent", key)),
+        ),
+        _ => Ok(()),
+    }
+}
+
+fn hash_field<const N: usize>(key: &str, hash: Option<Vec<u8>>) -> Result<Option<[u8; N]>> {
+    hash.map(|hash| {
+        hash.try_into()
+            .map_err(|_| Error::from_resume(format!("{} is not {} bytes long", key, N)))
+    })
+    .transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        create::{create_info, CreateOptions},
+        hash::sha1,
+        merkle::BLOCK_SIZE,
+        tests::create_test_dir,
+    };
+
+    fn value<T: serde::Serialize>(value: T) -> RawValue {
+        RawValue::from_value(&value).unwrap()
+    }
+
+    fn bytes(bytes: &[u8]) -> RawValue {
+        let mut encoded = format!("{}:", bytes.len()).into_bytes();
+        encoded.extend_from_slice(bytes);
+        RawValue::from_bytes(encoded).unwrap()
+    }
+
+    fn fastresume(entries: Vec<(&str, RawValue)>) -> Vec<u8> {
+        let entries: BTreeMap<String, RawValue> = entries
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), value))
+            .collect();
+        value(entries).into_bytes()
+    }
+
+    /// The info dictionary of a v1 torrent of 3 pieces
+    fn test_info(name: &str) -> RawValue {
+        let dir = create_test_dir(
+            name,
+            &[("a.bin", BLOCK_SIZE * 2), ("b.bin", BLOCK_SIZE / 2)],
+        );
+        let options = CreateOptions {
+            piece_length: BLOCK_SIZE,
+            ..Default::default()
+        };
+        value(create_info(&dir, &options).unwrap().0)
+    }
+
+    /// The info dictionary with a key `Info` doesn't know of appended
+    fn with_unknown_key(info: &RawValue) -> RawValue {
+        let mut bytes = info.as_bytes().to_vec();
+        bytes.pop();
+        bytes.extend_from_slice(b"7:x-extra5:valuee");
+        RawValue::from_bytes(bytes).unwrap()
+    }
+
+    #[test]
+    fn test_import_qbittorrent() {
+        let info = test_info("fastresume");
+        let info_hash = sha1(info.as_bytes());
+        let bytes = fastresume(vec![
+            ("added_time", value(1_700_000_000)),
+            ("file-format", value("libtorrent resume file")),
+            ("file_priority", value(vec![0, 9])),
+            ("info-hash", bytes(&info_hash)),
+            ("libtorrent-version", value("2.0.9.0")),
+            ("pieces", bytes(&[1, 0, 3])),
+            ("qBt-category", value("linux")),
+            ("save_path", value("/downloads")),
+            ("total_downloaded", value(1000)),
+            ("total_uploaded", value(500)),
+            (
+                "trackers",
+                value(vec![vec!["udp://tracker.example.com:6969"]]),
+            ),
+            ("unfinished", value(Vec::<i64>::new())),
+        ]);
+
+        let import = import_fastresume(&bytes, Some(&info)).unwrap();
+        let state = &import.state;
+        assert_eq!(state.info_hash, Some(info_hash));
+        assert_eq!(state.save_path, "/downloads");
+        assert_eq!(state.piece_count, 3);
+        assert_eq!(state.have, vec![0xa0]);
+        assert_eq!(state.file_priorities, vec![0, 7]);
+        assert_eq!(state.trackers, vec![vec!["udp://tracker.example.com:6969"]]);
+        assert_eq!(
+            state.added_time,
+            Some(UNIX_EPOCH + Duration::from_secs(1_700_000_000))
+        );
+        assert_eq!((state.downloaded, state.uploaded), (1000, 500));
+
+        assert_eq!(
+            import.carried,
+            vec![
+                "added_time",
+                "file_priority",
+                "info-hash",
+                "pieces",
+                "save_path",
+                "total_downloaded",
+                "total_uploaded",
+                "trackers"
+            ]
+        );
+        assert_eq!(import.dropped.len(), 2);
+        assert!(import.dropped[0].starts_with("qBt-category: "));
+        assert!(import.dropped[1].starts_with("unfinished: "));
+    }
+
+    #[test]
+    fn test_import_embedded_info_in_seed_mode() {
+        let info = test_info("fastresume-seed");
+        let bytes = fastresume(vec![
+            ("info", info.clone()),
+            ("save_path", value("/downloads")),
+            ("seed_mode", value(1)),
+        ]);
+
+        let import = import_fastresume(&bytes, None).unwrap();
+        assert_eq!(import.state.info_hash, Some(sha1(info.as_bytes())));
+        assert!(import.state.is_complete());
+        assert_eq!(import.state.file_priorities, vec![DEFAULT_PRIORITY; 2]);
+        assert!(import.dropped.is_empty());
+    }
+
+    #[test]
+    fn test_import_info_with_unknown_keys() {
+        let info = with_unknown_key(&test_info("fastresume-unknown"));
+        let info_hash = sha1(info.as_bytes());
+        let embedded = fastresume(vec![
+            ("info", info.clone()),
+            ("info-hash", bytes(&info_hash)),
+            ("save_path", value("/downloads")),
+        ]);
+        let import = import_fastresume(&embedded, None).unwrap();
+        assert_eq!(import.state.info_hash, Some(info_hash));
+        assert_eq!(import.state.piece_count, 3);
+
+        let separate = fastresume(vec![
+            ("info-hash", bytes(&info_hash)),
+            ("save_path", value("/downloads")),
+        ]);
+        let import = import_fastresume(&separate, Some(&info)).unwrap();
+        assert_eq!(import.state.info_hash, Some(info_hash));
+    }
+
+    #[test]
+    fn test_import_checks_the_torrent() {
+        let info = test_info("fastresume-mismatch");
+        let wrong_hash = fastresume(vec![
+            ("info-hash", bytes(&[0; 20])),
+            ("save_path", value("/downloads")),
+        ]);
+        assert!(import_fastresume(&wrong_hash, Some(&info)).is_err());
+
+        let wrong_pieces = fastresume(vec![
+            ("pieces", bytes(&[1, 1])),
+            ("save_path", value("/downloads")),
+        ]);
+        assert!(import_fastresume(&wrong_pieces, Some(&info)).is_err());
+
+        let no_hash = fastresume(vec![("save_path", value("/downloads"))]);
+        assert!(import_fastresume(&no_hash, None).is_err());
+    }
+}
@@ -0,0 +1,231 @@
+use crate::{merkle::Sha256Hash, FileAttributes};
+use serde::{
+    de::{MapAccess, Visitor},
+    ser::SerializeMap,
+    Deserialize,
+    Deserializer,
+    Serialize,
+    Serializer,
+};
+use serde_with::{serde_as, Bytes};
+use std::collections::BTreeMap;
+
+/// The BEP 52 `file tree` of a v2 torrent.
+/// Maps each file or directory name to its node, sorted by name.
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct FileTree(pub BTreeMap<String, FileTreeNode>);
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum FileTreeNode {
+    File(FileTreeEntry),
+    Directory(FileTree),
+}
+
+#[serde_as]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct FileTreeEntry {
+    /// NOTE: All fields must be sorted alphabetically!
+
+    /// File attributes (BEP 47), see [`FileAttributes`]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub attr: Option<String>,
+
+    /// Length of the file in bytes
+    pub length: usize,
+
+    /// Root of the merkle tree of the file. Absent for empty files.
+    #[serde(rename = "pieces root")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[serde_as(as = "Option<Bytes>")]
+    pub pieces_root: Option<Sha256Hash>,
+
+    /// Target of a symlink file, relative to the torrent root (BEP 47)
+    #[serde(rename = "symlink path")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub symlink_path: Option<Vec<String>>,
+}
+
+impl FileTreeEntry {
+    pub fn attributes(&self) -> FileAttributes {
+        FileAttributes::parse(self.attr.as_deref().unwrap_or_default())
+    }
+}
+
+impl FileTree {
+    /// All files of the tree, depth first, in the order they appear in the torrent
+    pub fn files(&self) -> Vec<(Vec<String>, &FileTreeEntry)> {
+        let mut files = Vec::new();
+        self.collect_files(&mut Vec::new(), &mut files);
+        files
+    }
+
+    fn collect_files<'a>(
+        &'a self,
+        prefix: &mut Vec<String>,
+        files: &mut Vec<(Vec<String>, &'a FileTreeEntry)>,
+    ) {
+        for (name, node) in &self.0 {
+            prefix.push(name.clone());
+            match node {
+                FileTreeNode::File(entry) => files.push((prefix.clone(), entry)),
+                FileTreeNode::Directory(tree) => tree.collect_files(prefix, files),
+            }
+            prefix.pop();
+        }
+    }
+
+    /// Inserts a file at `path`, creating the intermediate directories
+    pub fn insert(&mut self, path: &[String], entry: FileTreeEntry) {
+        match path {
+            [] => {}
+            [name] => {
+                self.0.insert(name.clone(), FileTreeNode::File(entry));
+            }
+            [name, rest @ ..] => {
+                let node = self
+                    .0
+                    .entry(name.clone())
+                    .or_insert_with(|| FileTreeNode::Directory(FileTree::default()));
+                if let FileTreeNode::File(_) = node {
+                    *node = FileTreeNode::Directory(FileTree::default());
+                }
+                if let FileTreeNode::Directory(tree) = node {
+                    tree.insert(rest, entry);
+                }
+            }
+        }
+    }
+}
+
+impl Serialize for FileTree {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut map = serializer.serialize_map(Some(self.0.len()))?;
+        for (name, node) in &self.0 {
+            map.serialize_entry(name, node)?;
+        }
+        map.end()
+    }
+}
+
+impl Serialize for FileTreeNode {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match self {
+            FileTreeNode::File(entry) => {
+                let mut map = serializer.serialize_map(Some(1))?;
+                map.serialize_entry("", entry)?;
+                map.end()
+            }
+            FileTreeNode::Directory(tree) => tree.serialize(serializer),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for FileTree {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        match FileTreeNode::deserialize(deserializer)? {
+            FileTreeNode::Directory(tree) => Ok(tree),
+            FileTreeNode::File(_) => Err(serde::de::Error::custom(
+                "file tree root must be a directory",
+            )),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for FileTreeNode {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_map(FileTreeNodeVisitor)
+    }
+}
+
+struct FileTreeNodeVisitor;
+
+impl<'de> Visitor<'de> for FileTreeNodeVisitor {
+    type Value = FileTreeNode;
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(formatter, "a file tree dictionary")
+    }
+
+    fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        let mut children = BTreeMap::new();
+        let mut entry = None;
+        while let Some(name) = map.next_key::<String>()? {
+            if name.is_empty() {
+                entry = Some(map.next_value::<FileTreeEntry>()?);
+            } else {
+                children.insert(name, map.next_value::<FileTreeNode>()?);
+            }
+        }
+
+        match entry {
+            Some(_) if !children.is_empty() => Err(serde::de::Error::custom(
+                "file tree node is both a file and a directory",
+            )),
+            Some(entry) => Ok(FileTreeNode::File(entry)),
+            None => Ok(FileTreeNode::Directory(FileTree(children))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufReader, Cursor};
+    use tforge_bencode::{deserializer::from_reader, serializer::from_writer};
+
+    #[test]
+    fn test_bencode_file_tree() {
+        let mut tree = FileTree::default();
+        tree.insert(
+            &["dir".to_string(), "a.txt".to_string()],
+            FileTreeEntry {
+                attr: None,
+                length: 5,
+                pieces_root: Some([1; 32]),
+                symlink_path: None,
+            },
+        );
+        tree.insert(
+            &["empty".to_string()],
+            FileTreeEntry {
+                attr: None,
+                length: 0,
+                pieces_root: None,
+                symlink_path: None,
+            },
+        );
+
+        let mut buffer = Vec::new();
+        let mut writer = from_writer(&mut buffer);
+        tree.serialize(&mut writer).unwrap();
+
+        let mut expected = b"d3:dird5:a.txtd0:d6:lengthi5e11:pieces root32:".to_vec();
+        expected.extend_from_slice(&[1; 32]);
+        expected.extend_from_slice(b"eee5:emptyd0:d6:lengthi0eeee");
+        assert_eq!(buffer, expected);
+
+        let mut reader = BufReader::new(Cursor::new(&buffer));
+        let decoded: FileTree = from_reader(&mut reader).unwrap();
+        assert_eq!(decoded, tree);
+
+        let files = decoded.files();
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0].0, vec!["dir".to_string(), "a.txt".to_string()]);
+        assert_eq!(files[1].0, vec!["empty".to_string()]);
+    }
+}
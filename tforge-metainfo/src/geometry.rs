@@ -0,0 +1,278 @@
+use crate::{
+    error::{Error, Result},
+    file_tree::FileTreeNode,
+    text::RawString,
+    FileAttributes,
+    FileInfo,
+    Info,
+};
+use std::{
+    fs,
+    io::{Read, Seek, SeekFrom},
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+/// A file of the torrent, placed in the continuous stream of data the pieces are cut from
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileEntry {
+    /// Path of the file, relative to the torrent root.
+    /// Single file torrents have a single component, the name of the torrent.
+    pub path: Vec<RawString>,
+
+    /// The UTF-8 version of the path, when the torrent has one
+    pub path_utf8: Option<Vec<String>>,
+
+    /// Length of the file in bytes
+    pub length: usize,
+
+    /// Offset of the first byte of the file in the torrent data
+    pub offset: usize,
+
+    /// BEP 47 attributes of the file
+    pub attributes: FileAttributes,
+
+    /// Target of a symlink, relative to the torrent root
+    pub symlink_path: Option<Vec<String>>,
+}
+
+impl FileEntry {
+    pub fn range(&self) -> Range<usize> {
+        self.offset..self.offset + self.length
+    }
+
+    /// Padding files are zero filled and never written to disk
+    pub fn is_padding(&self) -> bool {
+        self.attributes.padding
+    }
+
+    /// The path of the file, preferring its UTF-8 version and decoding it with `encoding` otherwise
+    pub fn display_path(&self, encoding: Option<&str>) -> Vec<String> {
+        crate::display_path(&self.path, self.path_utf8.as_ref(), encoding)
+    }
+}
+
+/// The part of a file overlapping a range of the torrent data
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileSlice {
+    /// Index of the file in [`Info::files`]
+    pub file_index: usize,
+
+    /// Offset of the slice within the file
+    pub offset: usize,
+
+    /// Length of the slice in bytes
+    pub length: usize,
+}
+
+impl Info {
+    /// Whether the torrent describes a single file rather than a directory
+    pub fn is_single_file(&self) -> bool {
+        match (&self.file_info, &self.file_tree) {
+            (Some(file_info), _) => matches!(file_info, FileInfo::SingleFile { .. }),
+            (None, Some(tree)) => {
+                tree.0.len() == 1
+                    && matches!(
+                        self.name.to_str().and_then(|name| tree.0.get(name)),
+                        Some(FileTreeNode::File(_))
+                    )
+            }
+            (None, None) => false,
+        }
+    }
+
+    /// The files of the torrent with their offsets in the torrent data.
+    /// v1 and hybrid torrents follow the v1 file list, padding files included.
+    /// v2 torrents align every file to a piece boundary.
+    pub fn files(&self) -> Vec<FileEntry> {
+        let mut offset = 0;
+        if self.file_info.is_some() {
+            return self
+                .v1_files()
+                .into_iter()
+                .map(|file| {
+                    let entry = FileEntry {
+                        path: file.path,
+                        path_utf8: file.path_utf8,
+                        length: file.length,
+                        offset,
+                        attributes: file.attributes,
+                        symlink_path: file.symlink_path,
+                    };
+                    offset += file.length;
+                    entry
+                })
+                .collect();
+        }
+
+        self.v2_files()
+            .into_iter()
+            .map(|(path, file)| {
+                let entry = FileEntry {
+                    path: path.into_iter().map(RawString::from).collect(),
+                    path_utf8: None,
+                    length: file.length,
+                    offset,
+                    attributes: file.attributes(),
+                    symlink_path: file.symlink_path.clone(),
+                };
+                offset += file.length.next_multiple_of(self.piece_length.max(1));
+                entry
+            })
+            .collect()
+    }
+
+    /// Total length of the torrent data in bytes
+    pub fn total_length(&self) -> usize {
+        self.files()
+            .last()
+            .map(|file| file.offset + file.length)
+            .unwrap_or(0)
+    }
+
+    pub fn piece_count(&self) -> usize {
+        self.total_length().div_ceil(self.piece_length.max(1))
+    }
+
+    /// The range of the torrent data covered by a piece. The last piece may be shorter.
+    pub fn piece_range(&self, index: usize) -> Option<Range<usize>> {
+        let total_length = self.total_length();
+        let start = index.checked_mul(self.piece_length)?;
+        if start >= total_length {
+            return None;
+        }
+        Some(start..(start + self.piece_length).min(total_length))
+    }
+
+    /// Splits a range of the torrent data into the file slices it covers
+    pub fn slices(&self, range: Range<usize>) -> Vec<FileSlice> {
+        self.files()
+            .iter()
+            .enumerate()
+            .filter_map(|(file_index, file)| {
+                let start = range.start.max(file.offset);
+                let end = range.end.min(file.offset + file.length);
+                (start < end).then(|| FileSlice {
+                    file_index,
+                    offset: start - file.offset,
+                    length: end - start,
+                })
+            })
+            .collect()
+    }
+
+    /// The file slices covered by a piece
+    pub fn piece_slices(&self, index: usize) -> Vec<FileSlice> {
+        self.piece_range(index)
+            .map(|range| self.slices(range))
+            .unwrap_or_default()
+    }
+
+    /// Where a file is stored when the torrent is saved in the `base` directory.
+    /// Names are decoded with the `encoding` of the torrent, see [`Info::display_name`].
+    pub fn file_path(&self, base: &Path, file: &FileEntry, encoding: Option<&str>) -> PathBuf {
+        let mut path = base.to_path_buf();
+        if !self.is_single_file() {
+            path.push(self.display_name(encoding));
+        }
+        path.extend(file.display_path(encoding));
+        path
+    }
+
+    /// Reads a piece from the torrent data saved in the `base` directory.
+    /// Padding files are not read from disk, their bytes are zeros.
+    pub fn read_piece(&self, base: &Path, index: usize, encoding: Option<&str>) -> Result<Vec<u8>> {
+        let range = self
+            .piece_range(index)
+            .ok_or_else(|| Error::from_invalid(format!("piece {} is out of range", index)))?;
+        let files = self.files();
+        let mut piece = vec![0; range.len()];
+        let mut position = 0;
+        for slice in self.slices(range) {
+            let file = &files[slice.file_index];
+            let buffer = &mut piece[position..position + slice.length];
+            if file.attributes.is_on_disk() {
+                let mut reader = fs::File::open(self.file_path(base, file, encoding))?;
+                reader.seek(SeekFrom::Start(slice.offset as u64))?;
+                reader.read_exact(buffer)?;
+            }
+            position += slice.length;
+        }
+        Ok(piece)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{File, FileInfo, Info};
+
+    fn multi_file_info() -> Info {
+        Info {
+            file_tree: None,
+            file_info: Some(FileInfo::MultiFile {
+                files: vec![
+                    File {
+                        attr: None,
+                        length: 10,
+                        path: vec!["a".into()],
+                        path_utf8: None,
+                        sha1: None,
+                        symlink_path: None,
+                    },
+                    File {
+                        attr: None,
+                        length: 25,
+                        path: vec!["dir".into(), "b".into()],
+                        path_utf8: None,
+                        sha1: None,
+                        symlink_path: None,
+                    },
+                ],
+            }),
+            meta_version: None,
+            name: "name".into(),
+            name_utf8: None,
+            piece_length: 16,
+            pieces: vec![[0; 20]; 3],
+            private: None,
+            source: None,
+        }
+    }
+
+    #[test]
+    fn test_files_offsets() {
+        let info = multi_file_info();
+        let files = info.files();
+        assert_eq!(files[0].range(), 0..10);
+        assert_eq!(files[1].range(), 10..35);
+        assert_eq!(info.total_length(), 35);
+        assert_eq!(info.piece_count(), 3);
+        assert!(!info.is_single_file());
+    }
+
+    #[test]
+    fn test_piece_slices() {
+        let info = multi_file_info();
+        assert_eq!(info.piece_range(0), Some(0..16));
+        assert_eq!(info.piece_range(2), Some(32..35));
+        assert_eq!(info.piece_range(3), None);
+
+        let slices = info.piece_slices(0);
+        assert_eq!(slices.len(), 2);
+        assert_eq!(
+            (slices[0].file_index, slices[0].offset, slices[0].length),
+            (0, 0, 10)
+        );
+        assert_eq!(
+            (slices[1].file_index, slices[1].offset, slices[1].length),
+            (1, 0, 6)
+        );
+
+        let slices = info.piece_slices(2);
+        assert_eq!(slices.len(), 1);
+        assert_eq!(
+            (slices[0].file_index, slices[0].offset, slices[0].length),
+            (1, 22, 3)
+        );
+    }
+}
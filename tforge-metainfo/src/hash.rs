@@ -0,0 +1,76 @@
+use crate::{merkle::Sha256Hash, Version};
+use sha1::{Digest, Sha1};
+use sha2::Sha256;
+
+pub type Sha1Hash = [u8; 20];
+
+pub fn sha1(data: &[u8]) -> Sha1Hash {
+    Sha1::digest(data).into()
+}
+
+pub fn sha256(data: &[u8]) -> Sha256Hash {
+    Sha256::digest(data).into()
+}
+
+/// The info-hashes identifying a torrent.
+/// v1 torrents only have `v1`, v2 torrents only `v2`, hybrid torrents both.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct InfoHashes {
+    /// SHA-1 of the bencoded info dictionary
+    pub v1: Option<Sha1Hash>,
+
+    /// SHA-256 of the bencoded info dictionary
+    pub v2: Option<Sha256Hash>,
+}
+
+impl InfoHashes {
+    /// Hashes a bencoded info dictionary of the given version
+    pub fn from_info_bytes(info: &[u8], version: Version) -> Self {
+        InfoHashes {
+            v1: version.has_v1().then(|| sha1(info)),
+            v2: version.has_v2().then(|| sha256(info)),
+        }
+    }
+
+    /// The v2 info-hash truncated to 20 bytes, as used in the tracker and peer protocols
+    pub fn truncated_v2(&self) -> Option<Sha1Hash> {
+        self.v2.map(|hash| {
+            hash[..20]
+                .try_into()
+                .expect("a SHA-256 hash is longer than 20 bytes")
+        })
+    }
+
+    /// The 20 byte hash to use on the wire: the v1 info-hash if any, the truncated v2 one otherwise
+    pub fn wire(&self) -> Option<Sha1Hash> {
+        self.v1.or_else(|| self.truncated_v2())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_truncated_v2() {
+        let mut v2 = [0; 32];
+        v2.iter_mut().enumerate().for_each(|(i, b)| *b = i as u8);
+        let hashes = InfoHashes {
+            v1: None,
+            v2: Some(v2),
+        };
+
+        let truncated = hashes.truncated_v2().unwrap();
+        assert_eq!(&truncated[..], &v2[..20]);
+        assert_eq!(hashes.wire(), Some(truncated));
+    }
+
+    #[test]
+    fn test_wire_prefers_v1() {
+        let hashes = InfoHashes {
+            v1: Some([1; 20]),
+            v2: Some([2; 32]),
+        };
+        assert_eq!(hashes.wire(), Some([1; 20]));
+    }
+}
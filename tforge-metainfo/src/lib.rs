@@ -1,7 +1,39 @@
+use crate::{
+    error::{Error, Result},
+    file_tree::FileTree,
+    hash::Sha1Hash,
+    merkle::BLOCK_SIZE,
+    piece_layers::PieceLayers,
+    text::RawString,
+    web_seed::UrlList,
+};
 use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, BoolFromInt, Bytes};
+use std::collections::BTreeMap;
+use tforge_bencode::raw::RawValue;
 
+pub mod create;
+pub mod edit;
+pub mod error;
+pub mod fastresume;
+pub mod file_tree;
+pub mod geometry;
+pub mod hash;
+pub mod magnet;
+pub mod merkle;
+pub mod piece_layers;
+pub mod repiece;
+pub mod resume;
+pub mod text;
+pub mod web_seed;
+
+/// The content of a .torrent file.
+///
+/// The info dictionary is decoded into an [`Info`] by default. Tools editing
+/// published torrents or needing their info-hashes use [`edit::RawMetaInfo`]
+/// instead, which keeps the info dictionary as the exact bytes it was read from.
 #[derive(Serialize, Deserialize, Debug, PartialEq)]
-pub struct MetaInfo {
+pub struct MetaInfo<I = Info> {
     /// NOTE: All fields must be sorted alphabetically!
 
     /// The announce URL of the tracker
@@ -15,7 +47,7 @@ pub struct MetaInfo {
 
     /// Free-form textual comments of the author
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub comment: Option<String>,
+    pub comment: Option<RawString>,
 
     /// Name and version of the program used to create the .torrent
     #[serde(rename = "created by")]
@@ -24,31 +56,352 @@ pub struct MetaInfo {
 
     /// The creation time of the torrent
     #[serde(rename = "creation date", with = "optional_system_time")]
-    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub creation_date: Option<std::time::SystemTime>,
 
     /// The string encoding format used to generate the pieces part of the info dictionary
     #[serde(skip_serializing_if = "Option::is_none")]
     pub encoding: Option<String>,
 
+    /// URLs of BEP 17 HTTP seeds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub httpseeds: Option<Vec<String>>,
+
     /// A dictionary that describes the file(s) of the torrent.
-    pub info: Info,
+    pub info: I,
+
+    /// The merkle tree piece layers of the files of a v2 torrent (BEP 52)
+    #[serde(rename = "piece layers")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub piece_layers: Option<PieceLayers>,
+
+    /// URLs of BEP 19 web seeds, serving the torrent data over plain HTTP
+    #[serde(rename = "url-list")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url_list: Option<UrlList>,
+
+    /// The keys not modelled above, which [`edit::RawMetaInfo`] keeps as read
+    /// and writes back. Left empty when decoding a `MetaInfo` directly.
+    #[serde(skip)]
+    pub extra: BTreeMap<String, RawValue>,
+}
+
+impl<I> MetaInfo<I> {
+    /// The comment of the torrent, decoded with the `encoding` of the torrent
+    pub fn display_comment(&self) -> Option<String> {
+        self.comment
+            .as_ref()
+            .map(|comment| comment.decode(self.encoding.as_deref()))
+    }
+
+    /// The BEP 19 web seed URLs of the torrent
+    pub fn web_seeds(&self) -> Vec<&str> {
+        self.url_list
+            .as_ref()
+            .map(|url_list| url_list.urls())
+            .unwrap_or_default()
+    }
+
+    /// The tracker URLs of the torrent: `announce` then the `announce-list` tiers, without duplicates
+    pub fn trackers(&self) -> Vec<&str> {
+        let mut trackers: Vec<&str> = Vec::new();
+        let tiers = self.announce_list.iter().flatten().flatten();
+        for tracker in std::iter::once(&self.announce).chain(tiers) {
+            if !tracker.is_empty() && !trackers.contains(&tracker.as_str()) {
+                trackers.push(tracker);
+            }
+        }
+        trackers
+    }
 }
 
+impl MetaInfo {
+    pub fn new(announce: String, info: Info, piece_layers: Option<PieceLayers>) -> Self {
+        MetaInfo {
+            announce,
+            announce_list: None,
+            comment: None,
+            created_by: None,
+            creation_date: None,
+            encoding: None,
+            httpseeds: None,
+            info,
+            piece_layers,
+            url_list: None,
+            extra: BTreeMap::new(),
+        }
+    }
+
+    /// The name of the torrent, decoded with the `encoding` of the torrent
+    pub fn display_name(&self) -> String {
+        self.info.display_name(self.encoding.as_deref())
+    }
+
+    /// Checks the consistency of the torrent: piece counts, merkle roots against
+    /// the piece layers and, for hybrid torrents, that the v1 and v2 layouts agree.
+    pub fn validate(&self) -> Result<()> {
+        let version = self.info.version()?;
+
+        if version.has_v1() {
+            let total_length: usize = self.info.v1_files().iter().map(|f| f.length).sum();
+            let expected = total_length.div_ceil(self.info.piece_length.max(1));
+            if self.info.pieces.len() != expected {
+                return Err(Error::from_invalid(format!(
+                    "expected {} pieces, found {}",
+                    expected,
+                    self.info.pieces.len()
+                )));
+            }
+        }
+
+        if version.has_v2() {
+            self.validate_v2()?;
+        }
+
+        if version == Version::Hybrid {
+            self.validate_hybrid()?;
+        }
+
+        Ok(())
+    }
+
+    fn validate_v2(&self) -> Result<()> {
+        let piece_length = self.info.piece_length;
+        if piece_length < BLOCK_SIZE || !piece_length.is_power_of_two() {
+            return Err(Error::from_invalid(format!(
+                "piece length {} is not a power of two of at least {}",
+                piece_length, BLOCK_SIZE
+            )));
+        }
+
+        let empty = PieceLayers::default();
+        let piece_layers = self.piece_layers.as_ref().unwrap_or(&empty);
+        for (path, entry) in self.info.v2_files() {
+            let root = match (entry.length, entry.pieces_root) {
+                (0, _) => continue,
+                (_, Some(root)) => root,
+                (_, None) => {
+                    return Err(Error::from_invalid(format!(
+                        "file {:?} has no pieces root",
+                        path
+                    )))
+                }
+            };
+            if entry.length <= piece_length {
+                continue;
+            }
+
+            let layer = piece_layers.0.get(&root).ok_or_else(|| {
+                Error::from_invalid(format!("file {:?} has no piece layer", path))
+            })?;
+            if layer.len() != entry.length.div_ceil(piece_length) {
+                return Err(Error::from_invalid(format!(
+                    "piece layer of file {:?} has {} hashes",
+                    path,
+                    layer.len()
+                )));
+            }
+            if !merkle::verify_piece_layer(&root, layer, piece_length) {
+                return Err(Error::from_invalid(format!(
+                    "piece layer of file {:?} does not match its pieces root",
+                    path
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn validate_hybrid(&self) -> Result<()> {
+        let piece_length = self.info.piece_length;
+        let mut offset = 0;
+        let mut v1_files = Vec::new();
+        for file in self.info.v1_files() {
+            if !file.attributes.padding {
+                if offset % piece_length != 0 {
+                    return Err(Error::from_invalid(format!(
+                        "file {:?} is not aligned to a piece boundary",
+                        file.path
+                    )));
+                }
+                v1_files.push((file.path, file.length));
+            }
+            offset += file.length;
+        }
+
+        let v2_files: Vec<(Vec<RawString>, usize)> = self
+            .info
+            .v2_files()
+            .into_iter()
+            .map(|(path, entry)| {
+                (
+                    path.into_iter().map(RawString::from).collect(),
+                    entry.length,
+                )
+            })
+            .collect();
+
+        if v1_files != v2_files {
+            return Err(Error::from_invalid(
+                "the v1 and v2 file lists of the hybrid torrent differ",
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// The metadata versions a torrent can be made of
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Version {
+    /// The original format, with SHA-1 piece hashes (BEP 3)
+    V1,
+    /// Per file SHA-256 merkle trees (BEP 52)
+    V2,
+    /// Both a v1 and a v2 description of the same data
+    Hybrid,
+}
+
+impl Version {
+    pub fn has_v1(&self) -> bool {
+        matches!(self, Version::V1 | Version::Hybrid)
+    }
+
+    pub fn has_v2(&self) -> bool {
+        matches!(self, Version::V2 | Version::Hybrid)
+    }
+}
+
+impl std::fmt::Display for Version {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Version::V1 => write!(f, "v1"),
+            Version::V2 => write!(f, "v2"),
+            Version::Hybrid => write!(f, "hybrid"),
+        }
+    }
+}
+
+#[serde_as]
 #[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub struct Info {
     /// NOTE: All fields must be sorted alphabetically!
 
+    /// The v2 description of the files of the torrent (BEP 52)
+    #[serde(rename = "file tree")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file_tree: Option<FileTree>,
+
+    /// The v1 description of the files of the torrent
     #[serde(flatten)]
-    pub file_info: FileInfo,
+    pub file_info: Option<FileInfo>,
 
-    pub name: String,
+    /// The version of the metainfo format, 2 for v2 and hybrid torrents
+    #[serde(rename = "meta version")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub meta_version: Option<u8>,
+
+    /// Name of the file or directory, in the encoding the torrent was made with
+    pub name: RawString,
+
+    /// The name as UTF-8, written by clients when `name` is in another encoding
+    #[serde(rename = "name.utf-8")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name_utf8: Option<String>,
 
     #[serde(rename = "piece length")]
     pub piece_length: usize,
 
-    #[serde(with = "pieces_bytes")]
-    pub pieces: Vec<[u8; 20]>,
+    /// The SHA-1 hashes of the v1 pieces
+    #[serde(default, with = "pieces_bytes")]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub pieces: Vec<Sha1Hash>,
+
+    /// Restricts peer discovery to the trackers of the torrent (BEP 27)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[serde_as(as = "Option<BoolFromInt>")]
+    pub private: Option<bool>,
+
+    /// Tag of the tracker the torrent was made for, which changes the info-hash
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+}
+
+/// A file as described by the v1 part of the info dictionary
+#[derive(Debug, PartialEq, Clone)]
+pub struct V1File {
+    pub path: Vec<RawString>,
+    pub path_utf8: Option<Vec<String>>,
+    pub length: usize,
+    pub attributes: FileAttributes,
+    pub symlink_path: Option<Vec<String>>,
+}
+
+impl Info {
+    /// The name of the torrent, preferring `name.utf-8` and decoding `name` with `encoding` otherwise
+    pub fn display_name(&self, encoding: Option<&str>) -> String {
+        match &self.name_utf8 {
+            Some(name) => name.clone(),
+            None => self.name.decode(encoding),
+        }
+    }
+
+    pub fn is_private(&self) -> bool {
+        self.private.unwrap_or(false)
+    }
+
+    pub fn version(&self) -> Result<Version> {
+        let v1 = self.file_info.is_some();
+        let v2 = self.meta_version == Some(2) && self.file_tree.is_some();
+        match (v1, v2) {
+            (true, false) => Ok(Version::V1),
+            (false, true) => Ok(Version::V2),
+            (true, true) => Ok(Version::Hybrid),
+            (false, false) => Err(Error::from_invalid("info describes no files")),
+        }
+    }
+
+    /// The files of the v1 part, padding files included, with paths relative to the torrent root
+    pub fn v1_files(&self) -> Vec<V1File> {
+        match &self.file_info {
+            Some(FileInfo::SingleFile { length }) => vec![V1File {
+                path: vec![self.name.clone()],
+                path_utf8: self.name_utf8.clone().map(|name| vec![name]),
+                length: *length,
+                attributes: FileAttributes::default(),
+                symlink_path: None,
+            }],
+            Some(FileInfo::MultiFile { files }) => files
+                .iter()
+                .map(|file| V1File {
+                    path: file.path.clone(),
+                    path_utf8: file.path_utf8.clone(),
+                    length: file.length,
+                    attributes: file.attributes(),
+                    symlink_path: file.symlink_path.clone(),
+                })
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// The files of the v2 file tree, with paths relative to the torrent root
+    pub fn v2_files(&self) -> Vec<(Vec<String>, &file_tree::FileTreeEntry)> {
+        self.file_tree
+            .as_ref()
+            .map(|tree| tree.files())
+            .unwrap_or_default()
+    }
+
+    /// The bencoded info dictionary. The keys `Info` doesn't model are not
+    /// part of it, so the info-hashes of a torrent are those of the bytes kept
+    /// by [`edit::RawMetaInfo`].
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut buffer = Vec::new();
+        let mut writer = tforge_bencode::serializer::from_writer(&mut buffer);
+        self.serialize(&mut writer)?;
+        Ok(buffer)
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq)]
@@ -63,13 +416,111 @@ pub enum FileInfo {
     },
 }
 
-#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde_as]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub struct File {
+    /// NOTE: All fields must be sorted alphabetically!
+
+    /// File attributes (BEP 47), see [`FileAttributes`]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub attr: Option<String>,
+
     pub length: usize,
-    pub path: Vec<String>,
+
+    /// Path components, in the encoding the torrent was made with
+    pub path: Vec<RawString>,
+
+    /// The path as UTF-8, written by clients when `path` is in another encoding
+    #[serde(rename = "path.utf-8")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path_utf8: Option<Vec<String>>,
+
+    /// SHA-1 of the whole file, to help find duplicates across torrents (BEP 47)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[serde_as(as = "Option<Bytes>")]
+    pub sha1: Option<Sha1Hash>,
+
+    /// Target of a symlink file, relative to the torrent root (BEP 47)
+    #[serde(rename = "symlink path")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub symlink_path: Option<Vec<String>>,
+}
+
+impl File {
+    pub fn attributes(&self) -> FileAttributes {
+        FileAttributes::parse(self.attr.as_deref().unwrap_or_default())
+    }
+
+    /// Padding files only exist to align the next file to a piece boundary
+    pub fn is_padding(&self) -> bool {
+        self.attributes().padding
+    }
+
+    /// The path of the file, preferring `path.utf-8` and decoding `path` with `encoding` otherwise
+    pub fn display_path(&self, encoding: Option<&str>) -> Vec<String> {
+        display_path(&self.path, self.path_utf8.as_ref(), encoding)
+    }
 }
 
-mod optional_system_time {
+pub(crate) fn display_path(
+    path: &[RawString],
+    path_utf8: Option<&Vec<String>>,
+    encoding: Option<&str>,
+) -> Vec<String> {
+    match path_utf8 {
+        Some(path) => path.clone(),
+        None => path
+            .iter()
+            .map(|component| component.decode(encoding))
+            .collect(),
+    }
+}
+
+/// The BEP 47 file attributes, each represented by a letter of the `attr` string
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct FileAttributes {
+    /// `p`: zero filled padding, never written to disk
+    pub padding: bool,
+    /// `x`: the file should be made executable
+    pub executable: bool,
+    /// `h`: the file should be hidden
+    pub hidden: bool,
+    /// `l`: the file is a symlink to its `symlink path`
+    pub symlink: bool,
+}
+
+impl FileAttributes {
+    /// Parses an `attr` string, ignoring unknown letters
+    pub fn parse(attr: &str) -> Self {
+        FileAttributes {
+            padding: attr.contains('p'),
+            executable: attr.contains('x'),
+            hidden: attr.contains('h'),
+            symlink: attr.contains('l'),
+        }
+    }
+
+    /// The `attr` string, or `None` when no attribute is set
+    pub fn to_attr(&self) -> Option<String> {
+        let attr: String = [
+            (self.executable, 'x'),
+            (self.hidden, 'h'),
+            (self.padding, 'p'),
+            (self.symlink, 'l'),
+        ]
+        .into_iter()
+        .filter_map(|(set, letter)| set.then_some(letter))
+        .collect();
+        (!attr.is_empty()).then_some(attr)
+    }
+
+    /// Whether the file has data stored on disk, as opposed to padding and symlinks
+    pub fn is_on_disk(&self) -> bool {
+        !self.padding && !self.symlink
+    }
+}
+
+pub(crate) mod optional_system_time {
     use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
     pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<std::time::SystemTime>, D::Error>
@@ -97,38 +548,42 @@ mod optional_system_time {
     }
 }
 
+/// Concatenated fixed size hashes, such as v1 `pieces` or v2 piece layers
 mod pieces_bytes {
     use serde::{de::Visitor, Deserializer, Serializer};
 
-    struct PiecesVisitor;
+    struct PiecesVisitor<const N: usize>;
 
-    impl<'de> Visitor<'de> for PiecesVisitor {
-        type Value = Vec<[u8; 20]>;
+    impl<'de, const N: usize> Visitor<'de> for PiecesVisitor<N> {
+        type Value = Vec<[u8; N]>;
 
         fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
-            write!(formatter, "a byte array")
+            write!(formatter, "a byte array with a length multiple of {}", N)
         }
 
         fn visit_bytes<E>(self, v: &[u8]) -> Result<Self::Value, E>
         where
             E: serde::de::Error,
         {
-            let r: Vec<[u8; 20]> = v
-                .chunks_exact(20)
+            if v.len() % N != 0 {
+                return Err(E::invalid_length(v.len(), &self));
+            }
+            let r: Vec<[u8; N]> = v
+                .chunks_exact(N)
                 .map(|chunk| chunk.try_into().expect("invalid piece length"))
                 .collect();
             Ok(r)
         }
     }
 
-    pub fn deserialize<'de, D>(deserializer: D) -> Result<Vec<[u8; 20]>, D::Error>
+    pub fn deserialize<'de, D, const N: usize>(deserializer: D) -> Result<Vec<[u8; N]>, D::Error>
     where
         D: Deserializer<'de>,
     {
-        deserializer.deserialize_bytes(PiecesVisitor)
+        deserializer.deserialize_bytes(PiecesVisitor::<N>)
     }
 
-    pub fn serialize<S>(data: &[[u8; 20]], serializer: S) -> Result<S::Ok, S::Error>
+    pub fn serialize<S, const N: usize>(data: &[[u8; N]], serializer: S) -> Result<S::Ok, S::Error>
     where
         S: Serializer,
     {
@@ -139,45 +594,157 @@ mod pieces_bytes {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::hash::InfoHashes;
     use std::io::{BufReader, Cursor};
     use tforge_bencode::{deserializer::from_reader, serializer::from_writer};
 
-    // #[test]
-    // fn test_bencode_real_torrent_file() {
-    //     let test_data_dir = path::Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata");
-    //     let test_file = test_data_dir.join("ubuntu-23.10.1-desktop-amd64.iso.torrent");
-    //     let file_content = std::fs::read(test_file).unwrap();
+    #[test]
+    fn test_bencode_real_torrent_file() {
+        let test_data_dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata");
+        let test_file = test_data_dir.join("ubuntu-23.10.1-desktop-amd64.iso.torrent");
+        let file_content = std::fs::read(test_file).unwrap();
 
-    //     let mut reader = BufReader::new(Cursor::new(&file_content));
-    //     let meta_info: MetaInfo = from_reader(&mut reader).unwrap();
+        let mut reader = BufReader::new(Cursor::new(&file_content));
+        let meta_info: MetaInfo = from_reader(&mut reader).unwrap();
 
-    //     let mut buffer = Vec::new();
-    //     let mut writer = from_writer(&mut buffer);
-    //     meta_info.serialize(&mut writer).unwrap();
+        let mut buffer = Vec::new();
+        let mut writer = from_writer(&mut buffer);
+        meta_info.serialize(&mut writer).unwrap();
+
+        assert_eq!(&file_content, &buffer);
+    }
+
+    #[test]
+    fn test_info_hash_real_torrent_file() {
+        let test_data_dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata");
+        let test_file = test_data_dir.join("ubuntu-23.10.1-desktop-amd64.iso.torrent");
+        let file_content = std::fs::read(test_file).unwrap();
+
+        let mut reader = BufReader::new(Cursor::new(&file_content));
+        let meta_info: MetaInfo = from_reader(&mut reader).unwrap();
+        assert_eq!(meta_info.info.version().unwrap(), Version::V1);
+        assert!(meta_info.validate().is_ok());
+
+        let raw = edit::RawMetaInfo::from_bytes(&file_content).unwrap();
+        let hashes = raw.info_hashes().unwrap();
+        assert_eq!(
+            hex::encode(hashes.v1.unwrap()),
+            "9ecd4676fd0f0474151a4b74a5958f42639cebdf"
+        );
+        assert!(hashes.v2.is_none());
+    }
+
+    #[test]
+    fn test_info_hash_keeps_unknown_keys() {
+        let content = b"d8:announce27:http://example.com/announce\
+4:infod6:lengthi3e4:name4:name12:piece lengthi16e6:pieces20:aaaaaaaaaaaaaaaaaaaa\
+7:x-extra5:valueee";
+        let start = content.windows(6).position(|w| w == b"4:info").unwrap() + 6;
+        let info_bytes = &content[start..content.len() - 1];
+
+        let raw = edit::RawMetaInfo::from_bytes(content).unwrap();
+        let hashes = raw.info_hashes().unwrap();
+        assert_eq!(hashes.v1, Some(hash::sha1(info_bytes)));
+
+        // Decoding into an `Info` drops the key, and would change the info-hash
+        let info = raw.decode_info().unwrap();
+        assert_ne!(hashes.v1, Some(hash::sha1(&info.to_bytes().unwrap())));
+    }
 
-    //     assert_eq!(&file_content, &buffer);
-    // }
+    #[test]
+    fn test_bencode_web_seeds_roundtrip() {
+        let content =
+            b"d8:announce27:http://example.com/announce9:httpseedsl23:http://example.com/seede\
+4:infod6:lengthi3e4:name4:name12:piece lengthi16e6:pieces20:aaaaaaaaaaaaaaaaaaaae\
+8:url-list23:http://example.com/namee";
+
+        let mut reader = BufReader::new(Cursor::new(&content));
+        let meta_info: MetaInfo = from_reader(&mut reader).unwrap();
+        assert_eq!(meta_info.web_seeds(), vec!["http://example.com/name"]);
+        assert_eq!(
+            meta_info.httpseeds,
+            Some(vec!["http://example.com/seed".to_string()])
+        );
+
+        let mut buffer = Vec::new();
+        let mut writer = from_writer(&mut buffer);
+        meta_info.serialize(&mut writer).unwrap();
+
+        assert_eq!(&content[..], &buffer);
+    }
+
+    #[test]
+    fn test_bencode_legacy_encoding_roundtrip() {
+        // "テスト" in Shift-JIS, which is not valid UTF-8
+        let content = b"d8:announce27:http://example.com/announce8:encoding9:Shift_JIS\
+4:infod5:filesld6:lengthi3e4:pathl6:\x83\x65\x83\x58\x83\x67eee\
+4:name6:\x83\x65\x83\x58\x83\x6712:piece lengthi16e6:pieces20:aaaaaaaaaaaaaaaaaaaaee";
+
+        let mut reader = BufReader::new(Cursor::new(&content));
+        let meta_info: MetaInfo = from_reader(&mut reader).unwrap();
+        assert_eq!(meta_info.info.name.to_str(), None);
+        assert_eq!(meta_info.display_name(), "テスト");
+        let files = meta_info.info.files();
+        assert_eq!(files[0].display_path(Some("Shift_JIS")), vec!["テスト"]);
+
+        let mut buffer = Vec::new();
+        let mut writer = from_writer(&mut buffer);
+        meta_info.serialize(&mut writer).unwrap();
+        assert_eq!(&content[..], &buffer);
+
+        let mut info_bytes = Vec::new();
+        let start = content.windows(6).position(|w| w == b"4:info").unwrap() + 6;
+        info_bytes.extend_from_slice(&content[start..content.len() - 1]);
+        let raw = edit::RawMetaInfo::from_bytes(content).unwrap();
+        assert_eq!(
+            raw.info_hashes().unwrap().v1,
+            Some(crate::hash::sha1(&info_bytes))
+        );
+    }
+
+    #[test]
+    fn test_utf8_fields_are_preferred() {
+        let content =
+            b"d6:lengthi3e4:name4:\xd2\xe5\xf1\xf210:name.utf-88:\xd0\xa2\xd0\xb5\xd1\x81\xd1\x82\
+12:piece lengthi16e6:pieces20:aaaaaaaaaaaaaaaaaaaae";
+
+        let mut reader = BufReader::new(Cursor::new(&content));
+        let info: Info = from_reader(&mut reader).unwrap();
+        assert_eq!(info.display_name(None), "Тест");
+        assert_eq!(info.name.decode(Some("cp1251")), "Тест");
+        assert_eq!(info.name.decode(None), "\u{fffd}\u{fffd}\u{fffd}\u{fffd}");
+        assert_eq!(&info.to_bytes().unwrap(), content);
+    }
 
     #[test]
     fn test_bencode_single_file() {
         let meta_info = MetaInfo {
             announce: "http://example.com/announce".to_string(),
             announce_list: Some(vec![vec!["http://example.com/announce".to_string()]]),
-            comment: Some("comment".to_string()),
+            comment: Some("comment".into()),
             created_by: Some("created_by".to_string()),
             creation_date: Some(
                 std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(123),
             ),
             encoding: Some("UTF-8".to_string()),
+            httpseeds: Some(vec!["http://example.com/seed".to_string()]),
             info: Info {
-                file_info: FileInfo::SingleFile { length: 123 },
-                name: "name".to_string(),
+                file_tree: None,
+                file_info: Some(FileInfo::SingleFile { length: 123 }),
+                meta_version: None,
+                name: "name".into(),
+                name_utf8: None,
                 piece_length: 123,
                 pieces: vec![
                     b"aaaaaaaaaaaaaaaaaaaa".to_owned(),
                     b"bbbbbbbbbbbbbbbbbbbb".to_owned(),
                 ],
+                private: None,
+                source: None,
             },
+            piece_layers: None,
+            url_list: Some(UrlList::Single("http://example.com/file".to_string())),
+            extra: BTreeMap::new(),
         };
 
         let mut buffer = Vec::new();
@@ -195,26 +762,42 @@ mod tests {
         let meta_info = MetaInfo {
             announce: "http://example.com/announce".to_string(),
             announce_list: Some(vec![vec!["http://example.com/announce".to_string()]]),
-            comment: Some("comment".to_string()),
+            comment: Some("comment".into()),
             created_by: Some("created_by".to_string()),
             creation_date: Some(
                 std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(123),
             ),
             encoding: Some("UTF-8".to_string()),
+            httpseeds: Some(vec!["http://example.com/seed".to_string()]),
             info: Info {
-                file_info: FileInfo::MultiFile {
+                file_tree: None,
+                file_info: Some(FileInfo::MultiFile {
                     files: vec![File {
+                        attr: None,
                         length: 123,
-                        path: vec!["path".to_string()],
+                        path: vec!["path".into()],
+                        path_utf8: None,
+                        sha1: None,
+                        symlink_path: None,
                     }],
-                },
-                name: "name".to_string(),
+                }),
+                meta_version: None,
+                name: "name".into(),
+                name_utf8: None,
                 piece_length: 123,
                 pieces: vec![
                     b"aaaaaaaaaaaaaaaaaaaa".to_owned(),
                     b"bbbbbbbbbbbbbbbbbbbb".to_owned(),
                 ],
+                private: None,
+                source: None,
             },
+            piece_layers: None,
+            url_list: Some(UrlList::Multiple(vec![
+                "http://example.com/a/".to_string(),
+                "http://example.com/b/".to_string(),
+            ])),
+            extra: BTreeMap::new(),
         };
 
         let mut buffer = Vec::new();
@@ -226,4 +809,303 @@ mod tests {
 
         assert_eq!(meta_info, decoded_meta_info);
     }
+
+    pub(crate) fn create_test_dir(name: &str, files: &[(&str, usize)]) -> std::path::PathBuf {
+        let dir = std::env::temp_dir()
+            .join(format!("tforge-metainfo-{}", std::process::id()))
+            .join(name);
+        let _ = std::fs::remove_dir_all(&dir);
+        for (path, length) in files {
+            let path = dir.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            let data: Vec<u8> = (0..*length).map(|i| (i % 251) as u8).collect();
+            std::fs::write(path, data).unwrap();
+        }
+        dir
+    }
+
+    fn create_test_torrent(dir: &std::path::Path, version: Version) -> MetaInfo {
+        let options = create::CreateOptions {
+            version,
+            piece_length: BLOCK_SIZE,
+            ..Default::default()
+        };
+        let (info, piece_layers) = create::create_info(dir, &options).unwrap();
+        MetaInfo::new(
+            "http://example.com/announce".to_string(),
+            info,
+            piece_layers,
+        )
+    }
+
+    /// The info-hashes of the torrent once written to a file
+    fn written_info_hashes(meta_info: &MetaInfo) -> InfoHashes {
+        let mut buffer = Vec::new();
+        let mut writer = from_writer(&mut buffer);
+        meta_info.serialize(&mut writer).unwrap();
+
+        let raw = edit::RawMetaInfo::from_bytes(&buffer).unwrap();
+        raw.info_hashes().unwrap()
+    }
+
+    fn bencode_roundtrip(meta_info: &MetaInfo) -> MetaInfo {
+        let mut buffer = Vec::new();
+        let mut writer = from_writer(&mut buffer);
+        meta_info.serialize(&mut writer).unwrap();
+
+        let mut reader = BufReader::new(Cursor::new(&buffer));
+        from_reader(&mut reader).unwrap()
+    }
+
+    const TEST_FILES: &[(&str, usize)] = &[
+        ("b/large.bin", BLOCK_SIZE * 3 + 100),
+        ("a.txt", 1000),
+        ("empty", 0),
+    ];
+
+    #[test]
+    fn test_create_v1() {
+        let dir = create_test_dir("v1", TEST_FILES);
+        let meta_info = create_test_torrent(&dir, Version::V1);
+
+        assert_eq!(meta_info.info.version().unwrap(), Version::V1);
+        assert_eq!(meta_info.info.pieces.len(), 4);
+        assert!(meta_info.piece_layers.is_none());
+        let paths: Vec<Vec<String>> = meta_info
+            .info
+            .v1_files()
+            .into_iter()
+            .map(|file| file.path.iter().map(|c| c.to_string()).collect())
+            .collect();
+        assert_eq!(
+            paths,
+            vec![
+                vec!["a.txt".to_string()],
+                vec!["b".to_string(), "large.bin".to_string()],
+                vec!["empty".to_string()],
+            ]
+        );
+
+        let hashes = written_info_hashes(&meta_info);
+        assert!(hashes.v1.is_some());
+        assert!(hashes.v2.is_none());
+
+        meta_info.validate().unwrap();
+        assert_eq!(bencode_roundtrip(&meta_info), meta_info);
+    }
+
+    #[test]
+    fn test_create_v2() {
+        let dir = create_test_dir("v2", TEST_FILES);
+        let meta_info = create_test_torrent(&dir, Version::V2);
+
+        assert_eq!(meta_info.info.version().unwrap(), Version::V2);
+        assert!(meta_info.info.pieces.is_empty());
+        assert!(meta_info.info.file_info.is_none());
+        assert_eq!(meta_info.info.meta_version, Some(2));
+        assert_eq!(meta_info.piece_layers.as_ref().unwrap().0.len(), 1);
+
+        let hashes = written_info_hashes(&meta_info);
+        assert!(hashes.v1.is_none());
+        assert_eq!(
+            hashes.v2,
+            Some(hash::sha256(&meta_info.info.to_bytes().unwrap()))
+        );
+
+        assert!(meta_info.validate().is_ok());
+        assert_eq!(bencode_roundtrip(&meta_info), meta_info);
+    }
+
+    #[test]
+    fn test_create_hybrid() {
+        let dir = create_test_dir("hybrid", TEST_FILES);
+        let meta_info = create_test_torrent(&dir, Version::Hybrid);
+
+        assert_eq!(meta_info.info.version().unwrap(), Version::Hybrid);
+        let padding: Vec<usize> = meta_info
+            .info
+            .v1_files()
+            .into_iter()
+            .filter(|file| file.attributes.padding)
+            .map(|file| file.length)
+            .collect();
+        assert_eq!(padding, vec![BLOCK_SIZE - 1000, BLOCK_SIZE - 100]);
+        assert_eq!(meta_info.info.pieces.len(), 5);
+
+        let hashes = written_info_hashes(&meta_info);
+        assert!(hashes.v1.is_some());
+        assert!(hashes.v2.is_some());
+
+        assert!(meta_info.validate().is_ok());
+        assert_eq!(bencode_roundtrip(&meta_info), meta_info);
+    }
+
+    #[test]
+    fn test_create_v2_single_file() {
+        let dir = create_test_dir("single", &[("file.bin", BLOCK_SIZE * 2 + 1)]);
+        let meta_info = create_test_torrent(&dir.join("file.bin"), Version::Hybrid);
+
+        assert_eq!(meta_info.info.name, "file.bin");
+        assert_eq!(
+            meta_info.info.file_info,
+            Some(FileInfo::SingleFile {
+                length: BLOCK_SIZE * 2 + 1
+            })
+        );
+        let v2_files = meta_info.info.v2_files();
+        assert_eq!(v2_files.len(), 1);
+        assert_eq!(v2_files[0].0, vec!["file.bin".to_string()]);
+
+        assert!(meta_info.validate().is_ok());
+    }
+
+    #[test]
+    fn test_validate_rejects_bad_piece_layer() {
+        let dir = create_test_dir("bad-layer", TEST_FILES);
+        let mut meta_info = create_test_torrent(&dir, Version::V2);
+
+        let layers = meta_info.piece_layers.as_mut().unwrap();
+        let layer = layers.0.values_mut().next().unwrap();
+        layer[0][0] ^= 1;
+
+        assert!(meta_info.validate().is_err());
+    }
+
+    #[test]
+    fn test_validate_rejects_mismatched_hybrid() {
+        let dir = create_test_dir("bad-hybrid", TEST_FILES);
+        let mut meta_info = create_test_torrent(&dir, Version::Hybrid);
+
+        if let Some(FileInfo::MultiFile { files }) = meta_info.info.file_info.as_mut() {
+            files[0].path = vec!["renamed.txt".into()];
+        }
+
+        assert!(meta_info.validate().is_err());
+    }
+
+    #[test]
+    fn test_file_attributes() {
+        let attributes = FileAttributes::parse("xhz");
+        assert!(attributes.executable);
+        assert!(attributes.hidden);
+        assert!(!attributes.padding);
+        assert!(!attributes.symlink);
+        assert_eq!(attributes.to_attr(), Some("xh".to_string()));
+        assert_eq!(FileAttributes::default().to_attr(), None);
+        assert!(!FileAttributes::parse("p").is_on_disk());
+        assert!(!FileAttributes::parse("l").is_on_disk());
+    }
+
+    #[test]
+    fn test_create_private_with_source() {
+        let dir = create_test_dir("private", TEST_FILES);
+        let options = create::CreateOptions {
+            private: true,
+            source: Some("tracker".to_string()),
+            ..Default::default()
+        };
+        let (info, _) = create::create_info(&dir, &options).unwrap();
+        assert!(info.is_private());
+        assert_eq!(info.source, Some("tracker".to_string()));
+
+        let (public, _) = create::create_info(&dir, &Default::default()).unwrap();
+        assert!(!public.is_private());
+
+        let meta_info = MetaInfo::new("http://example.com/announce".to_string(), info, None);
+        let public = MetaInfo::new("http://example.com/announce".to_string(), public, None);
+        assert_ne!(
+            written_info_hashes(&meta_info),
+            written_info_hashes(&public)
+        );
+        let decoded = bencode_roundtrip(&meta_info);
+        assert_eq!(decoded, meta_info);
+        assert!(meta_info
+            .info
+            .to_bytes()
+            .unwrap()
+            .ends_with(b"7:privatei1e6:source7:trackere"));
+    }
+
+    #[test]
+    fn test_create_v1_padded_reads_zeros() {
+        let dir = create_test_dir("padded", TEST_FILES);
+        let options = create::CreateOptions {
+            piece_length: BLOCK_SIZE,
+            pad_files: true,
+            file_sha1: true,
+            ..Default::default()
+        };
+        let (info, _) = create::create_info(&dir, &options).unwrap();
+
+        let files = info.files();
+        assert!(files[1].is_padding());
+        assert_eq!(files[1].length, BLOCK_SIZE - 1000);
+        assert_eq!(files[2].offset, BLOCK_SIZE);
+        assert_eq!(
+            files[0].attributes,
+            FileAttributes::default(),
+            "regular files have no attributes"
+        );
+
+        let Some(FileInfo::MultiFile { files: v1_files }) = &info.file_info else {
+            panic!("expected a multi file torrent");
+        };
+        let data = std::fs::read(dir.join("a.txt")).unwrap();
+        assert_eq!(v1_files[0].sha1, Some(hash::sha1(&data)));
+        assert_eq!(v1_files[1].sha1, None);
+
+        let parent = dir.parent().unwrap();
+        let piece = info.read_piece(parent, 0, None).unwrap();
+        assert_eq!(&piece[..1000], &data[..]);
+        assert!(piece[1000..].iter().all(|b| *b == 0));
+        for index in 0..info.piece_count() {
+            let piece = info.read_piece(parent, index, None).unwrap();
+            assert_eq!(hash::sha1(&piece), info.pieces[index]);
+        }
+
+        let requests = web_seed::piece_requests("http://example.com/", &info, 0);
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].range, 0..1000);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_create_symlink_and_executable() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = create_test_dir("attributes", &[("bin/run", 10), ("data", 20)]);
+        std::fs::set_permissions(dir.join("bin/run"), std::fs::Permissions::from_mode(0o755))
+            .unwrap();
+        std::os::unix::fs::symlink("bin/run", dir.join("link")).unwrap();
+        std::fs::write(dir.join(".hidden"), b"hidden").unwrap();
+
+        let meta_info = create_test_torrent(&dir, Version::Hybrid);
+        let files: Vec<_> = meta_info
+            .info
+            .files()
+            .into_iter()
+            .filter(|file| !file.is_padding())
+            .collect();
+
+        let paths: Vec<String> = files
+            .iter()
+            .map(|file| file.display_path(None).join("/"))
+            .collect();
+        assert_eq!(paths, vec![".hidden", "bin/run", "data", "link"]);
+        assert!(files[0].attributes.hidden);
+        assert!(files[1].attributes.executable);
+        assert!(!files[2].attributes.executable);
+        assert!(files[3].attributes.symlink);
+        assert_eq!(files[3].length, 0);
+        assert_eq!(
+            files[3].symlink_path,
+            Some(vec!["bin".to_string(), "run".to_string()])
+        );
+
+        let v2_link = meta_info.info.v2_files()[3].1.clone();
+        assert_eq!(v2_link.attributes(), files[3].attributes);
+
+        assert!(meta_info.validate().is_ok());
+        assert_eq!(bencode_roundtrip(&meta_info), meta_info);
+    }
 }
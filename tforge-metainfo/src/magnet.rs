@@ -0,0 +1,301 @@
+use crate::{
+    edit::RawMetaInfo,
+    error::{Error, Result},
+    hash::InfoHashes,
+};
+use std::{fmt, ops::RangeInclusive, str::FromStr};
+use tforge_urlencoded::value::{self, Value};
+
+const SCHEME: &str = "magnet:?";
+const BTIH: &str = "urn:btih:";
+const BTMH: &str = "urn:btmh:";
+
+/// Multihash prefix of a SHA-256 digest: function code 0x12 and a length of 32 bytes
+const SHA256_MULTIHASH: [u8; 2] = [0x12, 0x20];
+
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// A magnet link identifying a torrent by its info-hashes (BEP 9).
+///
+/// v1 torrents use `xt=urn:btih:`, v2 torrents `xt=urn:btmh:` (BEP 52), hybrid
+/// torrents carry both.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Magnet {
+    /// The info-hashes from the `xt` parameters
+    pub info_hashes: InfoHashes,
+
+    /// `dn`: the name to display while the metadata is fetched
+    pub display_name: Option<String>,
+
+    /// `tr`: tracker URLs
+    pub trackers: Vec<String>,
+
+    /// `ws`: BEP 19 web seed URLs
+    pub web_seeds: Vec<String>,
+
+    /// `x.pe`: addresses of peers to connect to, as `host:port`
+    pub peers: Vec<String>,
+
+    /// `so`: BEP 53 indices of the files to download, all of them when empty
+    pub select_only: Vec<RangeInclusive<usize>>,
+}
+
+impl Magnet {
+    pub fn parse(link: &str) -> Result<Self> {
+        let query = strip_prefix_ignore_case(link, SCHEME)
+            .ok_or_else(|| Error::from_magnet("missing the magnet:? prefix"))?;
+
+        let mut magnet = Magnet::default();
+        for parameter in query.split('&').filter(|p| !p.is_empty()) {
+            let (key, encoded) = parameter.split_once('=').unwrap_or((parameter, ""));
+            let value = value::decode(encoded).ok_or_else(|| {
+                Error::from_magnet(format!("parameter {} is not properly escaped", key))
+            })?;
+
+            match base_key(key) {
+                "xt" => magnet.parse_exact_topic(&value)?,
+                "dn" => magnet.display_name = Some(value),
+                "tr" => magnet.trackers.push(value),
+                "ws" => magnet.web_seeds.push(value),
+                "x.pe" => magnet.peers.push(value),
+                "so" => magnet.select_only.extend(parse_select_only(&value)?),
+                _ => {}
+            }
+        }
+
+        if magnet.info_hashes.v1.is_none() && magnet.info_hashes.v2.is_none() {
+            return Err(Error::from_magnet("no urn:btih: or urn:btmh: exact topic"));
+        }
+        Ok(magnet)
+    }
+
+    fn parse_exact_topic(&mut self, topic: &str) -> Result<()> {
+        if let Some(hash) = strip_prefix_ignore_case(topic, BTIH) {
+            let bytes = match hash.len() {
+                40 => hex::decode(hash).ok(),
+                32 => decode_base32(hash),
+                _ => None,
+            };
+            let hash = bytes
+                .and_then(|bytes| bytes.try_into().ok())
+                .ok_or_else(|| Error::from_magnet(format!("invalid btih hash {}", hash)))?;
+            self.info_hashes.v1 = Some(hash);
+        } else if let Some(hash) = strip_prefix_ignore_case(topic, BTMH) {
+            let hash = hex::decode(hash)
+                .ok()
+                .and_then(|bytes| bytes.strip_prefix(&SHA256_MULTIHASH)?.try_into().ok())
+                .ok_or_else(|| Error::from_magnet(format!("invalid btmh hash {}", hash)))?;
+            self.info_hashes.v2 = Some(hash);
+        }
+        Ok(())
+    }
+
+    /// Whether the file at `index` is selected for download
+    pub fn is_selected(&self, index: usize) -> bool {
+        self.select_only.is_empty() || self.select_only.iter().any(|r| r.contains(&index))
+    }
+}
+
+impl FromStr for Magnet {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Magnet::parse(s)
+    }
+}
+
+impl fmt::Display for Magnet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut parameters = Vec::new();
+        if let Some(v1) = &self.info_hashes.v1 {
+            parameters.push(format!("xt={}{}", BTIH, hex::encode(v1)));
+        }
+        if let Some(v2) = &self.info_hashes.v2 {
+            let multihash = [&SHA256_MULTIHASH[..], &v2[..]].concat();
+            parameters.push(format!("xt={}{}", BTMH, hex::encode(multihash)));
+        }
+        if let Some(name) = &self.display_name {
+            parameters.push(format!("dn={}", Value::from(name.as_str())));
+        }
+        let urls = [
+            ("tr", &self.trackers),
+            ("ws", &self.web_seeds),
+            ("x.pe", &self.peers),
+        ];
+        for (key, values) in urls {
+            for value in values {
+                parameters.push(format!("{}={}", key, Value::from(value.as_str())));
+            }
+        }
+        if !self.select_only.is_empty() {
+            let ranges: Vec<String> = self
+                .select_only
+                .iter()
+                .map(|range| match range.start() == range.end() {
+                    true => range.start().to_string(),
+                    false => format!("{}-{}", range.start(), range.end()),
+                })
+                .collect();
+            parameters.push(format!("so={}", ranges.join(",")));
+        }
+
+        write!(f, "{}{}", SCHEME, parameters.join("&"))
+    }
+}
+
+impl RawMetaInfo {
+    /// A magnet link for the torrent, with its trackers and web seeds. The
+    /// info-hashes are those of the info dictionary as it was read.
+    pub fn to_magnet(&self) -> Result<Magnet> {
+        let info = self.decode_info()?;
+        Ok(Magnet {
+            info_hashes: self.info_hashes()?,
+            display_name: Some(info.display_name(self.encoding.as_deref())),
+            trackers: self.trackers().into_iter().map(String::from).collect(),
+            web_seeds: self.web_seeds().into_iter().map(String::from).collect(),
+            peers: Vec::new(),
+            select_only: Vec::new(),
+        })
+    }
+}
+
+/// Parameters may be numbered to keep them apart, e.g. `tr.1`, `tr.2`
+fn base_key(key: &str) -> &str {
+    match key.rsplit_once('.') {
+        Some((base, index)) if !index.is_empty() && index.bytes().all(|b| b.is_ascii_digit()) => {
+            base
+        }
+        _ => key,
+    }
+}
+
+fn strip_prefix_ignore_case<'a>(s: &'a str, prefix: &str) -> Option<&'a str> {
+    s.get(..prefix.len())
+        .filter(|start| start.eq_ignore_ascii_case(prefix))
+        .map(|_| &s[prefix.len()..])
+}
+
+/// Parses a BEP 53 file selection such as `0,2,4-6`
+fn parse_select_only(value: &str) -> Result<Vec<RangeInclusive<usize>>> {
+    let invalid = || Error::from_magnet(format!("invalid file selection {}", value));
+    value
+        .split(',')
+        .map(|item| {
+            let (start, end) = item.split_once('-').unwrap_or((item, item));
+            let start: usize = start.parse().map_err(|_| invalid())?;
+            let end: usize = end.parse().map_err(|_| invalid())?;
+            (start <= end).then_some(start..=end).ok_or_else(invalid)
+        })
+        .collect()
+}
+
+/// Decodes RFC 4648 base32 without padding, as used by old v1 magnet links
+fn decode_base32(encoded: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(encoded.len() * 5 / 8);
+    let mut buffer: u64 = 0;
+    let mut bits = 0;
+    for c in encoded.bytes() {
+        let value = BASE32_ALPHABET
+            .iter()
+            .position(|&a| a == c.to_ascii_uppercase())?;
+        buffer = (buffer << 5) | value as u64;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+        }
+    }
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const V1_HEX: &str = "9ecd4676fd0f0474151a4b74a5958f42639cebdf";
+
+    #[test]
+    fn test_parse_v1_hex() {
+        let magnet = Magnet::parse(&format!(
+            "magnet:?xt=urn:btih:{}&dn=ubuntu%20server&tr=http%3A%2F%2Fa%2Fannounce&tr.1=udp://b:80",
+            V1_HEX
+        ))
+        .unwrap();
+        assert_eq!(hex::encode(magnet.info_hashes.v1.unwrap()), V1_HEX);
+        assert_eq!(magnet.info_hashes.v2, None);
+        assert_eq!(magnet.display_name.as_deref(), Some("ubuntu server"));
+        assert_eq!(magnet.trackers, vec!["http://a/announce", "udp://b:80"]);
+    }
+
+    #[test]
+    fn test_parse_v1_base32() {
+        let hex = Magnet::parse(&format!("magnet:?xt=urn:btih:{}", V1_HEX)).unwrap();
+        let base32 = Magnet::parse("magnet:?xt=urn:btih:T3GUM5X5B4CHIFI2JN2KLFMPIJRZZ267").unwrap();
+        assert_eq!(hex, base32);
+    }
+
+    #[test]
+    fn test_parse_hybrid_and_extensions() {
+        let v2 = "1220".to_string() + &"ab".repeat(32);
+        let magnet = Magnet::parse(&format!(
+            "magnet:?xt=urn:btih:{}&xt=urn:btmh:{}&ws=http%3A%2F%2Fseed%2F&x.pe=10.0.0.1%3A6881&so=0,2,4-6",
+            V1_HEX, v2
+        ))
+        .unwrap();
+        assert_eq!(magnet.info_hashes.v2, Some([0xab; 32]));
+        assert_eq!(magnet.web_seeds, vec!["http://seed/"]);
+        assert_eq!(magnet.peers, vec!["10.0.0.1:6881"]);
+        assert_eq!(magnet.select_only, vec![0..=0, 2..=2, 4..=6]);
+        assert!(magnet.is_selected(5));
+        assert!(!magnet.is_selected(3));
+    }
+
+    #[test]
+    fn test_display_roundtrip() {
+        let magnet = Magnet {
+            info_hashes: InfoHashes {
+                v1: Some([1; 20]),
+                v2: Some([2; 32]),
+            },
+            display_name: Some("a name/é".to_string()),
+            trackers: vec!["http://a/announce".to_string(), "udp://b:80".to_string()],
+            web_seeds: vec!["http://seed/".to_string()],
+            peers: vec!["[::1]:6881".to_string()],
+            select_only: vec![1..=1, 3..=5],
+        };
+        let link = magnet.to_string();
+        assert!(link.starts_with(&format!("magnet:?xt=urn:btih:{}&", "01".repeat(20))));
+        assert!(link.contains(&format!("xt=urn:btmh:1220{}", "02".repeat(32))));
+        assert!(link.contains("dn=a%20name%2F%C3%A9"));
+        assert!(link.ends_with("so=1,3-5"));
+        assert_eq!(Magnet::parse(&link).unwrap(), magnet);
+    }
+
+    #[test]
+    fn test_meta_info_to_magnet() {
+        // The info dictionary has a key `Info` doesn't know of, which is part of the info-hash
+        let torrent = b"d8:announce17:http://a/announce\
+13:announce-listll17:http://a/announceel10:udp://b:80ee\
+4:infod6:lengthi3e4:name4:name12:piece lengthi16e6:pieces20:aaaaaaaaaaaaaaaaaaaa\
+7:x-extra5:valuee8:url-list16:http://seed/namee";
+        let meta_info = RawMetaInfo::from_bytes(torrent).unwrap();
+
+        let magnet = meta_info.to_magnet().unwrap();
+        let info = meta_info.info.as_bytes();
+        assert!(info.ends_with(b"7:x-extra5:valuee"));
+        assert_eq!(magnet.info_hashes.v1, Some(crate::hash::sha1(info)));
+        assert_eq!(magnet.info_hashes.v2, None);
+        assert_eq!(magnet.display_name.as_deref(), Some("name"));
+        assert_eq!(magnet.trackers, vec!["http://a/announce", "udp://b:80"]);
+        assert_eq!(magnet.web_seeds, vec!["http://seed/name"]);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(Magnet::parse("http://example.com").is_err());
+        assert!(Magnet::parse("magnet:?dn=name").is_err());
+        assert!(Magnet::parse("magnet:?xt=urn:btih:1234").is_err());
+        assert!(Magnet::parse(&format!("magnet:?xt=urn:btmh:1120{}", "ab".repeat(32))).is_err());
+        assert!(Magnet::parse(&format!("magnet:?xt=urn:btih:{}&so=3-1", V1_HEX)).is_err());
+    }
+}
@@ -0,0 +1,199 @@
+use sha2::{Digest, Sha256};
+
+/// Size of the leaf blocks of a BEP 52 merkle tree
+pub const BLOCK_SIZE: usize = 16 * 1024;
+
+pub type Sha256Hash = [u8; 32];
+
+pub fn hash_block(data: &[u8]) -> Sha256Hash {
+    Sha256::digest(data).into()
+}
+
+pub fn hash_pair(left: &Sha256Hash, right: &Sha256Hash) -> Sha256Hash {
+    let mut hasher = Sha256::new();
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
+
+/// The hash of a subtree made only of padding, with `2^level` zeroed leaves
+pub fn pad_hash(level: u32) -> Sha256Hash {
+    (0..level).fold([0; 32], |hash, _| hash_pair(&hash, &hash))
+}
+
+/// Hashes one layer of the tree into the layer above it.
+/// An odd trailing node is paired with the padding hash of its level.
+fn reduce_layer(layer: &[Sha256Hash], level: u32) -> Vec<Sha256Hash> {
+    let pad = pad_hash(level);
+    layer
+        .chunks(2)
+        .map(|pair| hash_pair(&pair[0], pair.get(1).unwrap_or(&pad)))
+        .collect()
+}
+
+/// Computes the root of a tree whose nodes at `level` are `layer`.
+/// The leaves beyond the end of `layer` are zero, as per BEP 52.
+pub fn merkle_root(layer: &[Sha256Hash], level: u32) -> Option<Sha256Hash> {
+    let mut layer = layer.to_vec();
+    let mut level = level;
+    while layer.len() > 1 {
+        layer = reduce_layer(&layer, level);
+        level += 1;
+    }
+    layer.first().copied()
+}
+
+/// The number of leaf blocks covered by a single piece
+pub fn blocks_per_piece(piece_length: usize) -> usize {
+    piece_length / BLOCK_SIZE
+}
+
+/// Checks that a piece layer hashes up to the expected pieces root
+pub fn verify_piece_layer(
+    pieces_root: &Sha256Hash,
+    piece_layer: &[Sha256Hash],
+    piece_length: usize,
+) -> bool {
+    let level = blocks_per_piece(piece_length).trailing_zeros();
+    merkle_root(piece_layer, level).as_ref() == Some(pieces_root)
+}
+
+/// The merkle tree data of a single file of a v2 torrent
+#[derive(Debug, PartialEq)]
+pub struct FileMerkle {
+    /// Length of the file in bytes
+    pub length: usize,
+
+    /// Root of the merkle tree, absent for empty files
+    pub pieces_root: Option<Sha256Hash>,
+
+    /// Hashes of the layer where each node covers one piece.
+    /// Only filled for files larger than the piece length.
+    pub piece_layer: Vec<Sha256Hash>,
+}
+
+/// Incrementally hashes a file into its BEP 52 merkle tree
+pub struct FileHasher {
+    piece_length: usize,
+    length: usize,
+    buffer: Vec<u8>,
+    leaves: Vec<Sha256Hash>,
+}
+
+impl FileHasher {
+    pub fn new(piece_length: usize) -> Self {
+        FileHasher {
+            piece_length,
+            length: 0,
+            buffer: Vec::with_capacity(BLOCK_SIZE),
+            leaves: Vec::new(),
+        }
+    }
+
+    pub fn update(&mut self, mut data: &[u8]) {
+        self.length += data.len();
+        while !data.is_empty() {
+            let take = (BLOCK_SIZE - self.buffer.len()).min(data.len());
+            self.buffer.extend_from_slice(&data[..take]);
+            data = &data[take..];
+            if self.buffer.len() == BLOCK_SIZE {
+                self.leaves.push(hash_block(&self.buffer));
+                self.buffer.clear();
+            }
+        }
+    }
+
+    pub fn finish(mut self) -> FileMerkle {
+        if !self.buffer.is_empty() {
+            self.leaves.push(hash_block(&self.buffer));
+        }
+
+        if self.length <= self.piece_length {
+            return FileMerkle {
+                length: self.length,
+                pieces_root: merkle_root(&self.leaves, 0),
+                piece_layer: Vec::new(),
+            };
+        }
+
+        let piece_level = blocks_per_piece(self.piece_length).trailing_zeros();
+        let piece_layer =
+            (0..piece_level).fold(self.leaves, |layer, level| reduce_layer(&layer, level));
+        FileMerkle {
+            length: self.length,
+            pieces_root: merkle_root(&piece_layer, piece_level),
+            piece_layer,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hash_file(data: &[u8], piece_length: usize) -> FileMerkle {
+        let mut hasher = FileHasher::new(piece_length);
+        for chunk in data.chunks(1000) {
+            hasher.update(chunk);
+        }
+        hasher.finish()
+    }
+
+    #[test]
+    fn test_empty_file_has_no_root() {
+        let merkle = hash_file(b"", BLOCK_SIZE);
+        assert_eq!(merkle.length, 0);
+        assert_eq!(merkle.pieces_root, None);
+        assert!(merkle.piece_layer.is_empty());
+    }
+
+    #[test]
+    fn test_single_block_root_is_block_hash() {
+        let merkle = hash_file(b"hello", BLOCK_SIZE);
+        assert_eq!(merkle.pieces_root, Some(hash_block(b"hello")));
+    }
+
+    #[test]
+    fn test_leaves_are_padded_with_zeros() {
+        let data = vec![1u8; BLOCK_SIZE * 2 + 10];
+        let merkle = hash_file(&data, BLOCK_SIZE * 4);
+
+        let a = hash_block(&data[..BLOCK_SIZE]);
+        let b = hash_block(&data[BLOCK_SIZE..BLOCK_SIZE * 2]);
+        let c = hash_block(&data[BLOCK_SIZE * 2..]);
+        let expected = hash_pair(&hash_pair(&a, &b), &hash_pair(&c, &[0; 32]));
+
+        assert_eq!(merkle.pieces_root, Some(expected));
+        assert!(merkle.piece_layer.is_empty());
+    }
+
+    #[test]
+    fn test_piece_layer_matches_root() {
+        let data: Vec<u8> = (0..BLOCK_SIZE * 5 + 7).map(|i| i as u8).collect();
+        let piece_length = BLOCK_SIZE * 2;
+        let merkle = hash_file(&data, piece_length);
+
+        assert_eq!(merkle.piece_layer.len(), 3);
+        assert!(verify_piece_layer(
+            &merkle.pieces_root.unwrap(),
+            &merkle.piece_layer,
+            piece_length
+        ));
+
+        let mut tampered = merkle.piece_layer.clone();
+        tampered[1][0] ^= 1;
+        assert!(!verify_piece_layer(
+            &merkle.pieces_root.unwrap(),
+            &tampered,
+            piece_length
+        ));
+    }
+
+    #[test]
+    fn test_root_is_independent_of_piece_length() {
+        let data: Vec<u8> = (0..BLOCK_SIZE * 9).map(|i| (i % 251) as u8).collect();
+        let small = hash_file(&data, BLOCK_SIZE);
+        let large = hash_file(&data, BLOCK_SIZE * 4);
+        assert_eq!(small.pieces_root, large.pieces_root);
+    }
+}
@@ -0,0 +1,114 @@
+use crate::merkle::Sha256Hash;
+use serde::{
+    de::{MapAccess, Visitor},
+    ser::SerializeMap,
+    Deserialize,
+    Deserializer,
+    Serialize,
+    Serializer,
+};
+use serde_with::{serde_as, Bytes};
+use std::collections::BTreeMap;
+
+/// The BEP 52 `piece layers` of a v2 torrent.
+/// Maps the pieces root of every file larger than a piece to the hashes of its piece layer.
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct PieceLayers(pub BTreeMap<Sha256Hash, Vec<Sha256Hash>>);
+
+#[serde_as]
+#[derive(Serialize, Deserialize)]
+struct Key(#[serde_as(as = "Bytes")] Sha256Hash);
+
+struct Layer<'a>(&'a [Sha256Hash]);
+
+impl Serialize for Layer<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_bytes(&self.0.concat())
+    }
+}
+
+struct OwnedLayer(Vec<Sha256Hash>);
+
+impl<'de> Deserialize<'de> for OwnedLayer {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        crate::pieces_bytes::deserialize(deserializer).map(OwnedLayer)
+    }
+}
+
+impl Serialize for PieceLayers {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut map = serializer.serialize_map(Some(self.0.len()))?;
+        for (root, layer) in &self.0 {
+            map.serialize_entry(&Key(*root), &Layer(layer))?;
+        }
+        map.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for PieceLayers {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_map(PieceLayersVisitor)
+    }
+}
+
+struct PieceLayersVisitor;
+
+impl<'de> Visitor<'de> for PieceLayersVisitor {
+    type Value = PieceLayers;
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(formatter, "a dictionary of piece layers")
+    }
+
+    fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        let mut layers = BTreeMap::new();
+        while let Some(Key(root)) = map.next_key()? {
+            let OwnedLayer(layer) = map.next_value()?;
+            layers.insert(root, layer);
+        }
+        Ok(PieceLayers(layers))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufReader, Cursor};
+    use tforge_bencode::{deserializer::from_reader, serializer::from_writer};
+
+    #[test]
+    fn test_bencode_piece_layers() {
+        let layers = PieceLayers(BTreeMap::from([([1; 32], vec![[2; 32], [3; 32]])]));
+
+        let mut buffer = Vec::new();
+        let mut writer = from_writer(&mut buffer);
+        layers.serialize(&mut writer).unwrap();
+
+        let mut expected = b"d32:".to_vec();
+        expected.extend_from_slice(&[1; 32]);
+        expected.extend_from_slice(b"64:");
+        expected.extend_from_slice(&[2; 32]);
+        expected.extend_from_slice(&[3; 32]);
+        expected.extend_from_slice(b"e");
+        assert_eq!(buffer, expected);
+
+        let mut reader = BufReader::new(Cursor::new(&buffer));
+        let decoded: PieceLayers = from_reader(&mut reader).unwrap();
+        assert_eq!(decoded, layers);
+    }
+}
@@ -0,0 +1,399 @@
+use crate::{
+    error::{Error, Result},
+    geometry::FileEntry,
+    hash::{self, Sha1Hash},
+    File,
+    FileAttributes,
+    FileInfo,
+    Info,
+    MetaInfo,
+};
+use std::{
+    fs,
+    io::{Read, Seek, SeekFrom},
+    ops::Range,
+    path::PathBuf,
+};
+
+/// Files to take from an existing torrent whose data is available locally
+pub struct RepieceSource<'a> {
+    pub meta_info: &'a MetaInfo,
+
+    /// Directory the data of the torrent is saved in, see [`Info::file_path`]
+    pub base: PathBuf,
+
+    /// Indices in [`Info::files`] of the files to keep, in the order of the new torrent.
+    /// Padding files are skipped.
+    pub files: Vec<usize>,
+}
+
+#[derive(Debug, Clone)]
+pub struct RepieceOptions {
+    /// Name of the new torrent
+    pub name: String,
+
+    /// Length of the pieces, defaults to the one of the first source.
+    /// Piece hashes can only be reused from sources with the same piece length.
+    pub piece_length: Option<usize>,
+
+    /// Aligns every file to a piece boundary with padding files (BEP 47)
+    pub pad_files: bool,
+
+    /// Marks the torrent as private (BEP 27)
+    pub private: bool,
+
+    /// Source tag of the new torrent
+    pub source: Option<String>,
+}
+
+/// How a re-pieced torrent was hashed
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct RepieceReport {
+    /// Pieces whose hash was taken from a source torrent
+    pub pieces_reused: usize,
+
+    /// Pieces that had to be read and hashed
+    pub pieces_hashed: usize,
+
+    /// Bytes read from disk to hash the boundary pieces
+    pub bytes_read: usize,
+}
+
+/// A file of the new torrent and where its data comes from
+struct Placement {
+    /// Index of the source and the file in it, `None` for padding files
+    origin: Option<(usize, FileEntry)>,
+
+    /// Range of the file in the data of the new torrent
+    range: Range<usize>,
+}
+
+/// Builds a v1 torrent out of files of existing torrents.
+///
+/// Pieces of the new torrent that cover exactly a piece of a source torrent
+/// keep its hash. Only the pieces straddling files that weren't contiguous in
+/// their source are read from disk and hashed again. The trackers of the
+/// first source are kept.
+pub fn repiece(
+    sources: &[RepieceSource],
+    options: &RepieceOptions,
+) -> Result<(MetaInfo, RepieceReport)> {
+    let first = sources
+        .first()
+        .ok_or_else(|| Error::from_invalid("no source torrent to re-piece"))?;
+    let piece_length = options
+        .piece_length
+        .unwrap_or(first.meta_info.info.piece_length);
+    if piece_length == 0 {
+        return Err(Error::from_invalid("piece length must not be zero"));
+    }
+
+    let source_files: Vec<Vec<FileEntry>> = sources
+        .iter()
+        .map(|source| source.meta_info.info.files())
+        .collect();
+
+    let mut placements = Vec::new();
+    let mut files = Vec::new();
+    let mut offset = 0;
+    for (source_index, source) in sources.iter().enumerate() {
+        for &file_index in &source.files {
+            let entry = source_files[source_index].get(file_index).ok_or_else(|| {
+                Error::from_invalid(format!(
+                    "source {} has no file {}",
+                    source_index, file_index
+                ))
+            })?;
+            if entry.is_padding() {
+                continue;
+            }
+
+            let remainder = offset % piece_length;
+            if options.pad_files && remainder != 0 {
+                let padding = piece_length - remainder;
+                files.push(File {
+                    attr: FileAttributes {
+                        padding: true,
+                        ..Default::default()
+                    }
+                    .to_attr(),
+                    length: padding,
+                    path: vec![".pad".into(), padding.to_string().into()],
+                    path_utf8: None,
+                    sha1: None,
+                    symlink_path: None,
+                });
+                placements.push(Placement {
+                    origin: None,
+                    range: offset..offset + padding,
+                });
+                offset += padding;
+            }
+
+            files.push(File {
+                attr: entry.attributes.to_attr(),
+                length: entry.length,
+                path: entry.path.clone(),
+                path_utf8: entry.path_utf8.clone(),
+                sha1: None,
+                symlink_path: entry.symlink_path.clone(),
+            });
+            placements.push(Placement {
+                origin: Some((source_index, entry.clone())),
+                range: offset..offset + entry.length,
+            });
+            offset += entry.length;
+        }
+    }
+    if files.is_empty() {
+        return Err(Error::from_invalid("no file selected"));
+    }
+
+    let total_length = offset;
+    let mut report = RepieceReport::default();
+    let mut pieces = Vec::new();
+    for start in (0..total_length).step_by(piece_length) {
+        let range = start..(start + piece_length).min(total_length);
+        let segments: Vec<(&Placement, Range<usize>)> = placements
+            .iter()
+            .filter_map(|placement| {
+                let start = range.start.max(placement.range.start);
+                let end = range.end.min(placement.range.end);
+                (start < end).then(|| (placement, start..end))
+            })
+            .collect();
+
+        match reusable_hash(sources, &source_files, &segments, piece_length) {
+            Some(hash) => {
+                pieces.push(hash);
+                report.pieces_reused += 1;
+            }
+            None => {
+                let piece = read_piece(sources, &segments, &mut report.bytes_read)?;
+                pieces.push(hash::sha1(&piece));
+                report.pieces_hashed += 1;
+            }
+        }
+    }
+
+    let info = Info {
+        file_tree: None,
+        file_info: Some(FileInfo::MultiFile { files }),
+        meta_version: None,
+        name: options.name.as_str().into(),
+        name_utf8: None,
+        piece_length,
+        pieces,
+        private: options.private.then_some(true),
+        source: options.source.clone(),
+    };
+    let mut meta_info = MetaInfo::new(first.meta_info.announce.clone(), info, None);
+    meta_info
+        .announce_list
+        .clone_from(&first.meta_info.announce_list);
+    Ok((meta_info, report))
+}
+
+/// The hash of a piece of a source torrent covering exactly the same data as the
+/// `segments` of a new piece, if there is one.
+fn reusable_hash(
+    sources: &[RepieceSource],
+    source_files: &[Vec<FileEntry>],
+    segments: &[(&Placement, Range<usize>)],
+    piece_length: usize,
+) -> Option<Sha1Hash> {
+    let ((first, first_range), rest) = segments.split_first()?;
+    let (source_index, entry) = first.origin.as_ref()?;
+    let info = &sources[*source_index].meta_info.info;
+    if info.piece_length != piece_length || info.file_info.is_none() {
+        return None;
+    }
+
+    let start = entry.offset + first_range.start - first.range.start;
+    let mut end = start + first_range.len();
+    for (placement, range) in rest {
+        match &placement.origin {
+            // The data must follow the previous segment in the same source
+            Some((index, entry)) => {
+                let position = entry.offset + range.start - placement.range.start;
+                if index != source_index || position != end {
+                    return None;
+                }
+            }
+            // New padding must match padding of the source
+            None => {
+                let files = &source_files[*source_index];
+                let covered: usize = info
+                    .slices(end..end + range.len())
+                    .iter()
+                    .filter(|slice| files[slice.file_index].is_padding())
+                    .map(|slice| slice.length)
+                    .sum();
+                if covered != range.len() {
+                    return None;
+                }
+            }
+        }
+        end += range.len();
+    }
+
+    let index = start / piece_length;
+    (start % piece_length == 0 && info.piece_range(index) == Some(start..end))
+        .then(|| info.pieces.get(index).copied())
+        .flatten()
+}
+
+/// Reads the data of a new piece from the files of the sources
+fn read_piece(
+    sources: &[RepieceSource],
+    segments: &[(&Placement, Range<usize>)],
+    bytes_read: &mut usize,
+) -> Result<Vec<u8>> {
+    let mut piece = Vec::new();
+    for (placement, range) in segments {
+        let position = piece.len();
+        piece.resize(position + range.len(), 0);
+        let Some((source_index, entry)) = &placement.origin else {
+            continue;
+        };
+        if !entry.attributes.is_on_disk() {
+            continue;
+        }
+
+        let source = &sources[*source_index];
+        let encoding = source.meta_info.encoding.as_deref();
+        let path = source
+            .meta_info
+            .info
+            .file_path(&source.base, entry, encoding);
+        let mut reader = fs::File::open(path)?;
+        reader.seek(SeekFrom::Start(
+            (range.start - placement.range.start) as u64,
+        ))?;
+        reader.read_exact(&mut piece[position..])?;
+        *bytes_read += range.len();
+    }
+    Ok(piece)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        create::{create_info, CreateOptions},
+        merkle::BLOCK_SIZE,
+        tests::create_test_dir,
+    };
+    use std::path::Path;
+
+    const FILES: &[(&str, usize)] = &[
+        ("a.bin", BLOCK_SIZE * 2 + 10),
+        ("b.bin", BLOCK_SIZE * 3),
+        ("c.bin", BLOCK_SIZE + 500),
+        ("d.bin", 700),
+    ];
+
+    fn torrent(dir: &Path, pad_files: bool) -> MetaInfo {
+        let options = CreateOptions {
+            piece_length: BLOCK_SIZE,
+            pad_files,
+            ..Default::default()
+        };
+        let (info, _) = create_info(dir, &options).unwrap();
+        MetaInfo::new("http://example.com/announce".to_string(), info, None)
+    }
+
+    fn options(pad_files: bool) -> RepieceOptions {
+        RepieceOptions {
+            name: "subset".to_string(),
+            piece_length: None,
+            pad_files,
+            private: false,
+            source: None,
+        }
+    }
+
+    #[test]
+    fn test_repiece_subset() {
+        let dir = create_test_dir("repiece", FILES);
+        let source = torrent(&dir, false);
+
+        // b.bin starts at an unaligned offset in the source, c.bin right after it
+        let sources = [RepieceSource {
+            meta_info: &source,
+            base: dir.parent().unwrap().to_path_buf(),
+            files: vec![1, 2],
+        }];
+        let (meta_info, report) = repiece(&sources, &options(false)).unwrap();
+        assert!(meta_info.validate().is_ok());
+
+        let expected_dir = create_test_dir("repiece-expected", &FILES[1..3]);
+        let expected = torrent(&expected_dir, false);
+        assert_eq!(meta_info.info.pieces, expected.info.pieces);
+        assert_eq!(report.pieces_reused, 0);
+        assert_eq!(report.pieces_hashed, expected.info.pieces.len());
+        assert_eq!(report.bytes_read, BLOCK_SIZE * 4 + 500);
+    }
+
+    #[test]
+    fn test_repiece_reuses_aligned_pieces() {
+        let dir = create_test_dir("repiece-aligned", FILES);
+        let source = torrent(&dir, true);
+
+        // Every file of the source starts on a piece boundary and is followed by padding
+        let files = source.info.files();
+        let index = |name: &str| files.iter().position(|f| f.path[0] == name).unwrap();
+        let sources = [RepieceSource {
+            meta_info: &source,
+            base: dir.parent().unwrap().to_path_buf(),
+            files: vec![index("a.bin"), index("c.bin"), index("d.bin")],
+        }];
+        let (meta_info, report) = repiece(&sources, &options(true)).unwrap();
+        assert!(meta_info.validate().is_ok());
+
+        let expected_dir =
+            create_test_dir("repiece-aligned-expected", &[FILES[0], FILES[2], FILES[3]]);
+        let expected = torrent(&expected_dir, true);
+        assert_eq!(meta_info.info.pieces, expected.info.pieces);
+        assert_eq!(report.pieces_reused, expected.info.pieces.len());
+        assert_eq!(report.pieces_hashed, 0);
+        assert_eq!(report.bytes_read, 0);
+    }
+
+    #[test]
+    fn test_repiece_merges_torrents() {
+        let dir = create_test_dir(
+            "repiece-merge",
+            &[("one/x.bin", BLOCK_SIZE * 2), ("two/y.bin", 100)],
+        );
+        let one = torrent(&dir.join("one/x.bin"), false);
+        let two = torrent(&dir.join("two/y.bin"), false);
+
+        let sources = [
+            RepieceSource {
+                meta_info: &one,
+                base: dir.join("one"),
+                files: vec![0],
+            },
+            RepieceSource {
+                meta_info: &two,
+                base: dir.join("two"),
+                files: vec![0],
+            },
+        ];
+        let (meta_info, report) = repiece(&sources, &options(false)).unwrap();
+        assert!(meta_info.validate().is_ok());
+        assert_eq!(meta_info.info.pieces[..2], one.info.pieces[..]);
+        assert_eq!(meta_info.info.pieces[2], two.info.pieces[0]);
+        assert_eq!(report.pieces_reused, 3);
+        assert_eq!(report.bytes_read, 0);
+
+        let paths: Vec<_> = meta_info
+            .info
+            .files()
+            .iter()
+            .map(|f| f.display_path(None))
+            .collect();
+        assert_eq!(paths, vec![vec!["x.bin"], vec!["y.bin"]]);
+    }
+}
@@ -0,0 +1,170 @@
+use crate::{
+    error::Result,
+    hash::{InfoHashes, Sha1Hash},
+    merkle::Sha256Hash,
+    optional_system_time,
+};
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, Bytes};
+use std::{
+    io::{BufReader, Cursor},
+    time::SystemTime,
+};
+use tforge_bencode::{deserializer::from_reader, serializer::from_writer};
+
+/// Default priority of a file, on the 0 (skip) to 7 (highest) scale of libtorrent
+pub const DEFAULT_PRIORITY: u8 = 4;
+
+/// What tforge remembers about a torrent between runs, stored bencoded
+#[serde_as]
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct ResumeState {
+    /// NOTE: All fields must be sorted alphabetically!
+
+    /// When the torrent was added
+    #[serde(rename = "added time", with = "optional_system_time")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub added_time: Option<SystemTime>,
+
+    /// Bytes downloaded over the lifetime of the torrent
+    pub downloaded: u64,
+
+    /// Priority of every file, from 0 (skip) to 7 (highest)
+    #[serde_as(as = "Bytes")]
+    #[serde(rename = "file priorities")]
+    pub file_priorities: Vec<u8>,
+
+    /// The pieces downloaded and verified, one bit per piece, most significant bit first
+    #[serde_as(as = "Bytes")]
+    pub have: Vec<u8>,
+
+    #[serde_as(as = "Option<Bytes>")]
+    #[serde(rename = "info hash")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub info_hash: Option<Sha1Hash>,
+
+    #[serde_as(as = "Option<Bytes>")]
+    #[serde(rename = "info hash v2")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub info_hash_v2: Option<Sha256Hash>,
+
+    /// Number of pieces of the torrent, the length of the `have` bitfield in bits
+    #[serde(rename = "piece count")]
+    pub piece_count: usize,
+
+    /// Directory the torrent data is saved in
+    #[serde(rename = "save path")]
+    pub save_path: String,
+
+    /// Tracker tiers, as in the `announce-list` of a torrent
+    pub trackers: Vec<Vec<String>>,
+
+    /// Bytes uploaded over the lifetime of the torrent
+    pub uploaded: u64,
+}
+
+impl ResumeState {
+    pub fn new(info_hashes: InfoHashes, piece_count: usize, save_path: String) -> Self {
+        ResumeState {
+            have: vec![0; piece_count.div_ceil(8)],
+            info_hash: info_hashes.v1,
+            info_hash_v2: info_hashes.v2,
+            piece_count,
+            save_path,
+            ..Default::default()
+        }
+    }
+
+    pub fn info_hashes(&self) -> InfoHashes {
+        InfoHashes {
+            v1: self.info_hash,
+            v2: self.info_hash_v2,
+        }
+    }
+
+    pub fn has_piece(&self, index: usize) -> bool {
+        index < self.piece_count
+            && self
+                .have
+                .get(index / 8)
+                .is_some_and(|byte| byte & (0x80 >> (index % 8)) != 0)
+    }
+
+    pub fn set_piece(&mut self, index: usize, have: bool) {
+        if index >= self.piece_count {
+            return;
+        }
+        self.have.resize(self.piece_count.div_ceil(8), 0);
+        match have {
+            true => self.have[index / 8] |= 0x80 >> (index % 8),
+            false => self.have[index / 8] &= !(0x80 >> (index % 8)),
+        }
+    }
+
+    /// Number of pieces downloaded and verified
+    pub fn completed_pieces(&self) -> usize {
+        (0..self.piece_count)
+            .filter(|&index| self.has_piece(index))
+            .count()
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.completed_pieces() == self.piece_count
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let mut reader = BufReader::new(Cursor::new(bytes));
+        Ok(from_reader(&mut reader)?)
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut buffer = Vec::new();
+        let mut writer = from_writer(&mut buffer);
+        self.serialize(&mut writer)?;
+        Ok(buffer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bitfield() {
+        let mut state = ResumeState::new(InfoHashes::default(), 10, "/data".to_string());
+        assert_eq!(state.have, vec![0, 0]);
+        state.set_piece(0, true);
+        state.set_piece(9, true);
+        state.set_piece(10, true);
+        assert_eq!(state.have, vec![0x80, 0x40]);
+        assert!(state.has_piece(9));
+        assert!(!state.has_piece(10));
+        assert_eq!(state.completed_pieces(), 2);
+
+        state.set_piece(0, false);
+        assert_eq!(state.have, vec![0x00, 0x40]);
+        assert!(!state.is_complete());
+    }
+
+    #[test]
+    fn test_bencode_roundtrip() {
+        let mut state = ResumeState::new(
+            InfoHashes {
+                v1: Some([1; 20]),
+                v2: None,
+            },
+            3,
+            "/data".to_string(),
+        );
+        state.set_piece(1, true);
+        state.added_time = Some(SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(100));
+        state.file_priorities = vec![4, 0];
+        state.trackers = vec![vec!["http://example.com/announce".to_string()]];
+        state.uploaded = 10;
+        state.downloaded = 20;
+
+        let bytes = state.to_bytes().unwrap();
+        assert!(bytes.starts_with(b"d10:added timei100e10:downloadedi20e15:file priorities2:"));
+        assert_eq!(ResumeState::from_bytes(&bytes).unwrap(), state);
+    }
+}
@@ -0,0 +1,159 @@
+use serde::{de::Visitor, Deserialize, Deserializer, Serialize, Serializer};
+
+/// A string of the metainfo kept as the raw bytes found in the torrent.
+///
+/// Old torrents store names in legacy encodings such as Shift-JIS or CP1251,
+/// so they can't be assumed to be UTF-8. Keeping the bytes also keeps the
+/// info-hash intact when the torrent is written back.
+#[derive(Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct RawString(pub Vec<u8>);
+
+impl RawString {
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// The string, if it is valid UTF-8
+    pub fn to_str(&self) -> Option<&str> {
+        std::str::from_utf8(&self.0).ok()
+    }
+
+    /// Best effort decoding, see [`decode`]
+    pub fn decode(&self, encoding: Option<&str>) -> String {
+        decode(&self.0, encoding)
+    }
+}
+
+/// Decodes bytes of the metainfo into a displayable string.
+///
+/// `encoding` is the top-level `encoding` field of the torrent. When it names a
+/// known legacy encoding, the bytes are decoded with it. Otherwise the bytes are
+/// read as UTF-8, replacing invalid sequences.
+pub fn decode(bytes: &[u8], encoding: Option<&str>) -> String {
+    let encoding = encoding
+        .and_then(|label| encoding_rs::Encoding::for_label(label.trim().as_bytes()))
+        .filter(|encoding| *encoding != encoding_rs::UTF_8);
+    match encoding {
+        Some(encoding) => encoding.decode_without_bom_handling(bytes).0.into_owned(),
+        None => String::from_utf8_lossy(bytes).into_owned(),
+    }
+}
+
+impl std::fmt::Display for RawString {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", String::from_utf8_lossy(&self.0))
+    }
+}
+
+impl std::fmt::Debug for RawString {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", String::from_utf8_lossy(&self.0))
+    }
+}
+
+impl From<&str> for RawString {
+    fn from(s: &str) -> Self {
+        RawString(s.as_bytes().to_vec())
+    }
+}
+
+impl From<String> for RawString {
+    fn from(s: String) -> Self {
+        RawString(s.into_bytes())
+    }
+}
+
+impl From<Vec<u8>> for RawString {
+    fn from(bytes: Vec<u8>) -> Self {
+        RawString(bytes)
+    }
+}
+
+impl PartialEq<str> for RawString {
+    fn eq(&self, other: &str) -> bool {
+        self.0 == other.as_bytes()
+    }
+}
+
+impl PartialEq<&str> for RawString {
+    fn eq(&self, other: &&str) -> bool {
+        self.0 == other.as_bytes()
+    }
+}
+
+impl Serialize for RawString {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_bytes(&self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for RawString {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_bytes(RawStringVisitor)
+    }
+}
+
+struct RawStringVisitor;
+
+impl<'de> Visitor<'de> for RawStringVisitor {
+    type Value = RawString;
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(formatter, "a byte string")
+    }
+
+    fn visit_bytes<E>(self, v: &[u8]) -> Result<Self::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        Ok(RawString(v.to_vec()))
+    }
+
+    fn visit_byte_buf<E>(self, v: Vec<u8>) -> Result<Self::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        Ok(RawString(v))
+    }
+
+    fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        Ok(RawString::from(v))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_utf8() {
+        assert_eq!(decode("héllo".as_bytes(), None), "héllo");
+        assert_eq!(decode("héllo".as_bytes(), Some("UTF-8")), "héllo");
+        assert_eq!(decode(b"h\xffllo", None), "h\u{fffd}llo");
+    }
+
+    #[test]
+    fn test_decode_legacy_encodings() {
+        // "テスト" in Shift-JIS
+        assert_eq!(
+            decode(b"\x83\x65\x83\x58\x83\x67", Some("Shift_JIS")),
+            "テスト"
+        );
+        // "Тест" in CP1251
+        assert_eq!(decode(b"\xd2\xe5\xf1\xf2", Some("cp1251")), "Тест");
+    }
+
+    #[test]
+    fn test_unknown_encoding_falls_back_to_utf8() {
+        assert_eq!(decode(b"abc", Some("not-an-encoding")), "abc");
+    }
+}
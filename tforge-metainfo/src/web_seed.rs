@@ -0,0 +1,216 @@
+use crate::{geometry::FileSlice, text::RawString, Info};
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+use tforge_urlencoded::value::Value;
+
+/// The BEP 19 `url-list`, either a single URL or a list of URLs
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+#[serde(untagged)]
+pub enum UrlList {
+    Single(String),
+    Multiple(Vec<String>),
+}
+
+impl UrlList {
+    /// The web seed URLs, skipping the empty strings some tools write
+    pub fn urls(&self) -> Vec<&str> {
+        match self {
+            UrlList::Single(url) => vec![url.as_str()],
+            UrlList::Multiple(urls) => urls.iter().map(String::as_str).collect(),
+        }
+        .into_iter()
+        .filter(|url| !url.is_empty())
+        .collect()
+    }
+}
+
+impl From<Vec<String>> for UrlList {
+    fn from(mut urls: Vec<String>) -> Self {
+        if urls.len() == 1 {
+            UrlList::Single(urls.remove(0))
+        } else {
+            UrlList::Multiple(urls)
+        }
+    }
+}
+
+/// A ranged HTTP GET to fetch part of a file from a web seed
+#[derive(Debug, Clone, PartialEq)]
+pub struct WebSeedRequest {
+    /// Index of the file in [`Info::files`]
+    pub file_index: usize,
+
+    /// URL of the file on the web seed
+    pub url: String,
+
+    /// Range of bytes to fetch within the file
+    pub range: Range<usize>,
+}
+
+impl WebSeedRequest {
+    /// The value of the HTTP `Range` header, whose bounds are inclusive
+    pub fn range_header(&self) -> String {
+        format!("bytes={}-{}", self.range.start, self.range.end - 1)
+    }
+}
+
+/// The URL of a file on a BEP 19 web seed.
+///
+/// For single file torrents the URL points at the file itself, unless it ends
+/// with a slash, in which case the name of the torrent is appended. For multi
+/// file torrents the URL is the parent directory of the torrent directory.
+pub fn file_url(base: &str, info: &Info, file_index: usize) -> Option<String> {
+    let files = info.files();
+    let file = files.get(file_index)?;
+
+    if info.is_single_file() {
+        return Some(match base.ends_with('/') {
+            true => format!("{}{}", base, escape(&info.name)),
+            false => base.to_string(),
+        });
+    }
+
+    let mut url = base.to_string();
+    if !url.ends_with('/') {
+        url.push('/');
+    }
+    url.push_str(&escape(&info.name));
+    for component in &file.path {
+        url.push('/');
+        url.push_str(&escape(component));
+    }
+    Some(url)
+}
+
+/// The requests needed to fetch a whole file from a web seed
+pub fn file_request(base: &str, info: &Info, file_index: usize) -> Option<WebSeedRequest> {
+    let file = info.files().get(file_index)?.clone();
+    let length = file.length;
+    let url = file_url(base, info, file_index)?;
+    (length > 0 && file.attributes.is_on_disk()).then_some(WebSeedRequest {
+        file_index,
+        url,
+        range: 0..length,
+    })
+}
+
+/// The requests needed to fetch a piece from a web seed, one per file the piece spans.
+/// Padding files are zero filled locally and never requested.
+pub fn piece_requests(base: &str, info: &Info, piece: usize) -> Vec<WebSeedRequest> {
+    let files = info.files();
+    info.piece_slices(piece)
+        .into_iter()
+        .filter(|slice| files[slice.file_index].attributes.is_on_disk())
+        .filter_map(|slice| slice_request(base, info, slice))
+        .collect()
+}
+
+fn slice_request(base: &str, info: &Info, slice: FileSlice) -> Option<WebSeedRequest> {
+    Some(WebSeedRequest {
+        url: file_url(base, info, slice.file_index)?,
+        range: slice.offset..slice.offset + slice.length,
+        file_index: slice.file_index,
+    })
+}
+
+/// Percent-encodes a path component, keeping its raw bytes when it isn't UTF-8
+fn escape(component: &RawString) -> String {
+    match component.to_str() {
+        Some(component) => Value::from(component).to_string(),
+        None => Value::from(component.as_bytes()).to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{File, FileInfo};
+
+    fn info(file_info: FileInfo) -> Info {
+        Info {
+            file_tree: None,
+            file_info: Some(file_info),
+            meta_version: None,
+            name: "my torrent".into(),
+            name_utf8: None,
+            piece_length: 16,
+            pieces: Vec::new(),
+            private: None,
+            source: None,
+        }
+    }
+
+    #[test]
+    fn test_single_file_url() {
+        let info = info(FileInfo::SingleFile { length: 40 });
+        assert_eq!(
+            file_url("http://example.com/file.iso", &info, 0),
+            Some("http://example.com/file.iso".to_string())
+        );
+        assert_eq!(
+            file_url("http://example.com/files/", &info, 0),
+            Some("http://example.com/files/my%20torrent".to_string())
+        );
+        assert_eq!(file_url("http://example.com/files/", &info, 1), None);
+    }
+
+    #[test]
+    fn test_multi_file_piece_requests() {
+        let info = info(FileInfo::MultiFile {
+            files: vec![
+                File {
+                    attr: None,
+                    length: 10,
+                    path: vec!["a.txt".into()],
+                    path_utf8: None,
+                    sha1: None,
+                    symlink_path: None,
+                },
+                File {
+                    attr: None,
+                    length: 30,
+                    path: vec!["sub dir".into(), "b.txt".into()],
+                    path_utf8: None,
+                    sha1: None,
+                    symlink_path: None,
+                },
+            ],
+        });
+
+        let requests = piece_requests("http://example.com/seed", &info, 0);
+        assert_eq!(
+            requests,
+            vec![
+                WebSeedRequest {
+                    file_index: 0,
+                    url: "http://example.com/seed/my%20torrent/a.txt".to_string(),
+                    range: 0..10,
+                },
+                WebSeedRequest {
+                    file_index: 1,
+                    url: "http://example.com/seed/my%20torrent/sub%20dir/b.txt".to_string(),
+                    range: 0..6,
+                },
+            ]
+        );
+        assert_eq!(requests[1].range_header(), "bytes=0-5");
+
+        let requests = piece_requests("http://example.com/seed/", &info, 2);
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].range, 22..30);
+        assert!(piece_requests("http://example.com/seed/", &info, 3).is_empty());
+    }
+
+    #[test]
+    fn test_url_list_urls() {
+        assert_eq!(
+            UrlList::Single("http://a".to_string()).urls(),
+            vec!["http://a"]
+        );
+        assert!(UrlList::Single(String::new()).urls().is_empty());
+        assert_eq!(
+            UrlList::Multiple(vec!["http://a".to_string(), "http://b".to_string()]).urls(),
+            vec!["http://a", "http://b"]
+        );
+    }
+}
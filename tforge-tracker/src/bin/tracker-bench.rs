@@ -0,0 +1,169 @@
+//! Drives the tracker server in-process, announcing from several threads to
+//! swarms filled beforehand, and prints the announces it handled per second.
+//!
+//! Usage: `tracker-bench [--torrents N] [--peers N] [--threads N] [--seconds N]`,
+//! `--peers` being the peers of each torrent.
+
+use rand::Rng;
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+use tforge_config::ServerConfig;
+use tforge_tracker::{
+    protocol::{TrackerRequest, TrackerResponse},
+    server::{
+        swarm::{Peer, PeerRecord},
+        Tracker,
+    },
+};
+
+struct Options {
+    torrents: usize,
+    peers: usize,
+    threads: usize,
+    seconds: u64,
+}
+
+fn options() -> Result<Options, String> {
+    let mut options = Options {
+        torrents: 10_000,
+        peers: 100,
+        threads: thread::available_parallelism().map_or(4, |threads| threads.get()),
+        seconds: 10,
+    };
+    let mut args = std::env::args().skip(1);
+    while let Some(name) = args.next() {
+        let value = args
+            .next()
+            .ok_or_else(|| format!("missing value of {}", name))?;
+        let value: usize = value
+            .parse()
+            .map_err(|_| format!("invalid value of {}: {}", name, value))?;
+        match name.as_str() {
+            "--torrents" => options.torrents = value.max(1),
+            "--peers" => options.peers = value.max(1),
+            "--threads" => options.threads = value.max(1),
+            "--seconds" => options.seconds = value as u64,
+            _ => return Err(format!("unknown option {}", name)),
+        }
+    }
+    Ok(options)
+}
+
+fn info_hash(torrent: usize) -> [u8; 20] {
+    let mut info_hash = [0; 20];
+    info_hash[..8].copy_from_slice(&(torrent as u64).to_be_bytes());
+    info_hash
+}
+
+fn peer_id(peer: usize) -> [u8; 20] {
+    let mut peer_id = *b"-TF0001-000000000000";
+    peer_id[12..].copy_from_slice(&(peer as u64).to_be_bytes());
+    peer_id
+}
+
+/// A distinct IPv4 address for every peer of a torrent
+fn addr(peer: usize) -> SocketAddr {
+    let ip = IpAddr::from((0x0a00_0000 + peer as u32).to_be_bytes());
+    SocketAddr::new(ip, 6881)
+}
+
+fn main() {
+    let options = match options() {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(2);
+        }
+    };
+    let tracker = Arc::new(Tracker::new(ServerConfig::default()));
+
+    let start = Instant::now();
+    let storage = tracker.storage();
+    for torrent in 0..options.torrents {
+        for peer in 0..options.peers {
+            let record = Peer {
+                addr: addr(peer),
+                uploaded: 0,
+                downloaded: 0,
+                left: (peer % 4) as u64,
+                tracker_id: 0,
+                last_seen: SystemTime::now(),
+                connectable: true,
+            };
+            storage
+                .upsert_peer(&info_hash(torrent), &peer_id(peer), record)
+                .unwrap();
+        }
+    }
+    let peers = options.torrents * options.peers;
+    println!(
+        "filled {} torrents with {} peers in {:.2?}, {} MiB of peer records",
+        options.torrents,
+        peers,
+        start.elapsed(),
+        (peers * std::mem::size_of::<PeerRecord>()) >> 20
+    );
+
+    let duration = Duration::from_secs(options.seconds);
+    let threads: Vec<_> = (0..options.threads)
+        .map(|_| {
+            let tracker = tracker.clone();
+            let (torrents, peers) = (options.torrents, options.peers);
+            thread::spawn(move || {
+                let mut rng = rand::thread_rng();
+                let (mut announces, mut returned) = (0u64, 0u64);
+                let start = Instant::now();
+                while start.elapsed() < duration {
+                    // Checking the clock every announce would weigh on the measure
+                    for _ in 0..1000 {
+                        let peer = rng.gen_range(0..peers);
+                        let request = TrackerRequest {
+                            info_hash: info_hash(rng.gen_range(0..torrents)),
+                            peer_id: peer_id(peer),
+                            port: 6881,
+                            uploaded: 0,
+                            downloaded: 0,
+                            left: peer % 4,
+                            compact: true,
+                            event: None,
+                            ip: None,
+                            numwant: None,
+                            no_peer_id: true,
+                            key: None,
+                            trackerid: None,
+                        };
+                        match tracker.announce(&request, addr(peer).ip(), None) {
+                            TrackerResponse::Success(success) => {
+                                returned += success.peers.len() as u64
+                            }
+                            TrackerResponse::Failure(failure) => {
+                                panic!("announce failed: {}", failure.failure_reason)
+                            }
+                        }
+                        announces += 1;
+                    }
+                }
+                (announces, returned)
+            })
+        })
+        .collect();
+
+    let (mut announces, mut returned) = (0, 0);
+    for thread in threads {
+        let (thread_announces, thread_returned) = thread.join().unwrap();
+        announces += thread_announces;
+        returned += thread_returned;
+    }
+    println!(
+        "{} threads: {} announces in {:?}, {:.0} announces/s, {:.1} peers returned on average",
+        options.threads,
+        announces,
+        duration,
+        announces as f64 / duration.as_secs_f64(),
+        returned as f64 / announces.max(1) as f64
+    );
+}
@@ -0,0 +1,442 @@
+use super::{udp::UdpTrackerClient, TrackerClient};
+use crate::{
+    error::{Error, Result},
+    protocol::{
+        compact::{self, CompactPeers},
+        TrackerPeers,
+        TrackerRequest,
+        TrackerResponseSuccess,
+    },
+};
+use rand::seq::SliceRandom;
+use std::{collections::HashSet, net::SocketAddr, time::Duration};
+use tokio::time::Instant;
+
+/// Wait before retrying a tracker after its first failure, doubled on every other one
+pub const BACKOFF_BASE: Duration = Duration::from_secs(15);
+
+/// Longest wait before retrying a failing tracker
+pub const BACKOFF_MAX: Duration = Duration::from_secs(30 * 60);
+
+/// A client of an HTTP or UDP tracker, chosen by the scheme of its announce URL
+pub enum AnyTrackerClient {
+    Http(TrackerClient),
+    Udp(UdpTrackerClient),
+}
+
+impl AnyTrackerClient {
+    pub fn new(announce_url: String) -> Result<Self> {
+        match announce_url.split_once("://") {
+            Some(("http" | "https", _)) => Ok(Self::Http(TrackerClient::new(announce_url, None))),
+            Some(("udp", _)) => UdpTrackerClient::new(announce_url).map(Self::Udp),
+            _ => Err(Error::from_invalid(format!(
+                "unsupported tracker: {}",
+                announce_url
+            ))),
+        }
+    }
+
+    pub fn announce_url(&self) -> &str {
+        match self {
+            Self::Http(client) => client.announce_url(),
+            Self::Udp(client) => client.announce_url(),
+        }
+    }
+
+    pub async fn announce(&self, request: &TrackerRequest) -> Result<TrackerResponseSuccess> {
+        match self {
+            Self::Http(client) => client.announce(request).await,
+            Self::Udp(client) => client.announce(request).await,
+        }
+    }
+}
+
+/// What the manager remembers about a tracker between announces
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TrackerState {
+    /// When the tracker wants the next announce, or may be retried after a failure
+    pub next_announce: Option<Instant>,
+
+    /// The `min interval` of the last response
+    pub min_interval: Option<Duration>,
+
+    /// Why the last announce failed, cleared by a successful one
+    pub last_error: Option<String>,
+
+    /// The `tracker id` to send back
+    pub tracker_id: Option<String>,
+
+    /// Consecutive failures, the retry delay doubles with each of them
+    pub failures: u32,
+}
+
+impl TrackerState {
+    /// Whether the tracker failed recently and must not be retried yet
+    pub fn is_backing_off(&self, now: Instant) -> bool {
+        self.failures > 0 && self.next_announce.is_some_and(|next| now < next)
+    }
+
+    fn succeeded(&mut self, response: &TrackerResponseSuccess, now: Instant) {
+        self.next_announce = Some(now + Duration::from_secs(response.interval as u64));
+        self.min_interval = response
+            .min_interval
+            .map(|secs| Duration::from_secs(secs as u64));
+        self.last_error = None;
+        if response.tracker_id.is_some() {
+            self.tracker_id.clone_from(&response.tracker_id);
+        }
+        self.failures = 0;
+    }
+
+    fn failed(&mut self, err: &Error, now: Instant) {
+        self.failures += 1;
+        self.last_error = Some(err.to_string());
+        self.next_announce = Some(now + backoff(self.failures));
+    }
+}
+
+/// `BACKOFF_BASE·2ⁿ⁻¹` after `n` failures, at most `BACKOFF_MAX`
+fn backoff(failures: u32) -> Duration {
+    let factor = 2u32.saturating_pow(failures.saturating_sub(1));
+    BACKOFF_BASE.saturating_mul(factor).min(BACKOFF_MAX)
+}
+
+struct ManagedTracker {
+    client: AnyTrackerClient,
+    state: TrackerState,
+}
+
+impl ManagedTracker {
+    async fn announce(&mut self, request: &TrackerRequest) -> Result<TrackerResponseSuccess> {
+        let mut request = request.clone();
+        if request.trackerid.is_none() {
+            request.trackerid.clone_from(&self.state.tracker_id);
+        }
+        let result = self.client.announce(&request).await;
+        match &result {
+            Ok(response) => self.state.succeeded(response, Instant::now()),
+            Err(err) => self.state.failed(err, Instant::now()),
+        }
+        result
+    }
+}
+
+/// Announces to the tiers of trackers of a torrent (BEP 12).
+///
+/// Each tier is shuffled once. Its trackers are tried in order and the one
+/// that answers moves to the front of the tier, the next tier being tried
+/// only when all of them failed. Trackers that failed are skipped until their
+/// backoff has passed.
+pub struct TrackerManager {
+    tiers: Vec<Vec<ManagedTracker>>,
+    announce_to_all_tiers: bool,
+    next_announce: Option<Instant>,
+}
+
+impl TrackerManager {
+    /// A manager of `tiers` of announce URLs. Unsupported URLs are left out.
+    pub fn new(tiers: Vec<Vec<String>>) -> Self {
+        let mut rng = rand::thread_rng();
+        let tiers = tiers
+            .into_iter()
+            .map(|tier| {
+                let mut tier: Vec<_> = tier
+                    .into_iter()
+                    .filter_map(|url| AnyTrackerClient::new(url).ok())
+                    .map(|client| ManagedTracker {
+                        client,
+                        state: TrackerState::default(),
+                    })
+                    .collect();
+                tier.shuffle(&mut rng);
+                tier
+            })
+            .filter(|tier| !tier.is_empty())
+            .collect();
+        TrackerManager {
+            tiers,
+            announce_to_all_tiers: false,
+            next_announce: None,
+        }
+    }
+
+    /// The manager of a torrent: its `announce-list` when present, otherwise its `announce`
+    pub fn from_announce(announce: String, announce_list: Option<Vec<Vec<String>>>) -> Self {
+        match announce_list {
+            Some(tiers) if tiers.iter().any(|tier| !tier.is_empty()) => TrackerManager::new(tiers),
+            _ => TrackerManager::new(vec![vec![announce]]),
+        }
+    }
+
+    /// Announces to a tracker of every tier instead of the first one that
+    /// answers, merging their peers. Meant for public swarms.
+    pub fn with_announce_to_all_tiers(mut self, announce_to_all_tiers: bool) -> Self {
+        self.announce_to_all_tiers = announce_to_all_tiers;
+        self
+    }
+
+    /// Sends the announces of the HTTP trackers with `client`, e.g. one
+    /// trusting an internal CA made by [`super::http_client`]
+    pub fn with_http_client(mut self, client: reqwest::Client) -> Self {
+        for tracker in self.tiers.iter_mut().flatten() {
+            if let AnyTrackerClient::Http(http) = &mut tracker.client {
+                *http = TrackerClient::new(http.announce_url().to_string(), Some(client.clone()));
+            }
+        }
+        self
+    }
+
+    /// The announce URLs and states of the trackers, tier by tier, in the order they are tried
+    pub fn trackers(&self) -> Vec<Vec<(&str, &TrackerState)>> {
+        self.tiers
+            .iter()
+            .map(|tier| {
+                tier.iter()
+                    .map(|tracker| (tracker.client.announce_url(), &tracker.state))
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// When to announce next: the interval of the trackers that answered the
+    /// last announce, or the first retry when none did
+    pub fn next_announce(&self) -> Option<Instant> {
+        self.next_announce
+    }
+
+    /// Announces to the first tracker that answers, or to one tracker per tier
+    /// when announcing to all tiers.
+    ///
+    /// Fails with the last error when no tracker answered.
+    pub async fn announce(&mut self, request: &TrackerRequest) -> Result<TrackerResponseSuccess> {
+        let mut responses = Vec::new();
+        let mut last_error = None;
+        if self.announce_to_all_tiers {
+            let tiers = self
+                .tiers
+                .iter_mut()
+                .map(|tier| announce_tier(tier, request));
+            for result in futures::future::join_all(tiers).await.into_iter().flatten() {
+                match result {
+                    Ok(response) => responses.push(response),
+                    Err(err) => last_error = Some(err),
+                }
+            }
+        } else {
+            for tier in &mut self.tiers {
+                match announce_tier(tier, request).await {
+                    Some(Ok(response)) => {
+                        responses.push(response);
+                        break;
+                    }
+                    Some(Err(err)) => last_error = Some(err),
+                    None => {}
+                }
+            }
+        }
+
+        self.next_announce = self.schedule(!responses.is_empty());
+        match responses.len() {
+            0 => Err(last_error.unwrap_or(Error::NoTracker)),
+            1 => Ok(responses.remove(0)),
+            _ => Ok(merge(responses)),
+        }
+    }
+
+    fn schedule(&self, answered: bool) -> Option<Instant> {
+        let states = self.tiers.iter().flatten().map(|tracker| &tracker.state);
+        match answered {
+            // Trackers that answered are at the front of their tier
+            true => self
+                .tiers
+                .iter()
+                .map(|tier| &tier[0].state)
+                .filter(|state| state.failures == 0)
+                .filter_map(|state| state.next_announce)
+                .min(),
+            false => states.filter_map(|state| state.next_announce).min(),
+        }
+    }
+}
+
+/// Announces to the trackers of a tier in order, until one answers.
+/// `None` when all of them are backing off.
+async fn announce_tier(
+    tier: &mut [ManagedTracker],
+    request: &TrackerRequest,
+) -> Option<Result<TrackerResponseSuccess>> {
+    let now = Instant::now();
+    let mut result = None;
+    for index in 0..tier.len() {
+        if tier[index].state.is_backing_off(now) {
+            continue;
+        }
+        match tier[index].announce(request).await {
+            Ok(response) => {
+                tier[..=index].rotate_right(1);
+                return Some(Ok(response));
+            }
+            Err(err) => result = Some(Err(err)),
+        }
+    }
+    result
+}
+
+/// Combines the responses of several tiers: their peers without duplicates,
+/// the largest counts and the shortest interval. Peers given by DNS name are
+/// left out.
+fn merge(responses: Vec<TrackerResponseSuccess>) -> TrackerResponseSuccess {
+    let mut merged = TrackerResponseSuccess {
+        interval: usize::MAX,
+        ..Default::default()
+    };
+    let mut seen = HashSet::new();
+    let mut peers = Vec::new();
+    let mut peers6 = Vec::new();
+    for response in responses {
+        merged.complete = merged.complete.max(response.complete);
+        merged.incomplete = merged.incomplete.max(response.incomplete);
+        merged.interval = merged.interval.min(response.interval);
+        merged.min_interval = merged.min_interval.max(response.min_interval);
+        for peer in response.peer_addrs() {
+            match peer {
+                SocketAddr::V4(addr) if seen.insert(peer) => peers.push(addr),
+                SocketAddr::V6(addr) if seen.insert(peer) => peers6.push(addr),
+                _ => {}
+            }
+        }
+        merged.warning_message = merged.warning_message.or(response.warning_message);
+    }
+    merged.peers = TrackerPeers::Compact(CompactPeers::from(compact::encode_v4(&peers)));
+    merged.peers6 = (!peers6.is_empty()).then(|| CompactPeers::from(compact::encode_v6(&peers6)));
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        protocol::TrackerEvent,
+        server::{http, Tracker},
+    };
+    use std::sync::Arc;
+    use tforge_config::ServerConfig;
+    use tokio::net::TcpListener;
+
+    /// An announce URL nothing listens on
+    const DEAD: &str = "http://127.0.0.1:1/announce";
+
+    async fn start() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/announce", listener.local_addr().unwrap());
+        let tracker = Arc::new(Tracker::new(ServerConfig::default()));
+        tokio::spawn(http::serve(listener, tracker));
+        url
+    }
+
+    fn request(peer_id: u8) -> TrackerRequest {
+        TrackerRequest {
+            info_hash: [0xcc; 20],
+            peer_id: [peer_id; 20],
+            port: 6880 + peer_id as u16,
+            uploaded: 0,
+            downloaded: 0,
+            left: 100,
+            compact: true,
+            event: Some(TrackerEvent::Started),
+            ip: None,
+            numwant: None,
+            no_peer_id: false,
+            key: None,
+            trackerid: None,
+        }
+    }
+
+    #[test]
+    fn test_backoff() {
+        assert_eq!(backoff(1), BACKOFF_BASE);
+        assert_eq!(backoff(2), BACKOFF_BASE * 2);
+        assert_eq!(backoff(4), BACKOFF_BASE * 8);
+        assert_eq!(backoff(100), BACKOFF_MAX);
+    }
+
+    #[test]
+    fn test_from_announce() {
+        let manager = TrackerManager::from_announce(
+            "http://a.org/announce".to_string(),
+            Some(vec![
+                vec!["udp://b.org:6969".to_string(), "wss://c.org".to_string()],
+                vec![],
+            ]),
+        );
+        let trackers = manager.trackers();
+        assert_eq!(trackers.len(), 1);
+        assert_eq!(trackers[0][0].0, "udp://b.org:6969");
+
+        let manager = TrackerManager::from_announce("http://a.org/announce".to_string(), None);
+        assert_eq!(manager.trackers()[0][0].0, "http://a.org/announce");
+    }
+
+    #[tokio::test]
+    async fn test_fall_through_and_promote() {
+        let working = start().await;
+        let mut manager = TrackerManager::new(vec![
+            vec![DEAD.to_string()],
+            vec![DEAD.replace(":1/", ":2/"), working.clone()],
+        ]);
+        let response = manager.announce(&request(1)).await.unwrap();
+        assert_eq!(response.incomplete, 1);
+
+        let trackers = manager.trackers();
+        assert_eq!(trackers[0][0].1.failures, 1);
+        assert!(trackers[0][0].1.last_error.is_some());
+        assert_eq!(trackers[1][0].0, working);
+        assert!(trackers[1][0].1.tracker_id.is_some());
+        assert_eq!(
+            trackers[1][0].1.min_interval,
+            Some(Duration::from_secs(900))
+        );
+        let next = manager.next_announce().unwrap();
+        assert!(next > Instant::now() + Duration::from_secs(1700));
+
+        // The dead tracker of the first tier is backing off and skipped
+        manager.announce(&request(1)).await.unwrap();
+        assert_eq!(manager.trackers()[0][0].1.failures, 1);
+    }
+
+    #[tokio::test]
+    async fn test_all_fail() {
+        let mut manager = TrackerManager::new(vec![vec![DEAD.to_string()]]);
+        assert!(matches!(
+            manager.announce(&request(1)).await,
+            Err(Error::Http(_))
+        ));
+        let next = manager.next_announce().unwrap();
+        assert!(next <= Instant::now() + BACKOFF_BASE);
+        assert!(matches!(
+            manager.announce(&request(1)).await,
+            Err(Error::NoTracker)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_announce_to_all_tiers() {
+        let (first, second) = (start().await, start().await);
+        TrackerClient::new(first.clone(), None)
+            .announce(&request(1))
+            .await
+            .unwrap();
+        TrackerClient::new(second.clone(), None)
+            .announce(&request(2))
+            .await
+            .unwrap();
+
+        let mut manager = TrackerManager::new(vec![vec![first], vec![DEAD.to_string(), second]])
+            .with_announce_to_all_tiers(true);
+        let response = manager.announce(&request(3)).await.unwrap();
+        let mut ports: Vec<_> = response.peer_addrs().iter().map(SocketAddr::port).collect();
+        ports.sort();
+        assert_eq!(ports, vec![6881, 6882]);
+        assert_eq!(response.incomplete, 2);
+    }
+}
@@ -1,5 +1,116 @@
-use crate::protocol::{TrackerRequest, TrackerResponse};
-use anyhow::Result;
+pub mod manager;
+pub mod udp;
+
+use crate::{
+    error::{Error, Result},
+    protocol::{
+        scrape::{scrape_url, ScrapeRequest, ScrapeResponse},
+        TrackerRequest,
+        TrackerResponse,
+        TrackerResponseFailure,
+        TrackerResponseSuccess,
+    },
+};
+use reqwest::{Certificate, StatusCode};
+use rustls::{
+    client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    crypto::{self, CryptoProvider},
+    pki_types::{CertificateDer, ServerName, UnixTime},
+    CertificateError,
+    DigitallySignedStruct,
+    SignatureScheme,
+};
+use std::{path::Path, sync::Arc};
+use tforge_config::ClientTlsConfig;
+
+/// An HTTP client trusting the certificates of `tls`: the CA bundle besides
+/// the system roots, or the pinned certificates alone
+pub fn http_client(tls: &ClientTlsConfig) -> Result<reqwest::Client> {
+    let read = |path: &Path| {
+        std::fs::read(path).map_err(|err| Error::from_tls(format!("{}: {}", path.display(), err)))
+    };
+    let mut builder = reqwest::Client::builder();
+    if let Some(path) = &tls.ca_bundle {
+        let certificates = Certificate::from_pem_bundle(&read(path)?)
+            .map_err(|err| Error::from_tls(format!("{}: {}", path.display(), err)))?;
+        for certificate in certificates {
+            builder = builder.add_root_certificate(certificate);
+        }
+    }
+    if let Some(path) = &tls.pinned_certificate {
+        let pinned = rustls_pemfile::certs(&mut read(path)?.as_slice())
+            .collect::<std::result::Result<Vec<_>, _>>()
+            .map_err(|err| Error::from_tls(format!("{}: {}", path.display(), err)))?;
+        if pinned.is_empty() {
+            return Err(Error::from_tls(format!(
+                "{}: no certificate",
+                path.display()
+            )));
+        }
+        let verifier = PinnedVerifier {
+            pinned,
+            provider: Arc::new(crypto::ring::default_provider()),
+        };
+        let config = rustls::ClientConfig::builder()
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(verifier))
+            .with_no_client_auth();
+        builder = builder.use_preconfigured_tls(config);
+    }
+    builder.build().map_err(Error::from_http)
+}
+
+/// Accepts the servers whose own certificate is one of `pinned`, whatever
+/// signed it and whatever names it has, and only them
+#[derive(Debug)]
+struct PinnedVerifier {
+    pinned: Vec<CertificateDer<'static>>,
+    provider: Arc<CryptoProvider>,
+}
+
+impl ServerCertVerifier for PinnedVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer,
+        _intermediates: &[CertificateDer],
+        _server_name: &ServerName,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> std::result::Result<ServerCertVerified, rustls::Error> {
+        match self.pinned.iter().any(|pinned| pinned == end_entity) {
+            true => Ok(ServerCertVerified::assertion()),
+            false => Err(rustls::Error::InvalidCertificate(
+                CertificateError::ApplicationVerificationFailure,
+            )),
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer,
+        signature: &DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, rustls::Error> {
+        let algorithms = &self.provider.signature_verification_algorithms;
+        crypto::verify_tls12_signature(message, cert, signature, algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer,
+        signature: &DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, rustls::Error> {
+        let algorithms = &self.provider.signature_verification_algorithms;
+        crypto::verify_tls13_signature(message, cert, signature, algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
 
 pub struct TrackerClient {
     announce_url: String,
@@ -13,6 +124,10 @@ impl TrackerClient {
             client: client.unwrap_or_default(),
         }
     }
+
+    pub fn announce_url(&self) -> &str {
+        &self.announce_url
+    }
 }
 
 impl PartialEq for TrackerClient {
@@ -22,10 +137,171 @@ impl PartialEq for TrackerClient {
 }
 
 impl TrackerClient {
-    pub async fn announce(&self, request: TrackerRequest) -> Result<TrackerResponse> {
-        let req = self.client.get(self.announce_url.clone()).query(&request);
-        let response = req.send().await?;
-        let json = response.json::<TrackerResponse>().await?;
-        Ok(json)
+    /// Announces to the tracker.
+    ///
+    /// A `failure reason` in the reply is returned as [`Error::Failure`]. A
+    /// `warning message` leaves the response usable, it is kept in
+    /// [`TrackerResponseSuccess::warning_message`] for the caller to show.
+    pub async fn announce(&self, request: &TrackerRequest) -> Result<TrackerResponseSuccess> {
+        let (status, body) = self.get(&self.announce_url, &request.to_query()).await?;
+
+        // Trackers may send their failure reason along with an error status
+        match TrackerResponse::from_bytes(&body) {
+            Ok(TrackerResponse::Failure(failure)) => {
+                Err(Error::from_failure(failure.failure_reason))
+            }
+            Ok(TrackerResponse::Success(_)) | Err(_) if !status.is_success() => {
+                Err(Error::from_status(status.as_u16()))
+            }
+            Ok(TrackerResponse::Success(success)) => Ok(success),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Scrapes the tracker, at the URL derived from the announce URL by
+    /// [`scrape_url`]. An empty request asks for every torrent, which trackers
+    /// may refuse.
+    pub async fn scrape(&self, request: &ScrapeRequest) -> Result<ScrapeResponse> {
+        let url = scrape_url(&self.announce_url).ok_or(Error::ScrapeUnsupported)?;
+        let (status, body) = self.get(&url, &request.to_query()).await?;
+        if let Ok(failure) = TrackerResponseFailure::from_bytes(&body) {
+            return Err(Error::from_failure(failure.failure_reason));
+        }
+        if !status.is_success() {
+            return Err(Error::from_status(status.as_u16()));
+        }
+        ScrapeResponse::from_bytes(&body)
+    }
+
+    async fn get(&self, url: &str, query: &str) -> Result<(StatusCode, Vec<u8>)> {
+        let separator = match url.contains('?') {
+            true => '&',
+            false => '?',
+        };
+        let url = format!("{}{}{}", url, separator, query);
+        let response = self.client.get(url).send().await?;
+        let status = response.status();
+        Ok((status, response.bytes().await?.to_vec()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{TrackerEvent, TrackerPeers};
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    /// Serves a single HTTP request with `body`, returning the URL to request
+    /// and the request line the server received
+    async fn serve_once(
+        status: &'static str,
+        body: &'static [u8],
+    ) -> (String, tokio::task::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/announce", listener.local_addr().unwrap());
+        let handle = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = vec![0; 4096];
+            let read = stream.read(&mut request).await.unwrap();
+            let request = String::from_utf8_lossy(&request[..read]).to_string();
+            let header = format!(
+                "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                status,
+                body.len()
+            );
+            stream.write_all(header.as_bytes()).await.unwrap();
+            stream.write_all(body).await.unwrap();
+            request.lines().next().unwrap_or_default().to_string()
+        });
+        (url, handle)
+    }
+
+    fn request() -> TrackerRequest {
+        TrackerRequest {
+            info_hash: [0xab; 20],
+            peer_id: *b"-TF0001-123456789012",
+            port: 6881,
+            uploaded: 0,
+            downloaded: 0,
+            left: 100,
+            compact: true,
+            event: Some(TrackerEvent::Started),
+            ip: None,
+            numwant: Some(50),
+            no_peer_id: false,
+            key: None,
+            trackerid: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_announce_compact() {
+        let (url, server) = serve_once(
+            "200 OK",
+            b"d8:intervali1800e5:peers6:\x0a\x00\x00\x01\x1a\xe115:warning message4:slowe",
+        )
+        .await;
+        let client = TrackerClient::new(url, None);
+        let response = client.announce(&request()).await.unwrap();
+        assert_eq!(response.interval, 1800);
+        assert_eq!(response.peers.len(), 1);
+        assert!(matches!(response.peers, TrackerPeers::Compact(_)));
+        assert_eq!(response.warning_message.as_deref(), Some("slow"));
+
+        let request_line = server.await.unwrap();
+        assert!(request_line.starts_with("GET /announce?info_hash=%AB%AB"));
+        assert!(request_line.contains("&compact=1&event=started&numwant=50 "));
+    }
+
+    #[tokio::test]
+    async fn test_announce_failure() {
+        let (url, _) = serve_once(
+            "200 OK",
+            b"d14:failure reason17:torrent not found8:intervali60ee",
+        )
+        .await;
+        let client = TrackerClient::new(url, None);
+        match client.announce(&request()).await {
+            Err(Error::Failure(reason)) => assert_eq!(reason, "torrent not found"),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_scrape() {
+        let (url, server) = serve_once(
+            "200 OK",
+            b"d5:filesd20:\xab\xab\xab\xab\xab\xab\xab\xab\xab\xab\xab\xab\xab\xab\xab\xab\xab\xab\xab\xab\
+              d8:completei2e10:downloadedi7e10:incompletei3eeee",
+        )
+        .await;
+        let client = TrackerClient::new(format!("{}?passkey=abc", url), None);
+        let request = ScrapeRequest {
+            info_hashes: vec![[0xab; 20]],
+        };
+        let response = client.scrape(&request).await.unwrap();
+        assert_eq!(response.files[&[0xab; 20]].downloaded, 7);
+
+        let request_line = server.await.unwrap();
+        assert!(request_line.starts_with("GET /scrape?passkey=abc&info_hash=%AB%AB"));
+
+        let client = TrackerClient::new("http://127.0.0.1:1/tracker".to_string(), None);
+        assert!(matches!(
+            client.scrape(&request).await,
+            Err(Error::ScrapeUnsupported)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_announce_error_status() {
+        let (url, _) = serve_once("503 Service Unavailable", b"busy").await;
+        let client = TrackerClient::new(url, None);
+        assert!(matches!(
+            client.announce(&request()).await,
+            Err(Error::Status(503))
+        ));
     }
 }
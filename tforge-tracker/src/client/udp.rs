@@ -0,0 +1,450 @@
+use crate::{
+    error::{Error, Result},
+    protocol::{
+        compact::{self, CompactPeers},
+        udp::{UdpAnnounceRequest, UdpRequest, UdpResponse, UdpScrapeStats, MAX_SCRAPE_HASHES},
+        TrackerPeers,
+        TrackerRequest,
+        TrackerResponseSuccess,
+    },
+};
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Mutex,
+    time::Duration,
+};
+use tokio::{
+    net::UdpSocket,
+    time::{timeout_at, Instant},
+};
+
+/// How long a connection ID may be used after it was received (BEP 15)
+pub const CONNECTION_ID_LIFETIME: Duration = Duration::from_secs(60);
+
+/// Timeout of the first attempt, doubled on every retransmit: 15·2ⁿ seconds
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Retransmits after the first attempt, the last one waiting 15·2⁸ seconds
+pub const DEFAULT_MAX_RETRIES: u32 = 8;
+
+/// A client of a `udp://` tracker (BEP 15)
+pub struct UdpTrackerClient {
+    announce_url: String,
+
+    /// `host:port` of the tracker
+    address: String,
+
+    /// Path and query of the announce URL, sent as BEP 41 options
+    url_data: Vec<u8>,
+
+    timeout: Duration,
+    max_retries: u32,
+    connection: Mutex<Option<Connection>>,
+}
+
+struct Connection {
+    id: u64,
+    server: SocketAddr,
+    expires: Instant,
+}
+
+impl UdpTrackerClient {
+    pub fn new(announce_url: String) -> Result<Self> {
+        let rest = announce_url
+            .strip_prefix("udp://")
+            .ok_or_else(|| Error::from_invalid(format!("not a UDP tracker: {}", announce_url)))?;
+        let (address, url_data) = rest.split_at(rest.find(['/', '?']).unwrap_or(rest.len()));
+        Ok(Self {
+            address: address.to_string(),
+            url_data: url_data.as_bytes().to_vec(),
+            announce_url,
+            timeout: DEFAULT_TIMEOUT,
+            max_retries: DEFAULT_MAX_RETRIES,
+            connection: Mutex::new(None),
+        })
+    }
+
+    /// Changes the timeout of the first attempt and the number of retransmits
+    pub fn with_retransmit(mut self, timeout: Duration, max_retries: u32) -> Self {
+        self.timeout = timeout;
+        self.max_retries = max_retries;
+        self
+    }
+
+    pub fn announce_url(&self) -> &str {
+        &self.announce_url
+    }
+
+    /// Announces to the tracker. An error packet is returned as [`Error::Failure`].
+    pub async fn announce(&self, request: &TrackerRequest) -> Result<TrackerResponseSuccess> {
+        let build = |connection_id, transaction_id| {
+            UdpRequest::Announce(UdpAnnounceRequest {
+                connection_id,
+                transaction_id,
+                info_hash: request.info_hash,
+                peer_id: request.peer_id,
+                downloaded: request.downloaded as u64,
+                left: request.left as u64,
+                uploaded: request.uploaded as u64,
+                event: request.event,
+                ip: match request.ip {
+                    Some(IpAddr::V4(ip)) => ip,
+                    _ => Ipv4Addr::UNSPECIFIED,
+                },
+                key: request.key.as_deref().map_or(0, udp_key),
+                num_want: request
+                    .numwant
+                    .map_or(-1, |numwant| numwant.min(i32::MAX as usize) as i32),
+                port: request.port,
+                url_data: self.url_data.clone(),
+            })
+        };
+
+        let (response, server) = self.send(build).await?;
+        let UdpResponse::Announce(announce) = response else {
+            return Err(Error::from_invalid("expected an announce response"));
+        };
+
+        let mut peers = Vec::new();
+        let mut peers6 = Vec::new();
+        for peer in announce.peers {
+            match peer {
+                SocketAddr::V4(peer) => peers.push(peer),
+                SocketAddr::V6(peer) => peers6.push(peer),
+            }
+        }
+        Ok(TrackerResponseSuccess {
+            complete: announce.seeders as usize,
+            incomplete: announce.leechers as usize,
+            interval: announce.interval as usize,
+            peers: TrackerPeers::Compact(CompactPeers::from(compact::encode_v4(&peers))),
+            peers6: server
+                .is_ipv6()
+                .then(|| CompactPeers::from(compact::encode_v6(&peers6))),
+            ..Default::default()
+        })
+    }
+
+    /// Scrapes up to 74 torrents, returning their stats in the same order
+    pub async fn scrape(&self, info_hashes: &[[u8; 20]]) -> Result<Vec<UdpScrapeStats>> {
+        if info_hashes.is_empty() || info_hashes.len() > MAX_SCRAPE_HASHES {
+            return Err(Error::from_invalid(format!(
+                "a scrape takes 1 to {} info-hashes",
+                MAX_SCRAPE_HASHES
+            )));
+        }
+        let build = |connection_id, transaction_id| UdpRequest::Scrape {
+            connection_id,
+            transaction_id,
+            info_hashes: info_hashes.to_vec(),
+        };
+        match self.send(build).await? {
+            (UdpResponse::Scrape { stats, .. }, _) if stats.len() == info_hashes.len() => Ok(stats),
+            _ => Err(Error::from_invalid("expected a scrape response")),
+        }
+    }
+
+    /// Sends a request built from a connection ID and a transaction ID,
+    /// connecting first if no connection ID is cached for the tracker
+    async fn send(
+        &self,
+        build: impl Fn(u64, u32) -> UdpRequest,
+    ) -> Result<(UdpResponse, SocketAddr)> {
+        let server = tokio::net::lookup_host(&self.address)
+            .await?
+            .next()
+            .ok_or_else(|| Error::from_invalid(format!("{} has no address", self.address)))?;
+        let local: SocketAddr = match server {
+            SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+            SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+        };
+        let socket = UdpSocket::bind(local).await?;
+        socket.connect(server).await?;
+
+        let mut attempt = 0;
+        loop {
+            let connection_id = match self.cached_connection(server) {
+                Some(id) => id,
+                None => {
+                    let connect = UdpRequest::Connect {
+                        transaction_id: rand::random(),
+                    };
+                    match self.exchange(&socket, server, &connect, attempt).await? {
+                        Some(UdpResponse::Connect { connection_id, .. }) => {
+                            *self.connection.lock().unwrap() = Some(Connection {
+                                id: connection_id,
+                                server,
+                                expires: Instant::now() + CONNECTION_ID_LIFETIME,
+                            });
+                            connection_id
+                        }
+                        Some(UdpResponse::Error { message, .. }) => {
+                            return Err(Error::from_failure(message))
+                        }
+                        Some(_) => return Err(Error::from_invalid("expected a connect response")),
+                        None => {
+                            attempt += 1;
+                            continue;
+                        }
+                    }
+                }
+            };
+
+            let request = build(connection_id, rand::random());
+            match self.exchange(&socket, server, &request, attempt).await? {
+                Some(UdpResponse::Error { message, .. }) => {
+                    // The connection ID may be the reason, don't reuse it
+                    *self.connection.lock().unwrap() = None;
+                    return Err(Error::from_failure(message));
+                }
+                Some(response) => return Ok((response, server)),
+                None => attempt += 1,
+            }
+        }
+    }
+
+    fn cached_connection(&self, server: SocketAddr) -> Option<u64> {
+        self.connection
+            .lock()
+            .unwrap()
+            .as_ref()
+            .filter(|connection| connection.server == server && connection.expires > Instant::now())
+            .map(|connection| connection.id)
+    }
+
+    /// Sends a request and waits 15·2ⁿ seconds for its response, `None` when it timed out
+    async fn exchange(
+        &self,
+        socket: &UdpSocket,
+        server: SocketAddr,
+        request: &UdpRequest,
+        attempt: u32,
+    ) -> Result<Option<UdpResponse>> {
+        if attempt > self.max_retries {
+            return Err(Error::Timeout);
+        }
+        let deadline = Instant::now() + self.timeout * 2u32.pow(attempt);
+        socket.send(&request.to_bytes()).await?;
+
+        let mut buffer = vec![0; 65536];
+        loop {
+            let received = match timeout_at(deadline, socket.recv(&mut buffer)).await {
+                Ok(received) => received?,
+                Err(_) => return Ok(None),
+            };
+            // Late answers to earlier attempts and garbage are ignored
+            match UdpResponse::from_bytes(&buffer[..received], server.is_ipv6()) {
+                Ok(response) if response.transaction_id() == request.transaction_id() => {
+                    return Ok(Some(response))
+                }
+                _ => continue,
+            }
+        }
+    }
+}
+
+/// The HTTP `key` is free-form while UDP carries 32 bits:
+/// hexadecimal keys are kept as is, others are hashed
+fn udp_key(key: &str) -> u32 {
+    match u32::from_str_radix(key, 16) {
+        Ok(key) => key,
+        Err(_) => key.bytes().fold(0x811c9dc5, |hash, byte| {
+            (hash ^ byte as u32).wrapping_mul(0x01000193)
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{udp::UdpAnnounceResponse, TrackerEvent};
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    /// A tracker answering every request with `handler`, which can drop it by returning `None`
+    async fn stand_in<F>(address: &str, mut handler: F) -> SocketAddr
+    where
+        F: FnMut(UdpRequest) -> Option<UdpResponse> + Send + 'static,
+    {
+        let socket = UdpSocket::bind(address).await.unwrap();
+        let address = socket.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buffer = vec![0; 2048];
+            loop {
+                let (received, peer) = socket.recv_from(&mut buffer).await.unwrap();
+                let request = UdpRequest::from_bytes(&buffer[..received]).unwrap();
+                if let Some(response) = handler(request) {
+                    let bytes = response.to_bytes(peer.is_ipv6());
+                    socket.send_to(&bytes, peer).await.unwrap();
+                }
+            }
+        });
+        address
+    }
+
+    fn request() -> TrackerRequest {
+        TrackerRequest {
+            info_hash: [1; 20],
+            peer_id: [2; 20],
+            port: 6881,
+            uploaded: 0,
+            downloaded: 0,
+            left: 10,
+            compact: true,
+            event: Some(TrackerEvent::Started),
+            ip: None,
+            numwant: None,
+            no_peer_id: false,
+            key: Some("cafe".to_string()),
+            trackerid: None,
+        }
+    }
+
+    fn announce_response(transaction_id: u32, peers: &[&str]) -> UdpResponse {
+        UdpResponse::Announce(UdpAnnounceResponse {
+            transaction_id,
+            interval: 1800,
+            leechers: 1,
+            seeders: 2,
+            peers: peers.iter().map(|peer| peer.parse().unwrap()).collect(),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_announce_retransmits_and_caches_connection() {
+        let connects = Arc::new(AtomicUsize::new(0));
+        let counter = connects.clone();
+        let address = stand_in("127.0.0.1:0", move |request| match request {
+            UdpRequest::Connect { transaction_id } => {
+                // The first connect is lost
+                match counter.fetch_add(1, Ordering::SeqCst) {
+                    0 => None,
+                    _ => Some(UdpResponse::Connect {
+                        transaction_id,
+                        connection_id: 0xfeed,
+                    }),
+                }
+            }
+            UdpRequest::Announce(announce) => {
+                assert_eq!(announce.connection_id, 0xfeed);
+                assert_eq!(announce.url_data, b"/announce?passkey=abc");
+                assert_eq!(announce.key, 0xcafe);
+                assert_eq!(announce.num_want, -1);
+                Some(announce_response(
+                    announce.transaction_id,
+                    &["10.0.0.1:6881", "10.0.0.2:6882"],
+                ))
+            }
+            UdpRequest::Scrape { .. } => None,
+        })
+        .await;
+
+        let url = format!("udp://{}/announce?passkey=abc", address);
+        let client = UdpTrackerClient::new(url)
+            .unwrap()
+            .with_retransmit(Duration::from_millis(50), 2);
+        for _ in 0..2 {
+            let response = client.announce(&request()).await.unwrap();
+            assert_eq!(response.interval, 1800);
+            assert_eq!((response.complete, response.incomplete), (2, 1));
+            assert_eq!(response.peer_addrs().len(), 2);
+            assert_eq!(response.peers6, None);
+        }
+        assert_eq!(connects.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_announce_ipv6() {
+        let address = stand_in("[::1]:0", |request| match request {
+            UdpRequest::Connect { transaction_id } => Some(UdpResponse::Connect {
+                transaction_id,
+                connection_id: 1,
+            }),
+            UdpRequest::Announce(announce) => {
+                assert!(announce.url_data.is_empty());
+                Some(announce_response(
+                    announce.transaction_id,
+                    &["10.0.0.1:6881", "[2001:db8::1]:6881"],
+                ))
+            }
+            UdpRequest::Scrape { .. } => None,
+        })
+        .await;
+
+        let client = UdpTrackerClient::new(format!("udp://{}", address)).unwrap();
+        let response = client.announce(&request()).await.unwrap();
+        assert!(response.peers.is_empty());
+        assert_eq!(
+            response.peer_addrs(),
+            vec!["[2001:db8::1]:6881".parse::<SocketAddr>().unwrap()]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_scrape_and_error() {
+        let address = stand_in("127.0.0.1:0", |request| match request {
+            UdpRequest::Connect { transaction_id } => Some(UdpResponse::Connect {
+                transaction_id,
+                connection_id: 1,
+            }),
+            UdpRequest::Announce(announce) => Some(UdpResponse::Error {
+                transaction_id: announce.transaction_id,
+                message: "unregistered torrent".to_string(),
+            }),
+            UdpRequest::Scrape {
+                transaction_id,
+                info_hashes,
+                ..
+            } => Some(UdpResponse::Scrape {
+                transaction_id,
+                stats: info_hashes
+                    .iter()
+                    .map(|hash| UdpScrapeStats {
+                        seeders: hash[0] as u32,
+                        completed: 5,
+                        leechers: 1,
+                    })
+                    .collect(),
+            }),
+        })
+        .await;
+
+        let client = UdpTrackerClient::new(format!("udp://{}/announce", address)).unwrap();
+        let stats = client.scrape(&[[3; 20], [4; 20]]).await.unwrap();
+        assert_eq!(stats[0].seeders, 3);
+        assert_eq!(stats[1].seeders, 4);
+
+        match client.announce(&request()).await {
+            Err(Error::Failure(message)) => assert_eq!(message, "unregistered torrent"),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(client.scrape(&[]).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_timeout() {
+        let received = Arc::new(AtomicUsize::new(0));
+        let counter = received.clone();
+        let address = stand_in("127.0.0.1:0", move |_| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            None
+        })
+        .await;
+
+        let client = UdpTrackerClient::new(format!("udp://{}", address))
+            .unwrap()
+            .with_retransmit(Duration::from_millis(10), 2);
+        assert!(matches!(
+            client.announce(&request()).await,
+            Err(Error::Timeout)
+        ));
+        assert_eq!(received.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn test_invalid_url() {
+        assert!(UdpTrackerClient::new("http://tracker.example.com/announce".to_string()).is_err());
+    }
+}
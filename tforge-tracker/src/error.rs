@@ -0,0 +1,111 @@
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug)]
+pub enum Error {
+    IO(std::io::Error),
+    Http(reqwest::Error),
+    Status(u16),
+    Bencode(tforge_bencode::error::Error),
+    Invalid(String),
+    Request(String),
+    Failure(String),
+    Timeout,
+    ScrapeUnsupported,
+    NoTracker,
+    Storage(String),
+    IpList(String),
+    Tls(String),
+}
+
+impl Error {
+    pub fn from_io(err: std::io::Error) -> Self {
+        Error::IO(err)
+    }
+
+    pub fn from_http(err: reqwest::Error) -> Self {
+        Error::Http(err)
+    }
+
+    pub fn from_status(status: u16) -> Self {
+        Error::Status(status)
+    }
+
+    pub fn from_bencode(err: tforge_bencode::error::Error) -> Self {
+        Error::Bencode(err)
+    }
+
+    pub fn from_invalid(err: impl Into<String>) -> Self {
+        Error::Invalid(err.into())
+    }
+
+    /// A request the tracker server can't serve, the message is sent back as the failure reason
+    pub fn from_request(err: impl Into<String>) -> Self {
+        Error::Request(err.into())
+    }
+
+    /// The tracker server couldn't read or write its storage
+    pub fn from_storage(err: impl Into<String>) -> Self {
+        Error::Storage(err.into())
+    }
+
+    /// An IP list file the tracker server couldn't parse
+    pub fn from_ip_list(err: impl Into<String>) -> Self {
+        Error::IpList(err.into())
+    }
+
+    /// A certificate, key or CA bundle that couldn't be loaded
+    pub fn from_tls(err: impl Into<String>) -> Self {
+        Error::Tls(err.into())
+    }
+
+    /// The tracker refused the request, with a human-readable reason
+    pub fn from_failure(reason: impl Into<String>) -> Self {
+        Error::Failure(reason.into())
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Error::IO(err) => write!(f, "IO error: {}", err),
+            Error::Http(err) => write!(f, "HTTP error: {}", err),
+            Error::Status(status) => write!(f, "HTTP status {}", status),
+            Error::Bencode(err) => write!(f, "Bencode error: {}", err),
+            Error::Invalid(err) => write!(f, "Invalid tracker response: {}", err),
+            Error::Request(err) => write!(f, "Invalid request: {}", err),
+            Error::Failure(reason) => write!(f, "Tracker failure: {}", reason),
+            Error::Timeout => write!(f, "Tracker timed out"),
+            Error::ScrapeUnsupported => write!(f, "Tracker does not support scrape"),
+            Error::NoTracker => write!(f, "No tracker available"),
+            Error::Storage(err) => write!(f, "Storage error: {}", err),
+            Error::IpList(err) => write!(f, "IP list error: {}", err),
+            Error::Tls(err) => write!(f, "TLS error: {}", err),
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        Error::from_io(err)
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(err: reqwest::Error) -> Self {
+        Error::from_http(err)
+    }
+}
+
+impl From<tforge_bencode::error::Error> for Error {
+    fn from(err: tforge_bencode::error::Error) -> Self {
+        Error::from_bencode(err)
+    }
+}
+
+impl From<rusqlite::Error> for Error {
+    fn from(err: rusqlite::Error) -> Self {
+        Error::from_storage(err.to_string())
+    }
+}
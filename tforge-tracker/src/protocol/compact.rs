@@ -0,0 +1,171 @@
+//! Compact peer lists: 6 bytes per IPv4 peer (BEP 23) and 18 bytes per IPv6 peer (BEP 7),
+//! the address followed by the port, both in network byte order.
+
+use std::{
+    cell::RefCell,
+    fmt,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddrV4, SocketAddrV6},
+};
+
+pub const PEER_V4_LEN: usize = 6;
+pub const PEER_V6_LEN: usize = 18;
+
+/// Most buffers a thread keeps for its next compact peer strings
+const SPARE_BUFFERS: usize = 64;
+
+thread_local! {
+    /// The buffers of the compact peer strings a thread dropped
+    static SPARE: RefCell<Vec<Vec<u8>>> = const { RefCell::new(Vec::new()) };
+}
+
+/// A compact peer string as it is sent, IPv4 peers or IPv6 ones.
+///
+/// Its buffer goes back to the thread which drops it and serves the next
+/// string built there, so that a tracker writes the addresses of its
+/// responses without allocating for each.
+#[derive(Default, Clone, PartialEq, Eq)]
+pub struct CompactPeers {
+    bytes: Vec<u8>,
+}
+
+impl CompactPeers {
+    /// An empty string, in a spare buffer of the thread when there is one
+    pub fn new() -> Self {
+        let bytes = SPARE
+            .with_borrow_mut(|spare| spare.pop())
+            .unwrap_or_default();
+        CompactPeers { bytes }
+    }
+
+    /// Appends a peer already in the compact layout
+    pub fn push(&mut self, peer: &[u8]) {
+        self.bytes.extend_from_slice(peer);
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+}
+
+impl From<Vec<u8>> for CompactPeers {
+    fn from(bytes: Vec<u8>) -> Self {
+        CompactPeers { bytes }
+    }
+}
+
+impl Drop for CompactPeers {
+    fn drop(&mut self) {
+        let mut bytes = std::mem::take(&mut self.bytes);
+        if bytes.capacity() == 0 {
+            return;
+        }
+        bytes.clear();
+        SPARE.with_borrow_mut(|spare| {
+            if spare.len() < SPARE_BUFFERS {
+                spare.push(bytes);
+            }
+        });
+    }
+}
+
+impl fmt::Debug for CompactPeers {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "CompactPeers({} bytes)", self.bytes.len())
+    }
+}
+
+pub fn encode_v4(peers: &[SocketAddrV4]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(peers.len() * PEER_V4_LEN);
+    for peer in peers {
+        bytes.extend_from_slice(&peer.ip().octets());
+        bytes.extend_from_slice(&peer.port().to_be_bytes());
+    }
+    bytes
+}
+
+pub fn encode_v6(peers: &[SocketAddrV6]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(peers.len() * PEER_V6_LEN);
+    for peer in peers {
+        bytes.extend_from_slice(&peer.ip().octets());
+        bytes.extend_from_slice(&peer.port().to_be_bytes());
+    }
+    bytes
+}
+
+/// Returns `None` if the length is not a multiple of 6
+pub fn decode_v4(bytes: &[u8]) -> Option<Vec<SocketAddrV4>> {
+    if bytes.len() % PEER_V4_LEN != 0 {
+        return None;
+    }
+    let peers = bytes
+        .chunks_exact(PEER_V4_LEN)
+        .map(|peer| {
+            let ip: [u8; 4] = peer[..4].try_into().unwrap();
+            let port = u16::from_be_bytes([peer[4], peer[5]]);
+            SocketAddrV4::new(Ipv4Addr::from(ip), port)
+        })
+        .collect();
+    Some(peers)
+}
+
+/// Returns `None` if the length is not a multiple of 18
+pub fn decode_v6(bytes: &[u8]) -> Option<Vec<SocketAddrV6>> {
+    if bytes.len() % PEER_V6_LEN != 0 {
+        return None;
+    }
+    let peers = bytes
+        .chunks_exact(PEER_V6_LEN)
+        .map(|peer| {
+            let ip: [u8; 16] = peer[..16].try_into().unwrap();
+            let port = u16::from_be_bytes([peer[16], peer[17]]);
+            SocketAddrV6::new(Ipv6Addr::from(ip), port, 0, 0)
+        })
+        .collect();
+    Some(peers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compact_v4() {
+        let peers = vec![
+            SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 1), 6881),
+            SocketAddrV4::new(Ipv4Addr::new(192, 168, 1, 2), 51413),
+        ];
+        let bytes = encode_v4(&peers);
+        assert_eq!(bytes[..6], [10, 0, 0, 1, 0x1a, 0xe1]);
+        assert_eq!(decode_v4(&bytes), Some(peers));
+        assert_eq!(decode_v4(&bytes[..5]), None);
+    }
+
+    #[test]
+    fn test_compact_v6() {
+        let peers = vec![SocketAddrV6::new(Ipv6Addr::LOCALHOST, 6881, 0, 0)];
+        let bytes = encode_v6(&peers);
+        assert_eq!(bytes.len(), 18);
+        assert_eq!(bytes[15..], [1, 0x1a, 0xe1]);
+        assert_eq!(decode_v6(&bytes), Some(peers));
+        assert_eq!(decode_v6(&bytes[..17]), None);
+    }
+
+    #[test]
+    fn test_compact_peers_reuse() {
+        let mut peers = CompactPeers::new();
+        peers.push(&[10, 0, 0, 1, 0x1a, 0xe1]);
+        assert_eq!(peers.as_bytes(), [10, 0, 0, 1, 0x1a, 0xe1]);
+        let buffer = peers.as_bytes().as_ptr();
+        drop(peers);
+
+        // The next string of the thread is written in the same buffer
+        let mut peers = CompactPeers::new();
+        assert!(peers.is_empty());
+        peers.push(&[10, 0, 0, 2, 0x1a, 0xe2]);
+        assert_eq!(peers.as_bytes().as_ptr(), buffer);
+    }
+}
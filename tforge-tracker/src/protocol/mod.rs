@@ -1,8 +1,27 @@
-use serde::{Deserialize, Serialize};
-use serde_with::{serde_as, BoolFromInt};
+use crate::error::{Error, Result};
+use serde::{
+    de::{self, DeserializeOwned, Error as _, SeqAccess, Visitor},
+    Deserialize,
+    Deserializer,
+    Serialize,
+    Serializer,
+};
+use serde_with::{serde_as, BoolFromInt, Bytes};
+use std::{
+    io::{BufReader, Cursor},
+    net::SocketAddr,
+};
+use tforge_bencode::{deserializer::from_reader, raw::RawValue, serializer::from_writer};
+use tforge_urlencoded::value::{decode_bytes, Value};
+
+pub mod compact;
+pub mod scrape;
+pub mod udp;
+
+use compact::CompactPeers;
 
 #[serde_as]
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct TrackerRequest {
     /// urlencoded 20-byte SHA1 hash of the value of the info key from the Metainfo file.
     pub info_hash: [u8; 20],
@@ -38,6 +57,11 @@ pub struct TrackerRequest {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub numwant: Option<usize>,
 
+    /// Indicates that the tracker can omit the peer id field in the peers dictionary. Ignored when compact is set.
+    #[serde_as(as = "BoolFromInt")]
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub no_peer_id: bool,
+
     ///  An additional identification that is not shared with any other peers. It is intended to allow a client to prove their identity should their IP address change.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub key: Option<String>,
@@ -47,7 +71,7 @@ pub struct TrackerRequest {
     pub trackerid: Option<String>,
 }
 
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub enum TrackerEvent {
     #[serde(rename = "started")]
     Started,
@@ -57,13 +81,188 @@ pub enum TrackerEvent {
     Stopped,
 }
 
-#[derive(PartialEq, Debug, Serialize, Deserialize)]
+impl TrackerEvent {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TrackerEvent::Started => "started",
+            TrackerEvent::Completed => "completed",
+            TrackerEvent::Stopped => "stopped",
+        }
+    }
+}
+
+impl TrackerRequest {
+    /// The query string of the announce, with the hashes percent-encoded byte by byte
+    pub fn to_query(&self) -> String {
+        let mut query = vec![
+            ("info_hash", Value::from(&self.info_hash[..])),
+            ("peer_id", Value::from(&self.peer_id[..])),
+            ("port", Value::from(self.port as u64)),
+            ("uploaded", Value::from(self.uploaded as u64)),
+            ("downloaded", Value::from(self.downloaded as u64)),
+            ("left", Value::from(self.left as u64)),
+            ("compact", Value::from(self.compact as u64)),
+        ];
+        if let Some(event) = &self.event {
+            query.push(("event", Value::from(event.as_str())));
+        }
+        if let Some(ip) = &self.ip {
+            query.push(("ip", Value::from(ip.to_string())));
+        }
+        if let Some(numwant) = self.numwant {
+            query.push(("numwant", Value::from(numwant as u64)));
+        }
+        if self.no_peer_id {
+            query.push(("no_peer_id", Value::from(1u64)));
+        }
+        if let Some(key) = &self.key {
+            query.push(("key", Value::from(key.as_str())));
+        }
+        if let Some(trackerid) = &self.trackerid {
+            query.push(("trackerid", Value::from(trackerid.as_str())));
+        }
+
+        query
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect::<Vec<_>>()
+            .join("&")
+    }
+
+    /// Parses the query string of an announce, the counterpart of [`TrackerRequest::to_query`]
+    pub fn from_query(query: &str) -> Result<Self> {
+        let mut info_hash = None;
+        let mut peer_id = None;
+        let mut port = None;
+        let mut request = TrackerRequest {
+            info_hash: [0; 20],
+            peer_id: [0; 20],
+            port: 0,
+            uploaded: 0,
+            downloaded: 0,
+            left: 0,
+            compact: false,
+            event: None,
+            ip: None,
+            numwant: None,
+            no_peer_id: false,
+            key: None,
+            trackerid: None,
+        };
+
+        for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let value = decode_bytes(value)
+                .ok_or_else(|| Error::from_request(format!("invalid encoding of {}", name)))?;
+            match name {
+                "info_hash" => info_hash = Some(parse_hash(name, value)?),
+                "peer_id" => peer_id = Some(parse_hash(name, value)?),
+                "port" => port = Some(parse_number(name, &value)?),
+                "uploaded" => request.uploaded = parse_number(name, &value)?,
+                "downloaded" => request.downloaded = parse_number(name, &value)?,
+                "left" => request.left = parse_number(name, &value)?,
+                "compact" => request.compact = value == b"1",
+                "no_peer_id" => request.no_peer_id = value == b"1",
+                "event" => {
+                    request.event = match value.as_slice() {
+                        b"started" => Some(TrackerEvent::Started),
+                        b"completed" => Some(TrackerEvent::Completed),
+                        b"stopped" => Some(TrackerEvent::Stopped),
+                        b"" | b"empty" => None,
+                        _ => return Err(Error::from_request("invalid event")),
+                    }
+                }
+                // A hint which may be a DNS name, ignored when it isn't an address
+                "ip" => request.ip = parse_number(name, &value).ok(),
+                "numwant" => request.numwant = Some(parse_number(name, &value)?),
+                "key" => request.key = Some(String::from_utf8_lossy(&value).to_string()),
+                "trackerid" => {
+                    request.trackerid = Some(String::from_utf8_lossy(&value).to_string())
+                }
+                _ => {}
+            }
+        }
+
+        request.info_hash = info_hash.ok_or_else(|| Error::from_request("missing info_hash"))?;
+        request.peer_id = peer_id.ok_or_else(|| Error::from_request("missing peer_id"))?;
+        request.port = port.ok_or_else(|| Error::from_request("missing port"))?;
+        Ok(request)
+    }
+}
+
+fn parse_hash(name: &str, value: Vec<u8>) -> Result<[u8; 20]> {
+    value
+        .try_into()
+        .map_err(|_| Error::from_request(format!("{} must be 20 bytes long", name)))
+}
+
+fn parse_number<T: std::str::FromStr>(name: &str, value: &[u8]) -> Result<T> {
+    std::str::from_utf8(value)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .ok_or_else(|| Error::from_request(format!("invalid {}", name)))
+}
+
+/// The reply of a tracker to an announce.
+///
+/// Any response with a `failure reason` is a failure, whatever its other keys.
+#[derive(PartialEq, Debug, Serialize)]
 #[serde(untagged)]
 pub enum TrackerResponse {
     Failure(TrackerResponseFailure),
     Success(TrackerResponseSuccess),
 }
 
+impl TrackerResponse {
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        decode(bytes)
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        encode(self)
+    }
+
+    pub fn failure_reason(&self) -> Option<&str> {
+        match self {
+            TrackerResponse::Failure(failure) => Some(&failure.failure_reason),
+            TrackerResponse::Success(_) => None,
+        }
+    }
+}
+
+fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T> {
+    let mut reader = BufReader::new(Cursor::new(bytes));
+    Ok(from_reader(&mut reader)?)
+}
+
+fn encode<T: Serialize>(value: &T) -> Result<Vec<u8>> {
+    let mut buffer = Vec::new();
+    let mut writer = from_writer(&mut buffer);
+    value.serialize(&mut writer)?;
+    Ok(buffer)
+}
+
+impl<'de> Deserialize<'de> for TrackerResponse {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        struct FailureProbe {
+            #[serde(rename = "failure reason")]
+            failure_reason: Option<String>,
+        }
+
+        let raw = RawValue::deserialize(deserializer)?;
+        let probe: FailureProbe = raw.decode().map_err(D::Error::custom)?;
+        let response = match probe.failure_reason {
+            Some(_) => raw.decode().map(TrackerResponse::Failure),
+            None => raw.decode().map(TrackerResponse::Success),
+        };
+        response.map_err(D::Error::custom)
+    }
+}
+
 #[derive(PartialEq, Debug, Serialize, Deserialize)]
 pub struct TrackerResponseFailure {
     /// The value is a human-readable error message as to why the request failed
@@ -71,12 +270,20 @@ pub struct TrackerResponseFailure {
     pub failure_reason: String,
 }
 
-#[derive(PartialEq, Debug, Serialize, Deserialize)]
+impl TrackerResponseFailure {
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        decode(bytes)
+    }
+}
+
+#[derive(PartialEq, Debug, Default, Serialize, Deserialize)]
 pub struct TrackerResponseSuccess {
     /// number of peers with the entire file, i.e. seeders
+    #[serde(default)]
     pub complete: usize,
 
     /// number of non-seeder peers, aka "leechers"
+    #[serde(default)]
     pub incomplete: usize,
 
     /// Interval in seconds that the client should wait between sending regular requests to the tracker
@@ -84,38 +291,162 @@ pub struct TrackerResponseSuccess {
 
     /// Minimum announce interval. If present clients must not reannounce more frequently than this.
     #[serde(rename = "min interval")]
-    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub min_interval: Option<usize>,
 
-    /// list of peers
-    pub peers: Vec<TrackerPeer>,
+    /// list of peers, as dictionaries or as a compact string
+    #[serde(default)]
+    pub peers: TrackerPeers,
+
+    /// IPv6 peers as a compact string of 18 bytes per peer (BEP 7)
+    #[serde(with = "as_compact_peers6")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub peers6: Option<CompactPeers>,
 
     /// A string that the client should send back on its next announcements. If absent and a previous announce sent a tracker id, do not discard the old value; keep using it.
     #[serde(rename = "tracker id")]
-    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub tracker_id: Option<String>,
 
     /// Similar to failure reason, but the response still gets processed normally. The warning message is shown just like an error.
     #[serde(rename = "warning message")]
-    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub warning_message: Option<String>,
 }
 
-#[derive(PartialEq, Debug, Serialize, Deserialize)]
+impl TrackerResponseSuccess {
+    /// Addresses of all the peers, IPv4 and IPv6. Peers given by DNS name are left out.
+    pub fn peer_addrs(&self) -> Vec<SocketAddr> {
+        let peers: Vec<SocketAddr> = match &self.peers {
+            TrackerPeers::List(peers) => {
+                peers.iter().filter_map(TrackerPeer::socket_addr).collect()
+            }
+            TrackerPeers::Compact(peers) => compact::decode_v4(peers.as_bytes())
+                .unwrap_or_default()
+                .into_iter()
+                .map(SocketAddr::V4)
+                .collect(),
+        };
+        let peers6 = self
+            .peers6
+            .as_ref()
+            .and_then(|peers6| compact::decode_v6(peers6.as_bytes()))
+            .unwrap_or_default();
+        peers
+            .into_iter()
+            .chain(peers6.into_iter().map(SocketAddr::V6))
+            .collect()
+    }
+}
+
+/// The `peers` of a response, in one of the two models trackers use
+#[derive(PartialEq, Debug, Clone)]
+pub enum TrackerPeers {
+    /// A list of dictionaries, one per peer
+    List(Vec<TrackerPeer>),
+
+    /// A string of 6 bytes per IPv4 peer (BEP 23), sent when the client asks for `compact`
+    Compact(CompactPeers),
+}
+
+impl Default for TrackerPeers {
+    fn default() -> Self {
+        TrackerPeers::Compact(CompactPeers::default())
+    }
+}
+
+impl TrackerPeers {
+    pub fn len(&self) -> usize {
+        match self {
+            TrackerPeers::List(peers) => peers.len(),
+            TrackerPeers::Compact(peers) => peers.as_bytes().len() / compact::PEER_V4_LEN,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Serialize for TrackerPeers {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match self {
+            TrackerPeers::List(peers) => peers.serialize(serializer),
+            TrackerPeers::Compact(peers) => serializer.serialize_bytes(peers.as_bytes()),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for TrackerPeers {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct PeersVisitor;
+
+        impl<'de> Visitor<'de> for PeersVisitor {
+            type Value = TrackerPeers;
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                formatter.write_str("a list of peers or a compact peer string")
+            }
+
+            fn visit_bytes<E: de::Error>(
+                self,
+                bytes: &[u8],
+            ) -> std::result::Result<Self::Value, E> {
+                match bytes.len() % compact::PEER_V4_LEN {
+                    0 => Ok(TrackerPeers::Compact(CompactPeers::from(bytes.to_vec()))),
+                    _ => Err(E::invalid_length(bytes.len(), &"a multiple of 6 bytes")),
+                }
+            }
+
+            fn visit_seq<A>(self, mut seq: A) -> std::result::Result<Self::Value, A::Error>
+            where
+                A: SeqAccess<'de>,
+            {
+                let mut peers = Vec::new();
+                while let Some(peer) = seq.next_element()? {
+                    peers.push(peer);
+                }
+                Ok(TrackerPeers::List(peers))
+            }
+        }
+
+        deserializer.deserialize_any(PeersVisitor)
+    }
+}
+
+#[serde_as]
+#[derive(PartialEq, Debug, Clone, Serialize, Deserialize)]
 pub struct TrackerPeer {
     /// peer's IP address either IPv4, IPv6, or DNS name
     #[serde(with = "as_peer_address")]
     pub ip: TrackerPeerAddress,
 
     /// peer's self-selected ID, as described above for the tracker request (string)
+    #[serde_as(as = "Option<Bytes>")]
     #[serde(rename = "peer id")]
-    pub peer_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub peer_id: Option<Vec<u8>>,
 
     /// peer's port number
     pub port: u16,
 }
 
-#[derive(PartialEq, Debug, Serialize, Deserialize)]
+impl TrackerPeer {
+    pub fn socket_addr(&self) -> Option<SocketAddr> {
+        match self.ip {
+            TrackerPeerAddress::IP(ip) => Some(SocketAddr::new(ip, self.port)),
+            TrackerPeerAddress::DNS(_) => None,
+        }
+    }
+}
+
+#[derive(PartialEq, Debug, Clone, Serialize, Deserialize)]
 #[serde(untagged)]
 pub enum TrackerPeerAddress {
     IP(std::net::IpAddr),
@@ -148,6 +479,34 @@ mod as_peer_address {
     }
 }
 
+mod as_compact_peers6 {
+    use super::compact::{CompactPeers, PEER_V6_LEN};
+    use serde::{de, Deserializer, Serializer};
+    use serde_with::{Bytes, DeserializeAs};
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<CompactPeers>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let bytes: Vec<u8> = Bytes::deserialize_as(deserializer)?;
+        match bytes.len() % PEER_V6_LEN {
+            0 => Ok(Some(CompactPeers::from(bytes))),
+            _ => Err(de::Error::invalid_length(
+                bytes.len(),
+                &"a multiple of 18 bytes",
+            )),
+        }
+    }
+
+    pub fn serialize<S>(peers: &Option<CompactPeers>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let peers = peers.as_ref().map(CompactPeers::as_bytes);
+        serializer.serialize_bytes(peers.unwrap_or_default())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -256,20 +615,21 @@ mod tests {
             incomplete: 2,
             interval: 3,
             min_interval: Some(4),
-            peers: vec![
+            peers: TrackerPeers::List(vec![
                 TrackerPeer {
                     ip: TrackerPeerAddress::IP(std::net::IpAddr::V4(std::net::Ipv4Addr::new(
                         127, 0, 0, 1,
                     ))),
-                    peer_id: "peer_id".to_string(),
+                    peer_id: Some(b"peer_id".to_vec()),
                     port: 1234,
                 },
                 TrackerPeer {
                     ip: TrackerPeerAddress::DNS("example.com".to_string()),
-                    peer_id: "peer_id".to_string(),
+                    peer_id: Some(b"peer_id".to_vec()),
                     port: 1234,
                 },
-            ],
+            ]),
+            peers6: None,
             tracker_id: Some("tracker_id".to_string()),
             warning_message: Some("warning_message".to_string()),
         });
@@ -299,4 +659,102 @@ mod tests {
 
         assert_eq!(result, response);
     }
+
+    #[test]
+    fn test_tracker_compact_response_bencode() {
+        let bytes = b"d8:completei2e10:incompletei0e8:intervali900e\
+5:peers12:\x7f\x00\x00\x01\x1a\xe1\x0a\x00\x00\x02\x1a\xe2\
+6:peers618:\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x01\x1a\xe3e";
+        let response = match TrackerResponse::from_bytes(bytes).unwrap() {
+            TrackerResponse::Success(response) => response,
+            failure => panic!("unexpected {:?}", failure),
+        };
+        assert_eq!(response.complete, 2);
+        assert_eq!(
+            response.peer_addrs(),
+            vec![
+                "127.0.0.1:6881".parse::<SocketAddr>().unwrap(),
+                "10.0.0.2:6882".parse().unwrap(),
+                "[::1]:6883".parse().unwrap(),
+            ]
+        );
+        assert_eq!(
+            TrackerResponse::Success(response).to_bytes().unwrap(),
+            bytes
+        );
+
+        let truncated = b"d8:intervali900e5:peers5:\x7f\x00\x00\x01\x1ae";
+        assert!(TrackerResponse::from_bytes(truncated).is_err());
+    }
+
+    #[test]
+    fn test_tracker_failure_takes_precedence() {
+        // A failure may come with the keys of a successful response
+        let bytes = b"d14:failure reason6:banned8:intervali60e5:peers0:e";
+        assert_eq!(
+            TrackerResponse::from_bytes(bytes).unwrap(),
+            TrackerResponse::Failure(TrackerResponseFailure {
+                failure_reason: "banned".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn test_tracker_request_query() {
+        let request = TrackerRequest {
+            info_hash: *b"fake-info-hash\x00\xff\x12 34",
+            peer_id: *b"-TF0001-abcdefghijkl",
+            port: 6881,
+            uploaded: 1,
+            downloaded: 2,
+            left: 3,
+            compact: true,
+            event: Some(TrackerEvent::Stopped),
+            ip: None,
+            numwant: None,
+            no_peer_id: false,
+            key: Some("k y".to_string()),
+            trackerid: None,
+        };
+        let query = request.to_query();
+        assert!(query.starts_with("info_hash=%66%61%6B%65"));
+        assert!(query.contains("%00%FF%12%20%33%34&peer_id=%2D%54%46"));
+        assert!(query.ends_with(
+            "&port=6881&uploaded=1&downloaded=2&left=3&compact=1&event=stopped&key=k%20y"
+        ));
+    }
+
+    #[test]
+    fn test_tracker_request_from_query() {
+        let request = TrackerRequest {
+            info_hash: *b"fake-info-hash\x00\xff\x12 34",
+            peer_id: *b"-TF0001-abcdefghijkl",
+            port: 6881,
+            uploaded: 1,
+            downloaded: 2,
+            left: 3,
+            compact: false,
+            event: Some(TrackerEvent::Completed),
+            ip: Some("10.0.0.1".parse().unwrap()),
+            numwant: Some(20),
+            no_peer_id: true,
+            key: Some("k y".to_string()),
+            trackerid: Some("id".to_string()),
+        };
+        assert_eq!(
+            TrackerRequest::from_query(&request.to_query()).unwrap(),
+            request
+        );
+
+        let query = "info_hash=aaaaaaaaaaaaaaaaaaaa&peer_id=bbbbbbbbbbbbbbbbbbbb&port=1&extra=x";
+        assert!(TrackerRequest::from_query(query).is_ok());
+        assert!(
+            TrackerRequest::from_query("info_hash=short&peer_id=bbbbbbbbbbbbbbbbbbbb&port=1")
+                .is_err()
+        );
+        let named = TrackerRequest::from_query(&format!("{}&ip=peer.example.org", query));
+        assert_eq!(named.unwrap().ip, None);
+        assert!(TrackerRequest::from_query(&query.replace("port=1", "port=x")).is_err());
+        assert!(TrackerRequest::from_query(&query.replace("&port=1", "")).is_err());
+    }
 }
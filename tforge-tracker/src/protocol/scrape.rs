@@ -0,0 +1,174 @@
+use super::{decode, encode, parse_hash};
+use crate::error::{Error, Result};
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, Bytes};
+use std::collections::BTreeMap;
+use tforge_urlencoded::value::{decode_bytes, Value};
+
+/// The torrents to scrape. No info-hash at all asks for every torrent of the tracker.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ScrapeRequest {
+    pub info_hashes: Vec<[u8; 20]>,
+}
+
+impl ScrapeRequest {
+    /// The query string of the scrape, with one `info_hash` per torrent
+    pub fn to_query(&self) -> String {
+        self.info_hashes
+            .iter()
+            .map(|info_hash| format!("info_hash={}", Value::from(&info_hash[..])))
+            .collect::<Vec<_>>()
+            .join("&")
+    }
+
+    /// Parses the query string of a scrape, the counterpart of [`ScrapeRequest::to_query`]
+    pub fn from_query(query: &str) -> Result<Self> {
+        let mut request = ScrapeRequest::default();
+        for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            if name == "info_hash" {
+                let value = decode_bytes(value)
+                    .ok_or_else(|| Error::from_request("invalid encoding of info_hash"))?;
+                request.info_hashes.push(parse_hash(name, value)?);
+            }
+        }
+        Ok(request)
+    }
+}
+
+/// The reply of a tracker to a scrape
+#[serde_as]
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ScrapeResponse {
+    /// The stats of each torrent, keyed by its raw info-hash
+    #[serde_as(as = "BTreeMap<Bytes, _>")]
+    pub files: BTreeMap<[u8; 20], ScrapeFile>,
+}
+
+impl ScrapeResponse {
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        decode(bytes)
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        encode(self)
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScrapeFile {
+    /// number of peers with the entire file, i.e. seeders
+    pub complete: u64,
+
+    /// total number of times the tracker has registered a completion
+    pub downloaded: u64,
+
+    /// number of non-seeder peers, aka "leechers"
+    pub incomplete: u64,
+
+    /// the torrent's internal name, as specified by the "name" file in the info section of the .torrent file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+}
+
+/// The scrape URL of a tracker, found by replacing the `announce` that starts
+/// the last path segment of its announce URL with `scrape`.
+///
+/// Trackers whose announce URL doesn't follow this rule don't support scraping.
+pub fn scrape_url(announce_url: &str) -> Option<String> {
+    let (path, query) = match announce_url.find('?') {
+        Some(start) => announce_url.split_at(start),
+        None => (announce_url, ""),
+    };
+    let segment = path.rfind('/')? + 1;
+    let rest = path[segment..].strip_prefix("announce")?;
+    Some(format!("{}scrape{}{}", &path[..segment], rest, query))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scrape_url() {
+        let cases = [
+            (
+                "http://example.com/announce",
+                Some("http://example.com/scrape"),
+            ),
+            (
+                "http://example.com/x/announce",
+                Some("http://example.com/x/scrape"),
+            ),
+            (
+                "http://example.com/announce.php",
+                Some("http://example.com/scrape.php"),
+            ),
+            (
+                "http://example.com/announce?x2%0644",
+                Some("http://example.com/scrape?x2%0644"),
+            ),
+            (
+                "http://example.com/announce?x=2/4",
+                Some("http://example.com/scrape?x=2/4"),
+            ),
+            ("http://example.com/a", None),
+            ("http://example.com/x%064announce", None),
+            ("http://example.com/announce/x", None),
+        ];
+        for (announce_url, expected) in cases {
+            assert_eq!(
+                scrape_url(announce_url).as_deref(),
+                expected,
+                "{}",
+                announce_url
+            );
+        }
+    }
+
+    #[test]
+    fn test_scrape_request_query() {
+        let request = ScrapeRequest {
+            info_hashes: vec![[0xab; 20], *b"-abcdefghijklmnopqrs"],
+        };
+        let query = request.to_query();
+        assert!(query.starts_with("info_hash=%AB%AB"));
+        assert_eq!(query.matches("info_hash=").count(), 2);
+        assert_eq!(ScrapeRequest::from_query(&query).unwrap(), request);
+        assert_eq!(
+            ScrapeRequest::from_query("").unwrap(),
+            ScrapeRequest::default()
+        );
+        assert!(ScrapeRequest::from_query("info_hash=short").is_err());
+    }
+
+    #[test]
+    fn test_scrape_response_bencode() {
+        let mut response = ScrapeResponse::default();
+        response.files.insert(
+            *b"bbbbbbbbbbbbbbbbbbbb",
+            ScrapeFile {
+                complete: 5,
+                downloaded: 50,
+                incomplete: 10,
+                name: None,
+            },
+        );
+        response.files.insert(
+            *b"aaaaaaaaaaaaaaaaaaaa",
+            ScrapeFile {
+                complete: 1,
+                downloaded: 2,
+                incomplete: 3,
+                name: Some("debian.iso".to_string()),
+            },
+        );
+        let bytes = response.to_bytes().unwrap();
+        assert_eq!(
+            bytes,
+            b"d5:filesd20:aaaaaaaaaaaaaaaaaaaad8:completei1e10:downloadedi2e10:incompletei3e4:name10:debian.isoe\
+              20:bbbbbbbbbbbbbbbbbbbbd8:completei5e10:downloadedi50e10:incompletei10eeee"
+        );
+        assert_eq!(ScrapeResponse::from_bytes(&bytes).unwrap(), response);
+    }
+}
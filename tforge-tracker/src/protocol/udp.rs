@@ -0,0 +1,488 @@
+//! The packets of the UDP tracker protocol (BEP 15), with the URL data option of BEP 41.
+//!
+//! Every integer is in network byte order. A client first obtains a connection ID
+//! with a connect request, then sends it with its announces and scrapes.
+
+use super::{compact, TrackerEvent};
+use crate::error::{Error, Result};
+use std::net::{Ipv4Addr, SocketAddr};
+
+/// Magic constant a connect request starts with
+pub const PROTOCOL_ID: u64 = 0x41727101980;
+
+/// Most info-hashes a scrape can carry, to fit in a single packet
+pub const MAX_SCRAPE_HASHES: usize = 74;
+
+const ACTION_CONNECT: u32 = 0;
+const ACTION_ANNOUNCE: u32 = 1;
+const ACTION_SCRAPE: u32 = 2;
+const ACTION_ERROR: u32 = 3;
+
+const OPTION_END: u8 = 0x0;
+const OPTION_NOP: u8 = 0x1;
+const OPTION_URL_DATA: u8 = 0x2;
+
+const ANNOUNCE_REQUEST_LEN: usize = 98;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum UdpRequest {
+    Connect {
+        transaction_id: u32,
+    },
+    Announce(UdpAnnounceRequest),
+    Scrape {
+        connection_id: u64,
+        transaction_id: u32,
+        info_hashes: Vec<[u8; 20]>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct UdpAnnounceRequest {
+    pub connection_id: u64,
+    pub transaction_id: u32,
+    pub info_hash: [u8; 20],
+    pub peer_id: [u8; 20],
+    pub downloaded: u64,
+    pub left: u64,
+    pub uploaded: u64,
+    pub event: Option<TrackerEvent>,
+
+    /// Address the peer asks to be announced with, `0.0.0.0` for the one the packet came from
+    pub ip: Ipv4Addr,
+    pub key: u32,
+
+    /// Number of peers wanted, -1 for the tracker's default
+    pub num_want: i32,
+    pub port: u16,
+
+    /// Path and query of the announce URL (BEP 41), e.g. `/announce?passkey=...`
+    pub url_data: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum UdpResponse {
+    Connect {
+        transaction_id: u32,
+        connection_id: u64,
+    },
+    Announce(UdpAnnounceResponse),
+    Scrape {
+        transaction_id: u32,
+        stats: Vec<UdpScrapeStats>,
+    },
+    Error {
+        transaction_id: u32,
+        message: String,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct UdpAnnounceResponse {
+    pub transaction_id: u32,
+    pub interval: u32,
+    pub leechers: u32,
+    pub seeders: u32,
+
+    /// IPv4 peers in responses sent over IPv4, IPv6 peers over IPv6
+    pub peers: Vec<SocketAddr>,
+}
+
+/// What a scrape tells about one torrent
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct UdpScrapeStats {
+    pub seeders: u32,
+    pub completed: u32,
+    pub leechers: u32,
+}
+
+impl UdpRequest {
+    pub fn transaction_id(&self) -> u32 {
+        match self {
+            UdpRequest::Connect { transaction_id } => *transaction_id,
+            UdpRequest::Announce(announce) => announce.transaction_id,
+            UdpRequest::Scrape { transaction_id, .. } => *transaction_id,
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(ANNOUNCE_REQUEST_LEN);
+        match self {
+            UdpRequest::Connect { transaction_id } => {
+                bytes.extend_from_slice(&PROTOCOL_ID.to_be_bytes());
+                bytes.extend_from_slice(&ACTION_CONNECT.to_be_bytes());
+                bytes.extend_from_slice(&transaction_id.to_be_bytes());
+            }
+            UdpRequest::Announce(announce) => {
+                bytes.extend_from_slice(&announce.connection_id.to_be_bytes());
+                bytes.extend_from_slice(&ACTION_ANNOUNCE.to_be_bytes());
+                bytes.extend_from_slice(&announce.transaction_id.to_be_bytes());
+                bytes.extend_from_slice(&announce.info_hash);
+                bytes.extend_from_slice(&announce.peer_id);
+                bytes.extend_from_slice(&announce.downloaded.to_be_bytes());
+                bytes.extend_from_slice(&announce.left.to_be_bytes());
+                bytes.extend_from_slice(&announce.uploaded.to_be_bytes());
+                bytes.extend_from_slice(&event_to_u32(announce.event.as_ref()).to_be_bytes());
+                bytes.extend_from_slice(&announce.ip.octets());
+                bytes.extend_from_slice(&announce.key.to_be_bytes());
+                bytes.extend_from_slice(&announce.num_want.to_be_bytes());
+                bytes.extend_from_slice(&announce.port.to_be_bytes());
+                if !announce.url_data.is_empty() {
+                    for chunk in announce.url_data.chunks(u8::MAX as usize) {
+                        bytes.push(OPTION_URL_DATA);
+                        bytes.push(chunk.len() as u8);
+                        bytes.extend_from_slice(chunk);
+                    }
+                    bytes.push(OPTION_END);
+                }
+            }
+            UdpRequest::Scrape {
+                connection_id,
+                transaction_id,
+                info_hashes,
+            } => {
+                bytes.extend_from_slice(&connection_id.to_be_bytes());
+                bytes.extend_from_slice(&ACTION_SCRAPE.to_be_bytes());
+                bytes.extend_from_slice(&transaction_id.to_be_bytes());
+                info_hashes
+                    .iter()
+                    .for_each(|hash| bytes.extend_from_slice(hash));
+            }
+        }
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let mut reader = PacketReader::new(bytes);
+        let connection_id = reader.u64()?;
+        let action = reader.u32()?;
+        let transaction_id = reader.u32()?;
+        match action {
+            ACTION_CONNECT if connection_id == PROTOCOL_ID => {
+                Ok(UdpRequest::Connect { transaction_id })
+            }
+            ACTION_CONNECT => Err(Error::from_invalid("wrong protocol ID")),
+            ACTION_ANNOUNCE => {
+                let info_hash = reader.array()?;
+                let peer_id = reader.array()?;
+                let downloaded = reader.u64()?;
+                let left = reader.u64()?;
+                let uploaded = reader.u64()?;
+                let event = event_from_u32(reader.u32()?)?;
+                let ip = Ipv4Addr::from(reader.array::<4>()?);
+                let key = reader.u32()?;
+                let num_want = reader.u32()? as i32;
+                let port = reader.u16()?;
+                let url_data = read_url_data(reader.rest());
+                Ok(UdpRequest::Announce(UdpAnnounceRequest {
+                    connection_id,
+                    transaction_id,
+                    info_hash,
+                    peer_id,
+                    downloaded,
+                    left,
+                    uploaded,
+                    event,
+                    ip,
+                    key,
+                    num_want,
+                    port,
+                    url_data,
+                }))
+            }
+            ACTION_SCRAPE => {
+                let hashes = reader.rest();
+                if hashes.is_empty() || hashes.len() % 20 != 0 {
+                    return Err(Error::from_invalid("scrape without info-hashes"));
+                }
+                let info_hashes = hashes
+                    .chunks_exact(20)
+                    .take(MAX_SCRAPE_HASHES)
+                    .map(|hash| hash.try_into().unwrap())
+                    .collect();
+                Ok(UdpRequest::Scrape {
+                    connection_id,
+                    transaction_id,
+                    info_hashes,
+                })
+            }
+            action => Err(Error::from_invalid(format!("unknown action {}", action))),
+        }
+    }
+}
+
+impl UdpResponse {
+    pub fn transaction_id(&self) -> u32 {
+        match self {
+            UdpResponse::Connect { transaction_id, .. } => *transaction_id,
+            UdpResponse::Announce(announce) => announce.transaction_id,
+            UdpResponse::Scrape { transaction_id, .. } => *transaction_id,
+            UdpResponse::Error { transaction_id, .. } => *transaction_id,
+        }
+    }
+
+    /// Peers of IPv6 addresses are left out of announce responses when `ipv6` is false,
+    /// and IPv4 ones when it is true
+    pub fn to_bytes(&self, ipv6: bool) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        match self {
+            UdpResponse::Connect {
+                transaction_id,
+                connection_id,
+            } => {
+                bytes.extend_from_slice(&ACTION_CONNECT.to_be_bytes());
+                bytes.extend_from_slice(&transaction_id.to_be_bytes());
+                bytes.extend_from_slice(&connection_id.to_be_bytes());
+            }
+            UdpResponse::Announce(announce) => {
+                bytes.extend_from_slice(&ACTION_ANNOUNCE.to_be_bytes());
+                bytes.extend_from_slice(&announce.transaction_id.to_be_bytes());
+                bytes.extend_from_slice(&announce.interval.to_be_bytes());
+                bytes.extend_from_slice(&announce.leechers.to_be_bytes());
+                bytes.extend_from_slice(&announce.seeders.to_be_bytes());
+                for peer in &announce.peers {
+                    match (peer, ipv6) {
+                        (SocketAddr::V4(peer), false) => {
+                            bytes.extend_from_slice(&compact::encode_v4(&[*peer]))
+                        }
+                        (SocketAddr::V6(peer), true) => {
+                            bytes.extend_from_slice(&compact::encode_v6(&[*peer]))
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            UdpResponse::Scrape {
+                transaction_id,
+                stats,
+            } => {
+                bytes.extend_from_slice(&ACTION_SCRAPE.to_be_bytes());
+                bytes.extend_from_slice(&transaction_id.to_be_bytes());
+                for stats in stats {
+                    bytes.extend_from_slice(&stats.seeders.to_be_bytes());
+                    bytes.extend_from_slice(&stats.completed.to_be_bytes());
+                    bytes.extend_from_slice(&stats.leechers.to_be_bytes());
+                }
+            }
+            UdpResponse::Error {
+                transaction_id,
+                message,
+            } => {
+                bytes.extend_from_slice(&ACTION_ERROR.to_be_bytes());
+                bytes.extend_from_slice(&transaction_id.to_be_bytes());
+                bytes.extend_from_slice(message.as_bytes());
+            }
+        }
+        bytes
+    }
+
+    /// Parses a response received over IPv6 if `ipv6`, which decides the size of the peers
+    pub fn from_bytes(bytes: &[u8], ipv6: bool) -> Result<Self> {
+        let mut reader = PacketReader::new(bytes);
+        let action = reader.u32()?;
+        let transaction_id = reader.u32()?;
+        match action {
+            ACTION_CONNECT => Ok(UdpResponse::Connect {
+                transaction_id,
+                connection_id: reader.u64()?,
+            }),
+            ACTION_ANNOUNCE => {
+                let interval = reader.u32()?;
+                let leechers = reader.u32()?;
+                let seeders = reader.u32()?;
+                let peers = match ipv6 {
+                    false => compact::decode_v4(reader.rest())
+                        .map(|peers| peers.into_iter().map(SocketAddr::V4).collect()),
+                    true => compact::decode_v6(reader.rest())
+                        .map(|peers| peers.into_iter().map(SocketAddr::V6).collect()),
+                };
+                Ok(UdpResponse::Announce(UdpAnnounceResponse {
+                    transaction_id,
+                    interval,
+                    leechers,
+                    seeders,
+                    peers: peers.ok_or_else(|| Error::from_invalid("truncated peer list"))?,
+                }))
+            }
+            ACTION_SCRAPE => {
+                let rest = reader.rest();
+                if rest.len() % 12 != 0 {
+                    return Err(Error::from_invalid("truncated scrape response"));
+                }
+                let stats = rest
+                    .chunks_exact(12)
+                    .map(|chunk| {
+                        let mut reader = PacketReader::new(chunk);
+                        UdpScrapeStats {
+                            seeders: reader.u32().unwrap(),
+                            completed: reader.u32().unwrap(),
+                            leechers: reader.u32().unwrap(),
+                        }
+                    })
+                    .collect();
+                Ok(UdpResponse::Scrape {
+                    transaction_id,
+                    stats,
+                })
+            }
+            ACTION_ERROR => Ok(UdpResponse::Error {
+                transaction_id,
+                message: String::from_utf8_lossy(reader.rest()).to_string(),
+            }),
+            action => Err(Error::from_invalid(format!("unknown action {}", action))),
+        }
+    }
+}
+
+fn event_to_u32(event: Option<&TrackerEvent>) -> u32 {
+    match event {
+        None => 0,
+        Some(TrackerEvent::Completed) => 1,
+        Some(TrackerEvent::Started) => 2,
+        Some(TrackerEvent::Stopped) => 3,
+    }
+}
+
+fn event_from_u32(event: u32) -> Result<Option<TrackerEvent>> {
+    match event {
+        0 => Ok(None),
+        1 => Ok(Some(TrackerEvent::Completed)),
+        2 => Ok(Some(TrackerEvent::Started)),
+        3 => Ok(Some(TrackerEvent::Stopped)),
+        event => Err(Error::from_invalid(format!("unknown event {}", event))),
+    }
+}
+
+/// Concatenates the URL data options following an announce request (BEP 41)
+fn read_url_data(mut options: &[u8]) -> Vec<u8> {
+    let mut url_data = Vec::new();
+    while let Some((&option, rest)) = options.split_first() {
+        match option {
+            OPTION_END => break,
+            OPTION_NOP => options = rest,
+            _ => {
+                let Some((&len, rest)) = rest.split_first() else {
+                    break;
+                };
+                let len = (len as usize).min(rest.len());
+                if option == OPTION_URL_DATA {
+                    url_data.extend_from_slice(&rest[..len]);
+                }
+                options = &rest[len..];
+            }
+        }
+    }
+    url_data
+}
+
+struct PacketReader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> PacketReader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        PacketReader { bytes }
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N]> {
+        if self.bytes.len() < N {
+            return Err(Error::from_invalid("packet too short"));
+        }
+        let (array, rest) = self.bytes.split_at(N);
+        self.bytes = rest;
+        Ok(array.try_into().unwrap())
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        self.array().map(u16::from_be_bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        self.array().map(u32::from_be_bytes)
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        self.array().map(u64::from_be_bytes)
+    }
+
+    fn rest(self) -> &'a [u8] {
+        self.bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_announce_request_roundtrip() {
+        let announce = UdpAnnounceRequest {
+            connection_id: 42,
+            transaction_id: 7,
+            info_hash: [1; 20],
+            peer_id: [2; 20],
+            downloaded: 3,
+            left: 4,
+            uploaded: 5,
+            event: Some(TrackerEvent::Started),
+            ip: Ipv4Addr::UNSPECIFIED,
+            key: 6,
+            num_want: -1,
+            port: 6881,
+            url_data: b"/announce?passkey=".repeat(20),
+        };
+        let request = UdpRequest::Announce(announce);
+        let bytes = request.to_bytes();
+        assert_eq!(bytes[..8], 42u64.to_be_bytes());
+        assert_eq!(bytes[ANNOUNCE_REQUEST_LEN], OPTION_URL_DATA);
+        assert_eq!(bytes[ANNOUNCE_REQUEST_LEN + 1], 255);
+        assert_eq!(bytes.last(), Some(&OPTION_END));
+        assert_eq!(UdpRequest::from_bytes(&bytes).unwrap(), request);
+    }
+
+    #[test]
+    fn test_connect_request() {
+        let request = UdpRequest::Connect { transaction_id: 9 };
+        let bytes = request.to_bytes();
+        assert_eq!(bytes.len(), 16);
+        assert_eq!(UdpRequest::from_bytes(&bytes).unwrap(), request);
+
+        let mut wrong = bytes.clone();
+        wrong[0] = 1;
+        assert!(UdpRequest::from_bytes(&wrong).is_err());
+    }
+
+    #[test]
+    fn test_announce_response_families() {
+        let response = UdpResponse::Announce(UdpAnnounceResponse {
+            transaction_id: 1,
+            interval: 1800,
+            leechers: 2,
+            seeders: 3,
+            peers: vec![
+                "10.0.0.1:6881".parse().unwrap(),
+                "[2001:db8::1]:6882".parse().unwrap(),
+            ],
+        });
+        let v4 = response.to_bytes(false);
+        assert_eq!(v4.len(), 20 + 6);
+        let v6 = response.to_bytes(true);
+        assert_eq!(v6.len(), 20 + 18);
+
+        let UdpResponse::Announce(parsed) = UdpResponse::from_bytes(&v6, true).unwrap() else {
+            panic!("not an announce");
+        };
+        assert_eq!(parsed.peers, vec!["[2001:db8::1]:6882".parse().unwrap()]);
+        assert!(UdpResponse::from_bytes(&v4[..v4.len() - 1], false).is_err());
+    }
+
+    #[test]
+    fn test_url_data_options() {
+        assert_eq!(
+            read_url_data(&[1, 2, 2, b'/', b'a', 1, 2, 1, b'b', 0, 2]),
+            b"/ab"
+        );
+        assert_eq!(read_url_data(&[2, 10, b'/']), b"/");
+        assert!(read_url_data(&[]).is_empty());
+    }
+}
@@ -0,0 +1,246 @@
+use super::{
+    blocking,
+    swarm::{InfoHash, SwarmStats},
+    Tracker,
+};
+use crate::error::{Error, Result};
+use axum::{
+    extract::{Path, Query, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    routing::get,
+    Json,
+    Router,
+};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, sync::Arc, time::SystemTime};
+use tokio::net::TcpListener;
+
+/// A torrent and its stats, as listed by the admin API
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct TorrentInfo {
+    pub info_hash: String,
+    pub name: Option<String>,
+    pub seeders: u64,
+    pub leechers: u64,
+    pub completed: u64,
+}
+
+/// A peer of a torrent, as listed by the admin API
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct PeerInfo {
+    pub peer_id: String,
+    pub addr: String,
+    pub uploaded: u64,
+    pub downloaded: u64,
+    pub left: u64,
+
+    /// Seconds since the last announce of the peer
+    pub idle: u64,
+
+    /// Whether the peer accepts connections, as far as the tracker knows
+    pub connectable: bool,
+}
+
+#[derive(Deserialize)]
+struct TopQuery {
+    #[serde(default = "default_top")]
+    count: usize,
+}
+
+fn default_top() -> usize {
+    10
+}
+
+/// The routes of the metrics and of the read-only admin API
+pub fn router(tracker: Arc<Tracker>) -> Router {
+    Router::new()
+        .route("/metrics", get(metrics))
+        .route("/api/torrents", get(torrents))
+        .route("/api/torrents/:info_hash/peers", get(peers))
+        .route("/api/top", get(top))
+        .with_state(tracker)
+}
+
+/// Serves the admin API on `listener` until it fails
+pub async fn serve(listener: TcpListener, tracker: Arc<Tracker>) -> Result<()> {
+    axum::serve(listener, router(tracker))
+        .await
+        .map_err(Error::from_io)
+}
+
+async fn metrics(State(tracker): State<Arc<Tracker>>) -> Response {
+    let metrics = blocking(&tracker, |tracker| tracker.render_metrics()).await;
+    match metrics.and_then(|metrics| metrics) {
+        Ok(metrics) => {
+            let content_type = "text/plain; version=0.0.4";
+            ([(header::CONTENT_TYPE, content_type)], metrics).into_response()
+        }
+        Err(err) => internal(err),
+    }
+}
+
+async fn torrents(State(tracker): State<Arc<Tracker>>) -> Response {
+    match blocking(&tracker, torrent_infos)
+        .await
+        .and_then(|torrents| torrents)
+    {
+        Ok(mut torrents) => {
+            torrents.sort_by(|a, b| a.info_hash.cmp(&b.info_hash));
+            Json(torrents).into_response()
+        }
+        Err(err) => internal(err),
+    }
+}
+
+/// The torrents with the most peers first
+async fn top(State(tracker): State<Arc<Tracker>>, Query(query): Query<TopQuery>) -> Response {
+    match blocking(&tracker, torrent_infos)
+        .await
+        .and_then(|torrents| torrents)
+    {
+        Ok(mut torrents) => {
+            torrents.sort_by_key(|torrent| std::cmp::Reverse(torrent.seeders + torrent.leechers));
+            torrents.truncate(query.count);
+            Json(torrents).into_response()
+        }
+        Err(err) => internal(err),
+    }
+}
+
+async fn peers(State(tracker): State<Arc<Tracker>>, Path(info_hash): Path<String>) -> Response {
+    let Some(info_hash) = parse_info_hash(&info_hash) else {
+        return (StatusCode::BAD_REQUEST, "info-hashes are 40 hex digits").into_response();
+    };
+    let peers = blocking(&tracker, move |tracker| tracker.storage().peers(&info_hash)).await;
+    let peers = match peers.and_then(|peers| peers) {
+        Ok(peers) => peers,
+        Err(err) => return internal(err),
+    };
+    let now = SystemTime::now();
+    let peers: Vec<_> = peers
+        .into_iter()
+        .map(|(peer_id, peer)| PeerInfo {
+            peer_id: hex::encode(peer_id),
+            addr: peer.addr.to_string(),
+            uploaded: peer.uploaded,
+            downloaded: peer.downloaded,
+            left: peer.left,
+            idle: now
+                .duration_since(peer.last_seen)
+                .unwrap_or_default()
+                .as_secs(),
+            connectable: peer.connectable,
+        })
+        .collect();
+    Json(peers).into_response()
+}
+
+/// The torrents with peers or completed downloads, and the registered ones
+fn torrent_infos(tracker: &Tracker) -> Result<Vec<TorrentInfo>> {
+    let storage = tracker.storage();
+    let mut torrents: HashMap<InfoHash, (Option<String>, SwarmStats)> = storage
+        .all_stats()?
+        .into_iter()
+        .map(|(info_hash, stats)| (info_hash, (None, stats)))
+        .collect();
+    for torrent in storage.torrents()? {
+        torrents.entry(torrent.info_hash).or_default().0 = torrent.name;
+    }
+    Ok(torrents
+        .into_iter()
+        .map(|(info_hash, (name, stats))| TorrentInfo {
+            info_hash: hex::encode(info_hash),
+            name,
+            seeders: stats.complete,
+            leechers: stats.incomplete,
+            completed: stats.downloaded,
+        })
+        .collect())
+}
+
+fn parse_info_hash(info_hash: &str) -> Option<InfoHash> {
+    hex::decode(info_hash).ok()?.try_into().ok()
+}
+
+fn internal(err: Error) -> Response {
+    (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        protocol::{TrackerEvent, TrackerRequest},
+        server::storage::Torrent,
+    };
+    use std::net::IpAddr;
+    use tforge_config::ServerConfig;
+
+    fn request(info_hash: u8, peer_id: u8) -> TrackerRequest {
+        TrackerRequest {
+            info_hash: [info_hash; 20],
+            peer_id: [peer_id; 20],
+            port: 6881,
+            uploaded: 0,
+            downloaded: 0,
+            left: peer_id as usize % 2,
+            compact: true,
+            event: Some(TrackerEvent::Started),
+            ip: None,
+            numwant: None,
+            no_peer_id: false,
+            key: None,
+            trackerid: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_admin_api() {
+        let tracker = Arc::new(Tracker::new(ServerConfig::default()));
+        for (info_hash, peers) in [(0xaa, 1), (0xbb, 3)] {
+            for peer_id in 0..peers {
+                let remote = IpAddr::from([10, 0, 0, peer_id]);
+                tracker.announce(&request(info_hash, peer_id), remote, None);
+            }
+        }
+        tracker
+            .storage()
+            .register_torrent(Torrent {
+                info_hash: [0xcc; 20],
+                name: Some("debian.iso".to_string()),
+            })
+            .unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(serve(listener, tracker));
+
+        let get = |path: &str| reqwest::get(format!("{}{}", url, path));
+        let torrents: Vec<TorrentInfo> = get("/api/torrents").await.unwrap().json().await.unwrap();
+        assert_eq!(torrents.len(), 3);
+        assert_eq!(torrents[2].name.as_deref(), Some("debian.iso"));
+
+        let top: Vec<TorrentInfo> = get("/api/top?count=1").await.unwrap().json().await.unwrap();
+        assert_eq!(
+            top,
+            vec![TorrentInfo {
+                info_hash: "bb".repeat(20),
+                name: None,
+                seeders: 2,
+                leechers: 1,
+                completed: 0,
+            }]
+        );
+
+        let path = format!("/api/torrents/{}/peers", "aa".repeat(20));
+        let peers: Vec<PeerInfo> = get(&path).await.unwrap().json().await.unwrap();
+        assert_eq!(peers.len(), 1);
+        assert_eq!(peers[0].addr, "10.0.0.0:6881");
+        let response = get("/api/torrents/xyz/peers").await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let metrics = get("/metrics").await.unwrap().text().await.unwrap();
+        assert!(metrics.contains("\ntforge_tracker_peers 4\n"));
+        assert!(metrics.contains("\ntforge_tracker_torrents 2\n"));
+    }
+}
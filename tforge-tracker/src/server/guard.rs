@@ -0,0 +1,395 @@
+use super::{iplist::IpRanges, swarm::PeerId};
+use crate::error::{Error, Result};
+use std::{
+    collections::{hash_map::RandomState, HashMap},
+    hash::BuildHasher,
+    net::{IpAddr, Ipv6Addr},
+    path::PathBuf,
+    sync::{Mutex, RwLock},
+    time::{Instant, SystemTime},
+};
+use tforge_config::{RateLimitConfig, ServerConfig};
+
+const RATE_LIMIT_SHARDS: usize = 64;
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+
+    /// Whether the last request was refused, to log only the first of a row
+    limited: bool,
+}
+
+/// What a rate limiter makes of a request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Throttle {
+    Allowed,
+
+    /// Refused, `first` when the requests before were allowed
+    Limited {
+        first: bool,
+    },
+}
+
+/// Token buckets of the requests of each IP address.
+///
+/// An IPv6 host usually gets a whole /64, so IPv6 addresses share the
+/// bucket of their /64.
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    shards: Box<[Mutex<HashMap<IpAddr, Bucket>>]>,
+    hasher: RandomState,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        RateLimiter {
+            config,
+            shards: (0..RATE_LIMIT_SHARDS).map(|_| Mutex::default()).collect(),
+            hasher: RandomState::new(),
+        }
+    }
+
+    fn shard(&self, ip: &IpAddr) -> &Mutex<HashMap<IpAddr, Bucket>> {
+        let hash = self.hasher.hash_one(ip) as usize;
+        &self.shards[hash % self.shards.len()]
+    }
+
+    /// Takes a token from the bucket of `ip`, if there is one left
+    pub fn acquire(&self, ip: IpAddr, now: Instant) -> Throttle {
+        let ip = bucket_key(ip);
+        let mut shard = self.shard(&ip).lock().unwrap();
+        let burst = self.config.burst as f64;
+        let bucket = shard.entry(ip).or_insert(Bucket {
+            tokens: burst,
+            updated: now,
+            limited: false,
+        });
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.config.rate).min(burst);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            bucket.limited = false;
+            Throttle::Allowed
+        } else {
+            let first = !bucket.limited;
+            bucket.limited = true;
+            Throttle::Limited { first }
+        }
+    }
+
+    /// Forgets the buckets refilled by now, which a new one is the same as
+    pub fn prune(&self, now: Instant) {
+        let burst = self.config.burst as f64;
+        for shard in self.shards.iter() {
+            shard.lock().unwrap().retain(|_, bucket| {
+                let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+                bucket.tokens + elapsed * self.config.rate < burst
+            });
+        }
+    }
+
+    /// The addresses with a bucket
+    pub fn len(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| shard.lock().unwrap().len())
+            .sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+fn bucket_key(ip: IpAddr) -> IpAddr {
+    match ip.to_canonical() {
+        IpAddr::V6(ip) => IpAddr::V6(Ipv6Addr::from(u128::from(ip) & !(u64::MAX as u128))),
+        ip => ip,
+    }
+}
+
+/// An IP list read from a file, read again when the file changes
+struct ListFile {
+    path: PathBuf,
+    modified: Mutex<Option<SystemTime>>,
+    ranges: RwLock<IpRanges>,
+}
+
+impl ListFile {
+    fn new(path: PathBuf) -> Self {
+        ListFile {
+            path,
+            modified: Mutex::default(),
+            ranges: RwLock::default(),
+        }
+    }
+
+    fn contains(&self, ip: IpAddr) -> bool {
+        self.ranges.read().unwrap().contains(ip)
+    }
+
+    /// Reads the file if it changed since it was last read, keeping the
+    /// ranges read before when it can't be
+    fn reload(&self) -> Result<()> {
+        let mut modified = self.modified.lock().unwrap();
+        let changed = std::fs::metadata(&self.path)?.modified()?;
+        if *modified == Some(changed) {
+            return Ok(());
+        }
+        let contents = std::fs::read_to_string(&self.path)?;
+        let ranges = IpRanges::parse(&contents)?;
+        eprintln!(
+            "Loaded {} IP ranges from {}",
+            ranges.len(),
+            self.path.display()
+        );
+        *self.ranges.write().unwrap() = ranges;
+        *modified = Some(changed);
+        Ok(())
+    }
+}
+
+/// The rate limits and filters of `[protection]`, checked before the
+/// tracker serves a request. The requests it refuses are logged, but
+/// for the rate limits only the first of a row is.
+pub struct Guard {
+    announce_rate: Option<RateLimiter>,
+    scrape_rate: Option<RateLimiter>,
+    blocklist: Option<ListFile>,
+    allowlist: Option<ListFile>,
+    trusted_sources: Option<ListFile>,
+    banned_clients: Vec<String>,
+}
+
+impl Guard {
+    /// A guard whose lists are empty until they are loaded by [`Guard::reload`]
+    pub fn new(config: &ServerConfig) -> Self {
+        let protection = &config.protection;
+        let private_banned = config
+            .private
+            .iter()
+            .flat_map(|private| &private.banned_clients);
+        Guard {
+            announce_rate: protection.announce_rate.map(RateLimiter::new),
+            scrape_rate: protection.scrape_rate.map(RateLimiter::new),
+            blocklist: protection.blocklist.clone().map(ListFile::new),
+            allowlist: protection.allowlist.clone().map(ListFile::new),
+            trusted_sources: protection.trusted_sources.clone().map(ListFile::new),
+            banned_clients: protection
+                .banned_clients
+                .iter()
+                .chain(private_banned)
+                .cloned()
+                .collect(),
+        }
+    }
+
+    /// Whether there are lists to reload
+    pub fn has_lists(&self) -> bool {
+        self.lists().next().is_some()
+    }
+
+    fn lists(&self) -> impl Iterator<Item = &ListFile> {
+        self.blocklist
+            .iter()
+            .chain(&self.allowlist)
+            .chain(&self.trusted_sources)
+    }
+
+    /// Reads the lists whose files changed since they were last read
+    pub fn reload(&self) -> Result<()> {
+        for list in self.lists() {
+            list.reload().map_err(|err| match err {
+                Error::IO(err) => Error::from_ip_list(format!("{}: {}", list.path.display(), err)),
+                Error::IpList(err) => {
+                    Error::from_ip_list(format!("{}: {}", list.path.display(), err))
+                }
+                err => err,
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Forgets the rate limits of the addresses which haven't used them lately
+    pub fn prune(&self) {
+        let now = Instant::now();
+        for limiter in self.announce_rate.iter().chain(&self.scrape_rate) {
+            limiter.prune(now);
+        }
+    }
+
+    pub fn check_announce(&self, remote: IpAddr, peer_id: &PeerId) -> Result<()> {
+        check_rate(self.announce_rate.as_ref(), "announce", remote)?;
+        self.check_address(remote, "announce")?;
+        let banned = self
+            .banned_clients
+            .iter()
+            .any(|prefix| peer_id.starts_with(prefix.as_bytes()));
+        if banned {
+            return Err(refuse("announce", remote, "banned client"));
+        }
+        Ok(())
+    }
+
+    /// The address of the peer announcing from `remote`: the `ip` of its
+    /// announce when `remote` is a trusted source, refused like `remote`
+    /// would be by the lists, or `remote` itself
+    pub fn peer_address(&self, remote: IpAddr, ip: Option<IpAddr>) -> Result<IpAddr> {
+        match (ip, &self.trusted_sources) {
+            (Some(ip), Some(trusted)) if trusted.contains(remote) => {
+                self.check_address(ip, "announce")?;
+                Ok(ip)
+            }
+            _ => Ok(remote),
+        }
+    }
+
+    pub fn check_scrape(&self, remote: IpAddr) -> Result<()> {
+        check_rate(self.scrape_rate.as_ref(), "scrape", remote)?;
+        self.check_address(remote, "scrape")
+    }
+
+    fn check_address(&self, remote: IpAddr, request: &str) -> Result<()> {
+        if let Some(allowlist) = &self.allowlist {
+            if !allowlist.contains(remote) {
+                return Err(refuse(request, remote, "address not allowed"));
+            }
+        }
+        if let Some(blocklist) = &self.blocklist {
+            if blocklist.contains(remote) {
+                return Err(refuse(request, remote, "address blocked"));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn check_rate(limiter: Option<&RateLimiter>, request: &str, remote: IpAddr) -> Result<()> {
+    match limiter.map(|limiter| limiter.acquire(remote, Instant::now())) {
+        None | Some(Throttle::Allowed) => Ok(()),
+        Some(Throttle::Limited { first: true }) => Err(refuse(request, remote, "rate limited")),
+        Some(Throttle::Limited { first: false }) => Err(Error::from_request("rate limited")),
+    }
+}
+
+fn refuse(request: &str, remote: IpAddr, reason: &str) -> Error {
+    eprintln!("Refused {} from {}: {}", request, remote, reason);
+    Error::from_request(reason)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tforge_config::PrivateConfig;
+
+    #[test]
+    fn test_rate_limiter() {
+        let limiter = RateLimiter::new(RateLimitConfig {
+            rate: 2.0,
+            burst: 3,
+        });
+        let start = Instant::now();
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+        for _ in 0..3 {
+            assert_eq!(limiter.acquire(ip, start), Throttle::Allowed);
+        }
+        assert_eq!(
+            limiter.acquire(ip, start),
+            Throttle::Limited { first: true }
+        );
+        assert_eq!(
+            limiter.acquire(ip, start),
+            Throttle::Limited { first: false }
+        );
+        let other: IpAddr = "10.0.0.2".parse().unwrap();
+        assert_eq!(limiter.acquire(other, start), Throttle::Allowed);
+
+        // Two tokens a second
+        let later = start + Duration::from_millis(500);
+        assert_eq!(limiter.acquire(ip, later), Throttle::Allowed);
+        assert_eq!(
+            limiter.acquire(ip, later),
+            Throttle::Limited { first: true }
+        );
+
+        // The addresses of a /64 share their bucket
+        let ip6: IpAddr = "2001:db8::1".parse().unwrap();
+        for _ in 0..3 {
+            assert_eq!(limiter.acquire(ip6, start), Throttle::Allowed);
+        }
+        let neighbour: IpAddr = "2001:db8::ffff:2".parse().unwrap();
+        assert_ne!(limiter.acquire(neighbour, start), Throttle::Allowed);
+
+        limiter.prune(start + Duration::from_millis(1000));
+        assert_eq!(limiter.len(), 2);
+        limiter.prune(start + Duration::from_secs(2));
+        assert_eq!(limiter.len(), 0);
+    }
+
+    #[test]
+    fn test_guard() {
+        let dir = std::env::temp_dir().join(format!("tforge-guard-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let blocklist = dir.join("blocklist.p2p");
+        std::fs::write(&blocklist, "Bad guys:10.0.0.0-10.0.0.255\n").unwrap();
+
+        let mut config = ServerConfig::default();
+        config.protection.blocklist = Some(blocklist.clone());
+        config.protection.banned_clients = vec!["-XL".to_string()];
+        config.private = Some(PrivateConfig {
+            banned_clients: vec!["-BC".to_string()],
+            ..Default::default()
+        });
+        let guard = Guard::new(&config);
+        guard.reload().unwrap();
+
+        let blocked: IpAddr = "10.0.0.1".parse().unwrap();
+        let allowed: IpAddr = "10.0.1.1".parse().unwrap();
+        let peer_id = *b"-TF0001-000000000000";
+        assert!(guard.check_announce(allowed, &peer_id).is_ok());
+        assert!(guard.check_scrape(allowed).is_ok());
+        let err = guard.check_scrape(blocked).unwrap_err();
+        assert_eq!(err.to_string(), "Invalid request: address blocked");
+        let err = guard
+            .check_announce(allowed, b"-XL0012-000000000000")
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Invalid request: banned client");
+        assert!(guard
+            .check_announce(allowed, b"-BC0100-000000000000")
+            .is_err());
+
+        // A list that can't be parsed leaves the one read before
+        std::fs::write(&blocklist, "10.0.0.0/33\n").unwrap();
+        let touched = SystemTime::now() + Duration::from_secs(10);
+        std::fs::File::options()
+            .write(true)
+            .open(&blocklist)
+            .unwrap()
+            .set_modified(touched)
+            .unwrap();
+        let err = guard.reload().unwrap_err();
+        let message = format!(
+            "IP list error: {}: line 1: invalid range 10.0.0.0/33",
+            blocklist.display()
+        );
+        assert_eq!(err.to_string(), message);
+        assert!(guard.check_scrape(blocked).is_err());
+
+        std::fs::write(&blocklist, "10.0.1.0/24\n").unwrap();
+        let touched = touched + Duration::from_secs(10);
+        std::fs::File::options()
+            .write(true)
+            .open(&blocklist)
+            .unwrap()
+            .set_modified(touched)
+            .unwrap();
+        guard.reload().unwrap();
+        assert!(guard.check_scrape(blocked).is_ok());
+        assert!(guard.check_scrape(allowed).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
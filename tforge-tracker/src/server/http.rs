@@ -0,0 +1,328 @@
+use super::{blocking, failure, metrics::Protocol, websocket, Tracker};
+use crate::{
+    error::{Error, Result},
+    protocol::{scrape::ScrapeRequest, TrackerRequest, TrackerResponse},
+};
+use axum::{
+    extract::{ws::WebSocketUpgrade, ConnectInfo, Path, RawQuery, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    routing::get,
+    Router,
+};
+use axum_server::tls_rustls::RustlsConfig;
+use std::{net::SocketAddr, sync::Arc, time::Instant};
+use tokio::net::TcpListener;
+
+/// The routes of the HTTP tracker
+pub fn router(tracker: Arc<Tracker>) -> Router {
+    Router::new()
+        .route("/announce", get(announce))
+        .route("/announce/:passkey", get(announce))
+        .route("/scrape", get(scrape))
+        .route("/scrape/:passkey", get(scrape))
+        .with_state(tracker)
+}
+
+/// Serves the HTTP tracker on `listener` until it fails
+pub async fn serve(listener: TcpListener, tracker: Arc<Tracker>) -> Result<()> {
+    let service = router(tracker).into_make_service_with_connect_info::<SocketAddr>();
+    axum::serve(listener, service).await.map_err(Error::from_io)
+}
+
+/// Serves the HTTP tracker over TLS on `listener` until it fails
+pub async fn serve_tls(
+    listener: TcpListener,
+    tracker: Arc<Tracker>,
+    certificates: RustlsConfig,
+) -> Result<()> {
+    let service = router(tracker).into_make_service_with_connect_info::<SocketAddr>();
+    axum_server::from_tcp_rustls(listener.into_std()?, certificates)
+        .serve(service)
+        .await
+        .map_err(Error::from_io)
+}
+
+/// Largest message of a WebTorrent client, its offers included
+const MAX_WEBSOCKET_MESSAGE: usize = 1 << 20;
+
+async fn announce(
+    State(tracker): State<Arc<Tracker>>,
+    ConnectInfo(remote): ConnectInfo<SocketAddr>,
+    passkey: Option<Path<String>>,
+    upgrade: Option<WebSocketUpgrade>,
+    RawQuery(query): RawQuery,
+) -> Response {
+    let websocket = tracker
+        .config()
+        .http
+        .as_ref()
+        .is_some_and(|http| http.websocket);
+    if let Some(upgrade) = upgrade.filter(|_| websocket) {
+        let passkey = passkey.map(|Path(passkey)| passkey);
+        return upgrade
+            .max_message_size(MAX_WEBSOCKET_MESSAGE)
+            .on_upgrade(move |socket| websocket::serve(socket, tracker, remote, passkey));
+    }
+    let start = Instant::now();
+    let passkey = passkey.map(|Path(passkey)| passkey);
+    let (event, response) = match TrackerRequest::from_query(query.as_deref().unwrap_or_default()) {
+        Ok(request) => {
+            let event = request.event;
+            let response = announce_upstream(&tracker, request, remote, passkey).await;
+            (event, response.unwrap_or_else(|err| failure(&err)))
+        }
+        Err(err) => (None, failure(&err)),
+    };
+    let failure_reason = response.failure_reason();
+    tracker
+        .metrics()
+        .record_announce(Protocol::Http, event, failure_reason, start.elapsed());
+    bencoded(response.to_bytes())
+}
+
+/// Announces, waiting for the upstream peers of a proxy once the announce is admitted
+async fn announce_upstream(
+    tracker: &Arc<Tracker>,
+    request: TrackerRequest,
+    remote: SocketAddr,
+    passkey: Option<String>,
+) -> Result<TrackerResponse> {
+    let admitted = blocking(tracker, {
+        let request = request.clone();
+        move |tracker| tracker.admit(&request, remote.ip(), passkey.as_deref())
+    });
+    let admission = match admitted.await? {
+        Ok(admission) => admission,
+        Err(err) => return Ok(failure(&err)),
+    };
+    tracker.wait_upstream(&request).await;
+    let recorded = blocking(tracker, move |tracker| {
+        tracker.record(&request, remote.ip(), admission)
+    });
+    Ok(match recorded.await? {
+        Ok(success) => TrackerResponse::Success(success),
+        Err(err) => failure(&err),
+    })
+}
+
+async fn scrape(
+    State(tracker): State<Arc<Tracker>>,
+    ConnectInfo(remote): ConnectInfo<SocketAddr>,
+    passkey: Option<Path<String>>,
+    RawQuery(query): RawQuery,
+) -> Response {
+    let start = Instant::now();
+    let passkey = passkey.map(|Path(passkey)| passkey);
+    let full_scrape = tracker
+        .config()
+        .http
+        .as_ref()
+        .is_some_and(|http| http.full_scrape);
+    let query = query.unwrap_or_default();
+    let response = blocking(&tracker, move |tracker| {
+        let request = ScrapeRequest::from_query(&query)?;
+        tracker.guard().check_scrape(remote.ip())?;
+        if tracker.config().private.is_some() {
+            tracker.authenticate(passkey.as_deref())?;
+        }
+        match request.info_hashes.is_empty() {
+            false => tracker.scrape(&request.info_hashes),
+            true if full_scrape => tracker.full_scrape(),
+            true => Err(Error::from_request("full scrape is disabled")),
+        }
+    })
+    .await
+    .and_then(|response| response);
+    let response = match response {
+        Ok(response) => {
+            tracker
+                .metrics()
+                .record_scrape(Protocol::Http, None, start.elapsed());
+            response.to_bytes()
+        }
+        Err(err) => {
+            let response = failure(&err);
+            let failure_reason = response.failure_reason();
+            tracker
+                .metrics()
+                .record_scrape(Protocol::Http, failure_reason, start.elapsed());
+            response.to_bytes()
+        }
+    };
+    bencoded(response)
+}
+
+/// Failures are sent with a 200 status too, as clients only read the body
+fn bencoded(response: Result<Vec<u8>>) -> Response {
+    match response {
+        Ok(body) => ([(header::CONTENT_TYPE, "text/plain")], body).into_response(),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        client::TrackerClient,
+        protocol::{scrape::ScrapeResponse, TrackerEvent, TrackerPeers},
+        server::storage::{Torrent, User},
+    };
+    use tforge_config::{HttpConfig, ServerConfig};
+
+    async fn start() -> String {
+        start_with(ServerConfig::default()).await
+    }
+
+    async fn start_with(config: ServerConfig) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/announce", listener.local_addr().unwrap());
+        let tracker = Arc::new(Tracker::new(config));
+        tokio::spawn(serve(listener, tracker));
+        url
+    }
+
+    fn request(peer_id: u8, compact: bool) -> TrackerRequest {
+        TrackerRequest {
+            info_hash: [0xaa; 20],
+            peer_id: [peer_id; 20],
+            port: 6880 + peer_id as u16,
+            uploaded: 0,
+            downloaded: 0,
+            left: 100,
+            compact,
+            event: Some(TrackerEvent::Started),
+            ip: None,
+            numwant: None,
+            no_peer_id: false,
+            key: None,
+            trackerid: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_http_announce() {
+        let client = TrackerClient::new(start().await, None);
+        let first = client.announce(&request(1, true)).await.unwrap();
+        assert!(first.peers.is_empty());
+        assert_eq!(first.incomplete, 1);
+
+        let second = client.announce(&request(2, true)).await.unwrap();
+        assert_eq!(
+            second.peer_addrs(),
+            vec!["127.0.0.1:6881".parse::<SocketAddr>().unwrap()]
+        );
+
+        let third = client.announce(&request(3, false)).await.unwrap();
+        let TrackerPeers::List(peers) = third.peers else {
+            panic!("expected a peer list");
+        };
+        assert_eq!(peers.len(), 2);
+        assert!(peers.iter().all(|peer| peer.peer_id.is_some()));
+    }
+
+    #[tokio::test]
+    async fn test_http_announce_failure() {
+        let url = start().await;
+        let response = reqwest::get(format!("{}?info_hash=short", url))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.bytes().await.unwrap();
+        assert_eq!(
+            &body[..],
+            b"d14:failure reason31:info_hash must be 20 bytes longe"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_http_scrape() {
+        let client = TrackerClient::new(start().await, None);
+        client.announce(&request(1, true)).await.unwrap();
+        let request = ScrapeRequest {
+            info_hashes: vec![[0xaa; 20], [0xbb; 20]],
+        };
+        let response = client.scrape(&request).await.unwrap();
+        assert_eq!(response.files.len(), 2);
+        assert_eq!(response.files[&[0xaa; 20]].incomplete, 1);
+        assert_eq!(response.files[&[0xbb; 20]].incomplete, 0);
+
+        match client.scrape(&ScrapeRequest::default()).await {
+            Err(Error::Failure(reason)) => assert_eq!(reason, "full scrape is disabled"),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_http_full_scrape() {
+        let config = ServerConfig {
+            http: Some(HttpConfig {
+                full_scrape: true,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let client = TrackerClient::new(start_with(config).await, None);
+        client.announce(&request(1, true)).await.unwrap();
+        let response = client.scrape(&ScrapeRequest::default()).await.unwrap();
+        assert_eq!(response.files.keys().collect::<Vec<_>>(), vec![&[0xaa; 20]]);
+    }
+
+    #[tokio::test]
+    async fn test_http_private() {
+        let config = ServerConfig {
+            private: Some(Default::default()),
+            ..Default::default()
+        };
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let tracker = Arc::new(Tracker::new(config));
+        tracker
+            .storage()
+            .add_user(User {
+                passkey: "secret".to_string(),
+                name: "alice".to_string(),
+                uploaded: 0,
+                downloaded: 0,
+            })
+            .unwrap();
+        tracker
+            .storage()
+            .register_torrent(Torrent {
+                info_hash: [0xaa; 20],
+                name: Some("debian.iso".to_string()),
+            })
+            .unwrap();
+        tokio::spawn(serve(listener, tracker));
+
+        let client = TrackerClient::new(format!("{}/announce/secret", url), None);
+        client.announce(&request(1, true)).await.unwrap();
+        let scrape = ScrapeRequest {
+            info_hashes: vec![[0xaa; 20]],
+        };
+        // Passkeys after `announce` keep clients from deriving the scrape URL themselves
+        let query = scrape.to_query();
+        let body = reqwest::get(format!("{}/scrape/secret?{}", url, query))
+            .await
+            .unwrap()
+            .bytes()
+            .await
+            .unwrap();
+        let response = ScrapeResponse::from_bytes(&body).unwrap();
+        assert_eq!(
+            response.files[&[0xaa; 20]].name.as_deref(),
+            Some("debian.iso")
+        );
+
+        let client = TrackerClient::new(format!("{}/announce", url), None);
+        match client.announce(&request(2, true)).await {
+            Err(Error::Failure(reason)) => assert_eq!(reason, "missing passkey"),
+            other => panic!("unexpected {:?}", other),
+        }
+        match client.scrape(&scrape).await {
+            Err(Error::Failure(reason)) => assert_eq!(reason, "missing passkey"),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+}
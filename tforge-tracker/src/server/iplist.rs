@@ -0,0 +1,179 @@
+use crate::error::{Error, Result};
+use std::net::{IpAddr, Ipv4Addr};
+
+/// Ranges of IP addresses, for blocklists and allowlists.
+///
+/// IPv4 addresses are kept as IPv4-mapped IPv6 ones, so that both families
+/// share one sorted list of non-overlapping inclusive ranges.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct IpRanges {
+    ranges: Vec<(u128, u128)>,
+}
+
+impl IpRanges {
+    /// Parses a list with a range per line, in any of the formats:
+    /// - an address or a CIDR, e.g. `10.0.0.0/8` or `2001:db8::/32`;
+    /// - P2P, e.g. `Some name:1.2.3.0-1.2.3.255`;
+    /// - DAT, e.g. `001.002.003.000 - 001.002.003.255 , 000 , Some name`, whose
+    ///   entries of access level 128 and above, the allowed ones, are skipped.
+    ///
+    /// Empty lines and lines starting with `#` are ignored.
+    pub fn parse(contents: &str) -> Result<Self> {
+        let mut ranges = Vec::new();
+        for (number, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let range = parse_range(line)
+                .map(Some)
+                .or_else(|| parse_p2p(line).map(Some))
+                .or_else(|| parse_dat(line))
+                .ok_or_else(|| {
+                    Error::from_ip_list(format!("line {}: invalid range {}", number + 1, line))
+                })?;
+            ranges.extend(range);
+        }
+
+        ranges.sort_unstable();
+        let mut merged: Vec<(u128, u128)> = Vec::with_capacity(ranges.len());
+        for (start, end) in ranges {
+            match merged.last_mut() {
+                Some(last) if start <= last.1.saturating_add(1) => last.1 = last.1.max(end),
+                _ => merged.push((start, end)),
+            }
+        }
+        Ok(IpRanges { ranges: merged })
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        let ip = key(ip);
+        let after = self.ranges.partition_point(|(start, _)| *start <= ip);
+        after > 0 && self.ranges[after - 1].1 >= ip
+    }
+
+    /// The ranges left once the overlapping ones are merged
+    pub fn len(&self) -> usize {
+        self.ranges.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+}
+
+fn key(ip: IpAddr) -> u128 {
+    match ip {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped().into(),
+        IpAddr::V6(ip) => ip.into(),
+    }
+}
+
+/// An address, a CIDR or two addresses separated by `-`
+fn parse_range(range: &str) -> Option<(u128, u128)> {
+    if let Some((ip, prefix)) = range.split_once('/') {
+        let ip = parse_ip(ip)?;
+        let prefix: u32 = prefix.trim().parse().ok()?;
+        let prefix = match ip {
+            IpAddr::V4(_) if prefix <= 32 => prefix + 96,
+            IpAddr::V6(_) if prefix <= 128 => prefix,
+            _ => return None,
+        };
+        let hosts = u128::MAX.checked_shr(prefix).unwrap_or(0);
+        let start = key(ip) & !hosts;
+        return Some((start, start | hosts));
+    }
+    let (start, end) = match range.split_once('-') {
+        Some((start, end)) => (key(parse_ip(start)?), key(parse_ip(end)?)),
+        None => {
+            let ip = key(parse_ip(range)?);
+            (ip, ip)
+        }
+    };
+    (start <= end).then_some((start, end))
+}
+
+/// DAT files pad IPv4 addresses with zeros, which `Ipv4Addr` refuses as octal
+fn parse_ip(ip: &str) -> Option<IpAddr> {
+    let ip = ip.trim();
+    if let Ok(ip) = ip.parse() {
+        return Some(ip);
+    }
+    let mut octets = [0; 4];
+    let mut parts = ip.split('.');
+    for octet in &mut octets {
+        *octet = parts.next()?.parse().ok()?;
+    }
+    parts
+        .next()
+        .is_none()
+        .then_some(IpAddr::V4(Ipv4Addr::from(octets)))
+}
+
+/// `name:start-end`, the name may contain colons too
+fn parse_p2p(line: &str) -> Option<(u128, u128)> {
+    let (_, range) = line.rsplit_once(':')?;
+    parse_range(range)
+}
+
+/// `start - end , level , name`, none for the allowed levels
+fn parse_dat(line: &str) -> Option<Option<(u128, u128)>> {
+    let mut fields = line.split(',');
+    let range = parse_range(fields.next()?)?;
+    let level: u32 = match fields.next() {
+        Some(level) => level.trim().parse().ok()?,
+        None => 0,
+    };
+    Some((level < 128).then_some(range))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let contents = "
+            # A comment
+            10.0.0.0/8
+            192.168.1.1
+            2001:db8::/32
+            Bad guys, Inc:1.2.3.0-1.2.3.255
+            Bad guys:1.2.4.0-1.2.4.9
+            001.002.005.000 - 001.002.005.255 , 000 , Spammers: more of them
+            004.004.004.000 - 004.004.004.255 , 200 , Friends
+        ";
+        let ranges = IpRanges::parse(contents).unwrap();
+        // The two P2P ranges are merged, the allowed DAT one is left out
+        assert_eq!(ranges.len(), 5);
+        for ip in [
+            "10.1.2.3",
+            "192.168.1.1",
+            "2001:db8::1",
+            "1.2.3.0",
+            "1.2.4.9",
+            "1.2.5.255",
+            "::ffff:10.0.0.1",
+        ] {
+            assert!(ranges.contains(ip.parse().unwrap()), "{}", ip);
+        }
+        for ip in [
+            "11.0.0.0",
+            "192.168.1.2",
+            "2001:db9::",
+            "1.2.4.10",
+            "4.4.4.4",
+        ] {
+            assert!(!ranges.contains(ip.parse().unwrap()), "{}", ip);
+        }
+
+        assert!(IpRanges::parse("0.0.0.0/0")
+            .unwrap()
+            .contains("255.255.255.255".parse().unwrap()));
+        let err = IpRanges::parse("10.0.0.0/8\n10.0.0.9-10.0.0.1").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "IP list error: line 2: invalid range 10.0.0.9-10.0.0.1"
+        );
+    }
+}
@@ -0,0 +1,283 @@
+use crate::protocol::TrackerEvent;
+use std::{
+    fmt::Write,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+/// The listener a request came through
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    Http,
+    Udp,
+    WebSocket,
+}
+
+const PROTOCOLS: [Protocol; 3] = [Protocol::Http, Protocol::Udp, Protocol::WebSocket];
+
+impl Protocol {
+    fn label(self) -> &'static str {
+        match self {
+            Protocol::Http => "http",
+            Protocol::Udp => "udp",
+            Protocol::WebSocket => "websocket",
+        }
+    }
+}
+
+const EVENTS: [&str; 4] = ["none", "started", "stopped", "completed"];
+
+fn event_index(event: Option<TrackerEvent>) -> usize {
+    match event {
+        None => 0,
+        Some(TrackerEvent::Started) => 1,
+        Some(TrackerEvent::Stopped) => 2,
+        Some(TrackerEvent::Completed) => 3,
+    }
+}
+
+/// The failure reasons counted apart, by the start of their message
+const KNOWN_REASONS: [(&str, &str); 11] = [
+    ("missing passkey", "missing_passkey"),
+    ("unknown passkey", "unknown_passkey"),
+    ("unregistered torrent", "unregistered_torrent"),
+    ("banned client", "banned_client"),
+    ("ratio", "ratio"),
+    ("full scrape is disabled", "full_scrape_disabled"),
+    ("invalid connection id", "invalid_connection_id"),
+    ("rate limited", "rate_limited"),
+    ("address blocked", "blocked_address"),
+    ("address not allowed", "blocked_address"),
+    ("announced too soon", "too_soon"),
+];
+
+/// Failures of the tracker itself rather than of the request, by the start of their message
+const INTERNAL_PREFIXES: [&str; 3] = ["Storage error", "IO error", "Bencode error"];
+
+const REASONS: [&str; 12] = [
+    "missing_passkey",
+    "unknown_passkey",
+    "unregistered_torrent",
+    "banned_client",
+    "ratio",
+    "full_scrape_disabled",
+    "invalid_connection_id",
+    "rate_limited",
+    "blocked_address",
+    "too_soon",
+    "invalid_request",
+    "internal",
+];
+
+/// The reason label of a failure, out of a fixed set so that the series of
+/// errors don't grow with the messages
+pub fn reason(failure_reason: &str) -> &'static str {
+    let known = KNOWN_REASONS
+        .iter()
+        .find(|(prefix, _)| failure_reason.starts_with(prefix));
+    match known {
+        Some((_, reason)) => reason,
+        None if INTERNAL_PREFIXES
+            .iter()
+            .any(|prefix| failure_reason.starts_with(prefix)) =>
+        {
+            "internal"
+        }
+        None => "invalid_request",
+    }
+}
+
+/// Upper bounds of the latency buckets, in seconds
+const BUCKETS: [f64; 10] = [
+    0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.1, 1.0,
+];
+
+#[derive(Debug, Default)]
+struct Histogram {
+    /// Observations in each bucket, not cumulated
+    buckets: [AtomicU64; BUCKETS.len()],
+    count: AtomicU64,
+    sum_nanos: AtomicU64,
+}
+
+impl Histogram {
+    fn observe(&self, elapsed: Duration) {
+        let seconds = elapsed.as_secs_f64();
+        if let Some(bucket) = BUCKETS.iter().position(|bound| seconds <= *bound) {
+            self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_nanos
+            .fetch_add(elapsed.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    fn render(&self, out: &mut String, name: &str, labels: &str) {
+        let mut cumulated = 0;
+        for (bound, bucket) in BUCKETS.iter().zip(&self.buckets) {
+            cumulated += bucket.load(Ordering::Relaxed);
+            let _ = writeln!(
+                out,
+                "{}_bucket{{{},le=\"{}\"}} {}",
+                name, labels, bound, cumulated
+            );
+        }
+        let count = self.count.load(Ordering::Relaxed);
+        let sum = self.sum_nanos.load(Ordering::Relaxed) as f64 / 1e9;
+        let _ = writeln!(out, "{}_bucket{{{},le=\"+Inf\"}} {}", name, labels, count);
+        let _ = writeln!(out, "{}_sum{{{}}} {}", name, labels, sum);
+        let _ = writeln!(out, "{}_count{{{}}} {}", name, labels, count);
+    }
+}
+
+/// The counters of the requests served, by protocol
+#[derive(Debug, Default)]
+pub struct Metrics {
+    announces: [[AtomicU64; EVENTS.len()]; PROTOCOLS.len()],
+    scrapes: [AtomicU64; PROTOCOLS.len()],
+    errors: [[AtomicU64; REASONS.len()]; PROTOCOLS.len()],
+    announce_latency: [Histogram; PROTOCOLS.len()],
+    scrape_latency: [Histogram; PROTOCOLS.len()],
+}
+
+impl Metrics {
+    /// Counts an announce and how long it took, with the reason it was refused if it was
+    pub fn record_announce(
+        &self,
+        protocol: Protocol,
+        event: Option<TrackerEvent>,
+        failure_reason: Option<&str>,
+        elapsed: Duration,
+    ) {
+        let protocol = protocol as usize;
+        self.announces[protocol][event_index(event)].fetch_add(1, Ordering::Relaxed);
+        self.announce_latency[protocol].observe(elapsed);
+        self.record_failure(protocol, failure_reason);
+    }
+
+    /// Counts a scrape and how long it took, with the reason it was refused if it was
+    pub fn record_scrape(
+        &self,
+        protocol: Protocol,
+        failure_reason: Option<&str>,
+        elapsed: Duration,
+    ) {
+        let protocol = protocol as usize;
+        self.scrapes[protocol].fetch_add(1, Ordering::Relaxed);
+        self.scrape_latency[protocol].observe(elapsed);
+        self.record_failure(protocol, failure_reason);
+    }
+
+    fn record_failure(&self, protocol: usize, failure_reason: Option<&str>) {
+        if let Some(failure_reason) = failure_reason {
+            let reason = reason(failure_reason);
+            let index = REASONS.iter().position(|known| *known == reason).unwrap();
+            self.errors[protocol][index].fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Writes the counters in the Prometheus text format
+    pub fn render(&self, out: &mut String) {
+        out.push_str("# HELP tforge_tracker_announces_total Announces received.\n");
+        out.push_str("# TYPE tforge_tracker_announces_total counter\n");
+        for protocol in PROTOCOLS {
+            for (event, count) in EVENTS.iter().zip(&self.announces[protocol as usize]) {
+                let _ = writeln!(
+                    out,
+                    "tforge_tracker_announces_total{{protocol=\"{}\",event=\"{}\"}} {}",
+                    protocol.label(),
+                    event,
+                    count.load(Ordering::Relaxed)
+                );
+            }
+        }
+
+        out.push_str("# HELP tforge_tracker_scrapes_total Scrapes received.\n");
+        out.push_str("# TYPE tforge_tracker_scrapes_total counter\n");
+        for protocol in PROTOCOLS {
+            let _ = writeln!(
+                out,
+                "tforge_tracker_scrapes_total{{protocol=\"{}\"}} {}",
+                protocol.label(),
+                self.scrapes[protocol as usize].load(Ordering::Relaxed)
+            );
+        }
+
+        out.push_str("# HELP tforge_tracker_errors_total Requests refused or failed.\n");
+        out.push_str("# TYPE tforge_tracker_errors_total counter\n");
+        for protocol in PROTOCOLS {
+            for (reason, count) in REASONS.iter().zip(&self.errors[protocol as usize]) {
+                let _ = writeln!(
+                    out,
+                    "tforge_tracker_errors_total{{protocol=\"{}\",reason=\"{}\"}} {}",
+                    protocol.label(),
+                    reason,
+                    count.load(Ordering::Relaxed)
+                );
+            }
+        }
+
+        let histograms = [
+            ("announce", &self.announce_latency),
+            ("scrape", &self.scrape_latency),
+        ];
+        let name = "tforge_tracker_request_duration_seconds";
+        let _ = writeln!(out, "# HELP {} Time taken to serve requests.", name);
+        let _ = writeln!(out, "# TYPE {} histogram", name);
+        for (request, histograms) in histograms {
+            for protocol in PROTOCOLS {
+                let labels = format!("protocol=\"{}\",request=\"{}\"", protocol.label(), request);
+                histograms[protocol as usize].render(out, name, &labels);
+            }
+        }
+    }
+}
+
+/// Writes a gauge in the Prometheus text format
+pub fn render_gauge(out: &mut String, name: &str, help: &str, value: u64) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} gauge", name);
+    let _ = writeln!(out, "{} {}", name, value);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reason() {
+        assert_eq!(reason("unknown passkey"), "unknown_passkey");
+        assert_eq!(reason("ratio 0.30 is below the minimum of 0.50"), "ratio");
+        assert_eq!(reason("Storage error: disk I/O error"), "internal");
+        assert_eq!(reason("missing info_hash"), "invalid_request");
+        assert_eq!(reason("address not allowed"), "blocked_address");
+        assert!(KNOWN_REASONS
+            .iter()
+            .all(|(_, reason)| REASONS.contains(reason)));
+    }
+
+    #[test]
+    fn test_render() {
+        let metrics = Metrics::default();
+        let fast = Duration::from_micros(200);
+        metrics.record_announce(Protocol::Http, Some(TrackerEvent::Started), None, fast);
+        metrics.record_announce(Protocol::Udp, None, Some("banned client"), fast);
+        metrics.record_scrape(Protocol::Http, None, Duration::from_secs(2));
+
+        let mut out = String::new();
+        metrics.render(&mut out);
+        let lines: Vec<_> = out.lines().collect();
+        for expected in [
+            "tforge_tracker_announces_total{protocol=\"http\",event=\"started\"} 1",
+            "tforge_tracker_announces_total{protocol=\"udp\",event=\"none\"} 1",
+            "tforge_tracker_scrapes_total{protocol=\"http\"} 1",
+            "tforge_tracker_errors_total{protocol=\"udp\",reason=\"banned_client\"} 1",
+            "tforge_tracker_request_duration_seconds_bucket{protocol=\"http\",request=\"announce\",le=\"0.0001\"} 0",
+            "tforge_tracker_request_duration_seconds_bucket{protocol=\"http\",request=\"announce\",le=\"0.00025\"} 1",
+            "tforge_tracker_request_duration_seconds_bucket{protocol=\"http\",request=\"scrape\",le=\"1\"} 0",
+            "tforge_tracker_request_duration_seconds_bucket{protocol=\"http\",request=\"scrape\",le=\"+Inf\"} 1",
+            "tforge_tracker_request_duration_seconds_sum{protocol=\"http\",request=\"scrape\"} 2",
+        ] {
+            assert!(lines.contains(&expected), "{}", expected);
+        }
+    }
+}
@@ -0,0 +1,973 @@
+pub mod admin;
+pub mod guard;
+pub mod http;
+pub mod iplist;
+pub mod metrics;
+pub mod natcheck;
+pub mod policy;
+pub mod proxy;
+pub mod registry;
+pub mod storage;
+pub mod swarm;
+pub mod tls;
+pub mod udp;
+pub mod websocket;
+
+use crate::{
+    error::{Error, Result},
+    protocol::{
+        compact::CompactPeers,
+        scrape::{ScrapeFile, ScrapeResponse},
+        TrackerEvent,
+        TrackerPeer,
+        TrackerPeerAddress,
+        TrackerPeers,
+        TrackerRequest,
+        TrackerResponse,
+        TrackerResponseFailure,
+        TrackerResponseSuccess,
+    },
+};
+use guard::Guard;
+use metrics::Metrics;
+use natcheck::NatChecker;
+use policy::LoadMeter;
+use proxy::Proxy;
+use std::{
+    cell::RefCell,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+use storage::{memory::MemoryStorage, Storage, User};
+use swarm::{format_tracker_id, CompactAddr, InfoHash, Peer, PeerId, Selection, SwarmStats};
+use tforge_config::{PrivateConfig, ServerConfig, StorageConfig};
+use tokio::{net::TcpListener, task::JoinSet};
+use websocket::Signaling;
+
+thread_local! {
+    /// The peers selected by the announces of a thread, kept to reuse the buffer
+    static SELECTED: RefCell<Vec<(PeerId, CompactAddr)>> = const { RefCell::new(Vec::new()) };
+}
+
+/// An announce which passed every check of [`Tracker::admit`], to record
+pub struct Admission {
+    /// The address of the peer, the `ip` of the request for trusted sources
+    ip: IpAddr,
+
+    /// The user announcing to a private tracker
+    user: Option<User>,
+}
+
+/// The state of a tracker, shared by all its listeners
+pub struct Tracker {
+    config: ServerConfig,
+    storage: Arc<dyn Storage>,
+    load: LoadMeter,
+    metrics: Metrics,
+    guard: Guard,
+    nat_checker: Option<NatChecker>,
+    signaling: Signaling,
+    proxy: Option<Proxy>,
+}
+
+impl Tracker {
+    /// A tracker keeping its swarms in memory
+    pub fn new(config: ServerConfig) -> Self {
+        Tracker::with_storage(config, Arc::new(MemoryStorage::new()))
+    }
+
+    pub fn with_storage(config: ServerConfig, storage: Arc<dyn Storage>) -> Self {
+        Tracker {
+            guard: Guard::new(&config),
+            nat_checker: config.nat_check.clone().map(NatChecker::new),
+            proxy: config.proxy.clone().map(Proxy::new),
+            config,
+            storage,
+            load: LoadMeter::default(),
+            metrics: Metrics::default(),
+            signaling: Signaling::default(),
+        }
+    }
+
+    pub fn config(&self) -> &ServerConfig {
+        &self.config
+    }
+
+    pub fn storage(&self) -> &dyn Storage {
+        self.storage.as_ref()
+    }
+
+    pub fn load(&self) -> &LoadMeter {
+        &self.load
+    }
+
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    pub fn guard(&self) -> &Guard {
+        &self.guard
+    }
+
+    pub fn nat_checker(&self) -> Option<&NatChecker> {
+        self.nat_checker.as_ref()
+    }
+
+    pub fn signaling(&self) -> &Signaling {
+        &self.signaling
+    }
+
+    pub fn proxy(&self) -> Option<&Proxy> {
+        self.proxy.as_ref()
+    }
+
+    /// The metrics of the tracker and of its swarms, in the Prometheus text format
+    pub fn render_metrics(&self) -> Result<String> {
+        let mut out = String::new();
+        self.metrics.render(&mut out);
+
+        let all_stats = self.storage.all_stats()?;
+        let active = all_stats
+            .iter()
+            .filter(|(_, stats)| stats.complete + stats.incomplete > 0)
+            .count();
+        let seeders = all_stats.iter().map(|(_, stats)| stats.complete).sum();
+        let leechers = all_stats.iter().map(|(_, stats)| stats.incomplete).sum();
+        let gauges = [
+            (
+                "tforge_tracker_torrents",
+                "Torrents with peers.",
+                active as u64,
+            ),
+            (
+                "tforge_tracker_peers",
+                "Peers of all torrents.",
+                seeders + leechers,
+            ),
+            (
+                "tforge_tracker_seeders",
+                "Seeders of all torrents.",
+                seeders,
+            ),
+            (
+                "tforge_tracker_leechers",
+                "Leechers of all torrents.",
+                leechers,
+            ),
+            (
+                "tforge_tracker_announce_rate",
+                "Announces per second, as the adaptive interval measures them.",
+                self.load.rate(),
+            ),
+            (
+                "tforge_tracker_websocket_peers",
+                "Peers connected over WebSocket.",
+                self.signaling.len() as u64,
+            ),
+        ];
+        for (name, help, value) in gauges {
+            metrics::render_gauge(&mut out, name, help, value);
+        }
+        Ok(out)
+    }
+
+    /// Records an announce from `remote` and picks the peers to return.
+    /// The `ip` of the request only replaces the address of the peer when
+    /// `remote` is one of the `trusted_sources`, so that no one can add
+    /// another host to the swarms.
+    ///
+    /// A private tracker also checks the `passkey` of the user and the
+    /// torrent, and accounts the transfer of the user. A proxy adds the
+    /// upstream peers it has cached, and asks for them when they are stale.
+    pub fn announce(
+        &self,
+        request: &TrackerRequest,
+        remote: IpAddr,
+        passkey: Option<&str>,
+    ) -> TrackerResponse {
+        let success = self
+            .admit(request, remote, passkey)
+            .and_then(|admission| self.record(request, remote, admission));
+        match success {
+            Ok(success) => TrackerResponse::Success(success),
+            Err(err) => failure(&err),
+        }
+    }
+
+    /// Waits for the first upstream peers of the torrent of an admitted
+    /// announce, when the tracker is a proxy
+    pub async fn wait_upstream(&self, request: &TrackerRequest) {
+        let Some(proxy) = &self.proxy else {
+            return;
+        };
+        if request.event != Some(TrackerEvent::Stopped) {
+            proxy.wait(&request.info_hash).await;
+        }
+    }
+
+    /// Refuses an announce from `remote` before anything of it is recorded:
+    /// the guard, a private tracker and the min interval all have their say
+    pub fn admit(
+        &self,
+        request: &TrackerRequest,
+        remote: IpAddr,
+        passkey: Option<&str>,
+    ) -> Result<Admission> {
+        self.load.record();
+        self.guard.check_announce(remote, &request.peer_id)?;
+        let ip = self.guard.peer_address(remote, request.ip)?.to_canonical();
+        let user = match &self.config.private {
+            Some(private) => {
+                let user = self.authenticate(passkey)?;
+                self.check_private(private, &user, request)?;
+                Some(user)
+            }
+            None => None,
+        };
+        if self.config.protection.enforce_min_interval && request.event.is_none() {
+            self.check_interval(request)?;
+        }
+        Ok(Admission { ip, user })
+    }
+
+    /// Records an announce [`Tracker::admit`] let through, and picks the peers to return
+    pub fn record(
+        &self,
+        request: &TrackerRequest,
+        remote: IpAddr,
+        admission: Admission,
+    ) -> Result<TrackerResponseSuccess> {
+        let Admission { ip, user } = admission;
+        let peer = Peer {
+            addr: SocketAddr::new(ip, request.port),
+            uploaded: request.uploaded as u64,
+            downloaded: request.downloaded as u64,
+            left: request.left as u64,
+            tracker_id: rand::random(),
+            last_seen: SystemTime::now(),
+            connectable: self.connectable(request, remote),
+        };
+        let selection = Selection {
+            peer_id: request.peer_id,
+            addr: peer.addr,
+            seeder: peer.is_seeder(),
+            connectable: peer.connectable,
+            count: request
+                .numwant
+                .unwrap_or(self.config.default_numwant)
+                .min(self.config.max_numwant),
+            config: self.config.selection,
+        };
+
+        // Only accepted announces are accounted, refused ones are counted by the next accepted one
+        if let Some(user) = &user {
+            self.account(user, request)?;
+        }
+        let (info_hash, peer_id) = (&request.info_hash, &request.peer_id);
+        let (tracker_id, peers, peers6, upstream) = SELECTED.with_borrow_mut(|selected| {
+            selected.clear();
+            let mut upstream = (0, 0);
+            let tracker_id = match request.event {
+                Some(TrackerEvent::Stopped) => {
+                    self.storage.remove_peer(info_hash, peer_id)?;
+                    None
+                }
+                event => {
+                    if event == Some(TrackerEvent::Completed) {
+                        self.storage.add_completed(info_hash)?;
+                    }
+                    let tracker_id = self.storage.upsert_peer(info_hash, peer_id, peer)?;
+                    self.storage.select_peers(info_hash, &selection, selected)?;
+                    if let Some(proxy) = &self.proxy {
+                        proxy.request(info_hash);
+                        let count = selection.count.saturating_sub(selected.len());
+                        let selection = Selection { count, ..selection };
+                        upstream = proxy.extend(info_hash, &selection, selected);
+                    }
+                    Some(format_tracker_id(tracker_id))
+                }
+            };
+            let (peers, peers6) = peers(request, selected);
+            Ok::<_, Error>((tracker_id, peers, peers6, upstream))
+        })?;
+        let stats = self.storage.torrent_stats(info_hash)?;
+        let (complete, incomplete) = upstream;
+        let (interval, min_interval) = policy::intervals(
+            &self.config,
+            stats.complete + stats.incomplete,
+            self.load.rate(),
+        );
+
+        Ok(TrackerResponseSuccess {
+            complete: stats.complete as usize + complete,
+            incomplete: stats.incomplete as usize + incomplete,
+            interval: interval as usize,
+            min_interval: Some(min_interval as usize),
+            peers,
+            peers6,
+            tracker_id,
+            warning_message: None,
+        })
+    }
+
+    /// Whether the peer announcing accepts connections, as found by its last
+    /// NAT check, asking for one when its address wasn't checked lately. Until
+    /// it is, the peer is taken as connectable. WebRTC peers, without a
+    /// port, aren't checked.
+    ///
+    /// The address checked is the one the announce came from, never the `ip`
+    /// of the request, so that the tracker can't be sent to connect elsewhere.
+    fn connectable(&self, request: &TrackerRequest, remote: IpAddr) -> bool {
+        let Some(checker) = self.nat_checker.as_ref().filter(|_| request.port != 0) else {
+            return true;
+        };
+        let addr = SocketAddr::new(remote.to_canonical(), request.port);
+        if request.event != Some(TrackerEvent::Stopped) {
+            checker.request(&request.info_hash, &request.peer_id, addr);
+        }
+        checker.connectable(addr).unwrap_or(true)
+    }
+
+    /// Drops the peers that timed out, returning how many there were
+    pub fn reap(&self) -> Result<usize> {
+        let before = SystemTime::now() - policy::peer_timeout(&self.config);
+        self.guard.prune();
+        if let Some(checker) = &self.nat_checker {
+            checker.prune();
+        }
+        if let Some(proxy) = &self.proxy {
+            proxy.prune(policy::peer_timeout(&self.config));
+        }
+        self.storage.expire_peers(before)
+    }
+
+    /// Refuses a regular announce sent before `min_interval` is over. The
+    /// times of the peers are kept in seconds, a second short is let through.
+    fn check_interval(&self, request: &TrackerRequest) -> Result<()> {
+        let (info_hash, peer_id) = (&request.info_hash, &request.peer_id);
+        let Some(peer) = self.storage.peer(info_hash, peer_id)? else {
+            return Ok(());
+        };
+        let stats = self.storage.torrent_stats(info_hash)?;
+        let peers = stats.complete + stats.incomplete;
+        let (_, min_interval) = policy::intervals(&self.config, peers, self.load.rate());
+        let elapsed = SystemTime::now()
+            .duration_since(peer.last_seen)
+            .unwrap_or_default()
+            .as_secs();
+        if elapsed + 1 < min_interval {
+            return Err(Error::from_request(format!(
+                "announced too soon, min interval is {} seconds",
+                min_interval
+            )));
+        }
+        Ok(())
+    }
+
+    /// The user of a private tracker with `passkey`
+    pub fn authenticate(&self, passkey: Option<&str>) -> Result<User> {
+        let passkey = passkey.ok_or_else(|| Error::from_request("missing passkey"))?;
+        self.storage
+            .user(passkey)?
+            .ok_or_else(|| Error::from_request("unknown passkey"))
+    }
+
+    /// Refuses what a private tracker doesn't serve
+    fn check_private(
+        &self,
+        private: &PrivateConfig,
+        user: &User,
+        request: &TrackerRequest,
+    ) -> Result<()> {
+        if self.storage.torrent(&request.info_hash)?.is_none() {
+            return Err(Error::from_request("unregistered torrent"));
+        }
+        let leeching = request.left > 0 && request.event != Some(TrackerEvent::Stopped);
+        if let Some(min_ratio) = private.min_ratio {
+            if leeching && user.downloaded > private.ratio_grace && user.ratio() < min_ratio {
+                return Err(Error::from_request(format!(
+                    "ratio {:.2} is below the minimum of {:.2}",
+                    user.ratio(),
+                    min_ratio
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Adds the bytes transferred since the last announce of the peer to its
+    /// user, before the announce replaces the peer
+    fn account(&self, user: &User, request: &TrackerRequest) -> Result<()> {
+        let (info_hash, peer_id) = (&request.info_hash, &request.peer_id);
+        // Counters restart with `started`. Without it, only what they grew
+        // since the last announce known counts.
+        let (uploaded, downloaded) = (request.uploaded as u64, request.downloaded as u64);
+        let transfer = match request.event {
+            Some(TrackerEvent::Started) => Some((uploaded, downloaded)),
+            _ => self.storage.peer(info_hash, peer_id)?.and_then(|peer| {
+                let uploaded = uploaded.checked_sub(peer.uploaded)?;
+                let downloaded = downloaded.checked_sub(peer.downloaded)?;
+                Some((uploaded, downloaded))
+            }),
+        };
+        if let Some((uploaded, downloaded)) = transfer {
+            self.storage
+                .add_transfer(&user.passkey, uploaded, downloaded)?;
+        }
+        Ok(())
+    }
+
+    /// The stats of torrents, zero for unknown ones
+    pub fn stats(&self, info_hashes: &[InfoHash]) -> Result<Vec<SwarmStats>> {
+        self.storage.stats(info_hashes)
+    }
+
+    /// The scrape of torrents, unknown ones included, named when registered
+    pub fn scrape(&self, info_hashes: &[InfoHash]) -> Result<ScrapeResponse> {
+        let stats = self.storage.stats(info_hashes)?;
+        let mut response = ScrapeResponse::default();
+        for (info_hash, stats) in info_hashes.iter().zip(stats) {
+            let mut file = ScrapeFile::from(stats);
+            file.name = self
+                .storage
+                .torrent(info_hash)?
+                .and_then(|torrent| torrent.name);
+            response.files.insert(*info_hash, file);
+        }
+        Ok(response)
+    }
+
+    /// The scrape of every torrent with peers or completed downloads
+    pub fn full_scrape(&self) -> Result<ScrapeResponse> {
+        let mut response = ScrapeResponse::default();
+        for (info_hash, stats) in self.storage.all_stats()? {
+            response.files.insert(info_hash, stats.into());
+        }
+        for torrent in self.storage.torrents()? {
+            if let Some(file) = response.files.get_mut(&torrent.info_hash) {
+                file.name = torrent.name;
+            }
+        }
+        Ok(response)
+    }
+}
+
+/// The `peers` and `peers6` of a response from the selected peers, whose
+/// compact addresses are copied as they are kept
+fn peers(
+    request: &TrackerRequest,
+    selected: &[(PeerId, CompactAddr)],
+) -> (TrackerPeers, Option<CompactPeers>) {
+    match request.compact {
+        true => {
+            let (mut peers, mut peers6) = (CompactPeers::new(), CompactPeers::new());
+            for (_, addr) in selected {
+                match addr.is_ipv4() {
+                    true => peers.push(addr.as_bytes()),
+                    false => peers6.push(addr.as_bytes()),
+                }
+            }
+            let peers6 = (!peers6.is_empty()).then_some(peers6);
+            (TrackerPeers::Compact(peers), peers6)
+        }
+        false => {
+            let peers = selected
+                .iter()
+                .map(|(peer_id, addr)| {
+                    let addr = addr.to_socket_addr();
+                    TrackerPeer {
+                        ip: TrackerPeerAddress::IP(addr.ip()),
+                        peer_id: (!request.no_peer_id).then(|| peer_id.to_vec()),
+                        port: addr.port(),
+                    }
+                })
+                .collect();
+            (TrackerPeers::List(peers), None)
+        }
+    }
+}
+
+/// The passkey in the path of an announce URL: `/announce/<passkey>`
+pub fn passkey(path: &str) -> Option<&str> {
+    let path = path.split('?').next()?;
+    let passkey = path.strip_prefix("/announce/")?;
+    (!passkey.is_empty() && !passkey.contains('/')).then_some(passkey)
+}
+
+/// A reply refusing a request
+pub fn failure(err: &Error) -> TrackerResponse {
+    let failure_reason = refusal(err);
+    TrackerResponse::Failure(TrackerResponseFailure { failure_reason })
+}
+
+/// The reason sent back to a client whose request failed with `err`
+pub fn refusal(err: &Error) -> String {
+    match err {
+        Error::Request(reason) | Error::Failure(reason) => reason.clone(),
+        err => err.to_string(),
+    }
+}
+
+/// Runs `call` on the blocking threads when the storage of the tracker waits
+/// on the disk, right away otherwise. Async code calls the tracker through it.
+pub async fn blocking<T, F>(tracker: &Arc<Tracker>, call: F) -> Result<T>
+where
+    F: FnOnce(&Tracker) -> T + Send + 'static,
+    T: Send + 'static,
+{
+    if !tracker.storage.blocks() {
+        return Ok(call(tracker));
+    }
+    let tracker = tracker.clone();
+    tokio::task::spawn_blocking(move || call(&tracker))
+        .await
+        .map_err(|err| Error::from_io(err.into()))
+}
+
+/// Drops the peers that timed out every `reap_interval`, until it fails
+pub async fn reap_every(tracker: Arc<Tracker>) -> Result<()> {
+    let mut ticks = tokio::time::interval(Duration::from_secs(tracker.config.reap_interval));
+    loop {
+        ticks.tick().await;
+        let tracker = tracker.clone();
+        tokio::task::spawn_blocking(move || tracker.reap())
+            .await
+            .map_err(|err| Error::from_io(err.into()))??;
+    }
+}
+
+/// Measures the load of the tracker every `period`
+pub async fn measure_load_every(tracker: Arc<Tracker>, period: Duration) -> Result<()> {
+    let mut ticks = tokio::time::interval(period);
+    let mut start = ticks.tick().await;
+    loop {
+        let end = ticks.tick().await;
+        tracker.load.sample(end - start);
+        start = end;
+    }
+}
+
+/// Reads the IP lists again every `period` when they changed. A list that
+/// can't be read is logged and the one read before is kept.
+pub async fn reload_lists_every(tracker: Arc<Tracker>, period: Duration) -> Result<()> {
+    let mut ticks = tokio::time::interval(period);
+    ticks.tick().await;
+    loop {
+        ticks.tick().await;
+        if let Err(err) = tracker.guard.reload() {
+            eprintln!("{}", err);
+        }
+    }
+}
+
+/// Runs the listeners enabled in the configuration, until one of them stops
+pub async fn run(config: ServerConfig) -> Result<()> {
+    let mut tasks = JoinSet::new();
+    let storage = storage::open(&config.storage)?;
+    if let StorageConfig::Memory {
+        snapshot: Some(_),
+        snapshot_interval,
+    } = &config.storage
+    {
+        let interval = Duration::from_secs(*snapshot_interval);
+        tasks.spawn(storage::persist_every(storage.clone(), interval));
+    }
+    let tracker = Arc::new(Tracker::with_storage(config, storage));
+    tasks.spawn(reap_every(tracker.clone()));
+    tasks.spawn(measure_load_every(tracker.clone(), Duration::from_secs(10)));
+    if tracker.nat_checker.is_some() {
+        tasks.spawn(natcheck::run(tracker.clone()));
+    }
+    if let Some(proxy) = &tracker.proxy {
+        proxy.load_torrents()?;
+        tasks.spawn(proxy::run(tracker.clone()));
+    }
+    if tracker.guard.has_lists() {
+        tracker.guard.reload()?;
+        let interval = Duration::from_secs(tracker.config.protection.list_reload_interval);
+        tasks.spawn(reload_lists_every(tracker.clone(), interval));
+    }
+    let mut listeners = 0;
+
+    if let Some(http) = &tracker.config.http {
+        let listener = TcpListener::bind(http.bind).await?;
+        match &http.tls {
+            Some(config) => {
+                let certificates = Arc::new(tls::Certificates::load(config.clone()).await?);
+                tasks.spawn(http::serve_tls(
+                    listener,
+                    tracker.clone(),
+                    certificates.rustls(),
+                ));
+                let interval = Duration::from_secs(config.reload_interval);
+                tasks.spawn(tls::reload_every(certificates, interval));
+                if let Some(redirect) = config.redirect {
+                    let listener = TcpListener::bind(redirect).await?;
+                    tasks.spawn(tls::serve_redirect(listener, http.bind.port()));
+                }
+            }
+            None => {
+                tasks.spawn(http::serve(listener, tracker.clone()));
+            }
+        }
+        listeners += 1;
+    }
+    if let Some(config) = &tracker.config.udp {
+        let connection_ids = Arc::new(udp::ConnectionIds::new());
+        for &address in &config.bind {
+            let socket = udp::bind(address)?;
+            tasks.spawn(udp::serve(socket, tracker.clone(), connection_ids.clone()));
+            listeners += 1;
+        }
+    }
+    if let Some(admin) = &tracker.config.admin {
+        let listener = TcpListener::bind(admin.bind).await?;
+        tasks.spawn(admin::serve(listener, tracker.clone()));
+    }
+    if listeners == 0 {
+        return Err(Error::from_invalid("no listener configured"));
+    }
+
+    match tasks.join_next().await {
+        Some(Ok(result)) => result,
+        Some(Err(err)) => Err(Error::from_io(err.into())),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use storage::Torrent;
+    use tforge_config::{PrivateConfig, RateLimitConfig};
+
+    fn request(peer_id: u8, left: usize) -> TrackerRequest {
+        TrackerRequest {
+            info_hash: [1; 20],
+            peer_id: [peer_id; 20],
+            port: 6881,
+            uploaded: 0,
+            downloaded: 0,
+            left,
+            compact: true,
+            event: Some(TrackerEvent::Started),
+            ip: None,
+            numwant: None,
+            no_peer_id: false,
+            key: None,
+            trackerid: None,
+        }
+    }
+
+    fn success(response: TrackerResponse) -> TrackerResponseSuccess {
+        match response {
+            TrackerResponse::Success(success) => success,
+            failure => panic!("unexpected {:?}", failure),
+        }
+    }
+
+    #[test]
+    fn test_announce() {
+        let config = ServerConfig {
+            max_numwant: 2,
+            ..Default::default()
+        };
+        let tracker = Tracker::new(config);
+        for peer in 1..=3 {
+            let remote = IpAddr::from([10, 0, 0, peer]);
+            tracker.announce(&request(peer, 0), remote, None);
+        }
+        let remote = "2001:db8::1".parse().unwrap();
+        tracker.announce(&request(4, 0), remote, None);
+
+        let response =
+            success(tracker.announce(&request(5, 10), IpAddr::from([10, 0, 0, 5]), None));
+        assert_eq!((response.complete, response.incomplete), (4, 1));
+        assert_eq!(response.interval, 1800);
+        assert_eq!(response.min_interval, Some(900));
+        assert_eq!(response.peer_addrs().len(), 2);
+        assert!(response.tracker_id.is_some());
+
+        let mut request = request(5, 10);
+        request.compact = false;
+        request.no_peer_id = true;
+        request.numwant = Some(10);
+        let response = success(tracker.announce(&request, IpAddr::from([10, 0, 0, 5]), None));
+        let TrackerPeers::List(peers) = &response.peers else {
+            panic!("expected a peer list");
+        };
+        assert_eq!(peers.len(), 2);
+        assert!(peers.iter().all(|peer| peer.peer_id.is_none()));
+    }
+
+    fn failure_reason(response: TrackerResponse) -> String {
+        match response {
+            TrackerResponse::Failure(failure) => failure.failure_reason,
+            success => panic!("unexpected {:?}", success),
+        }
+    }
+
+    #[test]
+    fn test_passkey() {
+        assert_eq!(passkey("/announce/secret"), Some("secret"));
+        assert_eq!(passkey("/announce/secret?info_hash=x"), Some("secret"));
+        assert_eq!(passkey("/announce"), None);
+        assert_eq!(passkey("/announce/"), None);
+        assert_eq!(passkey("/announce/a/b"), None);
+    }
+
+    #[test]
+    fn test_private_announce() {
+        let config = ServerConfig {
+            private: Some(PrivateConfig {
+                min_ratio: Some(0.5),
+                ratio_grace: 100,
+                banned_clients: vec!["-XL".to_string()],
+            }),
+            ..Default::default()
+        };
+        let tracker = Tracker::new(config);
+        let storage = tracker.storage();
+        storage
+            .add_user(User {
+                passkey: "secret".to_string(),
+                name: "alice".to_string(),
+                uploaded: 0,
+                downloaded: 0,
+            })
+            .unwrap();
+        let remote = IpAddr::from([10, 0, 0, 1]);
+        let announce = |request: &TrackerRequest, passkey| {
+            tracker.announce(
+                request,
+                remote,
+                Some(passkey).filter(|key: &&str| !key.is_empty()),
+            )
+        };
+
+        assert_eq!(
+            failure_reason(announce(&request(1, 10), "")),
+            "missing passkey"
+        );
+        assert_eq!(
+            failure_reason(announce(&request(1, 10), "nope")),
+            "unknown passkey"
+        );
+        assert_eq!(
+            failure_reason(announce(&request(1, 10), "secret")),
+            "unregistered torrent"
+        );
+        storage
+            .register_torrent(Torrent {
+                info_hash: [1; 20],
+                name: None,
+            })
+            .unwrap();
+        let mut banned = request(1, 10);
+        banned.peer_id[..3].copy_from_slice(b"-XL");
+        assert_eq!(failure_reason(announce(&banned, "secret")), "banned client");
+
+        // Only what the counters grew since the last announce is accounted
+        success(announce(&request(1, 10), "secret"));
+        let mut regular = request(1, 10);
+        regular.event = None;
+        regular.uploaded = 50;
+        regular.downloaded = 100;
+        success(announce(&regular, "secret"));
+        regular.uploaded = 60;
+        regular.downloaded = 200;
+        success(announce(&regular, "secret"));
+        let user = storage.user("secret").unwrap().unwrap();
+        assert_eq!((user.uploaded, user.downloaded), (60, 200));
+
+        // Past the grace, leeching needs a ratio of 0.5 while seeding is always allowed
+        assert_eq!(
+            failure_reason(announce(&regular, "secret")),
+            "ratio 0.30 is below the minimum of 0.50"
+        );
+        regular.left = 0;
+        regular.uploaded = 100;
+        success(announce(&regular, "secret"));
+        assert_eq!(storage.user("secret").unwrap().unwrap().uploaded, 100);
+    }
+
+    #[test]
+    fn test_private_announce_too_soon() {
+        let mut config = ServerConfig {
+            private: Some(PrivateConfig::default()),
+            ..Default::default()
+        };
+        config.protection.enforce_min_interval = true;
+        let tracker = Tracker::new(config);
+        let storage = tracker.storage();
+        storage
+            .add_user(User {
+                passkey: "secret".to_string(),
+                name: "alice".to_string(),
+                uploaded: 0,
+                downloaded: 0,
+            })
+            .unwrap();
+        storage
+            .register_torrent(Torrent {
+                info_hash: [1; 20],
+                name: None,
+            })
+            .unwrap();
+        let remote = IpAddr::from([10, 0, 0, 1]);
+        let mut started = request(1, 10);
+        started.uploaded = 10;
+        success(tracker.announce(&started, remote, Some("secret")));
+
+        // The refused announce leaves its transfer to the next accepted one
+        let mut regular = request(1, 10);
+        regular.event = None;
+        regular.uploaded = 50;
+        assert_eq!(
+            failure_reason(tracker.announce(&regular, remote, Some("secret"))),
+            "announced too soon, min interval is 900 seconds"
+        );
+        let mut completed = request(1, 0);
+        completed.event = Some(TrackerEvent::Completed);
+        completed.uploaded = 50;
+        success(tracker.announce(&completed, remote, Some("secret")));
+        assert_eq!(storage.user("secret").unwrap().unwrap().uploaded, 50);
+    }
+
+    #[test]
+    fn test_scrape() {
+        let tracker = Tracker::new(ServerConfig::default());
+        tracker.announce(&request(1, 0), IpAddr::from([10, 0, 0, 1]), None);
+        tracker.announce(&request(2, 10), IpAddr::from([10, 0, 0, 2]), None);
+
+        tracker
+            .storage()
+            .register_torrent(Torrent {
+                info_hash: [1; 20],
+                name: Some("debian.iso".to_string()),
+            })
+            .unwrap();
+
+        let response = tracker.scrape(&[[1; 20], [2; 20]]).unwrap();
+        assert_eq!(response.files.len(), 2);
+        assert_eq!(response.files[&[1; 20]].complete, 1);
+        assert_eq!(response.files[&[1; 20]].incomplete, 1);
+        assert_eq!(response.files[&[1; 20]].name.as_deref(), Some("debian.iso"));
+        assert_eq!(response.files[&[2; 20]].complete, 0);
+
+        let response = tracker.full_scrape().unwrap();
+        assert_eq!(response.files[&[1; 20]].name.as_deref(), Some("debian.iso"));
+        assert_eq!(response.files.keys().collect::<Vec<_>>(), vec![&[1; 20]]);
+    }
+
+    #[test]
+    fn test_announce_ipv4_mapped_and_stopped() {
+        let tracker = Tracker::new(ServerConfig::default());
+        let mapped = "::ffff:10.0.0.1".parse().unwrap();
+        tracker.announce(&request(1, 0), mapped, None);
+
+        let mut stopped = request(2, 0);
+        stopped.event = Some(TrackerEvent::Stopped);
+        let response = success(tracker.announce(&stopped, IpAddr::from([10, 0, 0, 2]), None));
+        assert!(response.peers.is_empty());
+
+        let response =
+            success(tracker.announce(&request(3, 10), IpAddr::from([10, 0, 0, 3]), None));
+        assert_eq!(
+            response.peer_addrs(),
+            vec!["10.0.0.1:6881".parse::<SocketAddr>().unwrap()]
+        );
+    }
+
+    #[test]
+    fn test_reap() {
+        let tracker = Tracker::new(ServerConfig::default());
+        tracker.announce(&request(1, 10), IpAddr::from([10, 0, 0, 1]), None);
+        assert_eq!(tracker.reap().unwrap(), 0);
+
+        // Without any interval to miss, every peer timed out
+        let config = ServerConfig {
+            peer_timeout: 0,
+            ..Default::default()
+        };
+        let tracker = Tracker::with_storage(config, tracker.storage.clone());
+        std::thread::sleep(Duration::from_millis(10));
+        assert_eq!(tracker.reap().unwrap(), 1);
+        assert_eq!(tracker.stats(&[[1; 20]]).unwrap()[0].incomplete, 0);
+    }
+
+    #[test]
+    fn test_protection() {
+        let mut config = ServerConfig::default();
+        config.protection.enforce_min_interval = true;
+        config.protection.announce_rate = Some(RateLimitConfig {
+            rate: 0.001,
+            burst: 3,
+        });
+        let tracker = Tracker::new(config);
+        let remote = IpAddr::from([10, 0, 0, 1]);
+        success(tracker.announce(&request(1, 10), remote, None));
+
+        // Only regular announces have to wait
+        let mut regular = request(1, 10);
+        regular.event = None;
+        assert_eq!(
+            failure_reason(tracker.announce(&regular, remote, None)),
+            "announced too soon, min interval is 900 seconds"
+        );
+        let mut completed = request(1, 0);
+        completed.event = Some(TrackerEvent::Completed);
+        success(tracker.announce(&completed, remote, None));
+
+        assert_eq!(
+            failure_reason(tracker.announce(&request(2, 10), remote, None)),
+            "rate limited"
+        );
+        success(tracker.announce(&request(3, 10), IpAddr::from([10, 0, 0, 3]), None));
+    }
+
+    #[test]
+    fn test_announce_ip() {
+        let dir = std::env::temp_dir().join(format!("tforge-ip-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("lan.txt"), "192.168.0.0/16\n").unwrap();
+        std::fs::write(dir.join("blocklist.txt"), "203.0.113.0/24\n").unwrap();
+
+        let announce_from = |tracker: &Tracker, remote: [u8; 4], ip: [u8; 4]| {
+            let mut announce = request(1, 10);
+            announce.ip = Some(IpAddr::from(ip));
+            let response = tracker.announce(&announce, IpAddr::from(remote), None);
+            let peers =
+                success(tracker.announce(&request(2, 10), IpAddr::from([10, 0, 0, 2]), None));
+            (response, peers.peer_addrs())
+        };
+
+        // Ignored by default
+        let tracker = Tracker::new(ServerConfig::default());
+        let (_, peers) = announce_from(&tracker, [10, 0, 0, 1], [198, 51, 100, 1]);
+        assert_eq!(peers, vec!["10.0.0.1:6881".parse::<SocketAddr>().unwrap()]);
+
+        let mut config = ServerConfig::default();
+        config.protection.trusted_sources = Some(dir.join("lan.txt"));
+        config.protection.blocklist = Some(dir.join("blocklist.txt"));
+        let tracker = Tracker::new(config);
+        tracker.guard.reload().unwrap();
+
+        // Ignored from anywhere but the trusted sources
+        let (_, peers) = announce_from(&tracker, [10, 0, 0, 1], [198, 51, 100, 1]);
+        assert_eq!(peers, vec!["10.0.0.1:6881".parse::<SocketAddr>().unwrap()]);
+
+        let (_, peers) = announce_from(&tracker, [192, 168, 1, 1], [198, 51, 100, 1]);
+        assert_eq!(
+            peers,
+            vec!["198.51.100.1:6881".parse::<SocketAddr>().unwrap()]
+        );
+
+        // The address sent goes through the lists too
+        let (response, _) = announce_from(&tracker, [192, 168, 1, 1], [203, 0, 113, 1]);
+        assert_eq!(failure_reason(response), "address blocked");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
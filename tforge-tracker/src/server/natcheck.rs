@@ -0,0 +1,269 @@
+use super::{
+    blocking,
+    swarm::{tracker_peer_id, InfoHash, PeerId},
+    Tracker,
+};
+use crate::error::Result;
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tforge_config::NatCheckConfig;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    sync::{mpsc, Semaphore},
+};
+
+/// The length prefixed protocol name starting every handshake
+const PROTOCOL: &[u8; 20] = b"\x13BitTorrent protocol";
+
+/// The handshake of the BitTorrent protocol (BEP 3), without any extension
+pub fn handshake(info_hash: &InfoHash, peer_id: &PeerId) -> [u8; 68] {
+    let mut handshake = [0; 68];
+    handshake[..20].copy_from_slice(PROTOCOL);
+    handshake[28..48].copy_from_slice(info_hash);
+    handshake[48..].copy_from_slice(peer_id);
+    handshake
+}
+
+/// Whether the peer at `addr` answers a handshake for `info_hash` with the
+/// same info-hash within `timeout`. Its peer id isn't waited for, as some
+/// peers only send it once they know who they talk to.
+pub async fn check(
+    addr: SocketAddr,
+    info_hash: &InfoHash,
+    peer_id: &PeerId,
+    timeout: Duration,
+) -> bool {
+    let exchange = async {
+        let mut stream = TcpStream::connect(addr).await?;
+        stream.write_all(&handshake(info_hash, peer_id)).await?;
+        let mut reply = [0; 48];
+        stream.read_exact(&mut reply).await?;
+        Ok::<_, std::io::Error>(reply[..20] == PROTOCOL[..] && reply[28..] == info_hash[..])
+    };
+    matches!(tokio::time::timeout(timeout, exchange).await, Ok(Ok(true)))
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Check {
+    Queued { since: Instant },
+    Done { connectable: bool, at: Instant },
+}
+
+/// A check to run, for the peer which asked for it
+struct Job {
+    info_hash: InfoHash,
+    peer_id: PeerId,
+    addr: SocketAddr,
+}
+
+/// The NAT checks of a tracker and their results by `ip:port`.
+///
+/// Checks are queued by the announces and run by [`run`], those which don't
+/// fit in the queue being asked again by the next announce of their peer.
+pub struct NatChecker {
+    config: NatCheckConfig,
+
+    /// Sent in the handshakes
+    peer_id: PeerId,
+    checks: Mutex<HashMap<SocketAddr, Check>>,
+    queue: mpsc::Sender<Job>,
+    jobs: Mutex<Option<mpsc::Receiver<Job>>>,
+}
+
+impl NatChecker {
+    pub fn new(config: NatCheckConfig) -> Self {
+        let (queue, jobs) = mpsc::channel(config.concurrency.max(1) * 16);
+        NatChecker {
+            config,
+            peer_id: tracker_peer_id(),
+            checks: Mutex::default(),
+            queue,
+            jobs: Mutex::new(Some(jobs)),
+        }
+    }
+
+    /// Whether `addr` accepted connections when it was checked, none when it
+    /// wasn't lately
+    pub fn connectable(&self, addr: SocketAddr) -> Option<bool> {
+        match self.checks.lock().unwrap().get(&addr)? {
+            Check::Done { connectable, .. } => Some(*connectable),
+            Check::Queued { .. } => None,
+        }
+    }
+
+    /// Queues a check of the peer at `addr` unless its address was checked lately
+    pub fn request(&self, info_hash: &InfoHash, peer_id: &PeerId, addr: SocketAddr) {
+        let mut checks = self.checks.lock().unwrap();
+        if checks.contains_key(&addr) {
+            return;
+        }
+        let job = Job {
+            info_hash: *info_hash,
+            peer_id: *peer_id,
+            addr,
+        };
+        if self.queue.try_send(job).is_ok() {
+            let since = Instant::now();
+            checks.insert(addr, Check::Queued { since });
+        }
+    }
+
+    fn record(&self, addr: SocketAddr, connectable: bool) {
+        let at = Instant::now();
+        let check = Check::Done { connectable, at };
+        self.checks.lock().unwrap().insert(addr, check);
+    }
+
+    /// Forgets the results older than `cache_ttl`, and the checks queued for as long
+    pub fn prune(&self) {
+        let ttl = Duration::from_secs(self.config.cache_ttl);
+        self.checks.lock().unwrap().retain(|_, check| match check {
+            Check::Queued { since: at } | Check::Done { at, .. } => at.elapsed() < ttl,
+        });
+    }
+}
+
+/// Runs the checks queued by the announces of `tracker`, `concurrency` at a
+/// time, and marks their peers connectable or not
+pub async fn run(tracker: Arc<Tracker>) -> Result<()> {
+    let Some(checker) = tracker.nat_checker() else {
+        return Ok(());
+    };
+    let Some(mut jobs) = checker.jobs.lock().unwrap().take() else {
+        return Ok(());
+    };
+    let semaphore = Arc::new(Semaphore::new(checker.config.concurrency.max(1)));
+    let timeout = Duration::from_secs(checker.config.timeout);
+    while let Some(job) = jobs.recv().await {
+        let Ok(permit) = semaphore.clone().acquire_owned().await else {
+            break;
+        };
+        let tracker = tracker.clone();
+        tokio::spawn(async move {
+            let Some(checker) = tracker.nat_checker() else {
+                return;
+            };
+            let connectable = check(job.addr, &job.info_hash, &checker.peer_id, timeout).await;
+            drop(permit);
+            checker.record(job.addr, connectable);
+            let (info_hash, peer_id) = (job.info_hash, job.peer_id);
+            let marked = blocking(&tracker, move |tracker| {
+                tracker
+                    .storage()
+                    .set_connectable(&info_hash, &peer_id, connectable)
+            });
+            if let Err(err) = marked.await.and_then(|marked| marked) {
+                eprintln!("NAT check of {}: {}", job.addr, err);
+            }
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{TrackerEvent, TrackerRequest, TrackerResponse};
+    use std::net::{IpAddr, Ipv4Addr};
+    use tforge_config::ServerConfig;
+    use tokio::net::TcpListener;
+
+    /// A peer answering handshakes with `info_hash`, whatever they ask for
+    async fn listen(info_hash: InfoHash) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut request = [0; 68];
+                stream.read_exact(&mut request).await.unwrap();
+                let reply = handshake(&info_hash, b"-XX0001-000000000000");
+                stream.write_all(&reply).await.unwrap();
+            }
+        });
+        addr
+    }
+
+    /// An address nothing listens on
+    async fn closed() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        listener.local_addr().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_check() {
+        let timeout = Duration::from_secs(2);
+        let peer_id = [1; 20];
+        let addr = listen([0xaa; 20]).await;
+        assert!(check(addr, &[0xaa; 20], &peer_id, timeout).await);
+        assert!(!check(addr, &[0xbb; 20], &peer_id, timeout).await);
+        assert!(!check(closed().await, &[0xaa; 20], &peer_id, timeout).await);
+    }
+
+    #[tokio::test]
+    async fn test_nat_check() {
+        let config = ServerConfig {
+            nat_check: Some(NatCheckConfig::default()),
+            ..Default::default()
+        };
+        let tracker = Arc::new(Tracker::new(config));
+        tokio::spawn(run(tracker.clone()));
+
+        let info_hash = [0xaa; 20];
+        let reachable = listen(info_hash).await;
+        let unreachable = closed().await;
+        let announce = |peer_id: u8, addr: SocketAddr, event| {
+            let request = TrackerRequest {
+                info_hash,
+                peer_id: [peer_id; 20],
+                port: addr.port(),
+                uploaded: 0,
+                downloaded: 0,
+                left: 10,
+                compact: true,
+                event,
+                ip: None,
+                numwant: None,
+                no_peer_id: false,
+                key: None,
+                trackerid: None,
+            };
+            match tracker.announce(&request, IpAddr::V4(Ipv4Addr::LOCALHOST), None) {
+                TrackerResponse::Success(success) => success.peer_addrs(),
+                failure => panic!("unexpected {:?}", failure),
+            }
+        };
+        announce(1, reachable, Some(TrackerEvent::Started));
+        announce(2, unreachable, Some(TrackerEvent::Started));
+        let checker = tracker.nat_checker().unwrap();
+        for _ in 0..200 {
+            if checker.connectable(reachable).is_some()
+                && checker.connectable(unreachable).is_some()
+            {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(checker.connectable(reachable), Some(true));
+        assert_eq!(checker.connectable(unreachable), Some(false));
+        let storage = tracker.storage();
+        let peer = storage.peer(&info_hash, &[2; 20]).unwrap().unwrap();
+        assert!(!peer.connectable);
+
+        // Unreachable peers only get the reachable ones
+        assert_eq!(announce(2, unreachable, None), vec![reachable]);
+        assert_eq!(announce(1, reachable, None), vec![unreachable]);
+        assert!(
+            !storage
+                .peer(&info_hash, &[2; 20])
+                .unwrap()
+                .unwrap()
+                .connectable
+        );
+    }
+}
@@ -0,0 +1,90 @@
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+use tforge_config::ServerConfig;
+
+/// Announces per second, as measured over the last sampling period
+#[derive(Debug, Default)]
+pub struct LoadMeter {
+    announces: AtomicU64,
+    rate: AtomicU64,
+}
+
+impl LoadMeter {
+    pub fn record(&self) {
+        self.announces.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Ends a sampling period of `elapsed`, which started with the previous one
+    pub fn sample(&self, elapsed: Duration) {
+        let announces = self.announces.swap(0, Ordering::Relaxed);
+        let rate = announces as f64 / elapsed.as_secs_f64().max(1.0);
+        self.rate.store(rate.round() as u64, Ordering::Relaxed);
+    }
+
+    pub fn rate(&self) -> u64 {
+        self.rate.load(Ordering::Relaxed)
+    }
+}
+
+/// The `interval` and `min_interval` for a swarm of `peers` under a `load` in
+/// announces per second, longer past the thresholds of the adaptive interval
+pub fn intervals(config: &ServerConfig, peers: u64, load: u64) -> (u64, u64) {
+    let Some(adaptive) = &config.adaptive_interval else {
+        return (config.interval, config.min_interval);
+    };
+    let ratio = |value: u64, threshold: u64| (value as f64 / threshold.max(1) as f64).max(1.0);
+    let factor = ratio(peers, adaptive.swarm_size) * ratio(load, adaptive.max_load);
+    let max_interval = adaptive.max_interval.max(config.interval);
+    let interval = ((config.interval as f64 * factor) as u64).min(max_interval);
+    let min_interval = ((config.min_interval as f64 * factor) as u64).min(interval);
+    (interval, min_interval)
+}
+
+/// How long peers are kept without announcing: `peer_timeout` times the
+/// longest interval they may have been given
+pub fn peer_timeout(config: &ServerConfig) -> Duration {
+    let interval = match &config.adaptive_interval {
+        Some(adaptive) => adaptive.max_interval.max(config.interval),
+        None => config.interval,
+    };
+    Duration::from_secs(interval * config.peer_timeout as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tforge_config::AdaptiveIntervalConfig;
+
+    #[test]
+    fn test_intervals() {
+        let mut config = ServerConfig::default();
+        assert_eq!(intervals(&config, 1_000_000, 1_000_000), (1800, 900));
+        assert_eq!(peer_timeout(&config), Duration::from_secs(3600));
+
+        config.adaptive_interval = Some(AdaptiveIntervalConfig {
+            swarm_size: 100,
+            max_load: 10,
+            max_interval: 7200,
+        });
+        assert_eq!(intervals(&config, 50, 5), (1800, 900));
+        assert_eq!(intervals(&config, 200, 5), (3600, 1800));
+        assert_eq!(intervals(&config, 150, 20), (5400, 2700));
+        assert_eq!(intervals(&config, 1000, 0), (7200, 7200));
+        assert_eq!(peer_timeout(&config), Duration::from_secs(14400));
+    }
+
+    #[test]
+    fn test_load_meter() {
+        let load = LoadMeter::default();
+        for _ in 0..50 {
+            load.record();
+        }
+        assert_eq!(load.rate(), 0);
+        load.sample(Duration::from_secs(10));
+        assert_eq!(load.rate(), 5);
+        load.sample(Duration::from_secs(10));
+        assert_eq!(load.rate(), 0);
+    }
+}
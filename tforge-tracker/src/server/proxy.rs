@@ -0,0 +1,561 @@
+use super::{
+    blocking,
+    swarm::{tracker_peer_id, CompactAddr, InfoHash, Peer, PeerId, Selection},
+    Tracker,
+};
+use crate::{
+    client::manager::TrackerManager,
+    error::{Error, Result},
+    protocol::{TrackerEvent, TrackerPeers, TrackerRequest, TrackerResponseSuccess},
+};
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    path::Path,
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, Instant, SystemTime},
+};
+use tforge_config::ProxyConfig;
+use tforge_metainfo::edit::RawMetaInfo;
+use tokio::sync::{mpsc, watch, Semaphore};
+
+/// What the proxy knows of the upstream swarm of a torrent
+struct Upstream {
+    manager: Arc<tokio::sync::Mutex<TrackerManager>>,
+    peers: Vec<(PeerId, SocketAddr)>,
+    complete: usize,
+    incomplete: usize,
+
+    /// Until when the peers are served without announcing again, none before the first announce
+    fresh_until: Option<Instant>,
+    queued: bool,
+    started: bool,
+
+    /// The last local announce of the torrent
+    requested: Instant,
+
+    /// Set once the first upstream announce is over, whether it failed or not
+    answered: watch::Sender<bool>,
+}
+
+/// An upstream announce queued for [`run`]
+enum Job {
+    /// Refreshes the peers of a torrent
+    Announce(InfoHash),
+
+    /// Leaves the upstream swarm of a torrent no longer announced locally
+    Stop(InfoHash, Arc<tokio::sync::Mutex<TrackerManager>>),
+}
+
+/// A token bucket the upstream announces wait for
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// The upstream swarms of the torrents announced to a proxy.
+///
+/// The local announces of a torrent queue one upstream announce when its
+/// cached peers are stale, [`run`] sending them at the configured rate.
+pub struct Proxy {
+    config: ProxyConfig,
+
+    /// Sent upstream, the same for all torrents
+    peer_id: PeerId,
+
+    /// The tiers of the torrents read from `torrents`
+    torrent_tiers: RwLock<HashMap<InfoHash, Vec<Vec<String>>>>,
+    upstreams: Mutex<HashMap<InfoHash, Upstream>>,
+    bucket: Mutex<Bucket>,
+    queue: mpsc::Sender<Job>,
+    jobs: Mutex<Option<mpsc::Receiver<Job>>>,
+}
+
+impl Proxy {
+    pub fn new(config: ProxyConfig) -> Self {
+        let (queue, jobs) = mpsc::channel(config.concurrency.max(1) * 16);
+        Proxy {
+            bucket: Mutex::new(Bucket {
+                tokens: config.rate.burst as f64,
+                updated: Instant::now(),
+            }),
+            config,
+            peer_id: tracker_peer_id(),
+            torrent_tiers: RwLock::default(),
+            upstreams: Mutex::default(),
+            queue,
+            jobs: Mutex::new(Some(jobs)),
+        }
+    }
+
+    /// Reads the tiers of the .torrent files of `torrents`, returning how
+    /// many torrents have some. A hybrid torrent has them under both its
+    /// info-hashes, the v2 one truncated as in announces.
+    pub fn load_torrents(&self) -> Result<usize> {
+        let Some(dir) = &self.config.torrents else {
+            return Ok(0);
+        };
+        let mut torrent_tiers = HashMap::new();
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path
+                .extension()
+                .is_some_and(|extension| extension == "torrent")
+            {
+                let (info_hashes, tiers) = read_torrent(&path)?;
+                if tiers.iter().any(|tier| !tier.is_empty()) {
+                    for info_hash in info_hashes {
+                        torrent_tiers.insert(info_hash, tiers.clone());
+                    }
+                }
+            }
+        }
+        let count = torrent_tiers.len();
+        eprintln!(
+            "Loaded the tiers of {} torrents from {}",
+            count,
+            dir.display()
+        );
+        *self.torrent_tiers.write().unwrap() = torrent_tiers;
+        Ok(count)
+    }
+
+    /// The upstream tiers of a torrent: its own, or the default ones
+    pub fn tiers(&self, info_hash: &InfoHash) -> Option<Vec<Vec<String>>> {
+        let torrent_tiers = self.torrent_tiers.read().unwrap();
+        let tiers = torrent_tiers.get(info_hash).unwrap_or(&self.config.tiers);
+        (!tiers.is_empty()).then(|| tiers.clone())
+    }
+
+    /// Queues an upstream announce of the torrent unless its peers are fresh,
+    /// or one is queued already
+    pub fn request(&self, info_hash: &InfoHash) {
+        let now = Instant::now();
+        let mut upstreams = self.upstreams.lock().unwrap();
+        let upstream = match upstreams.get_mut(info_hash) {
+            Some(upstream) => upstream,
+            None => {
+                let Some(tiers) = self.tiers(info_hash) else {
+                    return;
+                };
+                let manager = TrackerManager::new(tiers)
+                    .with_announce_to_all_tiers(self.config.announce_to_all_tiers);
+                upstreams.entry(*info_hash).or_insert(Upstream {
+                    manager: Arc::new(tokio::sync::Mutex::new(manager)),
+                    peers: Vec::new(),
+                    complete: 0,
+                    incomplete: 0,
+                    fresh_until: None,
+                    queued: false,
+                    started: false,
+                    requested: now,
+                    answered: watch::channel(false).0,
+                })
+            }
+        };
+        upstream.requested = now;
+        let stale = upstream.fresh_until.map_or(true, |fresh| now >= fresh);
+        if stale && !upstream.queued && self.queue.try_send(Job::Announce(*info_hash)).is_ok() {
+            upstream.queued = true;
+        }
+    }
+
+    /// Requests the upstream peers of the torrent and waits up to `wait` for
+    /// them when it was never announced upstream
+    pub async fn wait(&self, info_hash: &InfoHash) {
+        self.request(info_hash);
+        let mut answered = match self.upstreams.lock().unwrap().get(info_hash) {
+            Some(upstream) => upstream.answered.subscribe(),
+            None => return,
+        };
+        let wait = Duration::from_secs(self.config.wait);
+        let _ = tokio::time::timeout(wait, answered.wait_for(|answered| *answered)).await;
+    }
+
+    /// Appends the upstream peers `selection` allows to `selected`, up to its
+    /// count, returning the upstream number of seeders and leechers.
+    ///
+    /// Whether upstream peers seed or accept connections is unknown, so they
+    /// are taken as seeders which may not: seeders don't get them unless
+    /// `seeders_to_seeders`, nor do the peers found unconnectable.
+    pub fn extend(
+        &self,
+        info_hash: &InfoHash,
+        selection: &Selection,
+        selected: &mut Vec<(PeerId, CompactAddr)>,
+    ) -> (usize, usize) {
+        let upstreams = self.upstreams.lock().unwrap();
+        let Some(upstream) = upstreams.get(info_hash) else {
+            return (0, 0);
+        };
+        let local = selected.len();
+        for (peer_id, addr) in &upstream.peers {
+            if selected.len() - local >= selection.count {
+                break;
+            }
+            let peer = Peer {
+                addr: *addr,
+                uploaded: 0,
+                downloaded: 0,
+                left: 0,
+                tracker_id: 0,
+                last_seen: SystemTime::UNIX_EPOCH,
+                connectable: false,
+            };
+            let addr = CompactAddr::from(*addr);
+            let allowed = selection.allows(peer_id, &peer)
+                && !selected[..local].iter().any(|(_, known)| *known == addr);
+            if allowed {
+                selected.push((*peer_id, addr));
+            }
+        }
+        (upstream.complete, upstream.incomplete)
+    }
+
+    /// Forgets the torrents not announced locally for `idle`, whose peers
+    /// timed out, and queues a `stopped` for those announced upstream
+    pub fn prune(&self, idle: Duration) {
+        let now = Instant::now();
+        self.upstreams
+            .lock()
+            .unwrap()
+            .retain(|info_hash, upstream| {
+                let active =
+                    upstream.queued || now.saturating_duration_since(upstream.requested) < idle;
+                if !active && upstream.started {
+                    // Dropped when the queue is full, the upstream tracker times the proxy out then
+                    let _ = self
+                        .queue
+                        .try_send(Job::Stop(*info_hash, upstream.manager.clone()));
+                }
+                active
+            });
+    }
+
+    /// Waits for a token of the rate limit, taking it ahead when there is
+    /// none left so that the next announces wait behind
+    async fn throttle(&self) {
+        let wait = {
+            let mut bucket = self.bucket.lock().unwrap();
+            let now = Instant::now();
+            let (rate, burst) = (self.config.rate.rate, self.config.rate.burst as f64);
+            let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+            bucket.tokens = (bucket.tokens + elapsed * rate).min(burst);
+            bucket.updated = now;
+            bucket.tokens -= 1.0;
+            match bucket.tokens < 0.0 {
+                true => Duration::from_secs_f64(-bucket.tokens / rate.max(f64::EPSILON)),
+                false => Duration::ZERO,
+            }
+        };
+        tokio::time::sleep(wait).await;
+    }
+
+    /// Announces the torrent upstream, as a seeder when its local peers all
+    /// are, and caches the peers
+    async fn announce(&self, info_hash: &InfoHash, seeding: bool, numwant: usize) {
+        let (manager, started) = match self.upstreams.lock().unwrap().get(info_hash) {
+            Some(upstream) => (upstream.manager.clone(), upstream.started),
+            None => return,
+        };
+        let event = (!started).then_some(TrackerEvent::Started);
+        let request = self.upstream_request(info_hash, seeding, event, numwant);
+        let response = manager.lock().await.announce(&request).await;
+
+        let now = Instant::now();
+        let mut upstreams = self.upstreams.lock().unwrap();
+        let Some(upstream) = upstreams.get_mut(info_hash) else {
+            return;
+        };
+        let mut ttl = Duration::from_secs(self.config.cache_ttl);
+        match response {
+            Ok(response) => {
+                upstream.peers = upstream_peers(&response);
+                // The proxy itself is counted upstream, its local peers already are
+                let (complete, incomplete) = match seeding {
+                    true => (response.complete.saturating_sub(1), response.incomplete),
+                    false => (response.complete, response.incomplete.saturating_sub(1)),
+                };
+                upstream.complete = complete;
+                upstream.incomplete = incomplete;
+                upstream.started = true;
+                let min_interval = response.min_interval.unwrap_or_default() as u64;
+                ttl = ttl.max(Duration::from_secs(min_interval));
+            }
+            Err(err) => eprintln!("Upstream announce of {}: {}", hex::encode(info_hash), err),
+        }
+        upstream.fresh_until = Some(now + ttl);
+        upstream.queued = false;
+        upstream.answered.send_replace(true);
+    }
+
+    /// Leaves the upstream swarm of a torrent the proxy forgot
+    async fn stop(&self, info_hash: &InfoHash, manager: &tokio::sync::Mutex<TrackerManager>) {
+        let request = self.upstream_request(info_hash, false, Some(TrackerEvent::Stopped), 0);
+        if let Err(err) = manager.lock().await.announce(&request).await {
+            eprintln!("Upstream stop of {}: {}", hex::encode(info_hash), err);
+        }
+    }
+
+    fn upstream_request(
+        &self,
+        info_hash: &InfoHash,
+        seeding: bool,
+        event: Option<TrackerEvent>,
+        numwant: usize,
+    ) -> TrackerRequest {
+        TrackerRequest {
+            info_hash: *info_hash,
+            peer_id: self.peer_id,
+            port: self.config.port,
+            uploaded: 0,
+            downloaded: 0,
+            left: !seeding as usize,
+            compact: true,
+            event,
+            ip: None,
+            numwant: Some(numwant),
+            no_peer_id: false,
+            key: None,
+            trackerid: None,
+        }
+    }
+}
+
+/// The info-hashes and tiers of a .torrent file
+fn read_torrent(path: &Path) -> Result<(Vec<InfoHash>, Vec<Vec<String>>)> {
+    let invalid = |err: tforge_metainfo::error::Error| {
+        Error::from_invalid(format!("{}: {}", path.display(), err))
+    };
+    let metainfo = RawMetaInfo::from_bytes(&std::fs::read(path)?).map_err(invalid)?;
+    let info_hashes = metainfo.info_hashes().map_err(invalid)?;
+    let info_hashes = info_hashes
+        .v1
+        .into_iter()
+        .chain(info_hashes.truncated_v2())
+        .collect();
+    let tiers = match metainfo.announce_list {
+        Some(tiers) if tiers.iter().any(|tier| !tier.is_empty()) => tiers,
+        _ => vec![vec![metainfo.announce]],
+    };
+    let tiers = tiers
+        .into_iter()
+        .map(|tier| tier.into_iter().filter(|url| !url.is_empty()).collect())
+        .filter(|tier: &Vec<String>| !tier.is_empty())
+        .collect();
+    Ok((info_hashes, tiers))
+}
+
+/// The peers of an upstream response, with their peer id when it was sent
+fn upstream_peers(response: &TrackerResponseSuccess) -> Vec<(PeerId, SocketAddr)> {
+    match &response.peers {
+        TrackerPeers::List(peers) => peers
+            .iter()
+            .filter_map(|peer| {
+                let peer_id = peer.peer_id.as_deref().and_then(|id| id.try_into().ok());
+                Some((peer_id.unwrap_or_default(), peer.socket_addr()?))
+            })
+            .collect(),
+        TrackerPeers::Compact(_) => response
+            .peer_addrs()
+            .into_iter()
+            .map(|addr| (PeerId::default(), addr))
+            .collect(),
+    }
+}
+
+/// Sends the upstream announces queued by the local ones, `concurrency` at a
+/// time and no faster than `rate`
+pub async fn run(tracker: Arc<Tracker>) -> Result<()> {
+    let Some(proxy) = tracker.proxy() else {
+        return Ok(());
+    };
+    let Some(mut jobs) = proxy.jobs.lock().unwrap().take() else {
+        return Ok(());
+    };
+    let semaphore = Arc::new(Semaphore::new(proxy.config.concurrency.max(1)));
+    while let Some(job) = jobs.recv().await {
+        proxy.throttle().await;
+        let Ok(permit) = semaphore.clone().acquire_owned().await else {
+            break;
+        };
+        let tracker = tracker.clone();
+        tokio::spawn(async move {
+            let Some(proxy) = tracker.proxy() else {
+                return;
+            };
+            let info_hash = match job {
+                Job::Announce(info_hash) => info_hash,
+                Job::Stop(info_hash, manager) => return proxy.stop(&info_hash, &manager).await,
+            };
+            let stats = blocking(&tracker, move |tracker| tracker.stats(&[info_hash]));
+            let seeding = match stats.await.and_then(|stats| stats) {
+                Ok(stats) => stats[0].incomplete == 0 && stats[0].complete > 0,
+                Err(_) => false,
+            };
+            let numwant = tracker.config().max_numwant;
+            proxy.announce(&info_hash, seeding, numwant).await;
+            drop(permit);
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{client::TrackerClient, server::http};
+    use tforge_config::{RateLimitConfig, ServerConfig};
+    use tokio::net::TcpListener;
+
+    async fn serve(tracker: Arc<Tracker>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/announce", listener.local_addr().unwrap());
+        tokio::spawn(http::serve(listener, tracker));
+        url
+    }
+
+    fn request(peer_id: u8, port: u16) -> TrackerRequest {
+        TrackerRequest {
+            info_hash: [0xaa; 20],
+            peer_id: [peer_id; 20],
+            port,
+            uploaded: 0,
+            downloaded: 0,
+            left: 100,
+            compact: true,
+            event: Some(TrackerEvent::Started),
+            ip: None,
+            numwant: None,
+            no_peer_id: false,
+            key: None,
+            trackerid: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_proxy() {
+        let upstream = Arc::new(Tracker::new(ServerConfig::default()));
+        let upstream_url = serve(upstream.clone()).await;
+        let remote = TrackerClient::new(upstream_url.clone(), None);
+        remote.announce(&request(9, 7000)).await.unwrap();
+
+        let config = ServerConfig {
+            proxy: Some(ProxyConfig {
+                tiers: vec![vec![upstream_url]],
+                port: 6999,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let tracker = Arc::new(Tracker::new(config));
+        tokio::spawn(run(tracker.clone()));
+        let client = TrackerClient::new(serve(tracker.clone()).await, None);
+
+        // The first announce waits for the upstream peers
+        let first = client.announce(&request(1, 6881)).await.unwrap();
+        let remote_addr: SocketAddr = "127.0.0.1:7000".parse().unwrap();
+        assert_eq!(first.peer_addrs(), vec![remote_addr]);
+        // One local leecher and the remote one, the proxy upstream left out
+        assert_eq!(first.incomplete, 2);
+
+        // The next ones get the local peers first, from the cache
+        for peer_id in 2..6 {
+            let response = client
+                .announce(&request(peer_id, 6880 + peer_id as u16))
+                .await;
+            let peers = response.unwrap().peer_addrs();
+            assert_eq!(peers.len(), peer_id as usize);
+            assert_eq!(peers.last(), Some(&remote_addr));
+        }
+
+        // Upstream only saw the remote peer and the proxy, once each
+        let metrics = upstream.render_metrics().unwrap();
+        let lines: Vec<_> = metrics.lines().collect();
+        assert!(lines
+            .contains(&"tforge_tracker_announces_total{protocol=\"http\",event=\"started\"} 2"));
+        assert!(
+            lines.contains(&"tforge_tracker_announces_total{protocol=\"http\",event=\"none\"} 0")
+        );
+
+        // Upstream peers may be seeders, which seeders aren't given
+        let mut seeder = request(6, 6886);
+        seeder.left = 0;
+        let peers = client.announce(&seeder).await.unwrap().peer_addrs();
+        assert_eq!(peers.len(), 5);
+        assert!(!peers.contains(&remote_addr));
+
+        // A forgotten torrent is left upstream
+        tracker.proxy().unwrap().prune(Duration::ZERO);
+        for _ in 0..200 {
+            if upstream.stats(&[[0xaa; 20]]).unwrap()[0].incomplete == 1 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(upstream.stats(&[[0xaa; 20]]).unwrap()[0].incomplete, 1);
+    }
+
+    #[tokio::test]
+    async fn test_proxy_refusal() {
+        // An upstream which never answers
+        let silent = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_url = format!("http://{}/announce", silent.local_addr().unwrap());
+        let mut config = ServerConfig {
+            proxy: Some(ProxyConfig {
+                tiers: vec![vec![upstream_url]],
+                wait: 5,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        config.protection.banned_clients = vec!["-XL".to_string()];
+        let tracker = Arc::new(Tracker::new(config));
+        tokio::spawn(run(tracker.clone()));
+        let client = TrackerClient::new(serve(tracker).await, None);
+
+        // Refused announces don't wait for the upstream peers
+        let mut banned = request(1, 6881);
+        banned.peer_id[..3].copy_from_slice(b"-XL");
+        let start = Instant::now();
+        match client.announce(&banned).await {
+            Err(Error::Failure(reason)) => assert_eq!(reason, "banned client"),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn test_throttle() {
+        let proxy = Proxy::new(ProxyConfig {
+            rate: RateLimitConfig {
+                rate: 20.0,
+                burst: 1,
+            },
+            ..Default::default()
+        });
+        let start = Instant::now();
+        proxy.throttle().await;
+        assert!(start.elapsed() < Duration::from_millis(20));
+        proxy.throttle().await;
+        proxy.throttle().await;
+        assert!(start.elapsed() >= Duration::from_millis(90));
+    }
+
+    #[test]
+    fn test_tiers() {
+        let proxy = Proxy::new(ProxyConfig::default());
+        assert_eq!(proxy.tiers(&[0xaa; 20]), None);
+        let tiers = vec![vec!["udp://tracker.example.org:6969".to_string()]];
+        let proxy = Proxy::new(ProxyConfig {
+            tiers: tiers.clone(),
+            ..Default::default()
+        });
+        proxy.torrent_tiers.write().unwrap().insert(
+            [0xbb; 20],
+            vec![vec!["http://other.example.org/announce".into()]],
+        );
+        assert_eq!(proxy.tiers(&[0xaa; 20]), Some(tiers));
+        assert_ne!(proxy.tiers(&[0xbb; 20]), proxy.tiers(&[0xaa; 20]));
+    }
+}
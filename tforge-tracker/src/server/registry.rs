@@ -0,0 +1,120 @@
+use super::swarm::{InfoHash, Swarm};
+use std::{
+    collections::{hash_map::RandomState, HashMap},
+    hash::BuildHasher,
+    sync::RwLock,
+};
+
+/// Shards of a registry unless told otherwise, plenty for the threads of a server
+pub const DEFAULT_SHARDS: usize = 256;
+
+type Shard = RwLock<HashMap<InfoHash, Swarm>>;
+
+/// The swarms of the memory storage, split in shards by info-hash so that
+/// announces of different torrents seldom wait for each other.
+///
+/// It isn't lock-free: each shard is behind a `RwLock`, which the announces
+/// of its torrents take in turn while scrapes share it.
+///
+/// Info-hashes are chosen by clients, so the shard of a torrent is picked
+/// with a keyed hash rather than with the bytes of its info-hash.
+pub struct SwarmRegistry {
+    shards: Box<[Shard]>,
+    hasher: RandomState,
+}
+
+impl Default for SwarmRegistry {
+    fn default() -> Self {
+        SwarmRegistry::with_shards(DEFAULT_SHARDS)
+    }
+}
+
+impl SwarmRegistry {
+    pub fn new() -> Self {
+        SwarmRegistry::default()
+    }
+
+    /// A registry of `count` shards, rounded up to a power of two
+    pub fn with_shards(count: usize) -> Self {
+        let shards = (0..count.max(1).next_power_of_two())
+            .map(|_| Shard::default())
+            .collect();
+        SwarmRegistry {
+            shards,
+            hasher: RandomState::new(),
+        }
+    }
+
+    fn shard(&self, info_hash: &InfoHash) -> &Shard {
+        let hash = self.hasher.hash_one(info_hash) as usize;
+        &self.shards[hash & (self.shards.len() - 1)]
+    }
+
+    /// Runs `f` on the swarm of a torrent, when there is one
+    pub fn read<R>(&self, info_hash: &InfoHash, f: impl FnOnce(&Swarm) -> R) -> Option<R> {
+        let shard = self.shard(info_hash).read().unwrap();
+        shard.get(info_hash).map(f)
+    }
+
+    /// Runs `f` on the swarm of a torrent, created when there is none
+    pub fn write<R>(&self, info_hash: &InfoHash, f: impl FnOnce(&mut Swarm) -> R) -> R {
+        let mut shard = self.shard(info_hash).write().unwrap();
+        f(shard.entry(*info_hash).or_default())
+    }
+
+    /// Runs `f` on the swarm of a torrent, when there is one
+    pub fn update<R>(&self, info_hash: &InfoHash, f: impl FnOnce(&mut Swarm) -> R) -> Option<R> {
+        let mut shard = self.shard(info_hash).write().unwrap();
+        shard.get_mut(info_hash).map(f)
+    }
+
+    /// Runs `f` on every swarm, one shard at a time
+    pub fn for_each(&self, mut f: impl FnMut(&InfoHash, &Swarm)) {
+        for shard in self.shards.iter() {
+            let shard = shard.read().unwrap();
+            for (info_hash, swarm) in shard.iter() {
+                f(info_hash, swarm);
+            }
+        }
+    }
+
+    /// Keeps the swarms for which `f` returns true, one shard at a time
+    pub fn retain(&self, mut f: impl FnMut(&InfoHash, &mut Swarm) -> bool) {
+        for shard in self.shards.iter() {
+            shard
+                .write()
+                .unwrap()
+                .retain(|info_hash, swarm| f(info_hash, swarm));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_registry() {
+        let registry = SwarmRegistry::with_shards(5);
+        assert_eq!(registry.shards.len(), 8);
+        for byte in 0..100 {
+            registry.write(&[byte; 20], |swarm| swarm.downloaded = byte as u64);
+        }
+        assert_eq!(registry.read(&[7; 20], |swarm| swarm.downloaded), Some(7));
+        assert_eq!(registry.read(&[200; 20], |swarm| swarm.downloaded), None);
+        assert_eq!(registry.update(&[200; 20], |swarm| swarm.downloaded), None);
+
+        // The torrents spread over the shards
+        let used = registry
+            .shards
+            .iter()
+            .filter(|shard| !shard.read().unwrap().is_empty())
+            .count();
+        assert!(used > 1);
+
+        registry.retain(|_, swarm| swarm.downloaded % 2 == 0);
+        let mut count = 0;
+        registry.for_each(|_, _| count += 1);
+        assert_eq!(count, 50);
+    }
+}
@@ -0,0 +1,471 @@
+use super::{Storage, Torrent, User};
+use crate::{
+    error::{Error, Result},
+    server::{
+        registry::SwarmRegistry,
+        swarm::{
+            format_tracker_id,
+            parse_tracker_id,
+            CompactAddr,
+            InfoHash,
+            Peer,
+            PeerId,
+            Selection,
+            Swarm,
+            SwarmStats,
+        },
+    },
+};
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, BoolFromInt, Bytes};
+use std::{
+    collections::HashMap,
+    io::{BufReader, Cursor},
+    net::{IpAddr, SocketAddr},
+    path::{Path, PathBuf},
+    sync::RwLock,
+    time::{Duration, SystemTime},
+};
+use tforge_bencode::{deserializer::from_reader, serializer::from_writer};
+
+/// The default storage, lost on restart unless snapshots are saved
+#[derive(Default)]
+pub struct MemoryStorage {
+    swarms: SwarmRegistry,
+
+    /// Registered torrents and users, seldom written
+    state: RwLock<State>,
+
+    /// Where [`Storage::persist`] saves the state
+    snapshot: Option<PathBuf>,
+}
+
+#[derive(Default)]
+struct State {
+    /// Registered torrents and their names
+    torrents: HashMap<InfoHash, Option<String>>,
+
+    /// Users by passkey
+    users: HashMap<String, User>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        MemoryStorage::default()
+    }
+
+    /// A storage persisted to `path`, restored from it when it exists
+    pub fn with_snapshot(path: PathBuf) -> Result<Self> {
+        let storage = MemoryStorage {
+            snapshot: Some(path.clone()),
+            ..Default::default()
+        };
+        if path.exists() {
+            storage.load(&path)?;
+        }
+        Ok(storage)
+    }
+
+    /// Reads a state saved by [`MemoryStorage::save`]
+    fn load(&self, path: &Path) -> Result<()> {
+        let bytes = std::fs::read(path)?;
+        let mut reader = BufReader::new(Cursor::new(bytes));
+        let snapshot: Snapshot = from_reader(&mut reader)?;
+
+        let mut state = self.state.write().unwrap();
+        for torrent in snapshot.torrents {
+            if torrent.registered {
+                state.torrents.insert(torrent.info_hash, torrent.name);
+            }
+            let mut peers = Vec::with_capacity(torrent.peers.len());
+            for peer in torrent.peers {
+                let ip: IpAddr = peer
+                    .ip
+                    .parse()
+                    .map_err(|_| Error::from_storage(format!("invalid peer ip {}", peer.ip)))?;
+                let tracker_id = parse_tracker_id(&peer.tracker_id).ok_or_else(|| {
+                    Error::from_storage(format!("invalid tracker id {}", peer.tracker_id))
+                })?;
+                let known = Peer {
+                    addr: SocketAddr::new(ip, peer.port),
+                    uploaded: peer.uploaded,
+                    downloaded: peer.downloaded,
+                    left: peer.left,
+                    tracker_id,
+                    last_seen: SystemTime::UNIX_EPOCH + Duration::from_secs(peer.last_seen),
+                    connectable: peer.connectable,
+                };
+                peers.push((peer.peer_id, known));
+            }
+            self.swarms.write(&torrent.info_hash, |swarm| {
+                swarm.downloaded = torrent.downloaded;
+                for (peer_id, peer) in &peers {
+                    swarm.upsert(*peer_id, peer);
+                }
+            });
+        }
+        for user in snapshot.users {
+            let user = User {
+                passkey: user.passkey,
+                name: user.name,
+                uploaded: user.uploaded,
+                downloaded: user.downloaded,
+            };
+            state.users.insert(user.passkey.clone(), user);
+        }
+        Ok(())
+    }
+
+    /// Saves the whole state to `path`, replacing it only once fully written
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let snapshot = self.snapshot();
+        let mut buffer = Vec::new();
+        snapshot.serialize(&mut from_writer(&mut buffer))?;
+
+        let mut temporary = path.as_os_str().to_owned();
+        temporary.push(".tmp");
+        std::fs::write(&temporary, buffer)?;
+        std::fs::rename(&temporary, path)?;
+        Ok(())
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        let state = self.state.read().unwrap();
+        let mut torrents = Vec::new();
+        self.swarms.for_each(|info_hash, swarm| {
+            torrents.push(SnapshotTorrent {
+                downloaded: swarm.downloaded,
+                info_hash: *info_hash,
+                name: state.torrents.get(info_hash).cloned().flatten(),
+                peers: swarm
+                    .peers()
+                    .map(|record| {
+                        let peer = record.peer();
+                        SnapshotPeer {
+                            connectable: peer.connectable,
+                            downloaded: peer.downloaded,
+                            ip: peer.addr.ip().to_string(),
+                            last_seen: record.last_seen as u64,
+                            left: peer.left,
+                            peer_id: record.peer_id,
+                            port: peer.addr.port(),
+                            tracker_id: format_tracker_id(peer.tracker_id),
+                            uploaded: peer.uploaded,
+                        }
+                    })
+                    .collect(),
+                registered: state.torrents.contains_key(info_hash),
+            })
+        });
+        for (info_hash, name) in &state.torrents {
+            if self.swarms.read(info_hash, |_| ()).is_none() {
+                torrents.push(SnapshotTorrent {
+                    downloaded: 0,
+                    info_hash: *info_hash,
+                    name: name.clone(),
+                    peers: Vec::new(),
+                    registered: true,
+                });
+            }
+        }
+        let users = state
+            .users
+            .values()
+            .map(|user| SnapshotUser {
+                downloaded: user.downloaded,
+                name: user.name.clone(),
+                passkey: user.passkey.clone(),
+                uploaded: user.uploaded,
+            })
+            .collect();
+        Snapshot { torrents, users }
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn upsert_peer(&self, info_hash: &InfoHash, peer_id: &PeerId, peer: Peer) -> Result<u64> {
+        Ok(self
+            .swarms
+            .write(info_hash, |swarm| swarm.upsert(*peer_id, &peer)))
+    }
+
+    fn peer(&self, info_hash: &InfoHash, peer_id: &PeerId) -> Result<Option<Peer>> {
+        let peer = self.swarms.read(info_hash, |swarm| {
+            swarm.get(peer_id).map(|record| record.peer())
+        });
+        Ok(peer.flatten())
+    }
+
+    fn peers(&self, info_hash: &InfoHash) -> Result<Vec<(PeerId, Peer)>> {
+        let peers = self.swarms.read(info_hash, |swarm| {
+            swarm
+                .peers()
+                .map(|record| (record.peer_id, record.peer()))
+                .collect()
+        });
+        Ok(peers.unwrap_or_default())
+    }
+
+    fn set_connectable(
+        &self,
+        info_hash: &InfoHash,
+        peer_id: &PeerId,
+        connectable: bool,
+    ) -> Result<bool> {
+        let known = self.swarms.update(info_hash, |swarm| {
+            swarm.set_connectable(peer_id, connectable)
+        });
+        Ok(known.unwrap_or(false))
+    }
+
+    fn remove_peer(&self, info_hash: &InfoHash, peer_id: &PeerId) -> Result<bool> {
+        let removed = self.swarms.update(info_hash, |swarm| swarm.remove(peer_id));
+        Ok(removed.unwrap_or(false))
+    }
+
+    fn expire_peers(&self, before: SystemTime) -> Result<usize> {
+        let mut expired = 0;
+        // Swarms without peers are only kept for their completed count
+        self.swarms.retain(|_, swarm| {
+            expired += swarm.expire(before);
+            !swarm.is_empty() || swarm.downloaded > 0
+        });
+        Ok(expired)
+    }
+
+    fn select_peers(
+        &self,
+        info_hash: &InfoHash,
+        selection: &Selection,
+        selected: &mut Vec<(PeerId, CompactAddr)>,
+    ) -> Result<()> {
+        self.swarms
+            .read(info_hash, |swarm| swarm.select(selection, selected));
+        Ok(())
+    }
+
+    fn add_completed(&self, info_hash: &InfoHash) -> Result<()> {
+        self.swarms.write(info_hash, |swarm| swarm.downloaded += 1);
+        Ok(())
+    }
+
+    fn stats(&self, info_hashes: &[InfoHash]) -> Result<Vec<SwarmStats>> {
+        Ok(info_hashes
+            .iter()
+            .map(|info_hash| {
+                self.swarms
+                    .read(info_hash, Swarm::stats)
+                    .unwrap_or_default()
+            })
+            .collect())
+    }
+
+    fn torrent_stats(&self, info_hash: &InfoHash) -> Result<SwarmStats> {
+        Ok(self
+            .swarms
+            .read(info_hash, Swarm::stats)
+            .unwrap_or_default())
+    }
+
+    fn all_stats(&self) -> Result<Vec<(InfoHash, SwarmStats)>> {
+        let mut stats = Vec::new();
+        self.swarms
+            .for_each(|info_hash, swarm| stats.push((*info_hash, swarm.stats())));
+        Ok(stats)
+    }
+
+    fn register_torrent(&self, torrent: Torrent) -> Result<()> {
+        let mut state = self.state.write().unwrap();
+        state.torrents.insert(torrent.info_hash, torrent.name);
+        Ok(())
+    }
+
+    fn unregister_torrent(&self, info_hash: &InfoHash) -> Result<bool> {
+        let mut state = self.state.write().unwrap();
+        Ok(state.torrents.remove(info_hash).is_some())
+    }
+
+    fn torrent(&self, info_hash: &InfoHash) -> Result<Option<Torrent>> {
+        let state = self.state.read().unwrap();
+        Ok(state.torrents.get(info_hash).map(|name| Torrent {
+            info_hash: *info_hash,
+            name: name.clone(),
+        }))
+    }
+
+    fn torrents(&self) -> Result<Vec<Torrent>> {
+        let state = self.state.read().unwrap();
+        Ok(state
+            .torrents
+            .iter()
+            .map(|(info_hash, name)| Torrent {
+                info_hash: *info_hash,
+                name: name.clone(),
+            })
+            .collect())
+    }
+
+    fn add_user(&self, user: User) -> Result<()> {
+        let mut state = self.state.write().unwrap();
+        state.users.insert(user.passkey.clone(), user);
+        Ok(())
+    }
+
+    fn remove_user(&self, passkey: &str) -> Result<bool> {
+        let mut state = self.state.write().unwrap();
+        Ok(state.users.remove(passkey).is_some())
+    }
+
+    fn user(&self, passkey: &str) -> Result<Option<User>> {
+        let state = self.state.read().unwrap();
+        Ok(state.users.get(passkey).cloned())
+    }
+
+    fn users(&self) -> Result<Vec<User>> {
+        let state = self.state.read().unwrap();
+        Ok(state.users.values().cloned().collect())
+    }
+
+    fn add_transfer(&self, passkey: &str, uploaded: u64, downloaded: u64) -> Result<()> {
+        let mut state = self.state.write().unwrap();
+        if let Some(user) = state.users.get_mut(passkey) {
+            user.uploaded += uploaded;
+            user.downloaded += downloaded;
+        }
+        Ok(())
+    }
+
+    fn persist(&self) -> Result<()> {
+        match &self.snapshot {
+            Some(path) => self.save(path),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The bencoded form of a [`MemoryStorage`]
+#[derive(Serialize, Deserialize)]
+struct Snapshot {
+    torrents: Vec<SnapshotTorrent>,
+
+    #[serde(default)]
+    users: Vec<SnapshotUser>,
+}
+
+#[serde_as]
+#[derive(Serialize, Deserialize)]
+struct SnapshotTorrent {
+    downloaded: u64,
+
+    #[serde_as(as = "Bytes")]
+    #[serde(rename = "info hash")]
+    info_hash: InfoHash,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+
+    peers: Vec<SnapshotPeer>,
+
+    #[serde_as(as = "BoolFromInt")]
+    registered: bool,
+}
+
+#[serde_as]
+#[derive(Serialize, Deserialize)]
+struct SnapshotPeer {
+    /// Snapshots from before NAT checks have none, their peers were all taken as connectable
+    #[serde_as(as = "BoolFromInt")]
+    #[serde(default = "connectable")]
+    connectable: bool,
+
+    downloaded: u64,
+    ip: String,
+
+    /// Seconds since the Unix epoch
+    #[serde(rename = "last seen")]
+    last_seen: u64,
+
+    left: u64,
+
+    #[serde_as(as = "Bytes")]
+    #[serde(rename = "peer id")]
+    peer_id: PeerId,
+
+    port: u16,
+
+    #[serde(rename = "tracker id")]
+    tracker_id: String,
+
+    uploaded: u64,
+}
+
+fn connectable() -> bool {
+    true
+}
+
+#[derive(Serialize, Deserialize)]
+struct SnapshotUser {
+    downloaded: u64,
+    name: String,
+    passkey: String,
+    uploaded: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::storage::tests::{check_storage, peer, selection};
+
+    #[test]
+    fn test_memory_storage() {
+        check_storage(&MemoryStorage::new());
+    }
+
+    #[test]
+    fn test_memory_snapshot() {
+        let storage = MemoryStorage::new();
+        storage.upsert_peer(&[1; 20], &[1; 20], peer(1, 0)).unwrap();
+        storage
+            .upsert_peer(&[1; 20], &[2; 20], peer(2, 10))
+            .unwrap();
+        storage.add_completed(&[1; 20]).unwrap();
+        storage
+            .register_torrent(Torrent {
+                info_hash: [2; 20],
+                name: Some("unseen".to_string()),
+            })
+            .unwrap();
+
+        storage
+            .add_user(User {
+                passkey: "secret".to_string(),
+                name: "alice".to_string(),
+                uploaded: 10,
+                downloaded: 5,
+            })
+            .unwrap();
+
+        let path = std::env::temp_dir().join(format!("tforge-snapshot-{}", std::process::id()));
+        storage.save(&path).unwrap();
+        let restored = MemoryStorage::with_snapshot(path.clone()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            restored.stats(&[[1; 20]]).unwrap(),
+            storage.stats(&[[1; 20]]).unwrap()
+        );
+        let mut selected = Vec::new();
+        restored
+            .select_peers(&[1; 20], &selection([2; 20], 10), &mut selected)
+            .unwrap();
+        assert_eq!(selected, vec![([1; 20], peer(1, 0).addr.into())]);
+        assert_eq!(restored.peer(&[1; 20], &[1; 20]).unwrap(), Some(peer(1, 0)));
+        assert_eq!(restored.torrents().unwrap(), storage.torrents().unwrap());
+        assert_eq!(restored.users().unwrap(), storage.users().unwrap());
+
+        // Restored storages save to their snapshot
+        restored.persist().unwrap();
+        assert!(path.exists());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
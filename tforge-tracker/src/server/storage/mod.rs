@@ -0,0 +1,317 @@
+pub mod memory;
+pub mod sqlite;
+
+use super::swarm::{CompactAddr, InfoHash, Peer, PeerId, Selection, SwarmStats};
+use crate::error::Result;
+use memory::MemoryStorage;
+use sqlite::SqliteStorage;
+use std::{
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+use tforge_config::StorageConfig;
+
+/// A torrent registered with the tracker
+#[derive(Debug, Clone, PartialEq)]
+pub struct Torrent {
+    pub info_hash: InfoHash,
+
+    /// Shown in scrapes
+    pub name: Option<String>,
+}
+
+/// A user of a private tracker
+#[derive(Debug, Clone, PartialEq)]
+pub struct User {
+    /// The secret in the announce URL of the user
+    pub passkey: String,
+    pub name: String,
+
+    /// Bytes uploaded and downloaded over all torrents
+    pub uploaded: u64,
+    pub downloaded: u64,
+}
+
+impl User {
+    /// Uploaded bytes per downloaded one, infinite before anything was downloaded
+    pub fn ratio(&self) -> f64 {
+        match self.downloaded {
+            0 => f64::INFINITY,
+            downloaded => self.uploaded as f64 / downloaded as f64,
+        }
+    }
+}
+
+/// Where the tracker keeps its swarms, shared by all its listeners
+pub trait Storage: Send + Sync {
+    /// Inserts or updates a peer, returning its tracker id: the one it was given first
+    fn upsert_peer(&self, info_hash: &InfoHash, peer_id: &PeerId, peer: Peer) -> Result<u64>;
+
+    /// A peer of a torrent, as of its last announce
+    fn peer(&self, info_hash: &InfoHash, peer_id: &PeerId) -> Result<Option<Peer>>;
+
+    /// Every peer of a torrent
+    fn peers(&self, info_hash: &InfoHash) -> Result<Vec<(PeerId, Peer)>>;
+
+    /// Marks whether a peer accepts connections, returning whether it was known
+    fn set_connectable(
+        &self,
+        info_hash: &InfoHash,
+        peer_id: &PeerId,
+        connectable: bool,
+    ) -> Result<bool>;
+
+    /// Removes a peer, returning whether it was known
+    fn remove_peer(&self, info_hash: &InfoHash, peer_id: &PeerId) -> Result<bool>;
+
+    /// Removes the peers of all torrents last seen before `before`, returning how many there were
+    fn expire_peers(&self, before: SystemTime) -> Result<usize>;
+
+    /// Appends up to `selection.count` random peers of a torrent it allows to
+    /// `selected`, the preferred ones first
+    fn select_peers(
+        &self,
+        info_hash: &InfoHash,
+        selection: &Selection,
+        selected: &mut Vec<(PeerId, CompactAddr)>,
+    ) -> Result<()>;
+
+    /// Counts a completed download of a torrent
+    fn add_completed(&self, info_hash: &InfoHash) -> Result<()>;
+
+    /// The stats of torrents, zero for unknown ones
+    fn stats(&self, info_hashes: &[InfoHash]) -> Result<Vec<SwarmStats>>;
+
+    /// The stats of one torrent, zero when it is unknown
+    fn torrent_stats(&self, info_hash: &InfoHash) -> Result<SwarmStats> {
+        Ok(self.stats(&[*info_hash])?[0])
+    }
+
+    /// The stats of every torrent with peers or completed downloads
+    fn all_stats(&self) -> Result<Vec<(InfoHash, SwarmStats)>>;
+
+    /// Registers a torrent, or renames it when it already is
+    fn register_torrent(&self, torrent: Torrent) -> Result<()>;
+
+    /// Unregisters a torrent, returning whether it was registered
+    fn unregister_torrent(&self, info_hash: &InfoHash) -> Result<bool>;
+
+    /// The torrent when it is registered
+    fn torrent(&self, info_hash: &InfoHash) -> Result<Option<Torrent>>;
+
+    /// All the registered torrents
+    fn torrents(&self) -> Result<Vec<Torrent>>;
+
+    /// Adds a user, or replaces the one with the same passkey
+    fn add_user(&self, user: User) -> Result<()>;
+
+    /// Removes a user, returning whether it existed
+    fn remove_user(&self, passkey: &str) -> Result<bool>;
+
+    /// The user with `passkey`
+    fn user(&self, passkey: &str) -> Result<Option<User>>;
+
+    fn users(&self) -> Result<Vec<User>>;
+
+    /// Adds the bytes transferred by a user since its last announce
+    fn add_transfer(&self, passkey: &str, uploaded: u64, downloaded: u64) -> Result<()>;
+
+    /// Writes what is only in memory to disk, when the backend can
+    fn persist(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Whether the calls wait on the disk, which async code mustn't do on
+    /// its own threads
+    fn blocks(&self) -> bool {
+        false
+    }
+}
+
+/// Opens the configured storage, restoring the snapshot of a memory one
+pub fn open(config: &StorageConfig) -> Result<Arc<dyn Storage>> {
+    Ok(match config {
+        StorageConfig::Memory {
+            snapshot: Some(path),
+            ..
+        } => Arc::new(MemoryStorage::with_snapshot(path.clone())?),
+        StorageConfig::Memory { snapshot: None, .. } => Arc::new(MemoryStorage::new()),
+        StorageConfig::Sqlite { path } => Arc::new(SqliteStorage::open(path)?),
+    })
+}
+
+/// Persists the storage every `interval`, until it fails
+pub async fn persist_every(storage: Arc<dyn Storage>, interval: Duration) -> Result<()> {
+    let mut ticks = tokio::time::interval(interval);
+    ticks.tick().await;
+    loop {
+        ticks.tick().await;
+        let storage = storage.clone();
+        tokio::task::spawn_blocking(move || storage.persist())
+            .await
+            .map_err(|err| crate::error::Error::from_io(err.into()))??;
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::{net::SocketAddr, time::Duration};
+    use tforge_config::SelectionConfig;
+
+    /// A peer last seen `port` seconds after the Unix epoch
+    pub fn peer(port: u16, left: u64) -> Peer {
+        Peer {
+            addr: SocketAddr::from(([10, 0, 0, 1], port)),
+            uploaded: 0,
+            downloaded: 0,
+            left,
+            tracker_id: port as u64,
+            last_seen: SystemTime::UNIX_EPOCH + Duration::from_secs(port as u64),
+            connectable: true,
+        }
+    }
+
+    /// The selection for `peer_id` leeching from another address than the peers
+    pub fn selection(peer_id: PeerId, count: usize) -> Selection {
+        Selection {
+            peer_id,
+            addr: SocketAddr::from(([10, 0, 0, 2], 6881)),
+            seeder: false,
+            connectable: true,
+            count,
+            config: SelectionConfig::default(),
+        }
+    }
+
+    /// The behaviour every backend must have, leaving one completed download
+    /// of torrent 1, one registered torrent and one user behind
+    pub fn check_storage(storage: &dyn Storage) {
+        let (torrent, other) = ([1; 20], [2; 20]);
+        assert_eq!(
+            storage
+                .upsert_peer(&torrent, &[1; 20], peer(1, 10))
+                .unwrap(),
+            1
+        );
+        storage.upsert_peer(&torrent, &[2; 20], peer(2, 0)).unwrap();
+        storage.upsert_peer(&other, &[3; 20], peer(3, 0)).unwrap();
+
+        // The tracker id sticks to the peer
+        assert_eq!(
+            storage
+                .upsert_peer(&torrent, &[1; 20], peer(4, 10))
+                .unwrap(),
+            1
+        );
+        let select = |selection: &Selection| {
+            let mut selected = Vec::new();
+            storage
+                .select_peers(&torrent, selection, &mut selected)
+                .unwrap();
+            selected
+        };
+        let seeding = ([2; 20], peer(2, 0).addr.into());
+        assert_eq!(select(&selection([1; 20], 10)), vec![seeding]);
+        assert_eq!(select(&selection([9; 20], 1)), vec![seeding]);
+        assert_eq!(
+            storage.peer(&torrent, &[1; 20]).unwrap(),
+            Some(Peer {
+                tracker_id: 1,
+                ..peer(4, 10)
+            })
+        );
+
+        let mut peers = storage.peers(&torrent).unwrap();
+        peers.sort_by_key(|(peer_id, _)| *peer_id);
+        assert_eq!(peers.len(), 2);
+        assert_eq!(peers[1], ([2; 20], peer(2, 0)));
+        assert_eq!(storage.peers(&[9; 20]).unwrap(), vec![]);
+
+        // Seeders don't get seeders, nor anyone the peer at their address
+        let mut seeder = selection([9; 20], 10);
+        seeder.seeder = true;
+        assert_eq!(select(&seeder), vec![([1; 20], peer(4, 10).addr.into())]);
+        seeder.addr = peer(4, 10).addr;
+        assert_eq!(select(&seeder), vec![]);
+        let mut v6 = selection([9; 20], 10);
+        v6.addr = SocketAddr::from(([0xfe80, 0, 0, 0, 0, 0, 0, 1], 6881));
+        assert_eq!(select(&v6), vec![]);
+
+        // Peers which don't accept connections only get the ones which do
+        assert!(storage.set_connectable(&torrent, &[2; 20], false).unwrap());
+        assert!(!storage.set_connectable(&torrent, &[9; 20], false).unwrap());
+        assert!(
+            !storage
+                .peer(&torrent, &[2; 20])
+                .unwrap()
+                .unwrap()
+                .connectable
+        );
+        let mut unconnectable = selection([9; 20], 10);
+        unconnectable.connectable = false;
+        assert_eq!(
+            select(&unconnectable),
+            vec![([1; 20], peer(4, 10).addr.into())]
+        );
+        assert_eq!(select(&selection([9; 20], 1)), vec![seeding]);
+        storage.set_connectable(&torrent, &[2; 20], true).unwrap();
+
+        storage.add_completed(&torrent).unwrap();
+        let stats = SwarmStats {
+            complete: 1,
+            downloaded: 1,
+            incomplete: 1,
+        };
+        assert_eq!(
+            storage.stats(&[torrent, [9; 20]]).unwrap(),
+            vec![stats, SwarmStats::default()]
+        );
+        let mut all = storage.all_stats().unwrap();
+        all.sort_by_key(|(info_hash, _)| *info_hash);
+        assert_eq!(all.len(), 2);
+        assert_eq!(all[0], (torrent, stats));
+
+        assert!(storage.remove_peer(&torrent, &[2; 20]).unwrap());
+        assert!(!storage.remove_peer(&torrent, &[2; 20]).unwrap());
+
+        // Peer 3 was last seen at 3 seconds, peer 1 at 4
+        let before = SystemTime::UNIX_EPOCH + Duration::from_secs(4);
+        assert_eq!(storage.expire_peers(before).unwrap(), 1);
+        assert_eq!(storage.stats(&[other]).unwrap()[0], SwarmStats::default());
+        assert_eq!(storage.stats(&[torrent]).unwrap()[0].incomplete, 1);
+
+        let registered = Torrent {
+            info_hash: other,
+            name: Some("debian.iso".to_string()),
+        };
+        storage.register_torrent(registered.clone()).unwrap();
+        storage
+            .register_torrent(Torrent {
+                info_hash: [3; 20],
+                name: None,
+            })
+            .unwrap();
+        assert_eq!(storage.torrent(&other).unwrap(), Some(registered));
+        assert!(storage.unregister_torrent(&[3; 20]).unwrap());
+        assert_eq!(storage.torrent(&[3; 20]).unwrap(), None);
+        assert_eq!(storage.torrents().unwrap().len(), 1);
+
+        let user = |passkey: &str| User {
+            passkey: passkey.to_string(),
+            name: format!("user-{}", passkey),
+            uploaded: 0,
+            downloaded: 0,
+        };
+        storage.add_user(user("a")).unwrap();
+        storage.add_user(user("b")).unwrap();
+        storage.add_transfer("a", 100, 40).unwrap();
+        storage.add_transfer("a", 1, 2).unwrap();
+        let a = storage.user("a").unwrap().unwrap();
+        assert_eq!((a.uploaded, a.downloaded), (101, 42));
+        assert!(storage.remove_user("b").unwrap());
+        assert!(!storage.remove_user("b").unwrap());
+        assert_eq!(storage.user("b").unwrap(), None);
+        assert_eq!(storage.users().unwrap(), vec![a]);
+    }
+}
@@ -0,0 +1,487 @@
+use super::{Storage, Torrent, User};
+use crate::{
+    error::{Error, Result},
+    server::swarm::{
+        format_tracker_id,
+        parse_tracker_id,
+        CompactAddr,
+        InfoHash,
+        Peer,
+        PeerId,
+        Selection,
+        SwarmStats,
+    },
+};
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use std::{
+    collections::BTreeMap,
+    net::{IpAddr, SocketAddr},
+    path::Path,
+    sync::Mutex,
+    time::{Duration, SystemTime},
+};
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS peers (
+        info_hash BLOB NOT NULL,
+        peer_id BLOB NOT NULL,
+        ip TEXT NOT NULL,
+        port INTEGER NOT NULL,
+        uploaded INTEGER NOT NULL,
+        downloaded INTEGER NOT NULL,
+        bytes_left INTEGER NOT NULL,
+        tracker_id TEXT NOT NULL,
+        last_seen INTEGER NOT NULL,
+        connectable INTEGER NOT NULL DEFAULT 1,
+        PRIMARY KEY (info_hash, peer_id)
+    );
+    CREATE INDEX IF NOT EXISTS peers_last_seen ON peers (last_seen);
+    CREATE TABLE IF NOT EXISTS completed (
+        info_hash BLOB PRIMARY KEY,
+        count INTEGER NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS torrents (
+        info_hash BLOB PRIMARY KEY,
+        name TEXT
+    );
+    CREATE TABLE IF NOT EXISTS users (
+        passkey TEXT PRIMARY KEY,
+        name TEXT NOT NULL,
+        uploaded INTEGER NOT NULL,
+        downloaded INTEGER NOT NULL
+    );
+";
+
+/// A storage in a SQLite database, keeping swarms and stats across restarts.
+///
+/// Times are stored as seconds since the Unix epoch.
+pub struct SqliteStorage {
+    connection: Mutex<Connection>,
+}
+
+impl SqliteStorage {
+    /// Opens the database at `path`, creating it when needed
+    pub fn open(path: &Path) -> Result<Self> {
+        let connection = Connection::open(path)?;
+        connection.pragma_update(None, "journal_mode", "WAL")?;
+        connection.execute_batch(SCHEMA)?;
+        migrate(&connection)?;
+        Ok(SqliteStorage {
+            connection: Mutex::new(connection),
+        })
+    }
+}
+
+/// Adds the columns missing from databases created by older versions
+fn migrate(connection: &Connection) -> Result<()> {
+    let mut statement = connection.prepare("SELECT name FROM pragma_table_info('peers')")?;
+    let columns = statement
+        .query_map([], |row| row.get::<_, String>(0))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    if !columns.iter().any(|column| column == "connectable") {
+        connection
+            .execute_batch("ALTER TABLE peers ADD COLUMN connectable INTEGER NOT NULL DEFAULT 1")?;
+    }
+    Ok(())
+}
+
+fn unix_secs(time: SystemTime) -> i64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as i64
+}
+
+fn hash(row: &Row, index: usize) -> rusqlite::Result<[u8; 20]> {
+    let bytes: Vec<u8> = row.get(index)?;
+    bytes.try_into().map_err(|_| {
+        rusqlite::Error::FromSqlConversionFailure(
+            index,
+            rusqlite::types::Type::Blob,
+            "hashes must be 20 bytes long".into(),
+        )
+    })
+}
+
+fn user(row: &Row) -> rusqlite::Result<User> {
+    Ok(User {
+        passkey: row.get(0)?,
+        name: row.get(1)?,
+        uploaded: row.get::<_, i64>(2)? as u64,
+        downloaded: row.get::<_, i64>(3)? as u64,
+    })
+}
+
+fn peer(row: &Row) -> rusqlite::Result<(PeerId, Peer)> {
+    let peer_id = hash(row, 0)?;
+    let ip: String = row.get(1)?;
+    let ip: IpAddr = ip.parse().map_err(|err| {
+        rusqlite::Error::FromSqlConversionFailure(1, rusqlite::types::Type::Text, Box::new(err))
+    })?;
+    let tracker_id: String = row.get(6)?;
+    let tracker_id = parse_tracker_id(&tracker_id).ok_or_else(|| {
+        rusqlite::Error::FromSqlConversionFailure(
+            6,
+            rusqlite::types::Type::Text,
+            format!("invalid tracker id {}", tracker_id).into(),
+        )
+    })?;
+    let last_seen: i64 = row.get(7)?;
+    let peer = Peer {
+        addr: SocketAddr::new(ip, row.get(2)?),
+        uploaded: row.get::<_, i64>(3)? as u64,
+        downloaded: row.get::<_, i64>(4)? as u64,
+        left: row.get::<_, i64>(5)? as u64,
+        tracker_id,
+        last_seen: SystemTime::UNIX_EPOCH + Duration::from_secs(last_seen as u64),
+        connectable: row.get(8)?,
+    };
+    Ok((peer_id, peer))
+}
+
+impl Storage for SqliteStorage {
+    fn upsert_peer(&self, info_hash: &InfoHash, peer_id: &PeerId, peer: Peer) -> Result<u64> {
+        let connection = self.connection.lock().unwrap();
+        let tracker_id = connection.query_row(
+            "INSERT INTO peers
+                (info_hash, peer_id, ip, port, uploaded, downloaded, bytes_left, tracker_id,
+                 last_seen, connectable)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)
+             ON CONFLICT (info_hash, peer_id) DO UPDATE SET
+                ip = excluded.ip,
+                port = excluded.port,
+                uploaded = excluded.uploaded,
+                downloaded = excluded.downloaded,
+                bytes_left = excluded.bytes_left,
+                last_seen = excluded.last_seen,
+                connectable = excluded.connectable
+             RETURNING tracker_id",
+            params![
+                &info_hash[..],
+                &peer_id[..],
+                peer.addr.ip().to_string(),
+                peer.addr.port(),
+                peer.uploaded as i64,
+                peer.downloaded as i64,
+                peer.left as i64,
+                format_tracker_id(peer.tracker_id),
+                unix_secs(peer.last_seen),
+                peer.connectable,
+            ],
+            |row| row.get::<_, String>(0),
+        )?;
+        parse_tracker_id(&tracker_id)
+            .ok_or_else(|| Error::from_storage(format!("invalid tracker id {}", tracker_id)))
+    }
+
+    fn peer(&self, info_hash: &InfoHash, peer_id: &PeerId) -> Result<Option<Peer>> {
+        let connection = self.connection.lock().unwrap();
+        let peer = connection
+            .query_row(
+                "SELECT peer_id, ip, port, uploaded, downloaded, bytes_left, tracker_id, last_seen,
+                        connectable
+                 FROM peers WHERE info_hash = ?1 AND peer_id = ?2",
+                params![&info_hash[..], &peer_id[..]],
+                peer,
+            )
+            .optional()?;
+        Ok(peer.map(|(_, peer)| peer))
+    }
+
+    fn peers(&self, info_hash: &InfoHash) -> Result<Vec<(PeerId, Peer)>> {
+        let connection = self.connection.lock().unwrap();
+        let mut statement = connection.prepare_cached(
+            "SELECT peer_id, ip, port, uploaded, downloaded, bytes_left, tracker_id, last_seen,
+                    connectable
+             FROM peers WHERE info_hash = ?1",
+        )?;
+        let peers = statement
+            .query_map(params![&info_hash[..]], peer)?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(peers)
+    }
+
+    fn set_connectable(
+        &self,
+        info_hash: &InfoHash,
+        peer_id: &PeerId,
+        connectable: bool,
+    ) -> Result<bool> {
+        let connection = self.connection.lock().unwrap();
+        let updated = connection.execute(
+            "UPDATE peers SET connectable = ?3 WHERE info_hash = ?1 AND peer_id = ?2",
+            params![&info_hash[..], &peer_id[..], connectable],
+        )?;
+        Ok(updated > 0)
+    }
+
+    fn remove_peer(&self, info_hash: &InfoHash, peer_id: &PeerId) -> Result<bool> {
+        let connection = self.connection.lock().unwrap();
+        let removed = connection.execute(
+            "DELETE FROM peers WHERE info_hash = ?1 AND peer_id = ?2",
+            params![&info_hash[..], &peer_id[..]],
+        )?;
+        Ok(removed > 0)
+    }
+
+    fn expire_peers(&self, before: SystemTime) -> Result<usize> {
+        let connection = self.connection.lock().unwrap();
+        let expired = connection.execute(
+            "DELETE FROM peers WHERE last_seen < ?1",
+            params![unix_secs(before)],
+        )?;
+        Ok(expired)
+    }
+
+    fn select_peers(
+        &self,
+        info_hash: &InfoHash,
+        selection: &Selection,
+        selected: &mut Vec<(PeerId, CompactAddr)>,
+    ) -> Result<()> {
+        let connection = self.connection.lock().unwrap();
+        // The same rules as `Selection::allows` and `Selection::prefers`,
+        // IPv6 addresses being the ones with a colon
+        let mut statement = connection.prepare_cached(
+            "SELECT peer_id, ip, port, uploaded, downloaded, bytes_left, tracker_id, last_seen,
+                    connectable
+             FROM peers WHERE info_hash = ?1 AND peer_id != ?2 AND NOT (ip = ?3 AND port = ?4)
+             AND port != 0
+             AND (?5 OR NOT ?6 OR bytes_left > 0)
+             AND (NOT ?7 OR (instr(ip, ':') > 0) = ?8)
+             AND (?11 OR connectable)
+             ORDER BY (?9 AND bytes_left = 0) DESC, RANDOM() LIMIT ?10",
+        )?;
+        let config = &selection.config;
+        let params = params![
+            &info_hash[..],
+            &selection.peer_id[..],
+            selection.addr.ip().to_string(),
+            selection.addr.port(),
+            config.seeders_to_seeders,
+            selection.seeder,
+            config.match_address_family,
+            selection.addr.is_ipv6(),
+            config.seeders_first && !selection.seeder,
+            selection.count as i64,
+            selection.connectable,
+        ];
+        for row in statement.query_map(params, peer)? {
+            let (peer_id, peer) = row?;
+            selected.push((peer_id, peer.addr.into()));
+        }
+        Ok(())
+    }
+
+    fn add_completed(&self, info_hash: &InfoHash) -> Result<()> {
+        let connection = self.connection.lock().unwrap();
+        connection.execute(
+            "INSERT INTO completed (info_hash, count) VALUES (?1, 1)
+             ON CONFLICT (info_hash) DO UPDATE SET count = count + 1",
+            params![&info_hash[..]],
+        )?;
+        Ok(())
+    }
+
+    fn stats(&self, info_hashes: &[InfoHash]) -> Result<Vec<SwarmStats>> {
+        let connection = self.connection.lock().unwrap();
+        let mut statement = connection.prepare_cached(
+            "SELECT
+                (SELECT COUNT(*) FROM peers WHERE info_hash = ?1 AND bytes_left = 0),
+                (SELECT COALESCE(SUM(count), 0) FROM completed WHERE info_hash = ?1),
+                (SELECT COUNT(*) FROM peers WHERE info_hash = ?1 AND bytes_left > 0)",
+        )?;
+        let mut stats = Vec::with_capacity(info_hashes.len());
+        for info_hash in info_hashes {
+            stats.push(statement.query_row(params![&info_hash[..]], |row| {
+                Ok(SwarmStats {
+                    complete: row.get::<_, i64>(0)? as u64,
+                    downloaded: row.get::<_, i64>(1)? as u64,
+                    incomplete: row.get::<_, i64>(2)? as u64,
+                })
+            })?);
+        }
+        Ok(stats)
+    }
+
+    fn all_stats(&self) -> Result<Vec<(InfoHash, SwarmStats)>> {
+        let connection = self.connection.lock().unwrap();
+        let mut all = BTreeMap::<InfoHash, SwarmStats>::new();
+
+        let mut statement = connection.prepare_cached(
+            "SELECT info_hash, SUM(bytes_left = 0), SUM(bytes_left > 0)
+             FROM peers GROUP BY info_hash",
+        )?;
+        let mut rows = statement.query([])?;
+        while let Some(row) = rows.next()? {
+            let stats = all.entry(hash(row, 0)?).or_default();
+            stats.complete = row.get::<_, i64>(1)? as u64;
+            stats.incomplete = row.get::<_, i64>(2)? as u64;
+        }
+
+        let mut statement = connection.prepare_cached("SELECT info_hash, count FROM completed")?;
+        let mut rows = statement.query([])?;
+        while let Some(row) = rows.next()? {
+            all.entry(hash(row, 0)?).or_default().downloaded = row.get::<_, i64>(1)? as u64;
+        }
+        Ok(all.into_iter().collect())
+    }
+
+    fn register_torrent(&self, torrent: Torrent) -> Result<()> {
+        let connection = self.connection.lock().unwrap();
+        connection.execute(
+            "INSERT INTO torrents (info_hash, name) VALUES (?1, ?2)
+             ON CONFLICT (info_hash) DO UPDATE SET name = excluded.name",
+            params![&torrent.info_hash[..], torrent.name],
+        )?;
+        Ok(())
+    }
+
+    fn unregister_torrent(&self, info_hash: &InfoHash) -> Result<bool> {
+        let connection = self.connection.lock().unwrap();
+        let removed = connection.execute(
+            "DELETE FROM torrents WHERE info_hash = ?1",
+            params![&info_hash[..]],
+        )?;
+        Ok(removed > 0)
+    }
+
+    fn torrent(&self, info_hash: &InfoHash) -> Result<Option<Torrent>> {
+        let connection = self.connection.lock().unwrap();
+        let name = connection
+            .query_row(
+                "SELECT name FROM torrents WHERE info_hash = ?1",
+                params![&info_hash[..]],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(name.map(|name| Torrent {
+            info_hash: *info_hash,
+            name,
+        }))
+    }
+
+    fn torrents(&self) -> Result<Vec<Torrent>> {
+        let connection = self.connection.lock().unwrap();
+        let mut statement = connection.prepare_cached("SELECT info_hash, name FROM torrents")?;
+        let torrents = statement
+            .query_map([], |row| {
+                Ok(Torrent {
+                    info_hash: hash(row, 0)?,
+                    name: row.get(1)?,
+                })
+            })?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(torrents)
+    }
+
+    fn add_user(&self, user: User) -> Result<()> {
+        let connection = self.connection.lock().unwrap();
+        connection.execute(
+            "INSERT OR REPLACE INTO users (passkey, name, uploaded, downloaded)
+             VALUES (?1, ?2, ?3, ?4)",
+            params![
+                user.passkey,
+                user.name,
+                user.uploaded as i64,
+                user.downloaded as i64
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn remove_user(&self, passkey: &str) -> Result<bool> {
+        let connection = self.connection.lock().unwrap();
+        let removed = connection.execute("DELETE FROM users WHERE passkey = ?1", [passkey])?;
+        Ok(removed > 0)
+    }
+
+    fn user(&self, passkey: &str) -> Result<Option<User>> {
+        let connection = self.connection.lock().unwrap();
+        let user = connection
+            .query_row(
+                "SELECT passkey, name, uploaded, downloaded FROM users WHERE passkey = ?1",
+                [passkey],
+                user,
+            )
+            .optional()?;
+        Ok(user)
+    }
+
+    fn users(&self) -> Result<Vec<User>> {
+        let connection = self.connection.lock().unwrap();
+        let mut statement =
+            connection.prepare_cached("SELECT passkey, name, uploaded, downloaded FROM users")?;
+        let users = statement
+            .query_map([], user)?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(users)
+    }
+
+    fn add_transfer(&self, passkey: &str, uploaded: u64, downloaded: u64) -> Result<()> {
+        let connection = self.connection.lock().unwrap();
+        connection.execute(
+            "UPDATE users SET uploaded = uploaded + ?2, downloaded = downloaded + ?3
+             WHERE passkey = ?1",
+            params![passkey, uploaded as i64, downloaded as i64],
+        )?;
+        Ok(())
+    }
+
+    fn blocks(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::storage::tests::check_storage;
+
+    #[test]
+    fn test_sqlite_storage() {
+        let path = std::env::temp_dir().join(format!("tforge-sqlite-{}.db", std::process::id()));
+        check_storage(&SqliteStorage::open(&path).unwrap());
+
+        // The stats survive a restart
+        let reopened = SqliteStorage::open(&path).unwrap();
+        assert_eq!(reopened.stats(&[[1; 20]]).unwrap()[0].downloaded, 1);
+        assert_eq!(reopened.torrents().unwrap().len(), 1);
+        assert_eq!(reopened.user("a").unwrap().unwrap().uploaded, 101);
+        drop(reopened);
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+        }
+    }
+
+    #[test]
+    fn test_sqlite_migrate() {
+        let path = std::env::temp_dir().join(format!("tforge-migrate-{}.db", std::process::id()));
+        let connection = Connection::open(&path).unwrap();
+        connection
+            .execute_batch(
+                "CREATE TABLE peers (
+                    info_hash BLOB NOT NULL,
+                    peer_id BLOB NOT NULL,
+                    ip TEXT NOT NULL,
+                    port INTEGER NOT NULL,
+                    uploaded INTEGER NOT NULL,
+                    downloaded INTEGER NOT NULL,
+                    bytes_left INTEGER NOT NULL,
+                    tracker_id TEXT NOT NULL,
+                    last_seen INTEGER NOT NULL,
+                    PRIMARY KEY (info_hash, peer_id)
+                );
+                INSERT INTO peers VALUES (x'0101010101010101010101010101010101010101',
+                    x'0202020202020202020202020202020202020202', '10.0.0.1', 6881, 0, 0, 0,
+                    '0000000000000001', 0);",
+            )
+            .unwrap();
+        drop(connection);
+
+        let storage = SqliteStorage::open(&path).unwrap();
+        let peer = storage.peer(&[1; 20], &[2; 20]).unwrap().unwrap();
+        assert!(peer.connectable);
+        drop(storage);
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,597 @@
+use crate::protocol::{
+    compact::{PEER_V4_LEN, PEER_V6_LEN},
+    scrape::ScrapeFile,
+};
+use rand::Rng;
+use std::{
+    collections::HashMap,
+    fmt,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::{Duration, SystemTime},
+};
+use tforge_config::SelectionConfig;
+
+pub type InfoHash = [u8; 20];
+pub type PeerId = [u8; 20];
+
+/// A peer as the tracker knows it from its last announce.
+///
+/// Peers announced over WebSocket connect through WebRTC and have no port,
+/// they are kept with port 0 and never given to the other peers.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Peer {
+    pub addr: SocketAddr,
+    pub uploaded: u64,
+    pub downloaded: u64,
+    pub left: u64,
+
+    /// The `tracker id` handed out to the peer, sent as 16 hex digits
+    pub tracker_id: u64,
+    pub last_seen: SystemTime,
+
+    /// Whether the peer accepts connections, as far as the tracker knows
+    pub connectable: bool,
+}
+
+impl Peer {
+    pub fn is_seeder(&self) -> bool {
+        self.left == 0
+    }
+}
+
+/// A peer id for the tracker itself, when it connects to peers or announces
+/// upstream, with a random suffix of digits
+pub fn tracker_peer_id() -> PeerId {
+    let mut peer_id = *b"-TF0001-000000000000";
+    for byte in &mut peer_id[8..] {
+        *byte = b'0' + rand::random::<u8>() % 10;
+    }
+    peer_id
+}
+
+/// A tracker id as it is sent and stored
+pub fn format_tracker_id(tracker_id: u64) -> String {
+    format!("{:016x}", tracker_id)
+}
+
+/// Parses a tracker id written by [`format_tracker_id`]
+pub fn parse_tracker_id(tracker_id: &str) -> Option<u64> {
+    u64::from_str_radix(tracker_id, 16).ok()
+}
+
+/// The counts a scrape reports about a torrent
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SwarmStats {
+    /// Peers with the whole torrent
+    pub complete: u64,
+
+    /// Number of times the torrent was completed
+    pub downloaded: u64,
+
+    /// Peers still downloading
+    pub incomplete: u64,
+}
+
+impl From<SwarmStats> for ScrapeFile {
+    fn from(stats: SwarmStats) -> Self {
+        ScrapeFile {
+            complete: stats.complete,
+            downloaded: stats.downloaded,
+            incomplete: stats.incomplete,
+            name: None,
+        }
+    }
+}
+
+/// An address as it is sent in compact peer lists: the IP then the port,
+/// in network byte order, 6 bytes long for IPv4 and 18 for IPv6
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct CompactAddr {
+    bytes: [u8; PEER_V6_LEN],
+    len: u8,
+}
+
+impl CompactAddr {
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..self.len as usize]
+    }
+
+    pub fn is_ipv4(&self) -> bool {
+        self.len as usize == PEER_V4_LEN
+    }
+
+    pub fn port(&self) -> u16 {
+        let len = self.len as usize;
+        u16::from_be_bytes([self.bytes[len - 2], self.bytes[len - 1]])
+    }
+
+    pub fn to_socket_addr(&self) -> SocketAddr {
+        let (ip, port) = self.as_bytes().split_at(self.len as usize - 2);
+        let ip = match <[u8; 4]>::try_from(ip) {
+            Ok(ip) => IpAddr::V4(Ipv4Addr::from(ip)),
+            Err(_) => IpAddr::V6(Ipv6Addr::from(<[u8; 16]>::try_from(ip).unwrap())),
+        };
+        SocketAddr::new(ip, u16::from_be_bytes([port[0], port[1]]))
+    }
+}
+
+impl From<SocketAddr> for CompactAddr {
+    fn from(addr: SocketAddr) -> Self {
+        let mut bytes = [0; PEER_V6_LEN];
+        let len = match addr.ip() {
+            IpAddr::V4(ip) => {
+                bytes[..4].copy_from_slice(&ip.octets());
+                PEER_V4_LEN
+            }
+            IpAddr::V6(ip) => {
+                bytes[..16].copy_from_slice(&ip.octets());
+                PEER_V6_LEN
+            }
+        };
+        bytes[len - 2..len].copy_from_slice(&addr.port().to_be_bytes());
+        CompactAddr {
+            bytes,
+            len: len as u8,
+        }
+    }
+}
+
+impl fmt::Debug for CompactAddr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.to_socket_addr())
+    }
+}
+
+/// A peer as a swarm keeps it: fixed-size, with its address ready to be
+/// copied into compact peer lists
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PeerRecord {
+    pub peer_id: PeerId,
+    pub addr: CompactAddr,
+    pub uploaded: u64,
+    pub downloaded: u64,
+    pub left: u64,
+    pub tracker_id: u64,
+
+    /// Seconds since the Unix epoch
+    pub last_seen: u32,
+    pub connectable: bool,
+}
+
+impl PeerRecord {
+    pub fn new(peer_id: PeerId, peer: &Peer) -> Self {
+        let last_seen = peer
+            .last_seen
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default();
+        PeerRecord {
+            peer_id,
+            addr: peer.addr.into(),
+            uploaded: peer.uploaded,
+            downloaded: peer.downloaded,
+            left: peer.left,
+            tracker_id: peer.tracker_id,
+            last_seen: last_seen.as_secs() as u32,
+            connectable: peer.connectable,
+        }
+    }
+
+    pub fn peer(&self) -> Peer {
+        Peer {
+            addr: self.addr.to_socket_addr(),
+            uploaded: self.uploaded,
+            downloaded: self.downloaded,
+            left: self.left,
+            tracker_id: self.tracker_id,
+            last_seen: SystemTime::UNIX_EPOCH + Duration::from_secs(self.last_seen as u64),
+            connectable: self.connectable,
+        }
+    }
+
+    pub fn is_seeder(&self) -> bool {
+        self.left == 0
+    }
+
+    /// Where the peer goes in [`Swarm::groups`]
+    fn group(&self) -> usize {
+        group(self.addr.is_ipv4(), self.is_seeder())
+    }
+}
+
+fn group(ipv4: bool, seeder: bool) -> usize {
+    (ipv4 as usize) << 1 | seeder as usize
+}
+
+/// The peers to pick for an announcing peer
+#[derive(Debug, Clone)]
+pub struct Selection {
+    /// The announcing peer, never picked, nor any peer at its address
+    pub peer_id: PeerId,
+    pub addr: SocketAddr,
+    pub seeder: bool,
+
+    /// Whether the announcing peer accepts connections, those which don't
+    /// only get the peers which do
+    pub connectable: bool,
+
+    /// Most peers to pick
+    pub count: usize,
+    pub config: SelectionConfig,
+}
+
+impl Selection {
+    /// Whether `peer` may be given to the announcing peer
+    pub fn allows(&self, peer_id: &PeerId, peer: &Peer) -> bool {
+        *peer_id != self.peer_id
+            && peer.addr != self.addr
+            && peer.addr.port() != 0
+            && (self.config.seeders_to_seeders || !self.seeder || !peer.is_seeder())
+            && (!self.config.match_address_family || peer.addr.is_ipv4() == self.addr.is_ipv4())
+            && (self.connectable || peer.connectable)
+    }
+
+    /// Whether `peer` goes before the others
+    pub fn prefers(&self, peer: &Peer) -> bool {
+        self.config.seeders_first && !self.seeder && peer.is_seeder()
+    }
+
+    /// The groups of [`Swarm::groups`] to pick from, in order, each group
+    /// being as preferred as the ones it is listed with
+    fn groups(&self) -> impl Iterator<Item = &'static [usize]> {
+        // Groups of IPv6 leechers, IPv6 seeders, IPv4 leechers and IPv4 seeders
+        const FAMILIES: [&[usize]; 3] = [&[0, 1], &[2, 3], &[0, 1, 2, 3]];
+        const SEEDERS: [&[usize]; 3] = [&[1], &[3], &[1, 3]];
+        const LEECHERS: [&[usize]; 3] = [&[0], &[2], &[0, 2]];
+        let family = match self.config.match_address_family {
+            true => self.addr.is_ipv4() as usize,
+            false => 2,
+        };
+        let only_leechers = self.seeder && !self.config.seeders_to_seeders;
+        let seeders_first = !self.seeder && self.config.seeders_first;
+        let groups: [Option<&[usize]>; 2] = match (only_leechers, seeders_first) {
+            (true, _) => [Some(LEECHERS[family]), None],
+            (false, true) => [Some(SEEDERS[family]), Some(LEECHERS[family])],
+            (false, false) => [Some(FAMILIES[family]), None],
+        };
+        groups.into_iter().flatten()
+    }
+}
+
+/// The peers of a torrent.
+///
+/// They are kept in groups by address family and by whether they seed, so
+/// that a selection only walks the peers it may pick, and are found in them
+/// through an index by peer id.
+#[derive(Debug, Default)]
+pub struct Swarm {
+    /// IPv6 leechers, IPv6 seeders, IPv4 leechers and IPv4 seeders
+    groups: [Vec<PeerRecord>; 4],
+
+    /// The group of each peer and its position in it
+    index: HashMap<PeerId, (u8, u32)>,
+
+    /// Number of times the torrent was completed
+    pub downloaded: u64,
+}
+
+impl Swarm {
+    /// Inserts or updates a peer, returning its tracker id: the one it was given first
+    pub fn upsert(&mut self, peer_id: PeerId, peer: &Peer) -> u64 {
+        let mut record = PeerRecord::new(peer_id, peer);
+        let group = record.group();
+        match self.index.get(&peer_id).copied() {
+            Some((known, position)) => {
+                let known = known as usize;
+                record.tracker_id = self.groups[known][position as usize].tracker_id;
+                if known == group {
+                    self.groups[group][position as usize] = record;
+                } else {
+                    self.take(known, position as usize);
+                    self.push(record);
+                }
+            }
+            None => self.push(record),
+        }
+        record.tracker_id
+    }
+
+    fn push(&mut self, record: PeerRecord) {
+        let group = record.group();
+        let position = self.groups[group].len() as u32;
+        self.groups[group].push(record);
+        self.index.insert(record.peer_id, (group as u8, position));
+    }
+
+    /// Removes the peer at `position` of a group, moving the last one of the group in its place
+    fn take(&mut self, group: usize, position: usize) -> PeerRecord {
+        let record = self.groups[group].swap_remove(position);
+        if let Some(moved) = self.groups[group].get(position) {
+            self.index
+                .insert(moved.peer_id, (group as u8, position as u32));
+        }
+        record
+    }
+
+    pub fn get(&self, peer_id: &PeerId) -> Option<&PeerRecord> {
+        let (group, position) = *self.index.get(peer_id)?;
+        Some(&self.groups[group as usize][position as usize])
+    }
+
+    /// Marks whether a peer accepts connections, returning whether it was there
+    pub fn set_connectable(&mut self, peer_id: &PeerId, connectable: bool) -> bool {
+        let Some(&(group, position)) = self.index.get(peer_id) else {
+            return false;
+        };
+        self.groups[group as usize][position as usize].connectable = connectable;
+        true
+    }
+
+    /// Removes a peer, returning whether it was there
+    pub fn remove(&mut self, peer_id: &PeerId) -> bool {
+        match self.index.remove(peer_id) {
+            Some((group, position)) => {
+                self.take(group as usize, position as usize);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Removes the peers last seen before `before`, returning how many there were
+    pub fn expire(&mut self, before: SystemTime) -> usize {
+        let before = before
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default();
+        let count = self.len();
+        for group in 0..self.groups.len() {
+            let mut position = 0;
+            while let Some(record) = self.groups[group].get(position) {
+                if Duration::from_secs(record.last_seen as u64) < before {
+                    let peer_id = record.peer_id;
+                    self.index.remove(&peer_id);
+                    self.take(group, position);
+                } else {
+                    position += 1;
+                }
+            }
+        }
+        count - self.len()
+    }
+
+    /// Every peer, by group
+    pub fn peers(&self) -> impl Iterator<Item = &PeerRecord> {
+        self.groups.iter().flatten()
+    }
+
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    pub fn seeders(&self) -> usize {
+        self.groups[1].len() + self.groups[3].len()
+    }
+
+    pub fn leechers(&self) -> usize {
+        self.groups[0].len() + self.groups[2].len()
+    }
+
+    pub fn stats(&self) -> SwarmStats {
+        SwarmStats {
+            complete: self.seeders() as u64,
+            downloaded: self.downloaded,
+            incomplete: self.leechers() as u64,
+        }
+    }
+
+    /// Appends up to `selection.count` peers it allows to `selected`, the preferred ones first.
+    ///
+    /// The peers of each preference are a uniform sample of those allowed,
+    /// drawn in `selected` itself (reservoir sampling), so that no peers come
+    /// together more often than others.
+    pub fn select(&self, selection: &Selection, selected: &mut Vec<(PeerId, CompactAddr)>) {
+        let mut rng = rand::thread_rng();
+        let addr = CompactAddr::from(selection.addr);
+        let end = selected.len() + selection.count;
+        for groups in selection.groups() {
+            let start = selected.len();
+            let wanted = end - start;
+            if wanted == 0 {
+                return;
+            }
+            let allowed = groups
+                .iter()
+                .flat_map(|&group| &self.groups[group])
+                .filter(|record| {
+                    record.peer_id != selection.peer_id
+                        && record.addr != addr
+                        && record.addr.port() != 0
+                        && (selection.connectable || record.connectable)
+                });
+            for (seen, record) in allowed.enumerate() {
+                if seen < wanted {
+                    selected.push((record.peer_id, record.addr));
+                } else {
+                    let slot = rng.gen_range(0..=seen);
+                    if slot < wanted {
+                        selected[start + slot] = (record.peer_id, record.addr);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peer(port: u16, left: u64) -> Peer {
+        Peer {
+            addr: SocketAddr::from(([10, 0, 0, 1], port)),
+            uploaded: 0,
+            downloaded: 0,
+            left,
+            tracker_id: port as u64,
+            last_seen: SystemTime::UNIX_EPOCH + Duration::from_secs(port as u64),
+            connectable: true,
+        }
+    }
+
+    fn selection(peer_id: PeerId, port: u16, seeder: bool) -> Selection {
+        Selection {
+            peer_id,
+            addr: SocketAddr::from(([10, 0, 0, 2], port)),
+            seeder,
+            connectable: true,
+            count: 50,
+            config: SelectionConfig::default(),
+        }
+    }
+
+    fn select(swarm: &Swarm, selection: &Selection) -> Vec<(PeerId, Peer)> {
+        let mut selected = Vec::new();
+        swarm.select(selection, &mut selected);
+        selected
+            .into_iter()
+            .map(|(peer_id, _)| (peer_id, swarm.get(&peer_id).unwrap().peer()))
+            .collect()
+    }
+
+    #[test]
+    fn test_compact_addr() {
+        assert_eq!(std::mem::size_of::<PeerRecord>(), 80);
+        let v4 = SocketAddr::from(([10, 0, 0, 1], 6881));
+        let compact = CompactAddr::from(v4);
+        assert_eq!(compact.as_bytes(), [10, 0, 0, 1, 0x1a, 0xe1]);
+        assert!(compact.is_ipv4());
+        assert_eq!(compact.to_socket_addr(), v4);
+        let v6: SocketAddr = "[2001:db8::1]:6881".parse().unwrap();
+        let compact = CompactAddr::from(v6);
+        assert_eq!(compact.as_bytes().len(), 18);
+        assert!(!compact.is_ipv4());
+        assert_eq!(compact.to_socket_addr(), v6);
+    }
+
+    #[test]
+    fn test_swarm_upsert() {
+        let mut swarm = Swarm::default();
+        assert_eq!(swarm.upsert([1; 20], &peer(1, 10)), 1);
+        swarm.upsert([2; 20], &peer(2, 0));
+        assert_eq!((swarm.seeders(), swarm.leechers()), (1, 1));
+
+        // The tracker id sticks to the peer, which moves to the seeders
+        assert_eq!(swarm.upsert([1; 20], &peer(3, 0)), 1);
+        assert_eq!(swarm.seeders(), 2);
+        assert_eq!(swarm.get(&[1; 20]).unwrap().peer().addr.port(), 3);
+
+        let selected = select(&swarm, &selection([1; 20], 10, false));
+        assert_eq!(selected.len(), 1);
+        assert_eq!(selected[0].0, [2; 20]);
+
+        // Peer 2 was last seen at 2 seconds, peer 1 at 3
+        let before = SystemTime::UNIX_EPOCH + Duration::from_secs(3);
+        assert_eq!(swarm.expire(before), 1);
+        assert!(swarm.get(&[1; 20]).is_some());
+        assert!(swarm.get(&[2; 20]).is_none());
+        assert!(swarm.remove(&[1; 20]));
+        assert!(!swarm.remove(&[1; 20]));
+        assert!(swarm.is_empty());
+    }
+
+    #[test]
+    fn test_swarm_remove_keeps_index() {
+        let mut swarm = Swarm::default();
+        for port in 1..=10 {
+            swarm.upsert([port as u8; 20], &peer(port, 0));
+        }
+        assert!(swarm.remove(&[3; 20]));
+        assert_eq!(
+            swarm.expire(SystemTime::UNIX_EPOCH + Duration::from_secs(6)),
+            4
+        );
+        for port in 6..=10u16 {
+            let record = swarm.get(&[port as u8; 20]).unwrap();
+            assert_eq!(record.peer().addr.port(), port);
+        }
+        assert_eq!(swarm.len(), 5);
+    }
+
+    #[test]
+    fn test_swarm_select() {
+        let mut swarm = Swarm::default();
+        for port in 1..=20 {
+            swarm.upsert([port as u8; 20], &peer(port, port as u64 % 2));
+        }
+        let mut v6 = peer(21, 0);
+        v6.addr = SocketAddr::from(([0xfe80, 0, 0, 0, 0, 0, 0, 1], 21));
+        swarm.upsert([21; 20], &v6);
+
+        // Leechers get the 10 IPv4 seeders first, then the other leechers
+        let mut leecher = selection([1; 20], 1, false);
+        leecher.count = 12;
+        let selected = select(&swarm, &leecher);
+        assert_eq!(selected.len(), 12);
+        assert!(selected[..10].iter().all(|(_, peer)| peer.is_seeder()));
+        assert!(selected[10..].iter().all(|(_, peer)| !peer.is_seeder()));
+        assert!(selected.iter().all(|(peer_id, _)| *peer_id != [1; 20]));
+        assert!(selected
+            .iter()
+            .all(|(peer_id, peer)| leecher.allows(peer_id, peer)));
+
+        // Seeders only get leechers
+        let selected = select(&swarm, &selection([2; 20], 2, true));
+        assert_eq!(selected.len(), 10);
+        assert!(selected.iter().all(|(_, peer)| !peer.is_seeder()));
+
+        // The announcing address is left out, whatever the peer id
+        let mut restarted = selection([99; 20], 3, false);
+        restarted.addr = SocketAddr::from(([10, 0, 0, 1], 3));
+        assert_eq!(select(&swarm, &restarted).len(), 19);
+
+        let mut v6 = selection([99; 20], 1, false);
+        v6.addr = SocketAddr::from(([0xfe80, 0, 0, 0, 0, 0, 0, 2], 1));
+        assert_eq!(select(&swarm, &v6).len(), 1);
+        v6.config.match_address_family = false;
+        v6.config.seeders_first = false;
+        assert_eq!(select(&swarm, &v6).len(), 21);
+
+        // Peers which don't accept connections only get the ones which do
+        for port in 1..=19 {
+            assert!(swarm.set_connectable(&[port as u8; 20], false));
+        }
+        assert!(!swarm.set_connectable(&[99; 20], false));
+        let mut unconnectable = selection([99; 20], 1, false);
+        assert_eq!(select(&swarm, &unconnectable).len(), 20);
+        unconnectable.connectable = false;
+        let selected = select(&swarm, &unconnectable);
+        assert_eq!(selected.len(), 1);
+        assert_eq!(selected[0].0, [20; 20]);
+        assert!(unconnectable.allows(&selected[0].0, &selected[0].1));
+    }
+
+    #[test]
+    fn test_swarm_select_spread() {
+        let mut swarm = Swarm::default();
+        for port in 1..=10 {
+            swarm.upsert([port as u8; 20], &peer(port, 1));
+        }
+        let mut selection = selection([99; 20], 99, false);
+        selection.count = 2;
+
+        // Any two of the 10 leechers come together, not only neighbours
+        let mut pairs = std::collections::HashSet::new();
+        for _ in 0..500 {
+            let mut pair: Vec<_> = select(&swarm, &selection)
+                .into_iter()
+                .map(|(peer_id, _)| peer_id[0])
+                .collect();
+            pair.sort();
+            pairs.insert(pair);
+        }
+        assert!(pairs.len() > 30);
+    }
+}
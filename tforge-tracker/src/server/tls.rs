@@ -0,0 +1,325 @@
+use crate::error::{Error, Result};
+use axum::{
+    extract::State,
+    http::{header, HeaderMap, StatusCode, Uri},
+    response::{IntoResponse, Redirect, Response},
+    Router,
+};
+use axum_server::tls_rustls::RustlsConfig;
+use std::{
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+use tforge_config::TlsConfig;
+use tokio::net::TcpListener;
+
+/// The certificate and key of the HTTPS listener, read again when their
+/// files change. Connections already open keep the certificate they got.
+pub struct Certificates {
+    config: TlsConfig,
+    rustls: RustlsConfig,
+    modified: Mutex<(SystemTime, SystemTime)>,
+}
+
+impl Certificates {
+    pub async fn load(config: TlsConfig) -> Result<Self> {
+        let modified = modified(&config)?;
+        let (cert, key) = read(&config)?;
+        let rustls = RustlsConfig::from_pem(cert, key)
+            .await
+            .map_err(|err| invalid(&config, err))?;
+        Ok(Certificates {
+            config,
+            rustls,
+            modified: Mutex::new(modified),
+        })
+    }
+
+    /// The configuration the listener reads on every handshake
+    pub fn rustls(&self) -> RustlsConfig {
+        self.rustls.clone()
+    }
+
+    /// Reads the files again if they changed since they were last read,
+    /// keeping the certificate read before when they can't be
+    pub async fn reload(&self) -> Result<bool> {
+        let changed = modified(&self.config)?;
+        if *self.modified.lock().unwrap() == changed {
+            return Ok(false);
+        }
+        let (cert, key) = read(&self.config)?;
+        self.rustls
+            .reload_from_pem(cert, key)
+            .await
+            .map_err(|err| invalid(&self.config, err))?;
+        *self.modified.lock().unwrap() = changed;
+        eprintln!("Reloaded TLS certificate {}", self.config.cert.display());
+        Ok(true)
+    }
+}
+
+fn modified(config: &TlsConfig) -> Result<(SystemTime, SystemTime)> {
+    let modified = |path: &Path| {
+        std::fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .map_err(|err| Error::from_tls(format!("{}: {}", path.display(), err)))
+    };
+    Ok((modified(&config.cert)?, modified(&config.key)?))
+}
+
+/// The PEM certificate chain and key, refusing a chain without any
+/// certificate, which rustls would take
+fn read(config: &TlsConfig) -> Result<(Vec<u8>, Vec<u8>)> {
+    let read = |path: &Path| {
+        std::fs::read(path).map_err(|err| Error::from_tls(format!("{}: {}", path.display(), err)))
+    };
+    let cert = read(&config.cert)?;
+    let certs = rustls_pemfile::certs(&mut cert.as_slice())
+        .collect::<std::io::Result<Vec<_>>>()
+        .map_err(|err| invalid(config, err))?;
+    if certs.is_empty() {
+        let err = format!("{}: no certificate", config.cert.display());
+        return Err(Error::from_tls(err));
+    }
+    Ok((cert, read(&config.key)?))
+}
+
+fn invalid(config: &TlsConfig, err: std::io::Error) -> Error {
+    Error::from_tls(format!(
+        "{} and {}: {}",
+        config.cert.display(),
+        config.key.display(),
+        err
+    ))
+}
+
+/// Reloads `certificates` every `period` when their files changed. Files
+/// that can't be read are logged and the certificate read before is kept.
+pub async fn reload_every(certificates: Arc<Certificates>, period: Duration) -> Result<()> {
+    let mut ticks = tokio::time::interval(period);
+    ticks.tick().await;
+    loop {
+        ticks.tick().await;
+        if let Err(err) = certificates.reload().await {
+            eprintln!("{}", err);
+        }
+    }
+}
+
+/// Redirects every request to the same host and path on the HTTPS port
+pub fn redirect_router(https_port: u16) -> Router {
+    Router::new().fallback(redirect).with_state(https_port)
+}
+
+/// Serves the redirects to the HTTPS listener on `https_port` until it fails
+pub async fn serve_redirect(listener: TcpListener, https_port: u16) -> Result<()> {
+    axum::serve(listener, redirect_router(https_port))
+        .await
+        .map_err(Error::from_io)
+}
+
+async fn redirect(State(https_port): State<u16>, headers: HeaderMap, uri: Uri) -> Response {
+    let Some(host) = headers
+        .get(header::HOST)
+        .and_then(|host| host.to_str().ok())
+    else {
+        return (StatusCode::BAD_REQUEST, "missing Host header").into_response();
+    };
+    // Leaves the port out, keeping the brackets of an IPv6 address
+    let host = match host.rfind(':') {
+        Some(colon) if !host[colon..].contains(']') => &host[..colon],
+        _ => host,
+    };
+    let path = uri.path_and_query().map_or("/", |path| path.as_str());
+    let location = match https_port {
+        443 => format!("https://{}{}", host, path),
+        port => format!("https://{}:{}{}", host, port, path),
+    };
+    Redirect::permanent(&location).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        client::{http_client, TrackerClient},
+        protocol::{TrackerEvent, TrackerRequest},
+        server::{http, Tracker},
+    };
+    use std::path::PathBuf;
+    use tforge_config::{ClientTlsConfig, ServerConfig};
+
+    /// Writes a self-signed certificate for localhost to `dir`
+    fn generate(dir: &Path) -> (PathBuf, PathBuf) {
+        let names = vec!["localhost".to_string(), "127.0.0.1".to_string()];
+        let certificate = rcgen::generate_simple_self_signed(names).unwrap();
+        let (cert, key) = (dir.join("cert.pem"), dir.join("key.pem"));
+        std::fs::write(&cert, certificate.serialize_pem().unwrap()).unwrap();
+        std::fs::write(&key, certificate.serialize_private_key_pem()).unwrap();
+        (cert, key)
+    }
+
+    /// Moves the modification time of `path` `secs` seconds ahead, past the
+    /// resolution of the file system
+    fn touch(path: &Path, secs: u64) {
+        std::fs::File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(SystemTime::now() + Duration::from_secs(secs))
+            .unwrap();
+    }
+
+    fn request() -> TrackerRequest {
+        TrackerRequest {
+            info_hash: [0xaa; 20],
+            peer_id: [1; 20],
+            port: 6881,
+            uploaded: 0,
+            downloaded: 0,
+            left: 100,
+            compact: true,
+            event: Some(TrackerEvent::Started),
+            ip: None,
+            numwant: None,
+            no_peer_id: false,
+            key: None,
+            trackerid: None,
+        }
+    }
+
+    /// A client trusting only the certificate in `dir`
+    fn client(dir: &Path, url: &str) -> TrackerClient {
+        let tls = ClientTlsConfig {
+            ca_bundle: None,
+            pinned_certificate: Some(dir.join("pinned.pem")),
+        };
+        std::fs::copy(dir.join("cert.pem"), dir.join("pinned.pem")).unwrap();
+        TrackerClient::new(url.to_string(), Some(http_client(&tls).unwrap()))
+    }
+
+    #[tokio::test]
+    async fn test_https_announce() {
+        let dir = std::env::temp_dir().join(format!("tforge-tls-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (cert, key) = generate(&dir);
+        let config = TlsConfig {
+            cert,
+            key,
+            reload_interval: 60,
+            redirect: None,
+        };
+        let certificates = Certificates::load(config).await.unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("https://{}/announce", listener.local_addr().unwrap());
+        let tracker = Arc::new(Tracker::new(ServerConfig::default()));
+        tokio::spawn(http::serve_tls(listener, tracker, certificates.rustls()));
+
+        let response = client(&dir, &url).announce(&request()).await.unwrap();
+        assert_eq!(response.incomplete, 1);
+
+        // Untrusted by default
+        let untrusted = TrackerClient::new(url.clone(), None);
+        assert!(matches!(
+            untrusted.announce(&request()).await,
+            Err(Error::Http(_))
+        ));
+
+        // A new certificate is served once reloaded, the old one isn't trusted anymore
+        assert!(!certificates.reload().await.unwrap());
+        let old = client(&dir, &url);
+        touch(&generate(&dir).0, 10);
+        assert!(certificates.reload().await.unwrap());
+        assert!(old.announce(&request()).await.is_err());
+        assert!(client(&dir, &url).announce(&request()).await.is_ok());
+
+        // A broken certificate is refused and the current one kept
+        let current = client(&dir, &url);
+        std::fs::write(dir.join("cert.pem"), "not a certificate").unwrap();
+        touch(&dir.join("cert.pem"), 20);
+        assert!(matches!(certificates.reload().await, Err(Error::Tls(_))));
+        assert!(current.announce(&request()).await.is_ok());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_pinned_certificate() {
+        let dir = std::env::temp_dir().join(format!("tforge-pin-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut params = rcgen::CertificateParams::new(Vec::new());
+        params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+        let ca = rcgen::Certificate::from_params(params).unwrap();
+        let names = vec!["localhost".to_string(), "127.0.0.1".to_string()];
+        let leaf = rcgen::generate_simple_self_signed(names).unwrap();
+        let (cert, key) = (dir.join("cert.pem"), dir.join("key.pem"));
+        std::fs::write(&cert, leaf.serialize_pem_with_signer(&ca).unwrap()).unwrap();
+        std::fs::write(&key, leaf.serialize_private_key_pem()).unwrap();
+        std::fs::write(dir.join("ca.pem"), ca.serialize_pem().unwrap()).unwrap();
+        let config = TlsConfig {
+            cert,
+            key,
+            reload_interval: 60,
+            redirect: None,
+        };
+        let certificates = Certificates::load(config).await.unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("https://{}/announce", listener.local_addr().unwrap());
+        let tracker = Arc::new(Tracker::new(ServerConfig::default()));
+        tokio::spawn(http::serve_tls(listener, tracker, certificates.rustls()));
+        let client = |tls| TrackerClient::new(url.clone(), Some(http_client(&tls).unwrap()));
+
+        // The CA is trusted as a bundle, but pinning it doesn't pin what it signs
+        let bundle = ClientTlsConfig {
+            ca_bundle: Some(dir.join("ca.pem")),
+            pinned_certificate: None,
+        };
+        assert!(client(bundle).announce(&request()).await.is_ok());
+        let pinned_ca = ClientTlsConfig {
+            ca_bundle: None,
+            pinned_certificate: Some(dir.join("ca.pem")),
+        };
+        assert!(client(pinned_ca).announce(&request()).await.is_err());
+        let pinned = ClientTlsConfig {
+            ca_bundle: None,
+            pinned_certificate: Some(dir.join("cert.pem")),
+        };
+        assert!(client(pinned).announce(&request()).await.is_ok());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_redirect() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve_redirect(listener, 8443));
+
+        let client = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .unwrap();
+        let response = client
+            .get(format!("http://{}/announce?info_hash=abc", addr))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::PERMANENT_REDIRECT);
+        assert_eq!(
+            response.headers()[header::LOCATION],
+            "https://127.0.0.1:8443/announce?info_hash=abc"
+        );
+
+        let response = client
+            .get(format!("http://{}/scrape", addr))
+            .header(header::HOST, "[::1]:80")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(
+            response.headers()[header::LOCATION],
+            "https://[::1]:8443/scrape"
+        );
+    }
+}
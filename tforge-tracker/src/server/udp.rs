@@ -0,0 +1,400 @@
+use super::{blocking, metrics, passkey, refusal, Tracker};
+use crate::{
+    error::{Error, Result},
+    protocol::{
+        udp::{UdpAnnounceRequest, UdpAnnounceResponse, UdpRequest, UdpResponse, UdpScrapeStats},
+        TrackerRequest,
+        TrackerResponse,
+    },
+};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use socket2::{Domain, Protocol, Socket, Type};
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use tokio::{net::UdpSocket, sync::Semaphore};
+
+/// Connection IDs change every minute and are accepted for two (BEP 15)
+const CONNECTION_ID_WINDOW: u64 = 60;
+
+/// Connection IDs derived from the IP of the client and the time, so the
+/// tracker keeps no state about them.
+///
+/// A client must receive the connect response to learn its ID, which prevents
+/// spoofed sources from using the tracker to amplify traffic. The port is left
+/// out as clients may announce from another socket than the one they connected with.
+pub struct ConnectionIds {
+    secret: [u8; 32],
+}
+
+impl ConnectionIds {
+    /// Uses a random secret, so the IDs are invalidated when the process restarts
+    pub fn new() -> Self {
+        ConnectionIds {
+            secret: rand::random(),
+        }
+    }
+
+    pub fn generate(&self, client: IpAddr, now: u64) -> u64 {
+        self.derive(client, now / CONNECTION_ID_WINDOW)
+    }
+
+    pub fn is_valid(&self, id: u64, client: IpAddr, now: u64) -> bool {
+        let window = now / CONNECTION_ID_WINDOW;
+        id == self.derive(client, window) || id == self.derive(client, window.saturating_sub(1))
+    }
+
+    fn derive(&self, client: IpAddr, window: u64) -> u64 {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.secret).expect("any key length works");
+        mac.update(&window.to_be_bytes());
+        match client {
+            IpAddr::V4(ip) => mac.update(&ip.octets()),
+            IpAddr::V6(ip) => mac.update(&ip.octets()),
+        }
+        let digest = mac.finalize().into_bytes();
+        u64::from_be_bytes(digest[..8].try_into().unwrap())
+    }
+}
+
+impl Default for ConnectionIds {
+    fn default() -> Self {
+        ConnectionIds::new()
+    }
+}
+
+/// Binds a UDP socket. IPv6 sockets only accept IPv6, so the same port can
+/// also be bound for IPv4.
+pub fn bind(address: SocketAddr) -> Result<UdpSocket> {
+    let socket = Socket::new(
+        Domain::for_address(address),
+        Type::DGRAM,
+        Some(Protocol::UDP),
+    )?;
+    if address.is_ipv6() {
+        socket.set_only_v6(true)?;
+    }
+    socket.set_nonblocking(true)?;
+    socket.bind(&address.into())?;
+    UdpSocket::from_std(socket.into()).map_err(Error::from_io)
+}
+
+/// Most requests of a socket handled at once, the next ones wait to be read
+const MAX_IN_FLIGHT: usize = 256;
+
+/// Serves the UDP tracker on `socket` until it fails
+pub async fn serve(
+    socket: UdpSocket,
+    tracker: Arc<Tracker>,
+    connection_ids: Arc<ConnectionIds>,
+) -> Result<()> {
+    let ipv6 = socket.local_addr()?.is_ipv6();
+    let socket = Arc::new(socket);
+    let in_flight = Arc::new(Semaphore::new(MAX_IN_FLIGHT));
+    let mut buffer = vec![0; 2048];
+    loop {
+        let (received, client) = socket.recv_from(&mut buffer).await?;
+        // Malformed packets are dropped without a reply
+        let Ok(request) = UdpRequest::from_bytes(&buffer[..received]) else {
+            continue;
+        };
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let start = Instant::now();
+        let Ok(permit) = in_flight.clone().acquire_owned().await else {
+            return Ok(());
+        };
+        let (socket, tracker, connection_ids) =
+            (socket.clone(), tracker.clone(), connection_ids.clone());
+        tokio::spawn(async move {
+            let handled = blocking(&tracker, move |tracker| {
+                let response = handle(tracker, &connection_ids, &request, client, now);
+                (request, response)
+            });
+            let (request, response) = match handled.await {
+                Ok(handled) => handled,
+                Err(err) => return eprintln!("UDP request from {}: {}", client, err),
+            };
+            record(&tracker, &request, &response, start.elapsed());
+            // A client can go away at any time, that's no reason to stop serving
+            let _ = socket.send_to(&response.to_bytes(ipv6), client).await;
+            drop(permit);
+        });
+    }
+}
+
+fn handle(
+    tracker: &Tracker,
+    connection_ids: &ConnectionIds,
+    request: &UdpRequest,
+    client: SocketAddr,
+    now: u64,
+) -> UdpResponse {
+    let transaction_id = request.transaction_id();
+    let connection_id = match request {
+        UdpRequest::Connect { .. } => None,
+        UdpRequest::Announce(announce) => Some(announce.connection_id),
+        UdpRequest::Scrape { connection_id, .. } => Some(*connection_id),
+    };
+    if connection_id.is_some_and(|id| !connection_ids.is_valid(id, client.ip(), now)) {
+        return UdpResponse::Error {
+            transaction_id,
+            message: "invalid connection id".to_string(),
+        };
+    }
+
+    match request {
+        UdpRequest::Connect { transaction_id } => UdpResponse::Connect {
+            transaction_id: *transaction_id,
+            connection_id: connection_ids.generate(client.ip(), now),
+        },
+        UdpRequest::Announce(announce) => {
+            let request = tracker_request(announce, client.ip());
+            // Private trackers get the passkey from the path sent as BEP 41 URL data
+            let path = String::from_utf8_lossy(&announce.url_data);
+            match tracker.announce(&request, client.ip(), passkey(&path)) {
+                // Peers of the other address family are left out when encoding
+                TrackerResponse::Success(success) => UdpResponse::Announce(UdpAnnounceResponse {
+                    transaction_id,
+                    interval: success.interval as u32,
+                    leechers: success.incomplete as u32,
+                    seeders: success.complete as u32,
+                    peers: success.peer_addrs(),
+                }),
+                TrackerResponse::Failure(failure) => UdpResponse::Error {
+                    transaction_id,
+                    message: failure.failure_reason,
+                },
+            }
+        }
+        UdpRequest::Scrape { info_hashes, .. } => {
+            let stats = tracker.guard().check_scrape(client.ip()).and_then(|()| {
+                match tracker.config().private {
+                    // UDP scrapes carry no passkey, private trackers refuse them
+                    Some(_) => Err(Error::from_request("missing passkey")),
+                    None => tracker.stats(info_hashes),
+                }
+            });
+            match stats {
+                Ok(stats) => UdpResponse::Scrape {
+                    transaction_id,
+                    stats: stats
+                        .into_iter()
+                        .map(|stats| UdpScrapeStats {
+                            seeders: stats.complete as u32,
+                            completed: stats.downloaded as u32,
+                            leechers: stats.incomplete as u32,
+                        })
+                        .collect(),
+                },
+                Err(err) => UdpResponse::Error {
+                    transaction_id,
+                    message: refusal(&err),
+                },
+            }
+        }
+    }
+}
+
+/// Counts an announce or a scrape in the metrics, connects are left out
+fn record(tracker: &Tracker, request: &UdpRequest, response: &UdpResponse, elapsed: Duration) {
+    let failure_reason = match response {
+        UdpResponse::Error { message, .. } => Some(message.as_str()),
+        _ => None,
+    };
+    let metrics = tracker.metrics();
+    match request {
+        UdpRequest::Connect { .. } => {}
+        UdpRequest::Announce(announce) => metrics.record_announce(
+            metrics::Protocol::Udp,
+            announce.event,
+            failure_reason,
+            elapsed,
+        ),
+        UdpRequest::Scrape { .. } => {
+            metrics.record_scrape(metrics::Protocol::Udp, failure_reason, elapsed)
+        }
+    }
+}
+
+/// The HTTP form of an announce, which the tracker works with. The `ip` of
+/// the announce is an IPv4 address, only kept for the IPv4 ones and used by
+/// the tracker when they come from one of its trusted sources.
+fn tracker_request(announce: &UdpAnnounceRequest, remote: IpAddr) -> TrackerRequest {
+    let ip = (remote.to_canonical().is_ipv4() && !announce.ip.is_unspecified())
+        .then_some(IpAddr::V4(announce.ip));
+    TrackerRequest {
+        info_hash: announce.info_hash,
+        peer_id: announce.peer_id,
+        port: announce.port,
+        uploaded: announce.uploaded as usize,
+        downloaded: announce.downloaded as usize,
+        left: announce.left as usize,
+        compact: true,
+        event: announce.event,
+        ip,
+        numwant: (announce.num_want >= 0).then_some(announce.num_want as usize),
+        no_peer_id: true,
+        key: Some(format!("{:08x}", announce.key)),
+        trackerid: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        client::{udp::UdpTrackerClient, TrackerClient},
+        protocol::TrackerEvent,
+        server::http,
+    };
+    use std::net::Ipv4Addr;
+    use tforge_config::ServerConfig;
+    use tokio::net::TcpListener;
+
+    fn request(peer_id: u8) -> TrackerRequest {
+        TrackerRequest {
+            info_hash: [0xbb; 20],
+            peer_id: [peer_id; 20],
+            port: 6880 + peer_id as u16,
+            uploaded: 0,
+            downloaded: 0,
+            left: 100,
+            compact: true,
+            event: Some(TrackerEvent::Started),
+            ip: None,
+            numwant: None,
+            no_peer_id: false,
+            key: None,
+            trackerid: None,
+        }
+    }
+
+    async fn start(address: &str, tracker: Arc<Tracker>) -> UdpTrackerClient {
+        let socket = bind(address.parse().unwrap()).unwrap();
+        let url = format!("udp://{}/announce", socket.local_addr().unwrap());
+        tokio::spawn(serve(socket, tracker, Arc::new(ConnectionIds::new())));
+        UdpTrackerClient::new(url).unwrap()
+    }
+
+    #[test]
+    fn test_connection_ids() {
+        let ids = ConnectionIds::new();
+        let client = IpAddr::from([10, 0, 0, 1]);
+        let id = ids.generate(client, 1000);
+        assert!(ids.is_valid(id, client, 1000));
+        assert!(ids.is_valid(id, client, 1000 + CONNECTION_ID_WINDOW));
+        assert!(!ids.is_valid(id, client, 1000 + 2 * CONNECTION_ID_WINDOW));
+        assert!(!ids.is_valid(id, IpAddr::from([10, 0, 0, 2]), 1000));
+
+        // Another tracker process hands out other IDs
+        assert_ne!(ConnectionIds::new().generate(client, 1000), id);
+    }
+
+    #[test]
+    fn test_invalid_connection_id() {
+        let tracker = Tracker::new(ServerConfig::default());
+        let ids = ConnectionIds::new();
+        let request = UdpRequest::Scrape {
+            connection_id: 42,
+            transaction_id: 7,
+            info_hashes: vec![[0xbb; 20]],
+        };
+        let client = SocketAddr::from((Ipv4Addr::LOCALHOST, 6881));
+        let response = handle(&tracker, &ids, &request, client, 1000);
+        assert_eq!(
+            response,
+            UdpResponse::Error {
+                transaction_id: 7,
+                message: "invalid connection id".to_string(),
+            }
+        );
+
+        record(&tracker, &request, &response, Duration::ZERO);
+        let metrics = tracker.render_metrics().unwrap();
+        let errors =
+            "tforge_tracker_errors_total{protocol=\"udp\",reason=\"invalid_connection_id\"} 1";
+        assert!(metrics.lines().any(|line| line == errors));
+    }
+
+    #[test]
+    fn test_announce_ip() {
+        let tracker = Tracker::new(ServerConfig::default());
+        let ids = ConnectionIds::new();
+        let announce = |client: SocketAddr, peer_id: u8| UdpAnnounceRequest {
+            connection_id: ids.generate(client.ip(), 1000),
+            transaction_id: 7,
+            info_hash: [0xbb; 20],
+            peer_id: [peer_id; 20],
+            downloaded: 0,
+            left: 100,
+            uploaded: 0,
+            event: Some(TrackerEvent::Started),
+            ip: Ipv4Addr::new(198, 51, 100, 1),
+            key: 0,
+            num_want: -1,
+            port: 6881,
+            url_data: Vec::new(),
+        };
+
+        // Only IPv4 announces carry an address
+        let v4 = SocketAddr::from((Ipv4Addr::LOCALHOST, 6881));
+        let v6: SocketAddr = "[::1]:6881".parse().unwrap();
+        let ip = |client: SocketAddr| tracker_request(&announce(client, 1), client.ip()).ip;
+        assert_eq!(ip(v4), Some(IpAddr::from([198, 51, 100, 1])));
+        assert_eq!(ip(v6), None);
+
+        // Which isn't trusted by default
+        let request = UdpRequest::Announce(announce(v4, 1));
+        handle(&tracker, &ids, &request, v4, 1000);
+        let client = SocketAddr::from(([127, 0, 0, 2], 6881));
+        let request = UdpRequest::Announce(announce(client, 2));
+        match handle(&tracker, &ids, &request, client, 1000) {
+            UdpResponse::Announce(response) => assert_eq!(response.peers, vec![v4]),
+            response => panic!("unexpected {:?}", response),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_udp_announce_and_scrape() {
+        for address in ["127.0.0.1:0", "[::1]:0"] {
+            let tracker = Arc::new(Tracker::new(ServerConfig::default()));
+            let client = start(address, tracker).await;
+            let first = client.announce(&request(1)).await.unwrap();
+            assert!(first.peers.is_empty());
+            assert_eq!(first.interval, 1800);
+
+            let second = client.announce(&request(2)).await.unwrap();
+            assert_eq!(second.incomplete, 2);
+            assert_eq!(second.peer_addrs().len(), 1);
+            assert_eq!(second.peer_addrs()[0].port(), 6881);
+
+            let stats = client.scrape(&[[0xbb; 20], [0xcc; 20]]).await.unwrap();
+            assert_eq!(stats[0].leechers, 2);
+            assert_eq!(stats[1], UdpScrapeStats::default());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_shared_swarms() {
+        let tracker = Arc::new(Tracker::new(ServerConfig::default()));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/announce", listener.local_addr().unwrap());
+        tokio::spawn(http::serve(listener, tracker.clone()));
+        TrackerClient::new(url, None)
+            .announce(&request(1))
+            .await
+            .unwrap();
+
+        let client = start("127.0.0.1:0", tracker).await;
+        let response = client.announce(&request(2)).await.unwrap();
+        assert_eq!(
+            response.peer_addrs(),
+            vec!["127.0.0.1:6881".parse::<SocketAddr>().unwrap()]
+        );
+    }
+}
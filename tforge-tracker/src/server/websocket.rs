@@ -0,0 +1,600 @@
+use super::{
+    blocking,
+    metrics::Protocol,
+    refusal,
+    swarm::{InfoHash, PeerId},
+    Tracker,
+};
+use crate::{
+    error::{Error, Result},
+    protocol::{TrackerEvent, TrackerRequest, TrackerResponse},
+};
+use axum::extract::ws::{Message, WebSocket};
+use futures::{SinkExt, StreamExt};
+use rand::seq::IteratorRandom;
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use std::{
+    collections::{HashMap, HashSet},
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+use tokio::sync::mpsc;
+
+/// Messages waiting to be sent to a peer, those relayed to it are dropped past that
+const QUEUE: usize = 64;
+
+type Outbox = mpsc::Sender<String>;
+
+/// The WebSocket connections of the WebTorrent peers by swarm, through which
+/// the tracker relays their WebRTC offers and answers
+#[derive(Default)]
+pub struct Signaling {
+    swarms: Mutex<HashMap<InfoHash, HashMap<PeerId, Outbox>>>,
+}
+
+impl Signaling {
+    fn join(&self, info_hash: &InfoHash, peer_id: &PeerId, outbox: &Outbox) {
+        let mut swarms = self.swarms.lock().unwrap();
+        let swarm = swarms.entry(*info_hash).or_default();
+        swarm.insert(*peer_id, outbox.clone());
+    }
+
+    /// Removes the peer unless it joined again on another connection since,
+    /// returning whether it was removed
+    fn leave(&self, info_hash: &InfoHash, peer_id: &PeerId, outbox: &Outbox) -> bool {
+        let mut swarms = self.swarms.lock().unwrap();
+        let Some(swarm) = swarms.get_mut(info_hash) else {
+            return false;
+        };
+        if !swarm
+            .get(peer_id)
+            .is_some_and(|joined| joined.same_channel(outbox))
+        {
+            return false;
+        }
+        swarm.remove(peer_id);
+        if swarm.is_empty() {
+            swarms.remove(info_hash);
+        }
+        true
+    }
+
+    /// Up to `count` peers of the swarm other than `peer_id`, at random
+    fn pick(&self, info_hash: &InfoHash, peer_id: &PeerId, count: usize) -> Vec<Outbox> {
+        let swarms = self.swarms.lock().unwrap();
+        let Some(swarm) = swarms.get(info_hash) else {
+            return Vec::new();
+        };
+        swarm
+            .iter()
+            .filter(|(joined, _)| *joined != peer_id)
+            .map(|(_, outbox)| outbox.clone())
+            .choose_multiple(&mut rand::thread_rng(), count)
+    }
+
+    fn get(&self, info_hash: &InfoHash, peer_id: &PeerId) -> Option<Outbox> {
+        let swarms = self.swarms.lock().unwrap();
+        swarms.get(info_hash)?.get(peer_id).cloned()
+    }
+
+    /// The peers connected, all swarms together
+    pub fn len(&self) -> usize {
+        self.swarms.lock().unwrap().values().map(HashMap::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.swarms.lock().unwrap().is_empty()
+    }
+}
+
+/// A message of a WebTorrent client. Hashes and ids are strings of 20
+/// characters, one per byte.
+#[derive(Deserialize, Debug)]
+struct Request {
+    action: String,
+    info_hash: Option<InfoHashes>,
+    peer_id: Option<String>,
+    event: Option<String>,
+    numwant: Option<usize>,
+    uploaded: Option<u64>,
+    downloaded: Option<u64>,
+    left: Option<u64>,
+    #[serde(default)]
+    offers: Vec<Offer>,
+    answer: Option<Value>,
+    offer_id: Option<String>,
+    to_peer_id: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum InfoHashes {
+    One(String),
+    Many(Vec<String>),
+}
+
+#[derive(Deserialize, Debug)]
+struct Offer {
+    offer: Value,
+    offer_id: String,
+}
+
+/// The bytes of a string with a character per byte
+fn decode(name: &str, value: Option<&str>) -> Result<[u8; 20]> {
+    let value = value.ok_or_else(|| Error::from_request(format!("missing {}", name)))?;
+    let bytes: Option<Vec<u8>> = value.chars().map(|c| u8::try_from(c).ok()).collect();
+    bytes
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| Error::from_request(format!("{} must be 20 bytes long", name)))
+}
+
+/// The string with a character per byte of `bytes`
+fn encode(bytes: &[u8]) -> String {
+    bytes.iter().copied().map(char::from).collect()
+}
+
+fn event(event: Option<&str>) -> Result<Option<TrackerEvent>> {
+    match event {
+        None | Some("" | "update") => Ok(None),
+        Some("started") => Ok(Some(TrackerEvent::Started)),
+        Some("completed") => Ok(Some(TrackerEvent::Completed)),
+        Some("stopped") => Ok(Some(TrackerEvent::Stopped)),
+        Some(_) => Err(Error::from_request("invalid event")),
+    }
+}
+
+/// Serves a WebTorrent client connected from `remote` until it disconnects,
+/// then removes the peers it announced from their swarms
+pub async fn serve(
+    socket: WebSocket,
+    tracker: Arc<Tracker>,
+    remote: SocketAddr,
+    passkey: Option<String>,
+) {
+    let (mut sink, mut stream) = socket.split();
+    let (outbox, mut messages) = mpsc::channel::<String>(QUEUE);
+    let writer = tokio::spawn(async move {
+        while let Some(message) = messages.recv().await {
+            if sink.send(Message::Text(message)).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut connection = Connection {
+        tracker: tracker.clone(),
+        remote,
+        passkey,
+        outbox,
+        joined: HashSet::new(),
+    };
+    while let Some(Ok(message)) = stream.next().await {
+        let text = match message {
+            Message::Text(text) => text,
+            Message::Close(_) => break,
+            _ => continue,
+        };
+        if let Some(reply) = connection.handle(&text).await {
+            if connection.outbox.send(reply.to_string()).await.is_err() {
+                break;
+            }
+        }
+    }
+    if let Err(err) = blocking(&tracker, move |_| connection.close()).await {
+        eprintln!("WebSocket peers of {}: {}", remote, err);
+    }
+    writer.abort();
+}
+
+/// A WebSocket connection and the swarms its peers joined
+struct Connection {
+    tracker: Arc<Tracker>,
+    remote: SocketAddr,
+    passkey: Option<String>,
+    outbox: Outbox,
+    joined: HashSet<(InfoHash, PeerId)>,
+}
+
+impl Connection {
+    /// The reply to a message, none for the answers which are only relayed
+    async fn handle(&mut self, text: &str) -> Option<Value> {
+        let request: Request = match serde_json::from_str(text) {
+            Ok(request) => request,
+            Err(err) => {
+                let reason = format!("invalid message: {}", err);
+                return Some(json!({ "failure reason": reason }));
+            }
+        };
+        let start = Instant::now();
+        let tracker = self.tracker.clone();
+        let metrics = tracker.metrics();
+        match request.action.as_str() {
+            "announce" if request.answer.is_some() => match self.answer(&request) {
+                Ok(()) => None,
+                Err(err) => Some(failed(&request, &err)),
+            },
+            "announce" => {
+                let reply = self.announce(&request).await;
+                let event = event(request.event.as_deref()).ok().flatten();
+                let failure_reason = reply.as_ref().err().map(refusal);
+                metrics.record_announce(
+                    Protocol::WebSocket,
+                    event,
+                    failure_reason.as_deref(),
+                    start.elapsed(),
+                );
+                Some(reply.unwrap_or_else(|err| failed(&request, &err)))
+            }
+            "scrape" => {
+                let reply = self.scrape(&request).await;
+                let failure_reason = reply.as_ref().err().map(refusal);
+                metrics.record_scrape(
+                    Protocol::WebSocket,
+                    failure_reason.as_deref(),
+                    start.elapsed(),
+                );
+                Some(reply.unwrap_or_else(|err| failed(&request, &err)))
+            }
+            _ => Some(failed(&request, &Error::from_request("invalid action"))),
+        }
+    }
+
+    /// Records the announce in the swarm shared with the other listeners,
+    /// then sends its offers to as many peers of the swarm
+    async fn announce(&mut self, request: &Request) -> Result<Value> {
+        let info_hash = decode("info_hash", single(request))?;
+        let peer_id = decode("peer_id", request.peer_id.as_deref())?;
+        let event = event(request.event.as_deref())?;
+        let announce = TrackerRequest {
+            info_hash,
+            peer_id,
+            port: 0,
+            uploaded: request.uploaded.unwrap_or_default() as usize,
+            downloaded: request.downloaded.unwrap_or_default() as usize,
+            // Clients which don't know what they have left send none, they are leeching
+            left: request.left.unwrap_or(1) as usize,
+            compact: true,
+            event,
+            ip: None,
+            numwant: Some(0),
+            no_peer_id: true,
+            key: None,
+            trackerid: None,
+        };
+        let (remote, passkey) = (self.remote.ip(), self.passkey.clone());
+        let response = blocking(&self.tracker, move |tracker| {
+            tracker.announce(&announce, remote, passkey.as_deref())
+        });
+        let success = match response.await? {
+            TrackerResponse::Success(success) => success,
+            TrackerResponse::Failure(failure) => {
+                return Err(Error::from_failure(failure.failure_reason))
+            }
+        };
+
+        let signaling = self.tracker.signaling();
+        match event {
+            Some(TrackerEvent::Stopped) => {
+                signaling.leave(&info_hash, &peer_id, &self.outbox);
+                self.joined.remove(&(info_hash, peer_id));
+            }
+            _ => {
+                signaling.join(&info_hash, &peer_id, &self.outbox);
+                self.joined.insert((info_hash, peer_id));
+            }
+        }
+
+        let config = self.tracker.config();
+        let count = request
+            .numwant
+            .unwrap_or(config.default_numwant)
+            .min(config.max_numwant)
+            .min(request.offers.len());
+        let peers = signaling.pick(&info_hash, &peer_id, count);
+        for (outbox, offer) in peers.iter().zip(&request.offers) {
+            let message = json!({
+                "action": "announce",
+                "info_hash": encode(&info_hash),
+                "offer": offer.offer,
+                "offer_id": offer.offer_id,
+                "peer_id": encode(&peer_id),
+            });
+            let _ = outbox.try_send(message.to_string());
+        }
+
+        Ok(json!({
+            "action": "announce",
+            "complete": success.complete,
+            "incomplete": success.incomplete,
+            "info_hash": encode(&info_hash),
+            "interval": success.interval,
+            "min interval": success.min_interval,
+        }))
+    }
+
+    /// Relays the answer to an offer to the peer which made it, from a peer
+    /// which joined the swarm on this connection
+    fn answer(&self, request: &Request) -> Result<()> {
+        let info_hash = decode("info_hash", single(request))?;
+        let peer_id = decode("peer_id", request.peer_id.as_deref())?;
+        if !self.joined.contains(&(info_hash, peer_id)) {
+            return Err(Error::from_request("peer_id didn't announce"));
+        }
+        self.tracker
+            .guard()
+            .check_announce(self.remote.ip(), &peer_id)?;
+        let to_peer_id = decode("to_peer_id", request.to_peer_id.as_deref())?;
+        let offer_id = request
+            .offer_id
+            .as_deref()
+            .ok_or_else(|| Error::from_request("missing offer_id"))?;
+        let outbox = self
+            .tracker
+            .signaling()
+            .get(&info_hash, &to_peer_id)
+            .ok_or_else(|| Error::from_request("unknown to_peer_id"))?;
+        let message = json!({
+            "action": "announce",
+            "answer": request.answer,
+            "info_hash": encode(&info_hash),
+            "offer_id": offer_id,
+            "peer_id": encode(&peer_id),
+        });
+        let _ = outbox.try_send(message.to_string());
+        Ok(())
+    }
+
+    async fn scrape(&self, request: &Request) -> Result<Value> {
+        let info_hashes = match &request.info_hash {
+            Some(InfoHashes::One(info_hash)) => vec![decode("info_hash", Some(info_hash))?],
+            Some(InfoHashes::Many(info_hashes)) => info_hashes
+                .iter()
+                .map(|info_hash| decode("info_hash", Some(info_hash)))
+                .collect::<Result<_>>()?,
+            None => Vec::new(),
+        };
+        let (remote, passkey) = (self.remote.ip(), self.passkey.clone());
+        let response = blocking(&self.tracker, move |tracker| {
+            tracker.guard().check_scrape(remote)?;
+            if tracker.config().private.is_some() {
+                tracker.authenticate(passkey.as_deref())?;
+            }
+            let full_scrape = tracker
+                .config()
+                .http
+                .as_ref()
+                .is_some_and(|http| http.full_scrape);
+            match info_hashes.is_empty() {
+                false => tracker.scrape(&info_hashes),
+                true if full_scrape => tracker.full_scrape(),
+                true => Err(Error::from_request("full scrape is disabled")),
+            }
+        });
+        let response = response.await??;
+        let files: Map<String, Value> = response
+            .files
+            .iter()
+            .map(|(info_hash, file)| {
+                let file = json!({
+                    "complete": file.complete,
+                    "downloaded": file.downloaded,
+                    "incomplete": file.incomplete,
+                });
+                (encode(info_hash), file)
+            })
+            .collect();
+        Ok(json!({ "action": "scrape", "files": files }))
+    }
+
+    /// Removes the peers of the connection from their swarms, as a client
+    /// closing its connection won't announce anymore
+    fn close(self) {
+        let signaling = self.tracker.signaling();
+        for (info_hash, peer_id) in &self.joined {
+            if !signaling.leave(info_hash, peer_id, &self.outbox) {
+                continue;
+            }
+            if let Err(err) = self.tracker.storage().remove_peer(info_hash, peer_id) {
+                eprintln!("WebSocket peer of {}: {}", self.remote, err);
+            }
+        }
+    }
+}
+
+/// The info-hash of an announce
+fn single(request: &Request) -> Option<&str> {
+    match &request.info_hash {
+        Some(InfoHashes::One(info_hash)) => Some(info_hash),
+        _ => None,
+    }
+}
+
+/// A failure, with the info-hash of the request for the client to find the
+/// torrent it is about
+fn failed(request: &Request, err: &Error) -> Value {
+    let mut failure = json!({
+        "action": request.action,
+        "failure reason": refusal(err),
+    });
+    if let Some(info_hash) = single(request) {
+        failure["info_hash"] = Value::from(info_hash);
+    }
+    failure
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{client::TrackerClient, server::http};
+    use std::time::Duration;
+    use tforge_config::{HttpConfig, ServerConfig};
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio_tungstenite::{tungstenite, MaybeTlsStream, WebSocketStream};
+
+    type Client = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+    async fn start() -> (SocketAddr, Arc<Tracker>) {
+        let config = ServerConfig {
+            http: Some(HttpConfig {
+                websocket: true,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let tracker = Arc::new(Tracker::new(config));
+        tokio::spawn(http::serve(listener, tracker.clone()));
+        (addr, tracker)
+    }
+
+    async fn connect(addr: SocketAddr) -> Client {
+        let url = format!("ws://{}/announce", addr);
+        tokio_tungstenite::connect_async(url).await.unwrap().0
+    }
+
+    async fn send(client: &mut Client, message: Value) {
+        let message = tungstenite::Message::Text(message.to_string());
+        client.send(message).await.unwrap();
+    }
+
+    async fn recv(client: &mut Client) -> Value {
+        let next = tokio::time::timeout(Duration::from_secs(5), client.next());
+        match next.await.unwrap().unwrap().unwrap() {
+            tungstenite::Message::Text(text) => serde_json::from_str(&text).unwrap(),
+            message => panic!("unexpected {:?}", message),
+        }
+    }
+
+    fn announce(peer_id: u8, offers: Value) -> Value {
+        json!({
+            "action": "announce",
+            "info_hash": encode(&[0xaa; 20]),
+            "peer_id": encode(&[peer_id; 20]),
+            "event": "started",
+            "numwant": 5,
+            "uploaded": 0,
+            "downloaded": 0,
+            "left": null,
+            "offers": offers,
+        })
+    }
+
+    #[tokio::test]
+    async fn test_websocket_signaling() {
+        let (addr, tracker) = start().await;
+        let info_hash = encode(&[0xaa; 20]);
+
+        let mut first = connect(addr).await;
+        send(&mut first, announce(1, json!([]))).await;
+        let reply = recv(&mut first).await;
+        assert_eq!(reply["action"], "announce");
+        assert_eq!(reply["info_hash"], info_hash);
+        assert_eq!(reply["incomplete"], 1);
+
+        // The offer of the second peer goes to the first one, and its answer back
+        let mut second = connect(addr).await;
+        let offer = json!({ "type": "offer", "sdp": "v=0" });
+        let offers = json!([{ "offer": offer, "offer_id": "o".repeat(20) }]);
+        send(&mut second, announce(2, offers)).await;
+        assert_eq!(recv(&mut second).await["incomplete"], 2);
+        let relayed = recv(&mut first).await;
+        assert_eq!(relayed["offer"], offer);
+        assert_eq!(relayed["offer_id"], "o".repeat(20));
+        assert_eq!(relayed["peer_id"], encode(&[2; 20]));
+
+        let answer = json!({ "type": "answer", "sdp": "v=0" });
+        let message = json!({
+            "action": "announce",
+            "info_hash": info_hash,
+            "peer_id": encode(&[1; 20]),
+            "to_peer_id": encode(&[2; 20]),
+            "answer": answer,
+            "offer_id": "o".repeat(20),
+        });
+        send(&mut first, message.clone()).await;
+        let relayed = recv(&mut second).await;
+        assert_eq!(relayed["answer"], answer);
+        assert_eq!(relayed["peer_id"], encode(&[1; 20]));
+
+        // Only the connection a peer announced on answers for it
+        let mut third = connect(addr).await;
+        send(&mut third, message).await;
+        let reply = recv(&mut third).await;
+        assert_eq!(reply["failure reason"], "peer_id didn't announce");
+        let next = tokio::time::timeout(Duration::from_millis(100), second.next());
+        assert!(next.await.is_err());
+
+        // The swarm is shared, but WebRTC peers aren't given to the others
+        let url = format!("http://{}/announce", addr);
+        let request = TrackerRequest {
+            info_hash: [0xaa; 20],
+            peer_id: [3; 20],
+            port: 6881,
+            uploaded: 0,
+            downloaded: 0,
+            left: 0,
+            compact: true,
+            event: Some(TrackerEvent::Started),
+            ip: None,
+            numwant: None,
+            no_peer_id: false,
+            key: None,
+            trackerid: None,
+        };
+        let response = TrackerClient::new(url, None)
+            .announce(&request)
+            .await
+            .unwrap();
+        assert!(response.peers.is_empty());
+        assert_eq!((response.complete, response.incomplete), (1, 2));
+
+        let scrape = json!({ "action": "scrape", "info_hash": [info_hash] });
+        send(&mut first, scrape).await;
+        let reply = recv(&mut first).await;
+        assert_eq!(reply["action"], "scrape");
+        assert_eq!(reply["files"][&info_hash]["complete"], 1);
+        assert_eq!(reply["files"][&info_hash]["incomplete"], 2);
+
+        send(
+            &mut first,
+            json!({ "action": "announce", "info_hash": "short" }),
+        )
+        .await;
+        let reply = recv(&mut first).await;
+        assert_eq!(reply["failure reason"], "info_hash must be 20 bytes long");
+        assert_eq!(reply["info_hash"], "short");
+
+        // A closed connection leaves its swarms
+        second.close(None).await.unwrap();
+        for _ in 0..200 {
+            if tracker.signaling().len() == 1 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(tracker.signaling().len(), 1);
+        let stats = tracker.stats(&[[0xaa; 20]]).unwrap();
+        assert_eq!((stats[0].complete, stats[0].incomplete), (1, 1));
+    }
+
+    #[tokio::test]
+    async fn test_websocket_disabled() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let tracker = Arc::new(Tracker::new(ServerConfig::default()));
+        tokio::spawn(http::serve(listener, tracker));
+        let url = format!("ws://{}/announce", addr);
+        assert!(tokio_tungstenite::connect_async(url).await.is_err());
+    }
+
+    #[test]
+    fn test_decode() {
+        let bytes: [u8; 20] = std::array::from_fn(|i| i as u8 * 13);
+        assert_eq!(decode("info_hash", Some(&encode(&bytes))).unwrap(), bytes);
+        assert!(decode("info_hash", Some(&"\u{100}".repeat(20))).is_err());
+        assert!(decode("info_hash", None).is_err());
+    }
+}
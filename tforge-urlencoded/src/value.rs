@@ -103,3 +103,50 @@ fn encode_bytes(bytes: &[u8]) -> String {
         output
     })
 }
+
+/// Decodes a percent-encoded string into its raw bytes.
+/// `+` is decoded as a space, as in `application/x-www-form-urlencoded`.
+/// Returns `None` when a `%` is not followed by two hexadecimal digits.
+pub fn decode_bytes(encoded: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(encoded.len());
+    let mut input = encoded.bytes();
+    while let Some(b) = input.next() {
+        match b {
+            b'%' => {
+                let high = (input.next()? as char).to_digit(16)?;
+                let low = (input.next()? as char).to_digit(16)?;
+                bytes.push((high * 16 + low) as u8);
+            }
+            b'+' => bytes.push(b' '),
+            _ => bytes.push(b),
+        }
+    }
+    Some(bytes)
+}
+
+/// Decodes a percent-encoded string, see [`decode_bytes`].
+/// Returns `None` if the decoded bytes are not valid UTF-8.
+pub fn decode(encoded: &str) -> Option<String> {
+    String::from_utf8(decode_bytes(encoded)?).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_roundtrip() {
+        let original = "a b/c:d?é";
+        let encoded = Value::from(original).to_string();
+        assert_eq!(decode(&encoded).as_deref(), Some(original));
+    }
+
+    #[test]
+    fn test_decode_bytes() {
+        assert_eq!(decode_bytes("a+b%2fc"), Some(b"a b/c".to_vec()));
+        assert_eq!(decode_bytes("%ff%00"), Some(vec![0xff, 0x00]));
+        assert_eq!(decode_bytes("%f"), None);
+        assert_eq!(decode_bytes("%zz"), None);
+        assert_eq!(decode("%ff"), None);
+    }
+}
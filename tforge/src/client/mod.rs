@@ -5,7 +5,10 @@ use std::{
 };
 use tforge_config::ClientConfig;
 use tforge_metainfo::MetaInfo;
-use tforge_tracker::{client::TrackerClient, protocol::TrackerRequest};
+use tforge_tracker::{
+    client::{http_client, manager::TrackerManager},
+    protocol::TrackerRequest,
+};
 
 pub async fn main(config: &PathBuf, torrent: &PathBuf) -> Result<()> {
     let contents = tokio::fs::read_to_string(config).await;
@@ -15,9 +18,11 @@ pub async fn main(config: &PathBuf, torrent: &PathBuf) -> Result<()> {
     let file_content = tokio::fs::read(torrent).await?;
     let mut reader = BufReader::new(Cursor::new(file_content));
     let metainfo: MetaInfo = tforge_bencode::deserializer::from_reader(&mut reader)?;
-    println!("{:?}", metainfo.announce);
+    println!("{:?}", metainfo.trackers());
 
-    let client = TrackerClient::new(metainfo.announce, None);
+    let mut trackers = TrackerManager::from_announce(metainfo.announce, metainfo.announce_list)
+        .with_announce_to_all_tiers(config.announce_to_all_tiers)
+        .with_http_client(http_client(&config.tls)?);
     let request = TrackerRequest {
         info_hash: *b"1234567890-abcedfghi",
         peer_id: *b"1234567890-abcedfghi",
@@ -29,11 +34,15 @@ pub async fn main(config: &PathBuf, torrent: &PathBuf) -> Result<()> {
         event: None,
         ip: None,
         numwant: None,
+        no_peer_id: false,
         key: None,
         trackerid: None,
     };
 
-    let response = client.announce(request).await?;
+    let response = trackers.announce(&request).await?;
+    if let Some(warning) = &response.warning_message {
+        eprintln!("Tracker warning: {}", warning);
+    }
     println!("{:?}", response);
 
     Ok(())
@@ -0,0 +1,115 @@
+use anyhow::{bail, Result};
+use clap::Args;
+use std::{
+    path::PathBuf,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tforge_metainfo::edit::Edit;
+
+#[derive(Args)]
+pub struct EditArgs {
+    /// The .torrent file to edit, or a directory whose .torrent files are all edited
+    path: PathBuf,
+
+    /// Where to write the edited torrent, instead of editing it in place
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+
+    /// New announce URL
+    #[arg(long)]
+    announce: Option<String>,
+
+    /// Replaces the announce-list, one comma separated list of URLs per tier
+    #[arg(long = "tier")]
+    tiers: Vec<String>,
+
+    /// Removes the announce-list
+    #[arg(long, conflicts_with = "tiers")]
+    no_announce_list: bool,
+
+    /// New comment, an empty one removes it
+    #[arg(long)]
+    comment: Option<String>,
+
+    /// New "created by", an empty one removes it
+    #[arg(long)]
+    created_by: Option<String>,
+
+    /// New creation date, in seconds since the Unix epoch
+    #[arg(long, conflicts_with = "now")]
+    creation_date: Option<u64>,
+
+    /// Sets the creation date to the current time
+    #[arg(long)]
+    now: bool,
+
+    /// Adds a BEP 19 web seed
+    #[arg(long = "web-seed")]
+    web_seeds: Vec<String>,
+
+    /// Removes the web seeds, before adding the --web-seed ones
+    #[arg(long)]
+    no_web_seeds: bool,
+
+    /// Replaces the host of the tracker URLs
+    #[arg(long, num_args = 2, value_names = ["FROM", "TO"])]
+    replace_tracker_host: Option<Vec<String>>,
+}
+
+impl EditArgs {
+    fn to_edit(&self) -> Edit {
+        let announce_list = match (self.no_announce_list, self.tiers.is_empty()) {
+            (true, _) => Some(Vec::new()),
+            (false, true) => None,
+            (false, false) => Some(
+                self.tiers
+                    .iter()
+                    .map(|tier| tier.split(',').map(String::from).collect())
+                    .collect(),
+            ),
+        };
+        let creation_date = match self.now {
+            true => Some(SystemTime::now()),
+            false => self
+                .creation_date
+                .map(|secs| UNIX_EPOCH + Duration::from_secs(secs)),
+        };
+
+        Edit {
+            announce: self.announce.clone(),
+            announce_list,
+            comment: self.comment.clone(),
+            created_by: self.created_by.clone(),
+            creation_date,
+            url_list: self.no_web_seeds.then(Vec::new),
+            add_web_seeds: self.web_seeds.clone(),
+            replace_tracker_host: self
+                .replace_tracker_host
+                .as_ref()
+                .map(|hosts| (hosts[0].clone(), hosts[1].clone())),
+        }
+    }
+}
+
+pub async fn main(args: &EditArgs) -> Result<()> {
+    let edit = args.to_edit();
+
+    if args.path.is_dir() {
+        if args.output.is_some() {
+            bail!("--output can't be used when editing a directory");
+        }
+        let changed = edit.apply_to_dir(&args.path)?;
+        for path in &changed {
+            println!("{}", path.display());
+        }
+        println!("{} torrents edited", changed.len());
+        return Ok(());
+    }
+
+    let output = args.output.as_ref().unwrap_or(&args.path);
+    if edit.apply_to_file_as(&args.path, output)? {
+        println!("{}", output.display());
+    }
+
+    Ok(())
+}
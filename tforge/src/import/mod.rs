@@ -0,0 +1,87 @@
+use anyhow::{bail, Context, Result};
+use clap::Args;
+use std::path::{Path, PathBuf};
+use tforge_metainfo::{edit::RawMetaInfo, fastresume::import_fastresume};
+
+#[derive(Args)]
+pub struct ImportArgs {
+    /// The .fastresume files to import
+    #[arg(required = true)]
+    fastresume: Vec<PathBuf>,
+
+    /// The torrent the resume data belongs to, defaults to the .torrent file next to it
+    #[arg(short, long)]
+    torrent: Option<PathBuf>,
+
+    /// Directory to write the resume states to, defaults to the one of each .fastresume file
+    #[arg(short, long)]
+    output_dir: Option<PathBuf>,
+}
+
+pub async fn main(args: &ImportArgs) -> Result<()> {
+    if args.torrent.is_some() && args.fastresume.len() > 1 {
+        bail!("--torrent can only be used with a single .fastresume file");
+    }
+
+    let mut failed = 0;
+    for path in &args.fastresume {
+        if let Err(err) = import(path, args) {
+            eprintln!("{}: {:#}", path.display(), err);
+            failed += 1;
+        }
+    }
+
+    if failed > 0 {
+        bail!("{} files could not be imported", failed);
+    }
+    Ok(())
+}
+
+fn import(path: &Path, args: &ImportArgs) -> Result<()> {
+    // qBittorrent keeps `<hash>.torrent` next to `<hash>.fastresume` in BT_backup
+    let sibling = path.with_extension("torrent");
+    let torrent = match &args.torrent {
+        Some(torrent) => Some(read_torrent(torrent)?),
+        None if sibling.is_file() => Some(read_torrent(&sibling)?),
+        None => None,
+    };
+
+    let bytes = std::fs::read(path)?;
+    let import = import_fastresume(&bytes, torrent.as_ref().map(|torrent| &torrent.info))?;
+    let state = &import.state;
+
+    let hashes = state.info_hashes();
+    let name = hashes
+        .v1
+        .map(hex::encode)
+        .or(hashes.v2.map(hex::encode))
+        .expect("imported states always have an info-hash");
+    let output_dir = match &args.output_dir {
+        Some(dir) => dir.as_path(),
+        None => path.parent().unwrap_or(Path::new(".")),
+    };
+    let output = output_dir.join(format!("{}.resume", name));
+    std::fs::write(&output, state.to_bytes()?)?;
+
+    println!("{} -> {}", path.display(), output.display());
+    println!("  Save path: {}", state.save_path);
+    println!(
+        "  Pieces:    {}/{}",
+        state.completed_pieces(),
+        state.piece_count
+    );
+    println!("  Carried over: {}", import.carried.join(", "));
+    if !import.dropped.is_empty() {
+        println!("  Not carried over:");
+        for dropped in &import.dropped {
+            println!("    {}", dropped);
+        }
+    }
+    Ok(())
+}
+
+/// Reads the torrent keeping its info dictionary as is, for its info-hashes
+fn read_torrent(path: &Path) -> Result<RawMetaInfo> {
+    let file_content = std::fs::read(path)?;
+    RawMetaInfo::from_bytes(&file_content).with_context(|| format!("reading {}", path.display()))
+}
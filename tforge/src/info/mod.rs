@@ -0,0 +1,259 @@
+use anyhow::{bail, Result};
+use clap::Args;
+use serde::Serialize;
+use std::{
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+use tforge_metainfo::edit::RawMetaInfo;
+
+#[derive(Args)]
+pub struct InfoArgs {
+    /// The .torrent files to inspect
+    #[arg(required = true)]
+    torrents: Vec<PathBuf>,
+
+    /// Prints JSON instead of human readable text
+    #[arg(long)]
+    json: bool,
+
+    /// Only lists the files whose path contains this text
+    #[arg(long)]
+    files: Option<String>,
+}
+
+/// What `tforge info` shows about a torrent
+#[derive(Serialize)]
+struct TorrentInfo {
+    path: PathBuf,
+    name: String,
+    version: String,
+    info_hash_v1: Option<String>,
+    info_hash_v2: Option<String>,
+    total_size: usize,
+    piece_length: usize,
+    piece_count: usize,
+    private: bool,
+    source: Option<String>,
+    comment: Option<String>,
+    created_by: Option<String>,
+    creation_date: Option<u64>,
+    trackers: Vec<Vec<String>>,
+    web_seeds: Vec<String>,
+    files: Vec<FileInfo>,
+}
+
+#[derive(Serialize)]
+struct FileInfo {
+    path: String,
+    size: usize,
+}
+
+impl TorrentInfo {
+    fn read(path: &Path, filter: Option<&str>) -> Result<Self> {
+        // The info-hashes are those of the info dictionary as read, with the keys `Info` drops
+        let metainfo = RawMetaInfo::from_bytes(&std::fs::read(path)?)?;
+        let info = metainfo.decode_info()?;
+        let encoding = metainfo.encoding.as_deref();
+        let hashes = metainfo.info_hashes()?;
+        let files: Vec<_> = info
+            .files()
+            .into_iter()
+            .filter(|file| !file.is_padding())
+            .collect();
+        let trackers = match &metainfo.announce_list {
+            Some(tiers) => tiers.clone(),
+            None if metainfo.announce.is_empty() => Vec::new(),
+            None => vec![vec![metainfo.announce.clone()]],
+        };
+
+        Ok(TorrentInfo {
+            path: path.to_path_buf(),
+            name: info.display_name(encoding),
+            version: info.version()?.to_string(),
+            info_hash_v1: hashes.v1.map(hex::encode),
+            info_hash_v2: hashes.v2.map(hex::encode),
+            total_size: files.iter().map(|file| file.length).sum(),
+            piece_length: info.piece_length,
+            piece_count: info.piece_count(),
+            private: info.is_private(),
+            source: info.source.clone(),
+            comment: metainfo.display_comment(),
+            created_by: metainfo.created_by.clone(),
+            creation_date: metainfo
+                .creation_date
+                .and_then(|date| date.duration_since(UNIX_EPOCH).ok())
+                .map(|date| date.as_secs()),
+            trackers,
+            web_seeds: metainfo.web_seeds().into_iter().map(String::from).collect(),
+            files: files
+                .iter()
+                .map(|file| FileInfo {
+                    path: file.display_path(encoding).join("/"),
+                    size: file.length,
+                })
+                .filter(|file| filter.map_or(true, |filter| file.path.contains(filter)))
+                .collect(),
+        })
+    }
+
+    fn print(&self) {
+        println!("Name:         {}", self.name);
+        println!("Version:      {}", self.version);
+        if let Some(hash) = &self.info_hash_v1 {
+            println!("Info hash v1: {}", hash);
+        }
+        if let Some(hash) = &self.info_hash_v2 {
+            println!("Info hash v2: {}", hash);
+        }
+        println!(
+            "Size:         {} ({} bytes)",
+            format_size(self.total_size),
+            self.total_size
+        );
+        println!(
+            "Pieces:       {} x {}",
+            self.piece_count,
+            format_size(self.piece_length)
+        );
+        println!("Private:      {}", if self.private { "yes" } else { "no" });
+        if let Some(source) = &self.source {
+            println!("Source:       {}", source);
+        }
+        if let Some(comment) = &self.comment {
+            println!("Comment:      {}", comment);
+        }
+        if let Some(created_by) = &self.created_by {
+            println!("Created by:   {}", created_by);
+        }
+        if let Some(creation_date) = self.creation_date {
+            println!("Created:      {} (Unix time)", creation_date);
+        }
+
+        if !self.trackers.is_empty() {
+            println!("Trackers:");
+            for (index, tier) in self.trackers.iter().enumerate() {
+                for (position, tracker) in tier.iter().enumerate() {
+                    match position {
+                        0 => println!("  Tier {}: {}", index + 1, tracker),
+                        _ => println!("  {:width$} {}", "", tracker, width = 6 + digits(index + 1)),
+                    }
+                }
+            }
+        }
+
+        if !self.web_seeds.is_empty() {
+            println!("Web seeds:");
+            for url in &self.web_seeds {
+                println!("  {}", url);
+            }
+        }
+
+        println!("Files:");
+        let mut directories: Vec<&str> = Vec::new();
+        for file in &self.files {
+            let components: Vec<&str> = file.path.split('/').collect();
+            let (name, parents) = components.split_last().expect("paths are never empty");
+            let common = directories
+                .iter()
+                .zip(parents)
+                .take_while(|(a, b)| a == b)
+                .count();
+            directories.truncate(common);
+            for parent in &parents[common..] {
+                println!("  {}{}/", "  ".repeat(directories.len()), parent);
+                directories.push(parent);
+            }
+            println!(
+                "  {}{} ({})",
+                "  ".repeat(directories.len()),
+                name,
+                format_size(file.size)
+            );
+        }
+    }
+}
+
+pub async fn main(args: &InfoArgs) -> Result<()> {
+    let mut torrents = Vec::new();
+    let mut failed = 0;
+    for path in &args.torrents {
+        match TorrentInfo::read(path, args.files.as_deref()) {
+            Ok(torrent) => torrents.push(torrent),
+            Err(err) => {
+                eprintln!("{}: {}", path.display(), err);
+                failed += 1;
+            }
+        }
+    }
+
+    match (args.json, args.torrents.len()) {
+        (true, 1) => {
+            if let Some(torrent) = torrents.first() {
+                println!("{}", serde_json::to_string_pretty(torrent)?);
+            }
+        }
+        (true, _) => println!("{}", serde_json::to_string_pretty(&torrents)?),
+        (false, 1) => torrents.iter().for_each(TorrentInfo::print),
+        (false, _) => print_summary(&torrents),
+    }
+
+    if failed > 0 {
+        bail!("{} torrents could not be read", failed);
+    }
+    Ok(())
+}
+
+/// One line per torrent, for batches of files
+fn print_summary(torrents: &[TorrentInfo]) {
+    let width = torrents
+        .iter()
+        .map(|torrent| torrent.name.chars().count())
+        .chain(std::iter::once(4))
+        .max()
+        .unwrap_or(4);
+
+    println!(
+        "{:<width$}  {:<6}  {:>10}  {:>6}  {:>7}  Info hash",
+        "Name", "Format", "Size", "Files", "Pieces"
+    );
+    for torrent in torrents {
+        let hash = torrent
+            .info_hash_v1
+            .as_ref()
+            .or(torrent.info_hash_v2.as_ref())
+            .map(String::as_str)
+            .unwrap_or("");
+        println!(
+            "{:<width$}  {:<6}  {:>10}  {:>6}  {:>7}  {}",
+            torrent.name,
+            torrent.version,
+            format_size(torrent.total_size),
+            torrent.files.len(),
+            torrent.piece_count,
+            hash
+        );
+    }
+
+    let total_size: usize = torrents.iter().map(|torrent| torrent.total_size).sum();
+    println!("{} torrents, {}", torrents.len(), format_size(total_size));
+}
+
+/// Formats a size with binary units, e.g. `1.5 MiB`
+fn format_size(size: usize) -> String {
+    const UNITS: [&str; 6] = ["B", "KiB", "MiB", "GiB", "TiB", "PiB"];
+    let mut value = size as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    match unit {
+        0 => format!("{} {}", size, UNITS[0]),
+        _ => format!("{:.1} {}", value, UNITS[unit]),
+    }
+}
+
+fn digits(number: usize) -> usize {
+    number.to_string().len()
+}
@@ -0,0 +1,12 @@
+use anyhow::Result;
+use std::path::PathBuf;
+use tforge_metainfo::edit::RawMetaInfo;
+
+pub async fn main(torrent: &PathBuf) -> Result<()> {
+    let file_content = tokio::fs::read(torrent).await?;
+    let metainfo = RawMetaInfo::from_bytes(&file_content)?;
+
+    println!("{}", metainfo.to_magnet()?);
+
+    Ok(())
+}
@@ -3,7 +3,12 @@ use clap::{Parser, Subcommand};
 use std::path::PathBuf;
 
 mod client;
+mod edit;
+mod import;
+mod info;
+mod magnet;
 mod server;
+mod tracker;
 
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
@@ -23,10 +28,20 @@ enum Commands {
         #[arg(short, long)]
         torrent: PathBuf,
     },
+    /// Edits the fields of torrents outside of their info dictionary, keeping their info-hash
+    Edit(edit::EditArgs),
+    /// Imports libtorrent and qBittorrent .fastresume files
+    Import(import::ImportArgs),
+    /// Shows the content of torrents
+    Info(info::InfoArgs),
+    /// Prints the magnet link of a torrent
+    Magnet { torrent: PathBuf },
     Server {
         #[arg(short, long)]
         config: PathBuf,
     },
+    /// Manages the whitelist and users of a private tracker server
+    Tracker(tracker::TrackerArgs),
 }
 
 #[tokio::main]
@@ -35,6 +50,11 @@ async fn main() -> Result<()> {
 
     match &cli.command {
         Commands::Client { config, torrent } => client::main(config, torrent).await,
+        Commands::Edit(args) => edit::main(args).await,
+        Commands::Import(args) => import::main(args).await,
+        Commands::Info(args) => info::main(args).await,
+        Commands::Magnet { torrent } => magnet::main(torrent).await,
         Commands::Server { config } => server::main(config).await,
+        Commands::Tracker(args) => tracker::main(args).await,
     }
 }
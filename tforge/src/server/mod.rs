@@ -1,7 +1,36 @@
 use anyhow::Result;
 use std::path::PathBuf;
+use tforge_config::ServerConfig;
 
 pub async fn main(config: &PathBuf) -> Result<()> {
-    let _contents = tokio::fs::read_to_string(config).await;
+    let contents = tokio::fs::read_to_string(config).await?;
+    let config = ServerConfig::try_from(contents)?;
+
+    if let Some(http) = &config.http {
+        match &http.tls {
+            Some(tls) => {
+                println!("HTTPS tracker listening on {}", http.bind);
+                if let Some(redirect) = tls.redirect {
+                    println!("Redirecting HTTP to HTTPS from {}", redirect);
+                }
+            }
+            None => println!("HTTP tracker listening on {}", http.bind),
+        }
+        if http.websocket {
+            println!("WebSocket tracker listening on {}/announce", http.bind);
+        }
+    }
+    for bind in config.udp.iter().flat_map(|udp| &udp.bind) {
+        println!("UDP tracker listening on {}", bind);
+    }
+    if let Some(proxy) = &config.proxy {
+        let tiers = proxy.tiers.len();
+        println!("Proxying announces to {} upstream tiers by default", tiers);
+    }
+    if let Some(admin) = &config.admin {
+        println!("Admin API listening on {}", admin.bind);
+    }
+    tforge_tracker::server::run(config).await?;
+
     Ok(())
 }
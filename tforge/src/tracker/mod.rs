@@ -0,0 +1,131 @@
+use anyhow::{bail, Context, Result};
+use clap::{Args, Subcommand};
+use rand::RngCore;
+use std::path::PathBuf;
+use tforge_config::{ServerConfig, StorageConfig};
+use tforge_tracker::server::storage::{self, Torrent, User};
+
+#[derive(Args)]
+pub struct TrackerArgs {
+    /// The config of the tracker server, whose SQLite storage is edited
+    #[arg(short, long)]
+    config: PathBuf,
+
+    #[command(subcommand)]
+    command: TrackerCommand,
+}
+
+#[derive(Subcommand)]
+enum TrackerCommand {
+    /// Manages the torrents a private tracker serves
+    Whitelist {
+        #[command(subcommand)]
+        command: WhitelistCommand,
+    },
+    /// Manages the users of a private tracker
+    User {
+        #[command(subcommand)]
+        command: UserCommand,
+    },
+}
+
+#[derive(Subcommand)]
+enum WhitelistCommand {
+    /// Registers a torrent, or renames it
+    Add {
+        /// The hex info-hash of the torrent
+        info_hash: String,
+
+        /// The name shown in scrapes
+        #[arg(short, long)]
+        name: Option<String>,
+    },
+    /// Unregisters a torrent
+    Remove { info_hash: String },
+    /// Lists the registered torrents
+    List,
+}
+
+#[derive(Subcommand)]
+enum UserCommand {
+    /// Adds a user and prints their passkey
+    Add { name: String },
+    /// Removes the user with a passkey
+    Remove { passkey: String },
+    /// Lists the users with their passkey and transfer totals
+    List,
+}
+
+pub async fn main(args: &TrackerArgs) -> Result<()> {
+    let contents = tokio::fs::read_to_string(&args.config).await?;
+    let config = ServerConfig::try_from(contents)?;
+    // A running server keeps a memory storage to itself and overwrites its snapshot
+    if let StorageConfig::Memory { .. } = config.storage {
+        bail!("only a sqlite storage can be edited, as the server may be running");
+    }
+    let storage = storage::open(&config.storage)?;
+
+    match &args.command {
+        TrackerCommand::Whitelist { command } => match command {
+            WhitelistCommand::Add { info_hash, name } => {
+                storage.register_torrent(Torrent {
+                    info_hash: parse_info_hash(info_hash)?,
+                    name: name.clone(),
+                })?;
+            }
+            WhitelistCommand::Remove { info_hash } => {
+                if !storage.unregister_torrent(&parse_info_hash(info_hash)?)? {
+                    bail!("{} is not registered", info_hash);
+                }
+            }
+            WhitelistCommand::List => {
+                for torrent in storage.torrents()? {
+                    let name = torrent.name.unwrap_or_default();
+                    println!("{} {}", hex::encode(torrent.info_hash), name);
+                }
+            }
+        },
+        TrackerCommand::User { command } => match command {
+            UserCommand::Add { name } => {
+                let mut passkey = [0; 16];
+                rand::thread_rng().fill_bytes(&mut passkey);
+                let passkey = hex::encode(passkey);
+                storage.add_user(User {
+                    passkey: passkey.clone(),
+                    name: name.clone(),
+                    uploaded: 0,
+                    downloaded: 0,
+                })?;
+                println!("{}", passkey);
+            }
+            UserCommand::Remove { passkey } => {
+                if !storage.remove_user(passkey)? {
+                    bail!("no user has the passkey {}", passkey);
+                }
+            }
+            UserCommand::List => {
+                for user in storage.users()? {
+                    println!(
+                        "{} {} uploaded={} downloaded={} ratio={:.2}",
+                        user.passkey,
+                        user.name,
+                        user.uploaded,
+                        user.downloaded,
+                        user.ratio()
+                    );
+                }
+            }
+        },
+    }
+
+    storage.persist()?;
+    Ok(())
+}
+
+fn parse_info_hash(info_hash: &str) -> Result<[u8; 20]> {
+    let bytes = hex::decode(info_hash).context("invalid info-hash")?;
+    bytes
+        .try_into()
+        .ok()
+        .context("an info-hash is 40 hex digits")
+}